@@ -0,0 +1,107 @@
+//! The audit log of denied requests and operations.
+//!
+//! Each denial is appended to the log file as a line of JSON, so that the
+//! file can be followed with `tail -f` and read with `jq`.
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::Role;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    /// The name of the operator or token, or `anonymous`.
+    pub who: String,
+    pub role: Role,
+    /// What was denied, e.g. `delete` or `POST /api/v1/accounts`.
+    pub action: String,
+    /// The item the operation was denied on, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Uuid>,
+    /// The tenant the operation was denied in, unless the default tenant.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(who: &str, role: Role, action: impl Into<String>, target: Option<Uuid>) -> Self {
+        AuditEntry {
+            at: Utc::now(),
+            who: who.to_string(),
+            role,
+            action: action.into(),
+            target,
+            tenant: None,
+        }
+    }
+
+    pub fn in_tenant(mut self, tenant: Option<&str>) -> Self {
+        self.tenant = tenant.map(str::to_string);
+        self
+    }
+}
+
+/// Appends entries to the audit log file.
+pub struct AuditLog {
+    path: PathBuf,
+    /// Keeps the lines of concurrent denials from interleaving.
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: &Path) -> Self {
+        AuditLog {
+            path: path.to_path_buf(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Records a denial. Failing to write the log is logged, but does not
+    /// change the outcome for the caller, who is denied either way.
+    pub fn record(&self, entry: &AuditEntry) {
+        log::warn!(
+            "Denied {} ({}) to {}{}{}",
+            entry.who,
+            entry.role,
+            entry.action,
+            entry
+                .target
+                .map(|target| format!(" {}", target))
+                .unwrap_or_default(),
+            entry
+                .tenant
+                .as_ref()
+                .map(|tenant| format!(" in {}", tenant))
+                .unwrap_or_default()
+        );
+        let _lock = self.lock.lock().unwrap();
+        let line = serde_json::to_string(entry).expect("Failed to serialize AuditEntry");
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        if let Err(e) = written {
+            log::error!("Failed to write audit log {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Reads the entries of the audit log file at `path`.
+#[cfg(test)]
+pub fn read(path: &Path) -> Vec<AuditEntry> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("Invalid audit entry"))
+        .collect()
+}
@@ -0,0 +1,669 @@
+//! Authentication of the web server.
+//!
+//! When `auth.enabled` is set, every request must either send
+//! `Authorization: Bearer <secret>` with a [`token::Token`] whose scopes
+//! grant what the request needs, or the cookie of a [`session`] started by
+//! logging in at `/login` as one of the operators of the [`user`] store.
+//! Tokens act in the role of their scope: `read` as a viewer, `write` as an
+//! editor and `admin` as an admin; operators in the role given to them. The
+//! [`policy`] decides what each role may do. Reads may be left public with
+//! `auth.public_reads`, except in tenants that only let their `members` in.
+//! Browsers asking for a page without a login are sent to the login page.
+
+pub mod audit;
+pub mod policy;
+pub mod session;
+pub mod token;
+pub mod user;
+
+use std::sync::{Arc, Mutex};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    web, HttpMessage, HttpRequest, HttpResponse,
+};
+use clap::builder::PossibleValue;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cfg::{AuthCfg, Cfg},
+    crud::{self, Crud, CrudError},
+    route::api::{self, Representation},
+    tenant,
+};
+use audit::{AuditEntry, AuditLog};
+use policy::Policy;
+use session::Sessions;
+use token::Tokens;
+use user::User;
+
+/// What a token may be used for. Each scope also grants the ones before it.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Scope {
+    /// The role a token with this scope acts in.
+    pub fn role(&self) -> Role {
+        match self {
+            Scope::Read => Role::Viewer,
+            Scope::Write => Role::Editor,
+            Scope::Admin => Role::Admin,
+        }
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scope::Read => write!(f, "read"),
+            Scope::Write => write!(f, "write"),
+            Scope::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl clap::ValueEnum for Scope {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Scope::Read, Scope::Write, Scope::Admin]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Scope::Read => PossibleValue::new("read").help("List and show accounts"),
+            Scope::Write => {
+                PossibleValue::new("write").help("Also create, change and delete accounts")
+            }
+            Scope::Admin => PossibleValue::new("admin").help("Also import accounts in bulk"),
+        })
+    }
+}
+
+/// What an operator or token may do with the accounts, as decided by the
+/// [`Policy`]. Each role may do what the ones before it may.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Admin,
+}
+
+impl Role {
+    /// The token scope that grants this role.
+    pub fn scope(&self) -> Scope {
+        match self {
+            Role::Viewer => Scope::Read,
+            Role::Editor => Scope::Write,
+            Role::Admin => Scope::Admin,
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::Viewer => write!(f, "viewer"),
+            Role::Editor => write!(f, "editor"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "editor" => Ok(Role::Editor),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role {:?}", s)),
+        }
+    }
+}
+
+impl clap::ValueEnum for Role {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Role::Viewer, Role::Editor, Role::Admin]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Role::Viewer => PossibleValue::new("viewer").help("List and show accounts"),
+            Role::Editor => {
+                PossibleValue::new("editor").help("Also create, change and delete accounts")
+            }
+            Role::Admin => PossibleValue::new("admin").help("Also import accounts in bulk"),
+        })
+    }
+}
+
+/// How a request proved who sent it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Credential {
+    Token,
+    Session,
+}
+
+/// Who sent a request: the name of its token or the operator logged in.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Identity {
+    pub name: String,
+    pub role: Role,
+    #[serde(skip)]
+    pub via: Credential,
+}
+
+/// Who sent `req`, when authentication is enabled and the request has a
+/// valid token or login.
+pub fn identity(req: &HttpRequest) -> Option<Identity> {
+    req.extensions().get::<Identity>().cloned()
+}
+
+/// The authentication settings, tokens, sessions and policy of a running
+/// server.
+pub struct Auth {
+    pub enabled: bool,
+    pub public_reads: bool,
+    pub tokens: Tokens,
+    pub sessions: Sessions,
+    pub policy: Policy,
+    pub audit: Arc<AuditLog>,
+    users: Mutex<Box<dyn Crud<User>>>,
+}
+
+impl Auth {
+    pub fn new(cfg: &AuthCfg, users: Box<dyn Crud<User>>) -> Self {
+        Auth {
+            enabled: cfg.enabled,
+            public_reads: cfg.public_reads,
+            tokens: Tokens::new(cfg.tokens.clone(), cfg.token_path().into()),
+            sessions: Sessions::new(cfg.session_ttl_secs, cfg.secure_cookies),
+            policy: cfg.policy,
+            audit: Arc::new(AuditLog::new(cfg.audit_path().as_ref())),
+            users: Mutex::new(users),
+        }
+    }
+
+    /// The operator with `username` if `password` is theirs.
+    pub fn verify_user(&self, username: &str, password: &str) -> crud::Result<Option<User>> {
+        // The lock is not held while the password is hashed.
+        let user = self
+            .users
+            .lock()
+            .map_err(|_| CrudError::UnknownError)?
+            .read_all()?
+            .into_iter()
+            .find(|user| user.username == username);
+        match user {
+            Some(user) if user.verify_password(password) => Ok(Some(user)),
+            Some(_) => Ok(None),
+            None => {
+                user::verify_nobody(password);
+                Ok(None)
+            }
+        }
+    }
+
+    /// The least role that may send `req`, or `None` if anyone may. Which
+    /// accounts a request may then read or change is up to the [`Policy`].
+    fn required_role(&self, req: &ServiceRequest) -> Option<Role> {
+        // Tenants have the same routes as the default tenant.
+        let (_, path) = tenant::split(req.path());
+        if !self.enabled
+            || path == "/"
+            || path == "/login"
+            || path == "/logout"
+            || path == "/api/openapi.json"
+            || path.starts_with("/api/docs")
+        {
+            return None;
+        }
+        // WebSocket clients send changes once connected.
+        if path == "/ws" {
+            return Some(self.policy.least_writer());
+        }
+        if path == "/account/import/commit" {
+            return Some(Role::Admin);
+        }
+        match *req.method() {
+            Method::GET | Method::HEAD | Method::OPTIONS if self.public_reads => None,
+            Method::GET | Method::HEAD | Method::OPTIONS => Some(self.policy.read),
+            _ => Some(self.policy.least_writer()),
+        }
+    }
+
+    /// Finds out who sent `req` from its bearer token or session cookie. A
+    /// token that is sent but not valid is an error saying why.
+    fn identify(&self, req: &ServiceRequest) -> Result<Option<Identity>, &'static str> {
+        let secret = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        if let Some(secret) = secret {
+            return match self.tokens.find(secret) {
+                None => Err("The token is not valid"),
+                Some(token) if token.is_expired(chrono::Utc::now()) => Err("The token has expired"),
+                Some(token) => match token.scopes.iter().max() {
+                    Some(scope) => Ok(Some(Identity {
+                        name: token.name,
+                        role: scope.role(),
+                        via: Credential::Token,
+                    })),
+                    None => Err("The token has no scopes"),
+                },
+            };
+        }
+        let Some(session) = req
+            .cookie(session::COOKIE)
+            .and_then(|cookie| self.sessions.find(cookie.value()))
+        else {
+            return Ok(None);
+        };
+        // Operators removed since they logged in are logged out, and a
+        // changed role takes effect right away.
+        let user = self
+            .users
+            .lock()
+            .ok()
+            .and_then(|users| users.read(&session.user_id).ok());
+        Ok(user.map(|user| Identity {
+            name: session.username,
+            role: user.role,
+            via: Credential::Session,
+        }))
+    }
+}
+
+fn refuse(status: StatusCode, challenge: String, message: &str) -> HttpResponse {
+    let mut response = api::error(status, message);
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        header::HeaderValue::from_str(&challenge).expect("Invalid challenge"),
+    );
+    response
+}
+
+/// Whether `req` comes from a browser asking for a page.
+fn wants_page(req: &ServiceRequest) -> bool {
+    let accepts_html = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    matches!(*req.method(), Method::GET | Method::HEAD)
+        && accepts_html
+        && api::negotiate(req.request()) == Representation::Html
+}
+
+/// Sends a browser to the login page, which returns it to where it was.
+fn login_redirect(req: &ServiceRequest) -> HttpResponse {
+    let next = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    let query = serde_urlencoded::to_string([("next", next)]).expect("Failed to encode query");
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, format!("/login?{}", query)))
+        .finish()
+}
+
+/// Lets a request through only if it has a token or login in the role it
+/// needs, and records its [`Identity`]. Denials are written to the audit
+/// log. Requests are let through as they are when no [`Auth`] is set up.
+pub async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let auth = match req.app_data::<web::Data<Auth>>() {
+        Some(auth) if auth.enabled => auth.clone(),
+        _ => return Ok(next.call(req).await?.map_into_left_body()),
+    };
+    let (tenant, path) = tenant::split(req.path());
+    let members = req
+        .app_data::<web::Data<Cfg>>()
+        .and_then(|cfg| cfg.tenants.get(tenant?)?.members.clone());
+    let mut required = auth.required_role(&req);
+    if members.is_some() && path != "/" {
+        // Only members may read the accounts of their tenant.
+        required = required.or(Some(auth.policy.read));
+    }
+    let identity = match auth.identify(&req) {
+        Ok(identity) => identity,
+        Err(reason) if required.is_some() => {
+            let refused = refuse(
+                StatusCode::UNAUTHORIZED,
+                r#"Bearer realm="accounts", error="invalid_token""#.to_string(),
+                reason,
+            );
+            return Ok(req.into_response(refused).map_into_right_body());
+        }
+        Err(_) => None,
+    };
+    let refused = match (required, &identity) {
+        (None, _) => None,
+        (Some(_), None) if wants_page(&req) => Some(login_redirect(&req)),
+        (Some(_), None) => Some(refuse(
+            StatusCode::UNAUTHORIZED,
+            r#"Bearer realm="accounts""#.to_string(),
+            "A bearer token or a login is required",
+        )),
+        (Some(_), Some(identity))
+            if members
+                .as_ref()
+                .is_some_and(|members| !members.contains(&identity.name)) =>
+        {
+            auth.audit.record(
+                &AuditEntry::new(
+                    &identity.name,
+                    identity.role,
+                    format!("{} {}", req.method(), req.path()),
+                    None,
+                )
+                .in_tenant(tenant),
+            );
+            Some(api::error(
+                StatusCode::FORBIDDEN,
+                "Only the members of this tenant may use it",
+            ))
+        }
+        (Some(required), Some(identity)) if identity.role < required => {
+            auth.audit.record(
+                &AuditEntry::new(
+                    &identity.name,
+                    identity.role,
+                    format!("{} {}", req.method(), req.path()),
+                    None,
+                )
+                .in_tenant(tenant),
+            );
+            Some(match identity.via {
+                Credential::Token => refuse(
+                    StatusCode::FORBIDDEN,
+                    format!(
+                        r#"Bearer realm="accounts", error="insufficient_scope", scope="{}""#,
+                        required.scope()
+                    ),
+                    &format!("The token lacks the {} scope", required.scope()),
+                ),
+                Credential::Session => api::error(
+                    StatusCode::FORBIDDEN,
+                    &format!("Only the {} role or above may do this", required),
+                ),
+            })
+        }
+        (Some(_), Some(_)) => None,
+    };
+    if let Some(refused) = refused {
+        return Ok(req.into_response(refused).map_into_right_body());
+    }
+    if let Some(identity) = identity {
+        log::debug!("{} {} by {}", req.method(), req.path(), identity.name);
+        req.extensions_mut().insert(identity);
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        middleware::from_fn,
+        test::{call_service, init_service, TestRequest},
+        App,
+    };
+    use chrono::{Duration, Utc};
+
+    use super::{token::Token, *};
+    use crate::crud::memory::MemoryStore;
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    fn no_users() -> Box<dyn Crud<User>> {
+        Box::new(MemoryStore::<User>::new())
+    }
+
+    #[actix_web::test]
+    async fn requests_need_a_token_with_the_required_scope() {
+        let (reader, reader_secret) = Token::generate("reader", vec![Scope::Read], None);
+        let (writer, writer_secret) = Token::generate("writer", vec![Scope::Write], None);
+        let (expired, expired_secret) = Token::generate(
+            "old",
+            vec![Scope::Admin],
+            Some(Utc::now() - Duration::days(1)),
+        );
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let audit_path = dir.path().join("audit.jsonl");
+        let cfg = AuthCfg {
+            enabled: true,
+            tokens: vec![reader, writer, expired],
+            audit_path: Some(audit_path.display().to_string()),
+            ..AuthCfg::default()
+        };
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Auth::new(&cfg, no_users())))
+                .wrap(from_fn(authenticate))
+                .route("/", web::get().to(ok))
+                .route("/api/v1/accounts", web::get().to(ok))
+                .route("/api/v1/accounts", web::post().to(ok)),
+        )
+        .await;
+
+        for (method, uri, secret, status) in [
+            (Method::GET, "/", None, StatusCode::OK),
+            (
+                Method::GET,
+                "/api/v1/accounts",
+                None,
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                Method::GET,
+                "/api/v1/accounts",
+                Some("guessed"),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                Method::GET,
+                "/api/v1/accounts",
+                Some(&reader_secret),
+                StatusCode::OK,
+            ),
+            (
+                Method::POST,
+                "/api/v1/accounts",
+                Some(&reader_secret),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::POST,
+                "/api/v1/accounts",
+                Some(&writer_secret),
+                StatusCode::OK,
+            ),
+            (
+                Method::GET,
+                "/api/v1/accounts",
+                Some(&expired_secret),
+                StatusCode::UNAUTHORIZED,
+            ),
+        ] {
+            let mut req = TestRequest::default().method(method.clone()).uri(uri);
+            if let Some(secret) = secret {
+                req = req.insert_header((header::AUTHORIZATION, format!("Bearer {}", secret)));
+            }
+            let response = call_service(&app, req.to_request()).await;
+            assert_eq!(
+                response.status(),
+                status,
+                "{} {} with {:?}",
+                method,
+                uri,
+                secret
+            );
+            if status == StatusCode::UNAUTHORIZED {
+                assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+            }
+        }
+        let denied = audit::read(&audit_path);
+        assert_eq!(denied.len(), 1);
+        assert_eq!(
+            (denied[0].who.as_str(), denied[0].action.as_str()),
+            ("reader", "POST /api/v1/accounts")
+        );
+    }
+
+    #[actix_web::test]
+    async fn reads_can_be_public() {
+        let cfg = AuthCfg {
+            enabled: true,
+            public_reads: true,
+            ..AuthCfg::default()
+        };
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Auth::new(&cfg, no_users())))
+                .wrap(from_fn(authenticate))
+                .route("/api/v1/accounts", web::get().to(ok))
+                .route("/api/v1/accounts", web::post().to(ok)),
+        )
+        .await;
+        let req = TestRequest::get().uri("/api/v1/accounts").to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+        let req = TestRequest::post().uri("/api/v1/accounts").to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_web::test]
+    async fn only_members_may_use_their_tenant() {
+        let (alice, alice_secret) = Token::generate("alice", vec![Scope::Read], None);
+        let (bob, bob_secret) = Token::generate("bob", vec![Scope::Admin], None);
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let audit_path = dir.path().join("audit.jsonl");
+        let auth_cfg = AuthCfg {
+            enabled: true,
+            public_reads: true,
+            tokens: vec![alice, bob],
+            audit_path: Some(audit_path.display().to_string()),
+            ..AuthCfg::default()
+        };
+        let mut cfg = Cfg::default();
+        let hr = crate::cfg::TenantCfg {
+            members: Some(vec!["alice".to_string()]),
+            ..Default::default()
+        };
+        cfg.tenants.insert("hr".to_string(), hr);
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Auth::new(&auth_cfg, no_users())))
+                .app_data(web::Data::new(cfg))
+                .wrap(from_fn(authenticate))
+                .route("/api/v1/accounts", web::get().to(ok))
+                .route("/t/hr/api/v1/accounts", web::get().to(ok)),
+        )
+        .await;
+
+        for (uri, secret, status) in [
+            ("/api/v1/accounts", None, StatusCode::OK),
+            ("/t/hr/api/v1/accounts", None, StatusCode::UNAUTHORIZED),
+            ("/t/hr/api/v1/accounts", Some(&alice_secret), StatusCode::OK),
+            (
+                "/t/hr/api/v1/accounts",
+                Some(&bob_secret),
+                StatusCode::FORBIDDEN,
+            ),
+        ] {
+            let mut req = TestRequest::get().uri(uri);
+            if let Some(secret) = secret {
+                req = req.insert_header((header::AUTHORIZATION, format!("Bearer {}", secret)));
+            }
+            let response = call_service(&app, req.to_request()).await;
+            assert_eq!(response.status(), status, "{} with {:?}", uri, secret);
+        }
+        let denied = audit::read(&audit_path);
+        assert_eq!(denied.len(), 1);
+        assert_eq!(
+            (denied[0].who.as_str(), denied[0].tenant.as_deref()),
+            ("bob", Some("hr"))
+        );
+    }
+
+    #[actix_web::test]
+    async fn pages_need_a_login() {
+        let cfg = AuthCfg {
+            enabled: true,
+            ..AuthCfg::default()
+        };
+        let user =
+            User::new("admin", "correct horse", Role::Viewer).expect("Failed to create user");
+        let mut users = no_users();
+        users.create(&user).expect("Failed to store user");
+        let auth = web::Data::new(Auth::new(&cfg, users));
+        assert!(auth
+            .verify_user("admin", "wrong password")
+            .unwrap()
+            .is_none());
+        assert!(auth
+            .verify_user("nobody", "correct horse")
+            .unwrap()
+            .is_none());
+        let verified = auth.verify_user("admin", "correct horse").unwrap();
+        assert_eq!(verified, Some(user.clone()));
+        let cookie = auth.sessions.start(user.id, &user.username);
+        let app = init_service(
+            App::new()
+                .app_data(auth.clone())
+                .wrap(from_fn(authenticate))
+                .route("/account", web::get().to(ok)),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/account?page=2")
+            .insert_header((header::ACCEPT, "text/html,*/*;q=0.8"))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "/login?next=%2Faccount%3Fpage%3D2"
+        );
+        let req = TestRequest::get().uri("/account").to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let req = TestRequest::get()
+            .uri("/account")
+            .cookie(cookie.clone())
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+
+        auth.users.lock().unwrap().delete(&user).unwrap();
+        let req = TestRequest::get()
+            .uri("/account")
+            .cookie(cookie)
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Who may do which operation on the accounts.
+//!
+//! The [`Policy`] names the least [`Role`] each [`Operation`] needs. Stores
+//! are used through a [`Guarded`] store on behalf of a [`Caller`], which
+//! refuses the operations the caller's role does not allow with
+//! [`CrudError::Forbidden`] and records them in the audit log.
+
+use std::{
+    future::{ready, Ready},
+    sync::Arc,
+};
+
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{
+    audit::{AuditEntry, AuditLog},
+    Auth, Role,
+};
+use crate::{
+    cfg::Cfg,
+    crud::{self, Crud, CrudError, Identifiable, Operation},
+};
+
+/// The least role each operation needs.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(default)]
+pub struct Policy {
+    pub create: Role,
+    pub read: Role,
+    pub update: Role,
+    pub delete: Role,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            create: Role::Editor,
+            read: Role::Viewer,
+            update: Role::Editor,
+            delete: Role::Editor,
+        }
+    }
+}
+
+impl Policy {
+    pub fn required(&self, operation: Operation) -> Role {
+        match operation {
+            Operation::Create => self.create,
+            Operation::Read => self.read,
+            Operation::Update => self.update,
+            Operation::Delete => self.delete,
+        }
+    }
+
+    pub fn allows(&self, role: Role, operation: Operation) -> bool {
+        role >= self.required(operation)
+    }
+
+    /// The least role that may change anything.
+    pub fn least_writer(&self) -> Role {
+        self.create.min(self.update).min(self.delete)
+    }
+}
+
+/// Who a store is used on behalf of.
+pub struct Caller {
+    pub name: String,
+    pub role: Role,
+    policy: Policy,
+    audit: Option<Arc<AuditLog>>,
+    tenant: Option<String>,
+}
+
+impl Caller {
+    pub fn new(name: &str, role: Role, policy: Policy, audit: Option<Arc<AuditLog>>) -> Self {
+        Caller {
+            name: name.to_string(),
+            role,
+            policy,
+            audit,
+            tenant: None,
+        }
+    }
+
+    /// Records denials as happening in `tenant`.
+    pub fn in_tenant(mut self, tenant: Option<&str>) -> Self {
+        self.tenant = tenant.map(str::to_string);
+        self
+    }
+
+    /// The sender of `req`. Without authentication anyone may do anything;
+    /// with it, requests without an identity only got past
+    /// [`super::authenticate`] as public reads, and may only view.
+    pub fn of(req: &HttpRequest) -> Self {
+        match req.app_data::<web::Data<Auth>>() {
+            Some(auth) if auth.enabled => {
+                let (name, role) = match super::identity(req) {
+                    Some(identity) => (identity.name, identity.role),
+                    None => ("anonymous".to_string(), Role::Viewer),
+                };
+                let tenant = req
+                    .app_data::<web::Data<Cfg>>()
+                    .and_then(|cfg| cfg.tenant.clone());
+                Caller::new(&name, role, auth.policy, Some(auth.audit.clone()))
+                    .in_tenant(tenant.as_deref())
+            }
+            _ => Caller::new("anonymous", Role::Admin, Policy::default(), None),
+        }
+    }
+
+    /// Fails with [`CrudError::Forbidden`], and records the denial, if the
+    /// caller may not do `operation`.
+    pub fn check(&self, operation: Operation, target: Option<Uuid>) -> crud::Result<()> {
+        if self.policy.allows(self.role, operation) {
+            return Ok(());
+        }
+        if let Some(audit) = &self.audit {
+            audit.record(
+                &AuditEntry::new(&self.name, self.role, operation.to_string(), target)
+                    .in_tenant(self.tenant.as_deref()),
+            );
+        }
+        Err(CrudError::Forbidden(operation))
+    }
+
+    /// Uses `store` on behalf of the caller.
+    pub fn guard<'a, T>(&'a self, store: &'a mut dyn Crud<T>) -> Guarded<'a, T> {
+        Guarded {
+            inner: store,
+            caller: self,
+        }
+    }
+}
+
+impl FromRequest for Caller {
+    type Error = std::convert::Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Caller::of(req)))
+    }
+}
+
+/// A store that lets a [`Caller`] do only what the policy allows its role.
+pub struct Guarded<'a, T> {
+    inner: &'a mut dyn Crud<T>,
+    caller: &'a Caller,
+}
+
+impl<T: Identifiable> Crud<T> for Guarded<'_, T> {
+    fn create(&mut self, item: &T) -> crud::Result<()> {
+        self.caller.check(Operation::Create, Some(item.id()))?;
+        self.inner.create(item)
+    }
+
+    fn create_all(&mut self, items: &[T]) -> crud::Result<Vec<crud::Result<()>>> {
+        for item in items {
+            self.caller.check(Operation::Create, Some(item.id()))?;
+        }
+        self.inner.create_all(items)
+    }
+
+    fn read_all(&self) -> crud::Result<Vec<T>> {
+        self.caller.check(Operation::Read, None)?;
+        self.inner.read_all()
+    }
+
+    fn read(&self, id: &Uuid) -> crud::Result<T>
+    where
+        T: Identifiable,
+    {
+        self.caller.check(Operation::Read, Some(*id))?;
+        self.inner.read(id)
+    }
+
+    fn iter(&self) -> crud::Result<Box<dyn Iterator<Item = crud::Result<T>> + Send>>
+    where
+        T: Send + 'static,
+    {
+        self.caller.check(Operation::Read, None)?;
+        self.inner.iter()
+    }
+
+    fn update(&mut self, item: &T) -> crud::Result<()> {
+        self.caller.check(Operation::Update, Some(item.id()))?;
+        self.inner.update(item)
+    }
+
+    fn delete(&mut self, item: &T) -> crud::Result<()> {
+        self.caller.check(Operation::Delete, Some(item.id()))?;
+        self.inner.delete(item)
+    }
+
+    fn in_memory(&self) -> bool {
+        self.inner.in_memory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::Account;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{auth::audit, crud::memory::MemoryStore};
+
+    #[test]
+    fn viewers_may_read_but_not_delete() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("audit.jsonl");
+        let audit = Arc::new(AuditLog::new(&path));
+        let mut store = MemoryStore::<Account>::new();
+        let account = Account::new("Test Account");
+        store.create(&account).expect("Failed to create Account");
+
+        let contractor = Caller::new(
+            "contractor",
+            Role::Viewer,
+            Policy::default(),
+            Some(audit.clone()),
+        );
+        let mut guarded = contractor.guard(&mut store);
+        assert_eq!(guarded.read(&account.id).unwrap(), account);
+        assert!(matches!(
+            guarded.delete(&account),
+            Err(CrudError::Forbidden(Operation::Delete))
+        ));
+        assert!(matches!(
+            guarded.create(&Account::new("Other")),
+            Err(CrudError::Forbidden(Operation::Create))
+        ));
+
+        let policy = Policy {
+            delete: Role::Admin,
+            ..Policy::default()
+        };
+        let editor = Caller::new("editor", Role::Editor, policy, Some(audit));
+        let mut guarded = editor.guard(&mut store);
+        guarded.update(&account).expect("Failed to update Account");
+        assert!(guarded.delete(&account).is_err());
+        assert_eq!(store.read_all().unwrap(), vec![account.clone()]);
+
+        let entries = audit::read(&path);
+        let denied: Vec<_> = entries
+            .iter()
+            .map(|e| (e.who.as_str(), e.role, e.action.as_str()))
+            .collect();
+        assert_eq!(
+            denied,
+            vec![
+                ("contractor", Role::Viewer, "delete"),
+                ("contractor", Role::Viewer, "create"),
+                ("editor", Role::Editor, "delete"),
+            ]
+        );
+        assert_eq!(entries[0].target, Some(account.id));
+    }
+}
@@ -0,0 +1,114 @@
+//! Login sessions of the operators using the HTML pages.
+//!
+//! A session is started when an operator logs in and is identified by a
+//! random id sent in the `session` cookie. The server keeps only the hash of
+//! the id, in memory, so sessions end when the server restarts.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use actix_web::cookie::{time, Cookie, SameSite};
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use super::token::hash;
+
+/// The name of the session cookie.
+pub const COOKIE: &str = "session";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    pub user_id: Uuid,
+    pub username: String,
+    pub expires: DateTime<Utc>,
+}
+
+/// The sessions of a running server.
+pub struct Sessions {
+    ttl: Duration,
+    secure: bool,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Sessions {
+    /// Sessions last `ttl_secs` from the login. Their cookie is only sent
+    /// over HTTPS when `secure` is set.
+    pub fn new(ttl_secs: u64, secure: bool) -> Self {
+        Sessions {
+            ttl: Duration::seconds(ttl_secs as i64),
+            secure,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Starts a session of `username` and returns the cookie that carries
+    /// its id.
+    pub fn start(&self, user_id: Uuid, username: &str) -> Cookie<'static> {
+        let id = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let now = Utc::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(
+            hash(&id),
+            Session {
+                user_id,
+                username: username.to_string(),
+                expires: now + self.ttl,
+            },
+        );
+        Cookie::build(COOKIE, id)
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax)
+            .max_age(time::Duration::seconds(self.ttl.num_seconds()))
+            .finish()
+    }
+
+    /// The session with the id `id`, unless it has expired.
+    pub fn find(&self, id: &str) -> Option<Session> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .get(&hash(id))
+            .filter(|session| session.expires > Utc::now())
+            .cloned()
+    }
+
+    /// Ends the session with the id `id` and returns the cookie that removes
+    /// it from the client.
+    pub fn end(&self, id: &str) -> Cookie<'static> {
+        self.sessions.lock().unwrap().remove(&hash(id));
+        Cookie::build(COOKIE, "")
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax)
+            .max_age(time::Duration::ZERO)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_are_found_until_they_end() {
+        let sessions = Sessions::new(60, true);
+        let user_id = Uuid::new_v4();
+        let cookie = sessions.start(user_id, "admin");
+        assert!(cookie.http_only().unwrap_or_default());
+        assert!(cookie.secure().unwrap_or_default());
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+        let session = sessions.find(cookie.value()).expect("Session not found");
+        assert_eq!(session.username, "admin");
+        assert!(sessions.find("guessed").is_none());
+
+        let removal = sessions.end(cookie.value());
+        assert_eq!(removal.max_age(), Some(time::Duration::ZERO));
+        assert!(sessions.find(cookie.value()).is_none());
+
+        let expiring = Sessions::new(0, false);
+        let cookie = expiring.start(user_id, "admin");
+        assert!(expiring.find(cookie.value()).is_none());
+    }
+}
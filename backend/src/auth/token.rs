@@ -0,0 +1,176 @@
+//! API tokens, kept as SHA-256 hashes of their secrets.
+//!
+//! Tokens come from the `auth.tokens` list of the config file or from the
+//! token file managed by the `token` commands. Only the hash of a secret is
+//! ever stored; the secret itself is shown once, when the token is created.
+//! Secrets are long random strings, so a fast hash is enough to keep them
+//! from being recovered.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::Scope;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Token {
+    pub name: String,
+    /// The hex SHA-256 hash of the secret.
+    pub hash: String,
+    pub scopes: Vec<Scope>,
+    #[serde(default)]
+    pub created: Option<DateTime<Utc>>,
+    /// The token is refused from then on; it never expires when unset.
+    #[serde(default)]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl Token {
+    /// Makes a token with a new random secret, returned alongside it.
+    pub fn generate(
+        name: &str,
+        scopes: Vec<Scope>,
+        expires: Option<DateTime<Utc>>,
+    ) -> (Token, String) {
+        let secret = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let token = Token {
+            name: name.to_string(),
+            hash: hash(&secret),
+            scopes,
+            created: Some(Utc::now()),
+            expires,
+        };
+        (token, secret)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// The hex SHA-256 hash of `secret`.
+pub fn hash(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Reads the tokens of the token file, which is a JSON array. A missing
+/// file holds no tokens.
+pub fn load(path: &Path) -> std::io::Result<Vec<Token>> {
+    match File::open(path) {
+        Ok(file) => serde_json::from_reader(BufReader::new(file)).map_err(std::io::Error::other),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replaces the tokens of the token file. The file is written next to the
+/// old one and then renamed over it, so it is never seen half written.
+pub fn save(path: &Path, tokens: &[Token]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let file = File::create(&tmp)?;
+    let mut out = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut out, tokens).map_err(std::io::Error::other)?;
+    out.write_all(b"\n")?;
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(tmp, path)
+}
+
+/// The modification time and size of the token file when it was read.
+type FileVersion = (SystemTime, u64);
+
+/// The tokens the server accepts: those of the config file and those of the
+/// token file, which is read again whenever it changes so that tokens
+/// created or revoked while the server runs take effect right away.
+pub struct Tokens {
+    configured: Vec<Token>,
+    path: PathBuf,
+    stored: Mutex<(Option<FileVersion>, Vec<Token>)>,
+}
+
+impl Tokens {
+    pub fn new(configured: Vec<Token>, path: PathBuf) -> Self {
+        Tokens {
+            configured,
+            path,
+            stored: Mutex::new((None, Vec::new())),
+        }
+    }
+
+    /// Finds the token whose secret is `secret`.
+    pub fn find(&self, secret: &str) -> Option<Token> {
+        let hash = hash(secret);
+        if let Some(token) = self.configured.iter().find(|t| t.hash == hash) {
+            return Some(token.clone());
+        }
+        let mut stored = self.stored.lock().unwrap();
+        // Modification times can be coarse, so a quick revoke after a create
+        // is told apart by the size of the file.
+        let version = std::fs::metadata(&self.path)
+            .and_then(|m| Ok((m.modified()?, m.len())))
+            .ok();
+        if version != stored.0 {
+            match load(&self.path) {
+                Ok(tokens) => *stored = (version, tokens),
+                Err(e) => log::error!("Failed to read tokens from {:?}: {}", self.path, e),
+            }
+        }
+        stored.1.iter().find(|t| t.hash == hash).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn only_the_hash_is_stored() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("tokens.json");
+        let (token, secret) = Token::generate("ci", vec![Scope::Write], None);
+        save(&path, std::slice::from_ref(&token)).expect("Failed to save tokens");
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains(&secret));
+        assert_eq!(load(&path).unwrap(), vec![token]);
+        assert!(load(&dir.path().join("missing.json")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tokens_are_found_by_secret() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("tokens.json");
+        let (configured, configured_secret) = Token::generate("config", vec![Scope::Read], None);
+        let tokens = Tokens::new(vec![configured], path.clone());
+        assert_eq!(tokens.find(&configured_secret).unwrap().name, "config");
+
+        let (stored, stored_secret) = Token::generate("stored", vec![Scope::Admin], None);
+        assert!(tokens.find(&stored_secret).is_none());
+        save(&path, &[stored]).expect("Failed to save tokens");
+        assert_eq!(tokens.find(&stored_secret).unwrap().name, "stored");
+        assert!(tokens.find("guessed").is_none());
+    }
+
+    #[test]
+    fn expiry() {
+        let now = Utc::now();
+        let (token, _) = Token::generate("ci", vec![Scope::Write], Some(now));
+        assert!(token.is_expired(now));
+        assert!(!token.is_expired(now - Duration::seconds(1)));
+    }
+}
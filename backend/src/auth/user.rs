@@ -0,0 +1,122 @@
+//! The operators who log in to the HTML pages.
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::Role;
+use crate::crud::Identifiable;
+
+/// The fewest characters a password may have.
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct User {
+    pub id: Uuid,
+    pub username: String,
+    pub role: Role,
+    /// The Argon2id hash of the password, as a PHC string.
+    pub password_hash: String,
+}
+
+impl Identifiable for User {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
+impl User {
+    pub fn new(username: &str, password: &str, role: Role) -> Result<Self, String> {
+        check_username(username)?;
+        Ok(User {
+            id: Uuid::new_v4(),
+            username: username.to_string(),
+            role,
+            password_hash: hash_password(password)?,
+        })
+    }
+
+    pub fn set_password(&mut self, password: &str) -> Result<(), String> {
+        self.password_hash = hash_password(password)?;
+        Ok(())
+    }
+
+    pub fn verify_password(&self, password: &str) -> bool {
+        verify_password(&self.password_hash, password)
+    }
+}
+
+/// Usernames are kept to letters, digits and `.`, `_`, `-` and `@`, so
+/// they need no quoting in the user store.
+pub fn check_username(username: &str) -> Result<(), String> {
+    let allowed = |c: char| c.is_alphanumeric() || matches!(c, '.' | '_' | '-' | '@');
+    if username.is_empty() || username.chars().count() > 64 {
+        Err("The username must have 1 to 64 characters".to_string())
+    } else if !username.chars().all(allowed) {
+        Err("The username may only hold letters, digits and . _ - @".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Hashes `password` with Argon2id and a random salt.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(format!(
+            "The password must be at least {} characters",
+            MIN_PASSWORD_LEN
+        ));
+    }
+    // Salts need to be unique rather than secret.
+    let salt = SaltString::encode_b64(Uuid::new_v4().as_bytes()).map_err(|e| e.to_string())?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+fn verify_password(password_hash: &str, password: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(e) => {
+            log::error!("Invalid password hash: {}", e);
+            false
+        }
+    }
+}
+
+/// Checks a password against a throwaway hash, so that logging in as an
+/// unknown user takes as long as with a wrong password.
+pub fn verify_nobody(password: &str) {
+    static NOBODY: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    let hash = NOBODY.get_or_init(|| hash_password("nobody's password").expect("Failed to hash"));
+    verify_password(hash, password);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_are_hashed_with_a_salt() {
+        let mut user =
+            User::new("admin", "correct horse", Role::Admin).expect("Failed to create user");
+        assert!(user.password_hash.starts_with("$argon2id$"));
+        assert!(user.verify_password("correct horse"));
+        assert!(!user.verify_password("battery staple"));
+        let other =
+            User::new("other", "correct horse", Role::Viewer).expect("Failed to create user");
+        assert_ne!(user.password_hash, other.password_hash);
+
+        assert!(User::new("a,b", "correct horse", Role::Viewer).is_err());
+        assert!(user.set_password("short").is_err());
+        user.set_password("battery staple")
+            .expect("Failed to change password");
+        assert!(user.verify_password("battery staple"));
+    }
+}
@@ -1,7 +1,15 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::PathBuf};
 
-use crate::APP_NAME;
+use crate::{
+    auth::{policy::Policy, token::Token, Role},
+    crud::{
+        self, membership::MembershipRules, mirror::Consistency, observer::ChangeKind,
+        unique::UniqueRule, Entity, Strategy,
+    },
+    APP_NAME,
+};
 use clap::builder::PossibleValue;
+use common::{validate::Rules, IdVersion};
 use config::Config;
 use directories::UserDirs;
 use serde::{Deserialize, Serialize};
@@ -9,34 +17,228 @@ use std::io::Write;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum CfgOutputFormat {
-    YAML,
+    Yaml,
 }
 
 impl Default for &CfgOutputFormat {
     fn default() -> Self {
-        &CfgOutputFormat::YAML
+        &CfgOutputFormat::Yaml
     }
 }
 
 impl clap::ValueEnum for CfgOutputFormat {
     fn value_variants<'a>() -> &'a [Self] {
-        &[CfgOutputFormat::YAML]
+        &[CfgOutputFormat::Yaml]
     }
 
     fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
         Some(match self {
-            CfgOutputFormat::YAML => PossibleValue::new("yaml").help("YAML"),
+            CfgOutputFormat::Yaml => PossibleValue::new("yaml").help("YAML"),
         })
     }
 }
 
+/// An endpoint that is sent a signed POST after each matching account change.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct WebhookCfg {
+    pub url: String,
+    /// The change kinds to deliver; all kinds when empty.
+    #[serde(default)]
+    pub events: Vec<ChangeKind>,
+    /// Key used to sign each payload with HMAC-SHA256.
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+/// Settings of the read-through cache in front of the account store.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct CacheCfg {
+    /// Set to `false` to read straight from the store. Stores that already
+    /// keep their items in memory are never cached.
+    pub enabled: bool,
+    pub ttl_secs: u64,
+    pub max_items: usize,
+}
+
+impl Default for CacheCfg {
+    fn default() -> Self {
+        CacheCfg {
+            enabled: true,
+            ttl_secs: 30,
+            max_items: 10_000,
+        }
+    }
+}
+
+/// A store opened with `storage` at `storage_path`, or at the strategy's
+/// default path.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StoreCfg {
+    pub storage: Strategy,
+    #[serde(default)]
+    pub storage_path: Option<String>,
+}
+
+impl StoreCfg {
+    /// The store of the `T`s, which are kept next to the accounts of this
+    /// one.
+    pub fn of<T: Entity>(&self) -> StoreCfg {
+        self.named(T::PLURAL)
+    }
+
+    /// The store of the memberships of the accounts of this one in groups.
+    pub fn memberships(&self) -> StoreCfg {
+        self.named("memberships")
+    }
+
+    fn named(&self, plural: &str) -> StoreCfg {
+        let path = self.storage_path.as_deref().or(self.storage.default_path());
+        StoreCfg {
+            storage: self.storage,
+            storage_path: path.map(|path| crud::path_named(self.storage, path, plural)),
+        }
+    }
+}
+
+impl std::fmt::Display for StoreCfg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.storage_path.as_deref().or(self.storage.default_path()) {
+            Some(path) => write!(f, "{}:{}", self.storage, path),
+            None => write!(f, "{}", self.storage),
+        }
+    }
+}
+
+impl std::str::FromStr for StoreCfg {
+    type Err = String;
+
+    /// Parses `<storage>` or `<storage>:<path>`, e.g. `sqlite:accounts.sqlite`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (storage, storage_path) = match s.split_once(':') {
+            Some((storage, path)) => (storage, Some(path.to_string())),
+            None => (s, None),
+        };
+        Ok(StoreCfg {
+            storage: storage.parse()?,
+            storage_path,
+        })
+    }
+}
+
+/// Secondary stores that every write to the account store is mirrored to.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct MirrorCfg {
+    pub consistency: Consistency,
+    pub secondaries: Vec<StoreCfg>,
+}
+
+/// A tenant served by the same process (see [`crate::tenant`]). Unset
+/// settings are taken from the top level, except for the store, its mirrors
+/// and the webhooks, which tenants never share.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct TenantCfg {
+    pub storage: Option<Strategy>,
+    /// By default the top-level store's path with `-<tenant>` added to the
+    /// file name, e.g. `accounts-sales.csv`.
+    pub storage_path: Option<String>,
+    pub mirror: MirrorCfg,
+    pub webhooks: Vec<WebhookCfg>,
+    pub webhook_queue_path: Option<String>,
+    pub validation: Option<Rules>,
+    pub id_version: Option<IdVersion>,
+    /// The operators and tokens that may use the tenant; anyone who may use
+    /// the server when unset.
+    pub members: Option<Vec<String>>,
+}
+
+/// Authentication of the web server (see [`crate::auth`]).
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct AuthCfg {
+    /// Require a token or a login. Off by default, so anyone who can reach
+    /// the server may use it.
+    pub enabled: bool,
+    /// Let reads through without a token.
+    pub public_reads: bool,
+    /// The file the `token` commands keep their tokens in.
+    pub token_path: Option<String>,
+    /// Tokens given in the config file, with the hash of their secret.
+    pub tokens: Vec<Token>,
+    /// The store of the operators who log in to the HTML pages; by default
+    /// `users.csv` or `users.sqlite` with the strategy of the account store.
+    pub users: Option<StoreCfg>,
+    /// Only send the session cookie over HTTPS. Turn off only to log in over
+    /// plain HTTP, e.g. on localhost.
+    pub secure_cookies: bool,
+    /// How long a login lasts.
+    pub session_ttl_secs: u64,
+    /// The least role each account operation needs.
+    pub policy: Policy,
+    /// The file denied requests and operations are recorded in.
+    pub audit_path: Option<String>,
+    /// The role the `account`, `import` and `export` commands act in.
+    pub cli_role: Role,
+}
+
+impl Default for AuthCfg {
+    fn default() -> Self {
+        AuthCfg {
+            enabled: false,
+            public_reads: false,
+            token_path: None,
+            tokens: Vec::new(),
+            users: None,
+            secure_cookies: true,
+            session_ttl_secs: 8 * 60 * 60,
+            policy: Policy::default(),
+            audit_path: None,
+            cli_role: Role::Admin,
+        }
+    }
+}
+
+impl AuthCfg {
+    pub fn token_path(&self) -> &str {
+        self.token_path.as_deref().unwrap_or("tokens.json")
+    }
+
+    pub fn audit_path(&self) -> &str {
+        self.audit_path.as_deref().unwrap_or("audit.jsonl")
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Cfg {
     pub verbose: String,
     pub address: String,
     pub port: u16,
     pub template_glob: String,
+    /// Directory of the static files, such as the API documentation UI.
+    pub static_dir: String,
+    pub storage: Strategy,
     pub storage_path: Option<String>,
+    pub mirror: MirrorCfg,
+    pub cache: CacheCfg,
+    pub webhooks: Vec<WebhookCfg>,
+    pub webhook_queue_path: Option<String>,
+    /// How account fields are cleaned up and checked.
+    pub validation: Rules,
+    /// The UUID version of the ids assigned to new accounts.
+    pub id_version: IdVersion,
+    /// What deleting an account or a group does to its memberships.
+    pub memberships: MembershipRules,
+    /// The fields no two items may share a value of, by the plural of the
+    /// items, e.g. `accounts`. The fields of [`Entity::UNIQUE`] when left
+    /// out.
+    pub unique: BTreeMap<String, Vec<UniqueRule>>,
+    pub auth: AuthCfg,
+    pub tenants: BTreeMap<String, TenantCfg>,
+    /// The tenant the settings are for, if they are not the top level.
+    #[serde(skip)]
+    pub tenant: Option<String>,
 }
 
 impl Default for Cfg {
@@ -46,14 +248,110 @@ impl Default for Cfg {
             address: "0.0.0.0".to_string(),
             port: 8080,
             template_glob: default_template_glob(),
-            #[cfg(feature = "csv")]
-            storage_path: None,
-            #[cfg(feature = "sqlite")]
+            static_dir: default_static_dir(),
+            storage: Strategy::default(),
             storage_path: None,
+            mirror: MirrorCfg::default(),
+            cache: CacheCfg::default(),
+            webhooks: Vec::new(),
+            webhook_queue_path: None,
+            validation: Rules::default(),
+            id_version: IdVersion::default(),
+            memberships: MembershipRules::default(),
+            unique: BTreeMap::new(),
+            auth: AuthCfg::default(),
+            tenants: BTreeMap::new(),
+            tenant: None,
         }
     }
 }
 
+impl Cfg {
+    /// The primary account store.
+    pub fn store(&self) -> StoreCfg {
+        StoreCfg {
+            storage: self.storage,
+            storage_path: self.storage_path.clone(),
+        }
+    }
+
+    /// The store of the `T`s, which are kept next to the accounts.
+    pub fn store_of<T: Entity>(&self) -> StoreCfg {
+        self.store().of::<T>()
+    }
+
+    /// The fields of the `T`s that no two of them may share a value of.
+    pub fn unique_rules<T: Entity>(&self) -> Vec<UniqueRule> {
+        self.unique
+            .get(T::PLURAL)
+            .cloned()
+            .unwrap_or_else(UniqueRule::defaults_of::<T>)
+    }
+
+    /// The store of the memberships of the accounts in groups, which is kept
+    /// next to the accounts.
+    pub fn membership_store(&self) -> StoreCfg {
+        self.store().memberships()
+    }
+
+    /// The store of the operators who log in to the web server.
+    pub fn user_store(&self) -> StoreCfg {
+        self.auth.users.clone().unwrap_or(StoreCfg {
+            storage: self.storage,
+            storage_path: None,
+        })
+    }
+
+    /// The settings of the tenant `name`, or `None` if there is no such
+    /// tenant.
+    pub fn tenant(&self, name: &str) -> Option<Cfg> {
+        let tenant = self.tenants.get(name)?;
+        let storage = tenant.storage.unwrap_or(self.storage);
+        let storage_path = tenant.storage_path.clone().or_else(|| {
+            let path = match storage == self.storage {
+                true => self.storage_path.as_deref().or(storage.default_path()),
+                false => storage.default_path(),
+            };
+            path.map(|path| crud::suffixed_path(path, name))
+        });
+        let webhook_queue_path = tenant
+            .webhook_queue_path
+            .clone()
+            .unwrap_or_else(|| crud::suffixed_path(self.webhook_queue_path(), name));
+        Some(Cfg {
+            storage,
+            storage_path,
+            mirror: tenant.mirror.clone(),
+            webhooks: tenant.webhooks.clone(),
+            webhook_queue_path: Some(webhook_queue_path),
+            validation: tenant.validation.clone().unwrap_or(self.validation.clone()),
+            id_version: tenant.id_version.unwrap_or(self.id_version),
+            tenants: BTreeMap::new(),
+            tenant: Some(name.to_string()),
+            ..self.clone()
+        })
+    }
+
+    pub fn webhook_queue_path(&self) -> &str {
+        self.webhook_queue_path
+            .as_deref()
+            .unwrap_or("webhooks.jsonl")
+    }
+
+    /// Describes the storage in use, e.g. `sqlite` or `sqlite + csv`.
+    pub fn backend(&self) -> String {
+        std::iter::once(self.storage.to_string())
+            .chain(
+                self.mirror
+                    .secondaries
+                    .iter()
+                    .map(|s| s.storage.to_string()),
+            )
+            .collect::<Vec<_>>()
+            .join(" + ")
+    }
+}
+
 impl std::fmt::Display for Cfg {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self)
@@ -75,6 +373,45 @@ impl From<Config> for Cfg {
         if let Ok(o) = value.get_string("template_glob") {
             cfg.template_glob = o;
         }
+        if let Ok(o) = value.get_string("static_dir") {
+            cfg.static_dir = o;
+        }
+        if let Ok(o) = value.get::<Strategy>("storage") {
+            cfg.storage = o;
+        }
+        if let Ok(o) = value.get_string("storage_path") {
+            cfg.storage_path = Some(o);
+        }
+        if let Ok(o) = value.get::<MirrorCfg>("mirror") {
+            cfg.mirror = o;
+        }
+        if let Ok(o) = value.get::<CacheCfg>("cache") {
+            cfg.cache = o;
+        }
+        if let Ok(o) = value.get::<Vec<WebhookCfg>>("webhooks") {
+            cfg.webhooks = o;
+        }
+        if let Ok(o) = value.get_string("webhook_queue_path") {
+            cfg.webhook_queue_path = Some(o);
+        }
+        if let Ok(o) = value.get::<Rules>("validation") {
+            cfg.validation = o;
+        }
+        if let Ok(o) = value.get::<IdVersion>("id_version") {
+            cfg.id_version = o;
+        }
+        if let Ok(o) = value.get::<MembershipRules>("memberships") {
+            cfg.memberships = o;
+        }
+        if let Ok(o) = value.get::<BTreeMap<String, Vec<UniqueRule>>>("unique") {
+            cfg.unique = o;
+        }
+        if let Ok(o) = value.get::<AuthCfg>("auth") {
+            cfg.auth = o;
+        }
+        if let Ok(o) = value.get::<BTreeMap<String, TenantCfg>>("tenants") {
+            cfg.tenants = o;
+        }
         // FUTURE add more parsing for new fields added to Cfg struct
         cfg
     }
@@ -83,7 +420,7 @@ impl From<Config> for Cfg {
 #[allow(dead_code)]
 pub fn write_cfg(out: &mut dyn Write, settings: &Cfg, fmt: &CfgOutputFormat) {
     match fmt {
-        CfgOutputFormat::YAML => writeln!(
+        CfgOutputFormat::Yaml => writeln!(
             out,
             "{}",
             serde_yaml::to_string(&settings).expect("Failed to serialize settings to YAML")
@@ -148,11 +485,17 @@ pub fn default_template_glob() -> String {
     concat!(env!("CARGO_MANIFEST_DIR"), "/templates/**/*").to_string()
 }
 
+pub fn default_static_dir() -> String {
+    concat!(env!("CARGO_MANIFEST_DIR"), "/static").to_string()
+}
+
 #[cfg(test)]
 mod tests {
+    use common::{Account, Group};
     use unindent::unindent;
 
     use super::*;
+    use crate::crud::membership::OnDelete;
 
     #[test]
     fn writing_default_cfg_as_yaml() {
@@ -162,14 +505,241 @@ mod tests {
         address: 0.0.0.0
         port: 8080
         template_glob: {}
+        static_dir: {}
+        storage: {}
         storage_path: null
+        mirror:
+          consistency: strict
+          secondaries: []
+        cache:
+          enabled: true
+          ttl_secs: 30
+          max_items: 10000
+        webhooks: []
+        webhook_queue_path: null
+        validation:
+          fullname:
+            trim: true
+            normalization: nfc
+            min_len: 1
+            max_len: 200
+            charset: printable
+          tag:
+            trim: true
+            normalization: nfc
+            min_len: 1
+            max_len: 50
+            charset: printable
+          attribute:
+            trim: true
+            normalization: nfc
+            min_len: 1
+            max_len: 50
+            charset: printable
+          groups:
+            name:
+              trim: true
+              normalization: nfc
+              min_len: 1
+              max_len: 200
+              charset: printable
+            description:
+              trim: true
+              normalization: nfc
+              min_len: 0
+              max_len: 1000
+              charset: printable
+        id_version: v4
+        memberships:
+          on_account_delete: cascade
+          on_group_delete: restrict
+        unique: {{}}
+        auth:
+          enabled: false
+          public_reads: false
+          token_path: null
+          tokens: []
+          users: null
+          secure_cookies: true
+          session_ttl_secs: 28800
+          policy:
+            create: editor
+            read: viewer
+            update: editor
+            delete: editor
+          audit_path: null
+          cli_role: admin
+        tenants: {{}}
 
         "#,
-            default_template_glob()
+            default_template_glob(),
+            default_static_dir(),
+            Strategy::default()
         );
         let mut actual = Vec::new();
         let settings = Cfg::default();
-        write_cfg(&mut actual, &settings, &CfgOutputFormat::YAML);
+        write_cfg(&mut actual, &settings, &CfgOutputFormat::Yaml);
         assert_eq!(unindent(&expected), String::from_utf8_lossy(&actual));
     }
+
+    #[test]
+    fn parsing_webhooks_from_yaml() {
+        let yaml = unindent(
+            r#"
+            webhooks:
+              - url: http://127.0.0.1:9000/hook
+                events: [created, deleted]
+                secret: s3cr3t
+              - url: http://127.0.0.1:9001/hook
+            "#,
+        );
+        let cfg = Cfg::from(
+            Config::builder()
+                .add_source(config::File::from_str(&yaml, config::FileFormat::Yaml))
+                .build()
+                .expect("Failed to parse YAML"),
+        );
+        assert_eq!(
+            cfg.webhooks,
+            vec![
+                WebhookCfg {
+                    url: "http://127.0.0.1:9000/hook".to_string(),
+                    events: vec![ChangeKind::Created, ChangeKind::Deleted],
+                    secret: Some("s3cr3t".to_string()),
+                },
+                WebhookCfg {
+                    url: "http://127.0.0.1:9001/hook".to_string(),
+                    events: Vec::new(),
+                    secret: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parsing_mirror_from_yaml() {
+        let yaml = unindent(
+            r#"
+            storage: sqlite
+            mirror:
+              consistency: queue
+              secondaries:
+                - storage: csv
+                  storage_path: shadow.csv
+            "#,
+        );
+        let cfg = Cfg::from(
+            Config::builder()
+                .add_source(config::File::from_str(&yaml, config::FileFormat::Yaml))
+                .build()
+                .expect("Failed to parse YAML"),
+        );
+        assert_eq!(cfg.storage, Strategy::Sqlite);
+        assert_eq!(cfg.mirror.consistency, Consistency::Queue);
+        assert_eq!(
+            cfg.mirror.secondaries,
+            vec![StoreCfg {
+                storage: Strategy::Csv,
+                storage_path: Some("shadow.csv".to_string()),
+            }]
+        );
+        assert_eq!(cfg.backend(), "sqlite + csv");
+    }
+
+    #[test]
+    fn parsing_store_from_str() {
+        assert_eq!(
+            "sqlite:data/accounts.sqlite".parse::<StoreCfg>(),
+            Ok(StoreCfg {
+                storage: Strategy::Sqlite,
+                storage_path: Some("data/accounts.sqlite".to_string()),
+            })
+        );
+        let csv: StoreCfg = "csv".parse().expect("Failed to parse store");
+        assert_eq!(csv.to_string(), "csv:accounts.csv");
+        assert!("postgres:accounts".parse::<StoreCfg>().is_err());
+    }
+
+    #[test]
+    fn tenants_get_stores_of_their_own() {
+        let yaml = unindent(
+            r#"
+            storage: csv
+            storage_path: data/accounts.csv
+            id_version: v7
+            memberships:
+              on_group_delete: cascade
+            webhooks:
+              - url: http://127.0.0.1:9000/hook
+            tenants:
+              sales: {}
+              hr:
+                storage: sqlite
+                id_version: v4
+                members: [alice]
+            "#,
+        );
+        let cfg = Cfg::from(
+            Config::builder()
+                .add_source(config::File::from_str(&yaml, config::FileFormat::Yaml))
+                .build()
+                .expect("Failed to parse YAML"),
+        );
+        let sales = cfg.tenant("sales").expect("No sales tenant");
+        assert_eq!(sales.tenant.as_deref(), Some("sales"));
+        assert_eq!(sales.store().to_string(), "csv:data/accounts-sales.csv");
+        assert_eq!(
+            sales.store_of::<Group>().to_string(),
+            "csv:data/accounts-sales-groups.csv"
+        );
+        assert_eq!(
+            sales.membership_store().to_string(),
+            "csv:data/accounts-sales-memberships.csv"
+        );
+        assert_eq!(sales.memberships.on_group_delete, OnDelete::Cascade);
+        assert_eq!(sales.memberships.on_account_delete, OnDelete::Cascade);
+        assert_eq!(sales.webhook_queue_path(), "webhooks-sales.jsonl");
+        assert_eq!(sales.id_version, IdVersion::V7);
+        assert!(sales.webhooks.is_empty());
+        assert!(sales.tenants.is_empty());
+
+        let hr = cfg.tenant("hr").expect("No hr tenant");
+        assert_eq!(hr.store().to_string(), "sqlite:accounts-hr.sqlite");
+        assert_eq!(hr.store_of::<Group>().to_string(), hr.store().to_string());
+        assert_eq!(hr.id_version, IdVersion::V4);
+        assert_eq!(cfg.tenants["hr"].members, Some(vec!["alice".to_string()]));
+        assert!(cfg.tenant("ops").is_none());
+    }
+
+    #[test]
+    fn parsing_unique_rules_from_yaml() {
+        let yaml = unindent(
+            r#"
+            unique:
+              accounts:
+                - field: fullname
+                  ignore_case: true
+                - field: email
+            "#,
+        );
+        let cfg = Cfg::from(
+            Config::builder()
+                .add_source(config::File::from_str(&yaml, config::FileFormat::Yaml))
+                .build()
+                .expect("Failed to parse YAML"),
+        );
+        let fullname = UniqueRule {
+            field: "fullname".to_string(),
+            ignore_case: true,
+        };
+        assert_eq!(
+            cfg.unique_rules::<Account>(),
+            vec![fullname, UniqueRule::new("email")]
+        );
+        assert!(cfg.unique_rules::<Group>().is_empty());
+        assert_eq!(
+            Cfg::default().unique_rules::<Account>(),
+            vec![UniqueRule::new("email")]
+        );
+    }
 }
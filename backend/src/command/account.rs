@@ -0,0 +1,371 @@
+use std::io::Write;
+
+use clap::{builder::PossibleValue, ArgMatches};
+use common::{Account, AccountFilter, AccountStatus, NewAccount};
+use cor_args::{ArgHandler, DefaultHandler, EnvHandler, Handler};
+use log::debug;
+use uuid::Uuid;
+
+use super::{Command, FixmeError};
+use crate::{
+    cfg::{default_config_path, Cfg},
+    crud::Crud,
+    transfer::{AccountWriter, Format},
+    APP_PREFIX,
+};
+
+/// How the `account` subcommands print accounts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Csv,
+}
+
+impl clap::ValueEnum for OutputFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[OutputFormat::Table, OutputFormat::Json, OutputFormat::Csv]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            OutputFormat::Table => PossibleValue::new("table").help("Aligned columns"),
+            OutputFormat::Json => PossibleValue::new("json").help("JSON"),
+            OutputFormat::Csv => PossibleValue::new("csv").help("CSV with a header row"),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccountAction {
+    Add {
+        fullname: String,
+        email: Option<String>,
+        tags: Vec<String>,
+    },
+    List {
+        filter: AccountFilter,
+    },
+    Show {
+        id: Uuid,
+    },
+    Rename {
+        id: Uuid,
+        fullname: String,
+    },
+    Remove {
+        id: Uuid,
+    },
+}
+
+/// Manages accounts directly in the configured store, without going through
+/// the web server.
+pub struct AccountCommand {
+    cfg: Cfg,
+    action: AccountAction,
+    format: OutputFormat,
+}
+
+impl AccountCommand {
+    pub fn new(cfg: Cfg, action: AccountAction, format: OutputFormat) -> Self {
+        AccountCommand {
+            cfg,
+            action,
+            format,
+        }
+    }
+
+    /// Builds the command from the matches of the `account` subcommand.
+    pub fn from_matches(matches: &ArgMatches) -> Result<Self, Box<dyn FixmeError>> {
+        let config_path = ArgHandler::new(matches)
+            .next(Box::new(EnvHandler::new().prefix(APP_PREFIX).next(
+                Box::new(DefaultHandler::new(
+                    &default_config_path().display().to_string(),
+                )),
+            )))
+            .handle_request("config");
+        let config_path = config_path.expect("No config path");
+        let mut cfg = Cfg::default();
+        super::read_storage_cfg(matches, &config_path, &mut cfg);
+        debug!("{}", cfg);
+
+        let format = matches
+            .get_one::<OutputFormat>("format")
+            .copied()
+            .unwrap_or_default();
+        let id = |m: &ArgMatches| -> Result<Uuid, Box<dyn FixmeError>> {
+            Ok(Uuid::parse_str(m.get_one::<String>("id").expect("No id"))?)
+        };
+        let fullname = |m: &ArgMatches| m.get_one::<String>("fullname").expect("No name").clone();
+        let action = match matches.subcommand() {
+            Some(("add", m)) => AccountAction::Add {
+                fullname: fullname(m),
+                email: m.get_one::<String>("email").cloned(),
+                tags: m
+                    .get_many::<String>("tag")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+            },
+            Some(("list", m)) => AccountAction::List {
+                filter: AccountFilter {
+                    tag: m.get_one::<String>("tag").cloned(),
+                    status: m.get_one::<AccountStatus>("status").copied(),
+                },
+            },
+            Some(("show", m)) => AccountAction::Show { id: id(m)? },
+            Some(("rename", m)) => AccountAction::Rename {
+                id: id(m)?,
+                fullname: fullname(m),
+            },
+            Some(("rm", m)) => AccountAction::Remove { id: id(m)? },
+            subcommand => unreachable!("Invalid account subcommand {:?}", subcommand),
+        };
+        Ok(AccountCommand::new(cfg, action, format))
+    }
+
+    /// Runs the action against `store` and prints the accounts it returns,
+    /// added, changed or removed to `out`.
+    fn run(
+        &self,
+        store: &mut dyn Crud<Account>,
+        out: &mut dyn Write,
+    ) -> Result<(), Box<dyn FixmeError>> {
+        let accounts = match &self.action {
+            AccountAction::Add {
+                fullname,
+                email,
+                tags,
+            } => {
+                let account = self
+                    .cfg
+                    .validation
+                    .new_account(&NewAccount {
+                        fullname: fullname.clone(),
+                        email: email.clone(),
+                        tags: tags.clone(),
+                        ..NewAccount::default()
+                    })?
+                    .into_account(self.cfg.id_version.generate());
+                store.create(&account)?;
+                vec![account]
+            }
+            AccountAction::List { filter } => store
+                .iter()?
+                .filter(|account| account.as_ref().map_or(true, |a| filter.matches(a)))
+                .collect::<Result<_, _>>()?,
+            AccountAction::Show { id } => vec![store.read(id)?],
+            AccountAction::Rename { id, fullname } => {
+                let mut account = store.read(id)?;
+                account.fullname = fullname.clone();
+                let account = self.cfg.validation.account(&account)?;
+                store.update(&account)?;
+                vec![account]
+            }
+            AccountAction::Remove { id } => {
+                let account = store.read(id)?;
+                store.delete(&account)?;
+                vec![account]
+            }
+        };
+        let single = !matches!(self.action, AccountAction::List { .. });
+        write_accounts(out, &accounts, self.format, single)?;
+        Ok(())
+    }
+}
+
+impl Command for AccountCommand {
+    fn execute(&self) -> Result<(), Box<dyn FixmeError>> {
+        let memberships = super::open_membership_store(&self.cfg)?;
+        let mut store = super::open_account_store(&self.cfg, &memberships)?;
+        let caller = super::cli_caller(&self.cfg);
+        self.run(
+            &mut caller.guard(&mut *store),
+            &mut std::io::stdout().lock(),
+        )
+    }
+}
+
+/// Writes `accounts` to `out` in `format`. A `single` account is written as
+/// a JSON object rather than an array.
+fn write_accounts(
+    out: &mut dyn Write,
+    accounts: &[Account],
+    format: OutputFormat,
+    single: bool,
+) -> std::io::Result<()> {
+    match format {
+        OutputFormat::Table => {
+            writeln!(out, "{:<36}  {:<9}  FULLNAME", "ID", "STATUS")?;
+            for account in accounts {
+                writeln!(
+                    out,
+                    "{:<36}  {:<9}  {}",
+                    account.id, account.status, account.fullname
+                )?;
+            }
+        }
+        OutputFormat::Json => {
+            let json = match accounts {
+                [account] if single => serde_json::to_string_pretty(account),
+                _ => serde_json::to_string_pretty(accounts),
+            };
+            writeln!(out, "{}", json.map_err(std::io::Error::other)?)?;
+        }
+        OutputFormat::Csv => {
+            let mut writer = AccountWriter::new(out, Format::Csv)?;
+            for account in accounts {
+                writer.write(account)?;
+            }
+            writer.finish()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crud::memory::MemoryStore;
+
+    fn run(
+        store: &mut MemoryStore<Account>,
+        action: AccountAction,
+        format: OutputFormat,
+    ) -> String {
+        let mut out = Vec::new();
+        AccountCommand::new(Cfg::default(), action, format)
+            .run(store, &mut out)
+            .unwrap_or_else(|e| panic!("Failed to run command: {}", e));
+        String::from_utf8(out).expect("Output is not UTF-8")
+    }
+
+    #[test]
+    fn add_rename_and_remove_account() {
+        let mut store = MemoryStore::new();
+        let added = run(
+            &mut store,
+            AccountAction::Add {
+                fullname: "Test Account".to_string(),
+                email: None,
+                tags: Vec::new(),
+            },
+            OutputFormat::Json,
+        );
+        let account: Account = serde_json::from_str(&added).expect("Failed to parse JSON");
+        assert_eq!(store.read(&account.id).unwrap().fullname, "Test Account");
+
+        run(
+            &mut store,
+            AccountAction::Rename {
+                id: account.id,
+                fullname: "Modified Account".to_string(),
+            },
+            OutputFormat::Table,
+        );
+        assert_eq!(
+            store.read(&account.id).unwrap().fullname,
+            "Modified Account"
+        );
+
+        run(
+            &mut store,
+            AccountAction::Remove { id: account.id },
+            OutputFormat::Table,
+        );
+        assert!(store.read_all().unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_name_is_not_added() {
+        let mut store = MemoryStore::new();
+        let mut out = Vec::new();
+        let result = AccountCommand::new(
+            Cfg::default(),
+            AccountAction::Add {
+                fullname: " ".to_string(),
+                email: None,
+                tags: Vec::new(),
+            },
+            OutputFormat::Table,
+        )
+        .run(&mut store, &mut out);
+        assert_eq!(
+            result.map_err(|e| e.to_string()).err().as_deref(),
+            Some("fullname must not be empty")
+        );
+        assert!(store.read_all().unwrap().is_empty());
+    }
+
+    #[test]
+    fn show_unknown_account_fails() {
+        let mut out = Vec::new();
+        let result = AccountCommand::new(
+            Cfg::default(),
+            AccountAction::Show { id: Uuid::new_v4() },
+            OutputFormat::Table,
+        )
+        .run(&mut MemoryStore::new(), &mut out);
+        assert!(result.is_err());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn list_as_csv_quotes_fields() {
+        let mut store = MemoryStore::new();
+        let account = Account::new("Schroeter, Erich");
+        store.create(&account).expect("Failed to create Account");
+        assert_eq!(
+            run(
+                &mut store,
+                AccountAction::List {
+                    filter: AccountFilter::default()
+                },
+                OutputFormat::Csv
+            ),
+            format!(
+                "id,fullname,email,status,tags,attributes\n{},\"Schroeter, Erich\",,active,,\n",
+                account.id
+            )
+        );
+    }
+
+    #[test]
+    fn list_filters_by_tag_and_status() {
+        let mut store = MemoryStore::new();
+        let added = run(
+            &mut store,
+            AccountAction::Add {
+                fullname: "Erich".to_string(),
+                email: Some("Erich@Example.com".to_string()),
+                tags: vec!["staff".to_string()],
+            },
+            OutputFormat::Json,
+        );
+        let erich: Account = serde_json::from_str(&added).expect("Failed to parse JSON");
+        assert_eq!(erich.email.as_deref(), Some("erich@example.com"));
+        let mut jane = Account::new("Jane");
+        jane.status = AccountStatus::Suspended;
+        store.create(&jane).expect("Failed to create Account");
+
+        let list = |store: &mut MemoryStore<Account>, tag: Option<&str>, status| {
+            let filter = AccountFilter {
+                tag: tag.map(str::to_string),
+                status,
+            };
+            let listed = run(store, AccountAction::List { filter }, OutputFormat::Json);
+            serde_json::from_str::<Vec<Account>>(&listed)
+                .expect("Failed to parse JSON")
+                .into_iter()
+                .map(|account| account.fullname)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(list(&mut store, Some("staff"), None), vec!["Erich"]);
+        assert_eq!(
+            list(&mut store, None, Some(AccountStatus::Suspended)),
+            vec!["Jane"]
+        );
+        assert!(list(&mut store, Some("staff"), Some(AccountStatus::Suspended)).is_empty());
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::HashSet,
+    io::{BufRead, Write},
+    sync::Mutex,
+};
+
+use clap::ArgMatches;
+use common::Account;
+use cor_args::{ArgHandler, DefaultHandler, EnvHandler, Handler};
+use log::debug;
+
+use super::{Command, FixmeError};
+use crate::{
+    cfg::{default_config_path, Cfg},
+    crud::{membership::MembershipStore, Crud},
+    dedupe, APP_PREFIX,
+};
+
+/// Finds accounts whose names are alike and merges those the user picks.
+pub struct DedupeCommand {
+    cfg: Cfg,
+    /// The least similarity of the names of a pair, from 0 to 1.
+    min_score: f64,
+    /// Only list the pairs.
+    dry_run: bool,
+    /// Merge every pair into its first account without asking.
+    yes: bool,
+}
+
+impl DedupeCommand {
+    pub fn new(cfg: Cfg, min_score: f64, dry_run: bool, yes: bool) -> Self {
+        DedupeCommand {
+            cfg,
+            min_score,
+            dry_run,
+            yes,
+        }
+    }
+
+    /// Builds the command from the matches of the `dedupe` subcommand.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let config_path = ArgHandler::new(matches)
+            .next(Box::new(EnvHandler::new().prefix(APP_PREFIX).next(
+                Box::new(DefaultHandler::new(
+                    &default_config_path().display().to_string(),
+                )),
+            )))
+            .handle_request("config");
+        let config_path = config_path.expect("No config path");
+        let mut cfg = Cfg::default();
+        super::read_storage_cfg(matches, &config_path, &mut cfg);
+        debug!("{}", cfg);
+
+        let min_score = matches
+            .get_one::<u8>("min_score")
+            .copied()
+            .expect("No least score");
+        DedupeCommand::new(
+            cfg,
+            f64::from(min_score) / 100.0,
+            matches.get_flag("dry_run"),
+            matches.get_flag("yes"),
+        )
+    }
+
+    /// Lists the pairs of alike accounts of `store` to `out`, asking on
+    /// `input` which account of each pair to merge the other into.
+    fn run(
+        &self,
+        store: &mut dyn Crud<Account>,
+        memberships: &Mutex<dyn MembershipStore>,
+        input: &mut dyn BufRead,
+        out: &mut dyn Write,
+    ) -> Result<(), Box<dyn FixmeError>> {
+        let candidates = dedupe::candidates(&store.read_all()?, self.min_score);
+        let (mut merged, mut gone) = (0, HashSet::new());
+        for candidate in &candidates {
+            let (first, second) = (&candidate.first, &candidate.second);
+            // Merged into an account of an earlier pair.
+            if gone.contains(&first.id) || gone.contains(&second.id) {
+                continue;
+            }
+            writeln!(
+                out,
+                "{:>3}%  {}  {}",
+                (candidate.score * 100.0).round(),
+                first.id,
+                first.fullname
+            )?;
+            writeln!(out, "      {}  {}", second.id, second.fullname)?;
+            let keep_first = if self.dry_run {
+                continue;
+            } else if self.yes {
+                true
+            } else {
+                write!(
+                    out,
+                    "Merge into [1] the first, [2] the second, [n]either or [q]uit? "
+                )?;
+                out.flush()?;
+                let mut answer = String::new();
+                if input.read_line(&mut answer)? == 0 {
+                    break;
+                }
+                match answer.trim() {
+                    "1" => true,
+                    "2" => false,
+                    "q" => break,
+                    _ => continue,
+                }
+            };
+            let (keep, other) = match keep_first {
+                true => (first, second),
+                false => (second, first),
+            };
+            // An earlier merge may have added to either account, and what
+            // was listed may have changed while the user answered.
+            let keep = store.read(&keep.id)?;
+            let other = store.read(&other.id)?;
+            let account = dedupe::merge(store, memberships, &keep, &other)?;
+            gone.insert(other.id);
+            merged += 1;
+            writeln!(out, "Merged into {}", account.id)?;
+        }
+        match self.dry_run {
+            true => writeln!(out, "{} alike pairs", candidates.len())?,
+            false => writeln!(out, "{} merged", merged)?,
+        }
+        Ok(())
+    }
+}
+
+impl Command for DedupeCommand {
+    fn execute(&self) -> Result<(), Box<dyn FixmeError>> {
+        let memberships = super::open_membership_store(&self.cfg)?;
+        let mut store = super::open_account_store(&self.cfg, &memberships)?;
+        let caller = super::cli_caller(&self.cfg);
+        self.run(
+            &mut caller.guard(&mut *store),
+            &*memberships,
+            &mut std::io::stdin().lock(),
+            &mut std::io::stdout().lock(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::crud::{membership::Membership, memory::MemoryStore};
+
+    use super::*;
+
+    /// Runs the command on `store` with the answers of `input`, returning
+    /// what it printed.
+    fn run(store: &mut MemoryStore<Account>, dry_run: bool, yes: bool, input: &str) -> String {
+        let memberships: Arc<Mutex<dyn MembershipStore>> =
+            Arc::new(Mutex::new(MemoryStore::<Membership>::new()));
+        let mut out = Vec::new();
+        DedupeCommand::new(Cfg::default(), 0.85, dry_run, yes)
+            .run(store, &*memberships, &mut input.as_bytes(), &mut out)
+            .unwrap_or_else(|e| panic!("Failed to run command: {}", e));
+        String::from_utf8(out).expect("Output is not UTF-8")
+    }
+
+    fn store_of(names: &[&str]) -> MemoryStore<Account> {
+        let mut store = MemoryStore::new();
+        for name in names {
+            store
+                .create(&Account::new(name))
+                .expect("Failed to create Account");
+        }
+        store
+    }
+
+    fn names(store: &MemoryStore<Account>) -> Vec<String> {
+        let mut names: Vec<_> = store
+            .read_all()
+            .unwrap()
+            .into_iter()
+            .map(|account| account.fullname)
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn pairs_are_merged_as_answered() {
+        let mut store = store_of(&[
+            "John Smith",
+            "Erich Schroeter",
+            "Jon Smith",
+            "Schroeter, Erich",
+        ]);
+        let out = run(&mut store, false, false, "2\nn\n");
+        assert_eq!(out.matches("Merge into").count(), 2);
+        assert!(out.ends_with("1 merged\n"));
+        assert_eq!(
+            names(&store),
+            ["John Smith", "Jon Smith", "Schroeter, Erich"]
+        );
+
+        let out = run(&mut store, false, false, "q\n");
+        assert!(out.ends_with("0 merged\n"));
+        assert_eq!(store.read_all().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn what_was_merged_into_an_account_moves_on_with_it() {
+        let mut store = MemoryStore::new();
+        for (name, tag) in [
+            ("Jane Doe", "sales"),
+            ("jane doe", "staff"),
+            ("Jane Do", "admin"),
+        ] {
+            let mut account = Account::new(name);
+            account.tags = vec![tag.to_string()];
+            store.create(&account).expect("Failed to create Account");
+        }
+        let out = run(&mut store, false, false, "2\n2\n");
+        assert!(out.ends_with("2 merged\n"));
+        let accounts = store.read_all().unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].fullname, "Jane Do");
+        assert_eq!(accounts[0].tags, ["admin", "staff", "sales"]);
+    }
+
+    #[test]
+    fn dry_runs_only_list_and_yes_merges_into_the_first() {
+        let mut store = store_of(&["Jane Doe", "jane  doe", "Jane Do", "John Smith"]);
+        let out = run(&mut store, true, false, "");
+        assert!(!out.contains("Merge into"));
+        assert!(out.ends_with("3 alike pairs\n"));
+        assert_eq!(store.read_all().unwrap().len(), 4);
+
+        let out = run(&mut store, false, true, "");
+        assert!(out.ends_with("2 merged\n"));
+        assert_eq!(names(&store), ["Jane Doe", "John Smith"]);
+    }
+}
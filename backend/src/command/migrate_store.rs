@@ -0,0 +1,193 @@
+use std::{collections::HashSet, error::Error, io::Write};
+
+use clap::ArgMatches;
+use common::{Account, Group};
+use cor_args::{ArgHandler, DefaultHandler, EnvHandler, Handler};
+use log::debug;
+
+use crate::{
+    auth::policy::Caller,
+    cfg::{default_config_path, Cfg, StoreCfg},
+    crud::{
+        self,
+        membership::MembershipStore,
+        migrate::{self, CopyStats},
+        Crud, Entity, Operation,
+    },
+    APP_PREFIX,
+};
+
+/// Progress is reported every this many items.
+const PROGRESS_STEP: usize = 100;
+
+/// Copies every account, group and membership from the `--from` store, the
+/// configured one by default, into the `--to` store and verifies the copy.
+pub fn migrate_store(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let config_path = ArgHandler::new(matches)
+        .next(Box::new(EnvHandler::new().prefix(APP_PREFIX).next(
+            Box::new(DefaultHandler::new(
+                &default_config_path().display().to_string(),
+            )),
+        )))
+        .handle_request("config");
+    let config_path = config_path.expect("No config path");
+    let mut cfg = Cfg::default();
+    super::read_storage_cfg(matches, &config_path, &mut cfg);
+    debug!("{}", cfg);
+
+    let from = match matches.get_one::<String>("from") {
+        Some(from) => from.parse::<StoreCfg>()?,
+        None => cfg.store(),
+    };
+    let to = matches
+        .get_one::<String>("to")
+        .expect("No target store")
+        .parse::<StoreCfg>()?;
+    if from.to_string() == to.to_string() {
+        return Err(format!("Cannot migrate {} onto itself", from).into());
+    }
+    let dry_run = matches.get_flag("dry_run");
+
+    let caller = super::cli_caller(&cfg);
+    migrate::<Account>(&cfg, &caller, &from, &to, dry_run)?;
+    migrate::<Group>(
+        &cfg,
+        &caller,
+        &from.of::<Group>(),
+        &to.of::<Group>(),
+        dry_run,
+    )?;
+    migrate_memberships(
+        &cfg,
+        &caller,
+        &from.memberships(),
+        &to.memberships(),
+        dry_run,
+    )
+}
+
+/// Copies every `T` from `from` into `to` and, unless `dry_run` is set,
+/// verifies the copy.
+fn migrate<T: Entity>(
+    cfg: &Cfg,
+    caller: &Caller,
+    from: &StoreCfg,
+    to: &StoreCfg,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    let rules = cfg.unique_rules::<T>();
+    let mut source = crud::open_store::<T>(from.storage, from.storage_path.as_deref(), &rules)?;
+    let mut target = crud::open_store(to.storage, to.storage_path.as_deref(), &rules)?;
+    let source = caller.guard(&mut *source);
+    let mut target = caller.guard(&mut *target);
+    println!(
+        "{} {} from {} to {}",
+        if dry_run { "Checking" } else { "Copying" },
+        T::PLURAL,
+        from,
+        to
+    );
+    let stats = migrate::copy_all(&source, &mut target, dry_run, |done, total| {
+        if done % PROGRESS_STEP == 0 || done == total {
+            eprint!("\r{}/{} {}", done, total, T::PLURAL);
+            std::io::stderr().flush().ok();
+        }
+    })?;
+    if stats.total > 0 {
+        eprintln!();
+    }
+    print_stats(&stats, dry_run);
+    if dry_run {
+        return Ok(());
+    }
+
+    let copied = source.read_all()?;
+    let ids: HashSet<_> = copied.iter().map(|item| item.id()).collect();
+    let (matched, extra): (Vec<_>, Vec<_>) = target
+        .read_all()?
+        .into_iter()
+        .partition(|item| ids.contains(&item.id()));
+    let (source_sum, target_sum) = (migrate::checksum(&copied), migrate::checksum(&matched));
+    println!(
+        "Source: {} {}, sha256 {}",
+        copied.len(),
+        T::PLURAL,
+        source_sum
+    );
+    println!(
+        "Target: {} {}, sha256 {}",
+        matched.len(),
+        T::PLURAL,
+        target_sum
+    );
+    if !extra.is_empty() {
+        println!(
+            "Target also holds {} {} not in the source",
+            extra.len(),
+            T::PLURAL
+        );
+    }
+    if copied.len() != matched.len() || source_sum != target_sum {
+        return Err("Verification failed, run the migration again to resume it".into());
+    }
+    Ok(())
+}
+
+/// Copies the memberships of the accounts in groups from `from` into `to`,
+/// which changes the groups, and verifies that `to` holds all of them.
+fn migrate_memberships(
+    cfg: &Cfg,
+    caller: &Caller,
+    from: &StoreCfg,
+    to: &StoreCfg,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    caller.check(Operation::Read, None)?;
+    if !dry_run {
+        caller.check(Operation::Update, None)?;
+    }
+    let source =
+        crud::open_membership_store(from.storage, from.storage_path.as_deref(), cfg.memberships)?;
+    let mut target =
+        crud::open_membership_store(to.storage, to.storage_path.as_deref(), cfg.memberships)?;
+    println!(
+        "{} memberships from {} to {}",
+        if dry_run { "Checking" } else { "Copying" },
+        from,
+        to
+    );
+    let stats = migrate::copy_memberships(&source, &mut target, dry_run)?;
+    print_stats(&stats, dry_run);
+    if dry_run {
+        return Ok(());
+    }
+
+    let copied: HashSet<_> = target
+        .read_all()?
+        .into_iter()
+        .map(|membership| (membership.group, membership.account))
+        .collect();
+    let missing = source
+        .read_all()?
+        .iter()
+        .filter(|membership| !copied.contains(&(membership.group, membership.account)))
+        .count();
+    if missing > 0 {
+        return Err(format!(
+            "Verification failed, {} memberships are missing, run the migration again to resume it",
+            missing
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn print_stats(stats: &CopyStats, dry_run: bool) {
+    println!(
+        "{}{} created, {} updated, {} already present",
+        if dry_run { "Would have: " } else { "" },
+        stats.created,
+        stats.updated,
+        stats.skipped
+    );
+}
@@ -1,11 +1,218 @@
+pub mod account;
+pub mod dedupe;
+pub mod migrate_store;
+pub mod reconcile;
 pub mod serve;
+pub mod token;
+pub mod transfer;
+pub mod user;
 
-use std::error::Error;
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+};
+
+use clap::ArgMatches;
+use common::{Account, Group};
+use cor_args::{ArgHandler, ConfigHandler, DefaultHandler, EnvHandler, Handler};
+
+use crate::{
+    auth::{audit::AuditLog, policy::Caller},
+    cfg::Cfg,
+    crud::{
+        self,
+        membership::{self, MembershipStore, Side},
+        mirror::MirroredStore,
+        Crud, Strategy,
+    },
+    APP_PREFIX,
+};
 
 pub trait FixmeError: Error {}
 
 impl FixmeError for std::io::Error {}
+impl FixmeError for crud::CrudError {}
+impl FixmeError for uuid::Error {}
+impl FixmeError for common::validate::ValidationErrors {}
+
+impl<E: FixmeError + 'static> From<E> for Box<dyn FixmeError> {
+    fn from(err: E) -> Self {
+        Box::new(err)
+    }
+}
 
 pub trait Command {
     fn execute(&self) -> Result<(), Box<dyn FixmeError>>;
 }
+
+/// Reads the storage strategy and path into `cfg`, from the command line,
+/// environment, config file or defaults, and the mirror, validation rules, id
+/// version, membership and uniqueness rules, authentication and tenants from
+/// the config file. With `--tenant`
+/// `cfg` becomes the tenant's.
+fn read_storage_cfg(matches: &ArgMatches, config_path: &str, cfg: &mut Cfg) {
+    let file = || config::File::new(config_path, config::FileFormat::Yaml);
+    let config = || {
+        config::Config::builder()
+            .add_source(file())
+            .build()
+            .unwrap_or_default()
+    };
+    // The config handler finds keys at any depth, so it is only shown the
+    // top-level `storage` and `storage_path`, not those of the tenants,
+    // mirrors or users.
+    let top_level = || {
+        let config = config();
+        let mut builder = config::Config::builder();
+        for key in ["storage", "storage_path"] {
+            if let Ok(value) = config.get::<String>(key) {
+                builder = builder.set_override(key, value).unwrap_or_default();
+            }
+        }
+        builder.build().unwrap_or_default()
+    };
+
+    let storage = ArgHandler::new(matches)
+        .next(Box::new(EnvHandler::new().prefix(APP_PREFIX).next(
+            Box::new(ConfigHandler::new(Box::new(top_level())).next(Box::new(
+                DefaultHandler::new(&Strategy::default().to_string()),
+            ))),
+        )))
+        .handle_request("storage");
+    if let Some(storage) = storage {
+        cfg.storage = storage.parse().unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    let default_path = cfg
+        .storage
+        .default_path()
+        .map(|file| {
+            std::env::current_dir()
+                .unwrap_or_default()
+                .join(file)
+                .display()
+                .to_string()
+        })
+        .unwrap_or_default();
+    let storage_path = ArgHandler::new(matches)
+        .next(Box::new(
+            EnvHandler::new().prefix(APP_PREFIX).next(Box::new(
+                ConfigHandler::new(Box::new(top_level()))
+                    .next(Box::new(DefaultHandler::new(&default_path))),
+            )),
+        ))
+        .handle_request("storage_path");
+    if let Some(storage_path) = storage_path.filter(|path| !path.is_empty()) {
+        cfg.storage_path = Some(storage_path);
+    }
+
+    let file_cfg = Cfg::from(config());
+    cfg.mirror = file_cfg.mirror;
+    cfg.validation = file_cfg.validation;
+    cfg.id_version = file_cfg.id_version;
+    cfg.memberships = file_cfg.memberships;
+    cfg.unique = file_cfg.unique;
+    cfg.auth = file_cfg.auth;
+    cfg.tenants = file_cfg.tenants;
+
+    // Commands that work on one store may be pointed at a tenant's.
+    if let Some(name) = matches.try_get_one::<String>("tenant").ok().flatten() {
+        *cfg = cfg
+            .tenant(name)
+            .unwrap_or_else(|| panic!("No tenant is named {:?}", name));
+    }
+}
+
+/// Opens the primary account store together with its configured mirrors.
+fn open_mirrored_store(cfg: &Cfg) -> crud::Result<MirroredStore<Account>> {
+    let rules = cfg.unique_rules::<Account>();
+    let primary = crud::open_store(cfg.storage, cfg.storage_path.as_deref(), &rules)?;
+    let mut store = MirroredStore::new(primary, cfg.mirror.consistency);
+    for secondary in &cfg.mirror.secondaries {
+        let inner = crud::open_store(secondary.storage, secondary.storage_path.as_deref(), &rules)?;
+        store.add_secondary(secondary.to_string(), inner);
+    }
+    Ok(store)
+}
+
+/// Who the commands that use the account store act as: the user running
+/// them, in `auth.cli_role`.
+fn cli_caller(cfg: &Cfg) -> Caller {
+    let name = std::env::var("USER").unwrap_or_else(|_| "cli".to_string());
+    let audit = AuditLog::new(cfg.auth.audit_path().as_ref());
+    Caller::new(
+        &name,
+        cfg.auth.cli_role,
+        cfg.auth.policy,
+        Some(Arc::new(audit)),
+    )
+    .in_tenant(cfg.tenant.as_deref())
+}
+
+/// Opens the memberships of the accounts described by `cfg` in groups.
+fn open_membership_store(cfg: &Cfg) -> crud::Result<Arc<Mutex<dyn MembershipStore>>> {
+    let store = cfg.membership_store();
+    let memberships = crud::open_membership_store(
+        store.storage,
+        store.storage_path.as_deref(),
+        cfg.memberships,
+    )?;
+    Ok(Arc::new(Mutex::new(memberships)))
+}
+
+/// Opens the account store described by `cfg`, whose deletes apply the
+/// delete rule of the accounts to `memberships`.
+fn open_account_store(
+    cfg: &Cfg,
+    memberships: &Arc<Mutex<dyn MembershipStore>>,
+) -> crud::Result<Box<dyn Crud<Account>>> {
+    let store: Box<dyn Crud<Account>> = if cfg.mirror.secondaries.is_empty() {
+        let rules = cfg.unique_rules::<Account>();
+        crud::open_store(cfg.storage, cfg.storage_path.as_deref(), &rules)?
+    } else {
+        Box::new(open_mirrored_store(cfg)?)
+    };
+    membership::with_rules(store, Side::Account, cfg.memberships, memberships.clone())
+}
+
+/// Opens the group store next to the accounts described by `cfg`, like
+/// [`open_account_store`].
+fn open_group_store(
+    cfg: &Cfg,
+    memberships: &Arc<Mutex<dyn MembershipStore>>,
+) -> crud::Result<Box<dyn Crud<Group>>> {
+    let store = cfg.store_of::<Group>();
+    let rules = cfg.unique_rules::<Group>();
+    let groups = crud::open_store(store.storage, store.storage_path.as_deref(), &rules)?;
+    membership::with_rules(groups, Side::Group, cfg.memberships, memberships.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn storage_is_only_read_from_the_top_level() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let config_path = dir.path().join("config.yaml");
+        std::fs::write(
+            &config_path,
+            "mirror:\n  secondaries:\n    - storage: memory\n      storage_path: mirror.csv\n\
+             auth:\n  users:\n    storage_path: users.csv\n",
+        )
+        .expect("Failed to write config");
+        let matches = clap::Command::new("test").get_matches_from(["test"]);
+        let mut cfg = Cfg::default();
+        read_storage_cfg(&matches, &config_path.display().to_string(), &mut cfg);
+        assert_eq!(cfg.storage, Strategy::default());
+        let file_name = cfg
+            .storage_path
+            .as_deref()
+            .and_then(|path| std::path::Path::new(path).file_name())
+            .and_then(|name| name.to_str());
+        assert_eq!(file_name, Strategy::default().default_path());
+        assert_eq!(cfg.mirror.secondaries.len(), 1);
+    }
+}
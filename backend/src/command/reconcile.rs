@@ -0,0 +1,166 @@
+use std::io::Write;
+
+use clap::ArgMatches;
+use common::Account;
+use cor_args::{ArgHandler, DefaultHandler, EnvHandler, Handler};
+use log::debug;
+
+use super::{Command, FixmeError};
+use crate::{
+    cfg::{default_config_path, Cfg},
+    crud::{mirror::MirroredStore, Operation},
+    APP_PREFIX,
+};
+
+/// Compares every configured mirror with the primary account store and
+/// repairs the differences, or only reports them with `--dry-run`.
+pub struct ReconcileCommand {
+    cfg: Cfg,
+    /// Only report the differences.
+    dry_run: bool,
+}
+
+impl ReconcileCommand {
+    pub fn new(cfg: Cfg, dry_run: bool) -> Self {
+        ReconcileCommand { cfg, dry_run }
+    }
+
+    /// Builds the command from the matches of the `reconcile` subcommand.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let config_path = ArgHandler::new(matches)
+            .next(Box::new(EnvHandler::new().prefix(APP_PREFIX).next(
+                Box::new(DefaultHandler::new(
+                    &default_config_path().display().to_string(),
+                )),
+            )))
+            .handle_request("config");
+        let config_path = config_path.expect("No config path");
+        let mut cfg = Cfg::default();
+        super::read_storage_cfg(matches, &config_path, &mut cfg);
+        debug!("{}", cfg);
+
+        ReconcileCommand::new(cfg, matches.get_flag("dry_run"))
+    }
+
+    /// Reconciles the mirrors of `store`, listing their drift to `out`.
+    fn run(
+        &self,
+        store: &mut MirroredStore<Account>,
+        out: &mut dyn Write,
+    ) -> Result<(), Box<dyn FixmeError>> {
+        for (name, drift) in store.reconcile(!self.dry_run)? {
+            if drift.is_empty() {
+                writeln!(out, "{}: in sync", name)?;
+                continue;
+            }
+            writeln!(
+                out,
+                "{}: {} missing, {} changed, {} extra{}",
+                name,
+                drift.missing.len(),
+                drift.changed.len(),
+                drift.extra.len(),
+                if self.dry_run { "" } else { " (repaired)" }
+            )?;
+            for (label, ids) in [
+                ("missing", &drift.missing),
+                ("changed", &drift.changed),
+                ("extra", &drift.extra),
+            ] {
+                for id in ids {
+                    writeln!(out, "  {} {}", label, id)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Command for ReconcileCommand {
+    fn execute(&self) -> Result<(), Box<dyn FixmeError>> {
+        if self.cfg.mirror.secondaries.is_empty() {
+            println!("No mirrors configured for {}", self.cfg.store());
+            return Ok(());
+        }
+        // Repairs write to the mirrors without going through a guarded store.
+        let operations: &[Operation] = match self.dry_run {
+            true => &[Operation::Read],
+            false => &[
+                Operation::Read,
+                Operation::Create,
+                Operation::Update,
+                Operation::Delete,
+            ],
+        };
+        let caller = super::cli_caller(&self.cfg);
+        for operation in operations {
+            caller.check(*operation, None)?;
+        }
+        let mut store = super::open_mirrored_store(&self.cfg)?;
+        self.run(&mut store, &mut std::io::stdout().lock())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        auth::Role,
+        cfg::StoreCfg,
+        crud::{memory::MemoryStore, mirror::Consistency, Crud, CrudError, Strategy},
+    };
+
+    #[test]
+    fn drift_is_listed_and_repaired() {
+        let account = Account::new("Test Account");
+        let mut primary = MemoryStore::new();
+        primary.create(&account).expect("Failed to create Account");
+        let mut store = MirroredStore::new(Box::new(primary), Consistency::Queue);
+        store.add_secondary("shadow", Box::new(MemoryStore::new()));
+
+        for (dry_run, expected) in [
+            (
+                true,
+                format!(
+                    "shadow: 1 missing, 0 changed, 0 extra\n  missing {}\n",
+                    account.id
+                ),
+            ),
+            (
+                false,
+                format!(
+                    "shadow: 1 missing, 0 changed, 0 extra (repaired)\n  missing {}\n",
+                    account.id
+                ),
+            ),
+            (false, "shadow: in sync\n".to_string()),
+        ] {
+            let mut out = Vec::new();
+            ReconcileCommand::new(Cfg::default(), dry_run)
+                .run(&mut store, &mut out)
+                .unwrap_or_else(|e| panic!("Failed to reconcile: {}", e));
+            assert_eq!(String::from_utf8(out).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn forbidden_reconcile_fails() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let mut cfg = Cfg::default();
+        cfg.auth.cli_role = Role::Viewer;
+        cfg.auth.audit_path = Some(dir.path().join("audit.jsonl").display().to_string());
+        cfg.mirror.secondaries.push(StoreCfg {
+            storage: Strategy::Memory,
+            storage_path: None,
+        });
+        let err = ReconcileCommand::new(cfg, false)
+            .execute()
+            .expect_err("Reconciled as a viewer");
+        assert_eq!(
+            err.to_string(),
+            CrudError::Forbidden(Operation::Create).to_string()
+        );
+    }
+}
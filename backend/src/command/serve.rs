@@ -1,60 +1,215 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use actix_web::{rt, web, HttpServer};
+use actix_web::{middleware, rt, web, HttpServer};
 use clap::ArgMatches;
-use common::Account;
+use common::{Account, Group};
 use cor_args::{ArgHandler, ConfigHandler, DefaultHandler, EnvHandler, Handler};
 use log::{debug, info};
 use tera::Tera;
 
-#[cfg(feature = "csv")]
-use crate::crud::csv::CsvAccountStore;
-#[cfg(feature = "sqlite")]
-use crate::crud::sqlite::SqliteAccountStore;
 use crate::{
-    cfg::{default_config_path, default_template_glob, Cfg},
-    crud::Crud,
+    auth::Auth,
+    cfg::{default_config_path, default_static_dir, default_template_glob, Cfg},
+    crud::{
+        self,
+        cache::{CacheStats, CachedStore},
+        membership::MembershipStore,
+        observer::ObservedStore,
+        Crud,
+    },
+    event::EventBus,
+    route::import::ImportStaging,
+    webhook::Webhooks,
     APP_PREFIX,
 };
 
-#[cfg(feature = "csv")]
-fn create_store(cfg: &Cfg) -> Mutex<CsvAccountStore> {
-    let storage_path = cfg
-        .storage_path
-        .to_owned()
-        .unwrap_or("accounts.csv".to_string());
-    Mutex::new(CsvAccountStore::new(&storage_path))
+/// The store of a tenant, or of the default tenant, with the settings and
+/// state that go with it.
+struct Tenant {
+    cfg: web::Data<Cfg>,
+    storage: Arc<Mutex<dyn Crud<Account>>>,
+    groups: Arc<Mutex<dyn Crud<Group>>>,
+    memberships: Arc<Mutex<dyn MembershipStore>>,
+    events: Arc<EventBus<Account>>,
+    staging: web::Data<ImportStaging>,
+    webhooks: Option<Arc<Webhooks>>,
+    cache_stats: Option<Arc<CacheStats>>,
 }
 
-#[cfg(feature = "sqlite")]
-fn create_store(cfg: &Cfg) -> Mutex<SqliteAccountStore> {
-    let storage_path = cfg
-        .storage_path
-        .to_owned()
-        .unwrap_or("accounts.sqlite".to_string());
-    Mutex::new(SqliteAccountStore::new(&storage_path))
+impl Tenant {
+    fn open(cfg: Cfg) -> std::io::Result<Self> {
+        // A single store is shared by all workers so that writes, and the
+        // change events they produce, are serialized in commit order.
+        let events: Arc<EventBus<Account>> = Arc::new(EventBus::default());
+        let memberships = super::open_membership_store(&cfg).map_err(std::io::Error::other)?;
+        let inner = super::open_account_store(&cfg, &memberships).map_err(std::io::Error::other)?;
+        let (inner, cache_stats): (Box<dyn Crud<Account>>, _) =
+            if cfg.cache.enabled && !inner.in_memory() {
+                let cache = CachedStore::new(
+                    inner,
+                    Duration::from_secs(cfg.cache.ttl_secs),
+                    cfg.cache.max_items,
+                );
+                let stats = cache.stats();
+                (Box::new(cache), Some(stats))
+            } else {
+                (inner, None)
+            };
+        let mut store = ObservedStore::new(inner);
+        store.subscribe(events.clone());
+        let webhooks = if cfg.webhooks.is_empty() {
+            None
+        } else {
+            let queue_path = Path::new(cfg.webhook_queue_path());
+            let webhooks = Arc::new(Webhooks::new(cfg.webhooks.clone(), queue_path)?);
+            store.subscribe(webhooks.clone());
+            Some(webhooks)
+        };
+        let groups = super::open_group_store(&cfg, &memberships).map_err(std::io::Error::other)?;
+        Ok(Tenant {
+            cfg: web::Data::new(cfg),
+            storage: Arc::new(Mutex::new(store)),
+            groups: Arc::new(Mutex::new(groups)),
+            memberships,
+            events,
+            staging: web::Data::new(ImportStaging::default()),
+            webhooks,
+            cache_stats,
+        })
+    }
+
+    /// Registers the routes of the tenant, which are given its store and
+    /// settings only.
+    fn configure(&self, config: &mut web::ServiceConfig) {
+        config
+            .app_data(self.cfg.clone())
+            .app_data(web::Data::from(self.storage.clone()))
+            .app_data(web::Data::from(self.groups.clone()))
+            .app_data(web::Data::from(self.memberships.clone()))
+            .app_data(web::Data::from(self.events.clone()))
+            .app_data(self.staging.clone())
+            .route("/", web::get().to(crate::route::index::index))
+            .route("/account", web::get().to(crate::route::account::list_accounts))
+            .route(
+                "/account.json",
+                web::get().to(crate::route::account::list_accounts),
+            )
+            .route(
+                "/account/events",
+                web::get().to(crate::route::account::account_events),
+            )
+            .route(
+                "/account/create",
+                web::post().to(crate::route::account::create_account),
+            )
+            .configure(crate::route::form::configure)
+            .route(
+                "/account/import",
+                web::get().to(crate::route::import::import_form),
+            )
+            .route(
+                "/account/import",
+                web::post().to(crate::route::import::import_accounts),
+            )
+            .route(
+                "/account/import/commit",
+                web::post().to(crate::route::import::commit_import),
+            )
+            .route(
+                "/account/export",
+                web::get().to(crate::route::export::export_accounts),
+            )
+            // Last, so that the fixed paths above are not taken for an id.
+            .route(
+                "/account/{id}",
+                web::get().to(crate::route::account::show_account),
+            )
+            .configure(crate::route::membership::configure)
+            .configure(crate::route::entity::configure::<Group>)
+            .route("/ws", web::get().to(crate::route::ws::ws))
+            .configure(crate::route::api::configure);
+    }
 }
 
 fn run_http_server(cfg: Cfg) -> std::io::Result<()> {
     info!("Running HTTP Server at http://{}:{}", cfg.address, cfg.port);
-    let cfg_clone = cfg.clone();
+    crate::tenant::check(&cfg)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
     let tera = Tera::new(&cfg.template_glob).unwrap();
+    let default = Arc::new(Tenant::open(cfg.clone())?);
+    let mut tenants = Vec::new();
+    for name in cfg.tenants.keys() {
+        let tenant_cfg = cfg.tenant(name).expect("Tenant is configured");
+        info!("Serving tenant {} from {}", name, tenant_cfg.store());
+        tenants.push((
+            crate::tenant::prefix(name),
+            Arc::new(Tenant::open(tenant_cfg)?),
+        ));
+    }
+    let static_dir = cfg.static_dir.clone();
+    let user_store = cfg.user_store();
+    let users = crud::open_user_store(user_store.storage, user_store.storage_path.as_deref())
+        .map_err(std::io::Error::other)?;
+    if cfg.auth.enabled && users.in_memory() {
+        log::warn!("Operators are kept in memory only; nobody can log in to the HTML pages");
+    }
+    let auth = web::Data::new(Auth::new(&cfg.auth, users));
+    if !cfg.auth.enabled {
+        log::warn!(
+            "Authentication is disabled; anyone who can reach the server may change accounts"
+        );
+    }
+    let served = std::iter::once(default.clone())
+        .chain(tenants.iter().map(|(_, tenant)| tenant.clone()))
+        .collect::<Vec<_>>();
     let server = HttpServer::new(move || {
-        let storage: Arc<Mutex<dyn Crud<Account>>> = Arc::new(create_store(&cfg_clone));
-        actix_web::App::new()
+        let mut app = actix_web::App::new()
             .app_data(web::Data::new(tera.clone()))
-            .app_data(web::Data::from(storage))
-            .route("/", web::get().to(crate::route::index::index))
-            .route("/account", web::get().to(crate::route::account::list_accounts))
+            .app_data(auth.clone())
+            .wrap(middleware::from_fn(crate::auth::authenticate))
+            // Outermost, so that authentication sees where requests are routed.
+            .wrap(middleware::from_fn(crate::tenant::route))
+            .configure(|config| default.configure(config))
+            .configure(crate::route::login::configure)
             .route(
-                "/account/create",
-                web::post().to(crate::route::account::create_account),
+                "/api/openapi.json",
+                web::get().to(crate::route::openapi::openapi_json),
             )
+            .service(
+                actix_files::Files::new("/api/docs", format!("{}/swagger-ui", static_dir))
+                    .index_file("index.html")
+                    .redirect_to_slash_directory(),
+            );
+        for (prefix, tenant) in &tenants {
+            let tenant = tenant.clone();
+            app = app.service(web::scope(prefix).configure(move |config| tenant.configure(config)));
+        }
+        app
     })
     .bind((cfg.address.as_str(), cfg.port));
 
     if let Ok(server) = server {
-        rt::System::new().block_on(server.run())
+        let result = rt::System::new().block_on(async {
+            for webhooks in served.iter().filter_map(|tenant| tenant.webhooks.clone()) {
+                rt::spawn(webhooks.run());
+            }
+            server.run().await
+        });
+        for tenant in &served {
+            if let Some(stats) = &tenant.cache_stats {
+                info!(
+                    "Cache of {}: {} hits, {} misses",
+                    tenant.cfg.tenant.as_deref().unwrap_or("the default tenant"),
+                    stats.hits(),
+                    stats.misses()
+                );
+            }
+        }
+        result
     } else {
         unimplemented!()
     }
@@ -88,6 +243,23 @@ pub fn serve(matches: &ArgMatches) {
         cfg.template_glob = template_glob;
     }
 
+    let static_dir = ArgHandler::new(matches)
+        .next(Box::new(
+            EnvHandler::new().prefix(APP_PREFIX).next(Box::new(
+                ConfigHandler::new(Box::new(
+                    config::Config::builder()
+                        .add_source(config::File::new(&config_path, config::FileFormat::Yaml))
+                        .build()
+                        .unwrap_or_default(),
+                ))
+                .next(Box::new(DefaultHandler::new(&default_static_dir()))),
+            )),
+        ))
+        .handle_request("static_dir");
+    if let Some(static_dir) = static_dir {
+        cfg.static_dir = static_dir;
+    }
+
     let address = ArgHandler::new(matches)
         .next(Box::new(
             EnvHandler::new().prefix(APP_PREFIX).next(Box::new(
@@ -119,66 +291,27 @@ pub fn serve(matches: &ArgMatches) {
         ))
         .handle_request("port");
     if let Some(port) = port {
-        cfg.port = port.parse::<u16>().expect(&format!(
-            "Failed to convert {} to unsigned 16-bit integer",
-            port
-        ))
+        cfg.port = port
+            .parse::<u16>()
+            .unwrap_or_else(|_| panic!("Failed to convert {} to unsigned 16-bit integer", port))
     }
 
     // Validate the Storage strategy here before calling `run_http_server` to avoid runtime erros there.
-    if cfg!(feature = "csv") {
-        let storage_path = ArgHandler::new(matches)
-            .next(Box::new(
-                EnvHandler::new().prefix(APP_PREFIX).next(Box::new(
-                    ConfigHandler::new(Box::new(
-                        config::Config::builder()
-                            .add_source(config::File::new(&config_path, config::FileFormat::Yaml))
-                            .build()
-                            .unwrap_or_default(),
-                    ))
-                    .next(Box::new(DefaultHandler::new(
-                        std::env::current_dir()
-                            .unwrap_or_default()
-                            .join("accounts.csv")
-                            .display()
-                            .to_string()
-                            .as_str(),
-                    ))),
-                )),
-            ))
-            .handle_request("storage_path");
-        if let Some(storage_path) = storage_path {
-            cfg.storage_path = Some(storage_path.to_owned());
-        }
-    } else if cfg!(feature = "sqlite") {
-        let storage_path = ArgHandler::new(matches)
-            .next(Box::new(
-                EnvHandler::new().prefix(APP_PREFIX).next(Box::new(
-                    ConfigHandler::new(Box::new(
-                        config::Config::builder()
-                            .add_source(config::File::new(&config_path, config::FileFormat::Yaml))
-                            .build()
-                            .unwrap_or_default(),
-                    ))
-                    .next(Box::new(DefaultHandler::new(
-                        std::env::current_dir()
-                            .unwrap_or_default()
-                            .join("accounts.sqlite")
-                            .display()
-                            .to_string()
-                            .as_str(),
-                    ))),
-                )),
-            ))
-            .handle_request("storage_path");
-        if let Some(storage_path) = storage_path {
-            cfg.storage_path = Some(storage_path.to_owned());
-        }
-    }
+    super::read_storage_cfg(matches, &config_path, &mut cfg);
+
+    // Lists such as webhooks are only read from the config file.
+    let file_cfg = Cfg::from(
+        config::Config::builder()
+            .add_source(config::File::new(&config_path, config::FileFormat::Yaml))
+            .build()
+            .unwrap_or_default(),
+    );
+    cfg.cache = file_cfg.cache;
+    cfg.webhooks = file_cfg.webhooks;
+    cfg.webhook_queue_path = file_cfg.webhook_queue_path;
 
     debug!("{}", cfg);
-    match run_http_server(cfg) {
-        Ok(_) => {}
-        Err(_) => {}
+    if let Err(e) = run_http_server(cfg) {
+        log::error!("HTTP server failed: {}", e);
     }
 }
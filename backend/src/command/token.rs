@@ -0,0 +1,199 @@
+use std::{io::Write, path::Path};
+
+use chrono::{Duration, Utc};
+use clap::ArgMatches;
+use cor_args::{ArgHandler, DefaultHandler, EnvHandler, Handler};
+use log::debug;
+
+use super::{Command, FixmeError};
+use crate::{
+    auth::{
+        token::{self, Token},
+        Scope,
+    },
+    cfg::{default_config_path, Cfg},
+    APP_PREFIX,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenAction {
+    Create {
+        name: String,
+        scopes: Vec<Scope>,
+        expires_in_days: Option<i64>,
+    },
+    List,
+    Revoke {
+        name: String,
+    },
+}
+
+/// Manages the API tokens of the token file.
+pub struct TokenCommand {
+    cfg: Cfg,
+    action: TokenAction,
+}
+
+impl TokenCommand {
+    pub fn new(cfg: Cfg, action: TokenAction) -> Self {
+        TokenCommand { cfg, action }
+    }
+
+    /// Builds the command from the matches of the `token` subcommand.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let config_path = ArgHandler::new(matches)
+            .next(Box::new(EnvHandler::new().prefix(APP_PREFIX).next(
+                Box::new(DefaultHandler::new(
+                    &default_config_path().display().to_string(),
+                )),
+            )))
+            .handle_request("config");
+        let config_path = config_path.expect("No config path");
+        let mut cfg = Cfg::default();
+        super::read_storage_cfg(matches, &config_path, &mut cfg);
+        debug!("{}", cfg);
+
+        let name = |m: &ArgMatches| m.get_one::<String>("name").expect("No name").clone();
+        let action = match matches.subcommand() {
+            Some(("create", m)) => TokenAction::Create {
+                name: name(m),
+                scopes: m
+                    .get_many::<Scope>("scope")
+                    .map(|scopes| scopes.copied().collect())
+                    .unwrap_or_else(|| vec![Scope::Read]),
+                expires_in_days: m.get_one::<i64>("expires_in").copied(),
+            },
+            Some(("list", _)) => TokenAction::List,
+            Some(("revoke", m)) => TokenAction::Revoke { name: name(m) },
+            subcommand => unreachable!("Invalid token subcommand {:?}", subcommand),
+        };
+        TokenCommand::new(cfg, action)
+    }
+
+    fn run(&self, out: &mut dyn Write) -> Result<(), Box<dyn FixmeError>> {
+        let path = Path::new(self.cfg.auth.token_path());
+        let mut stored = token::load(path)?;
+        let configured = &self.cfg.auth.tokens;
+        match &self.action {
+            TokenAction::Create {
+                name,
+                scopes,
+                expires_in_days,
+            } => {
+                if stored.iter().chain(configured).any(|t| &t.name == name) {
+                    return Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        format!("A token named \"{}\" already exists", name),
+                    )));
+                }
+                let expires = expires_in_days.map(|days| Utc::now() + Duration::days(days));
+                let (token, secret) = Token::generate(name, scopes.clone(), expires);
+                stored.push(token);
+                token::save(path, &stored)?;
+                writeln!(out, "{}", secret)?;
+                eprintln!(
+                    "Created token \"{}\". Send the secret above as `Authorization: Bearer <secret>`; \
+                     it is not shown again.",
+                    name
+                );
+            }
+            TokenAction::List => {
+                writeln!(
+                    out,
+                    "{:<20}  {:<18}  {:<25}  SOURCE",
+                    "NAME", "SCOPES", "EXPIRES"
+                )?;
+                let sources = configured
+                    .iter()
+                    .map(|t| (t, "config"))
+                    .chain(stored.iter().map(|t| (t, "file")));
+                for (token, source) in sources {
+                    let scopes: Vec<String> = token.scopes.iter().map(Scope::to_string).collect();
+                    let expires = match token.expires {
+                        Some(expires) if token.is_expired(Utc::now()) => {
+                            format!("{} (expired)", expires.format("%Y-%m-%d %H:%M"))
+                        }
+                        Some(expires) => expires.format("%Y-%m-%d %H:%M").to_string(),
+                        None => "never".to_string(),
+                    };
+                    writeln!(
+                        out,
+                        "{:<20}  {:<18}  {:<25}  {}",
+                        token.name,
+                        scopes.join(","),
+                        expires,
+                        source
+                    )?;
+                }
+            }
+            TokenAction::Revoke { name } => {
+                if configured.iter().any(|t| &t.name == name) {
+                    return Err(Box::new(std::io::Error::other(format!(
+                        "Token \"{}\" is set in the config file; remove it there",
+                        name
+                    ))));
+                }
+                let count = stored.len();
+                stored.retain(|t| &t.name != name);
+                if stored.len() == count {
+                    return Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("No token is named \"{}\"", name),
+                    )));
+                }
+                token::save(path, &stored)?;
+                writeln!(out, "Revoked token \"{}\"", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Command for TokenCommand {
+    fn execute(&self) -> Result<(), Box<dyn FixmeError>> {
+        self.run(&mut std::io::stdout().lock())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::auth::token::Tokens;
+
+    fn run(cfg: &Cfg, action: TokenAction) -> Result<String, Box<dyn FixmeError>> {
+        let mut out = Vec::new();
+        TokenCommand::new(cfg.clone(), action).run(&mut out)?;
+        Ok(String::from_utf8(out).expect("Output is not UTF-8"))
+    }
+
+    #[test]
+    fn tokens_are_created_listed_and_revoked() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let mut cfg = Cfg::default();
+        cfg.auth.token_path = Some(dir.path().join("tokens.json").display().to_string());
+        let create = TokenAction::Create {
+            name: "ci".to_string(),
+            scopes: vec![Scope::Write],
+            expires_in_days: Some(30),
+        };
+        let secret = run(&cfg, create.clone()).expect("Failed to create token");
+        let secret = secret.trim();
+        assert!(run(&cfg, create).is_err());
+
+        let tokens = Tokens::new(Vec::new(), cfg.auth.token_path().into());
+        assert_eq!(tokens.find(secret).expect("Token not found").name, "ci");
+
+        let list = run(&cfg, TokenAction::List).expect("Failed to list tokens");
+        assert!(list.lines().nth(1).unwrap().starts_with("ci "));
+        assert!(!list.contains(secret));
+
+        let revoke = TokenAction::Revoke {
+            name: "ci".to_string(),
+        };
+        run(&cfg, revoke.clone()).expect("Failed to revoke token");
+        assert!(tokens.find(secret).is_none());
+        assert!(run(&cfg, revoke).is_err());
+    }
+}
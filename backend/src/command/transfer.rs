@@ -0,0 +1,154 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::ArgMatches;
+use cor_args::{ArgHandler, DefaultHandler, EnvHandler, Handler};
+use log::debug;
+
+use super::{Command, FixmeError};
+use crate::{
+    cfg::{default_config_path, Cfg},
+    crud::Crud,
+    transfer::{self, AccountWriter, Conflict, Format},
+    APP_PREFIX,
+};
+
+/// The file name that stands for stdin or stdout.
+const STDIO: &str = "-";
+
+fn read_cfg(matches: &ArgMatches) -> Cfg {
+    let config_path = ArgHandler::new(matches)
+        .next(Box::new(EnvHandler::new().prefix(APP_PREFIX).next(
+            Box::new(DefaultHandler::new(
+                &default_config_path().display().to_string(),
+            )),
+        )))
+        .handle_request("config");
+    let config_path = config_path.expect("No config path");
+    let mut cfg = Cfg::default();
+    super::read_storage_cfg(matches, &config_path, &mut cfg);
+    debug!("{}", cfg);
+    cfg
+}
+
+/// Loads accounts from a file, or stdin, into the configured store.
+pub struct ImportCommand {
+    cfg: Cfg,
+    input: PathBuf,
+    format: Option<Format>,
+    conflict: Conflict,
+    rejected: Option<PathBuf>,
+}
+
+impl ImportCommand {
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        ImportCommand {
+            cfg: read_cfg(matches),
+            input: PathBuf::from(matches.get_one::<String>("file").expect("No input file")),
+            format: matches.get_one::<Format>("format").copied(),
+            conflict: matches
+                .get_one::<Conflict>("on_conflict")
+                .copied()
+                .unwrap_or_default(),
+            rejected: matches.get_one::<String>("rejected").map(PathBuf::from),
+        }
+    }
+}
+
+impl Command for ImportCommand {
+    fn execute(&self) -> Result<(), Box<dyn FixmeError>> {
+        let mut input: Box<dyn BufRead> = if self.input == Path::new(STDIO) {
+            Box::new(std::io::stdin().lock())
+        } else {
+            Box::new(BufReader::new(File::open(&self.input)?))
+        };
+        let format = match self.format.or_else(|| Format::from_path(&self.input)) {
+            Some(format) => format,
+            None => Format::sniff(input.fill_buf()?),
+        };
+        debug!("Importing {} as {}", self.input.display(), format);
+
+        let memberships = super::open_membership_store(&self.cfg)?;
+        let mut store = super::open_account_store(&self.cfg, &memberships)?;
+        let caller = super::cli_caller(&self.cfg);
+        let rows = transfer::read_records(input, format)?;
+        let report = transfer::import(
+            &mut caller.guard(&mut *store),
+            rows,
+            self.conflict,
+            &self.cfg.validation,
+            self.cfg.id_version,
+        )?;
+        println!(
+            "{} created, {} updated, {} skipped, {} rejected",
+            report.created,
+            report.updated,
+            report.skipped,
+            report.rejected.len()
+        );
+        for rejected in &report.rejected {
+            eprintln!("Row {}: {}", rejected.row, rejected.reason);
+        }
+        if let Some(path) = &self.rejected {
+            report.write_rejected(File::create(path)?)?;
+        }
+        if let Some(row) = report.failed_at {
+            return Err(Box::new(std::io::Error::other(format!(
+                "Import stopped at row {}, the rows before it were imported",
+                row
+            ))));
+        }
+        Ok(())
+    }
+}
+
+/// Writes every account of the configured store to a file, or stdout.
+pub struct ExportCommand {
+    cfg: Cfg,
+    output: PathBuf,
+    format: Format,
+}
+
+impl ExportCommand {
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let output = PathBuf::from(
+            matches
+                .get_one::<String>("output")
+                .map(String::as_str)
+                .unwrap_or(STDIO),
+        );
+        let format = matches
+            .get_one::<Format>("format")
+            .copied()
+            .or_else(|| Format::from_path(&output))
+            .unwrap_or(Format::Csv);
+        ExportCommand {
+            cfg: read_cfg(matches),
+            output,
+            format,
+        }
+    }
+}
+
+impl Command for ExportCommand {
+    fn execute(&self) -> Result<(), Box<dyn FixmeError>> {
+        let memberships = super::open_membership_store(&self.cfg)?;
+        let mut store = super::open_account_store(&self.cfg, &memberships)?;
+        let caller = super::cli_caller(&self.cfg);
+        let store = caller.guard(&mut *store);
+        let output: Box<dyn Write> = if self.output == Path::new(STDIO) {
+            Box::new(std::io::stdout().lock())
+        } else {
+            Box::new(File::create(&self.output)?)
+        };
+        let mut writer = AccountWriter::new(BufWriter::new(output), self.format)?;
+        for account in store.iter()? {
+            writer.write(&account?)?;
+        }
+        writer.finish()?.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use std::io::{BufRead, IsTerminal, Write};
+
+use clap::ArgMatches;
+use cor_args::{ArgHandler, DefaultHandler, EnvHandler, Handler};
+use log::debug;
+
+use super::{Command, FixmeError};
+use crate::{
+    auth::{user::User, Role},
+    cfg::{default_config_path, Cfg},
+    crud::{self, Crud},
+    APP_PREFIX,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum UserAction {
+    Add { username: String, role: Role },
+    List,
+    Passwd { username: String },
+    SetRole { username: String, role: Role },
+    Remove { username: String },
+}
+
+/// Manages the operators who log in to the HTML pages.
+pub struct UserCommand {
+    cfg: Cfg,
+    action: UserAction,
+}
+
+fn invalid_input(message: String) -> Box<dyn FixmeError> {
+    Box::new(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        message,
+    ))
+}
+
+/// Asks for a new password twice on a terminal, or reads it from the first
+/// line of stdin otherwise.
+fn read_password() -> std::io::Result<String> {
+    if !std::io::stdin().is_terminal() {
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line)?;
+        return Ok(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+    let password = rpassword::prompt_password("Password: ")?;
+    if rpassword::prompt_password("Repeat password: ")? != password {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "The passwords do not match",
+        ));
+    }
+    Ok(password)
+}
+
+impl UserCommand {
+    pub fn new(cfg: Cfg, action: UserAction) -> Self {
+        UserCommand { cfg, action }
+    }
+
+    /// Builds the command from the matches of the `user` subcommand.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let config_path = ArgHandler::new(matches)
+            .next(Box::new(EnvHandler::new().prefix(APP_PREFIX).next(
+                Box::new(DefaultHandler::new(
+                    &default_config_path().display().to_string(),
+                )),
+            )))
+            .handle_request("config");
+        let config_path = config_path.expect("No config path");
+        let mut cfg = Cfg::default();
+        super::read_storage_cfg(matches, &config_path, &mut cfg);
+        debug!("{}", cfg);
+
+        let username = |m: &ArgMatches| {
+            m.get_one::<String>("username")
+                .expect("No username")
+                .clone()
+        };
+        let role = |m: &ArgMatches| *m.get_one::<Role>("role").expect("No role");
+        let action = match matches.subcommand() {
+            Some(("add", m)) => UserAction::Add {
+                username: username(m),
+                role: role(m),
+            },
+            Some(("list", _)) => UserAction::List,
+            Some(("passwd", m)) => UserAction::Passwd {
+                username: username(m),
+            },
+            Some(("role", m)) => UserAction::SetRole {
+                username: username(m),
+                role: role(m),
+            },
+            Some(("rm", m)) => UserAction::Remove {
+                username: username(m),
+            },
+            subcommand => unreachable!("Invalid user subcommand {:?}", subcommand),
+        };
+        UserCommand::new(cfg, action)
+    }
+
+    /// Runs the action against `store`, asking for passwords with
+    /// `password`.
+    fn run(
+        &self,
+        store: &mut dyn Crud<User>,
+        out: &mut dyn Write,
+        password: &mut dyn FnMut() -> std::io::Result<String>,
+    ) -> Result<(), Box<dyn FixmeError>> {
+        let find = |store: &dyn Crud<User>, username: &str| -> Result<User, Box<dyn FixmeError>> {
+            store
+                .read_all()?
+                .into_iter()
+                .find(|user| user.username == username)
+                .ok_or_else(|| {
+                    Box::new(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("No user is named \"{}\"", username),
+                    )) as Box<dyn FixmeError>
+                })
+        };
+        match &self.action {
+            UserAction::Add { username, role } => {
+                if find(store, username).is_ok() {
+                    return Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        format!("A user named \"{}\" already exists", username),
+                    )));
+                }
+                let user = User::new(username, &password()?, *role).map_err(invalid_input)?;
+                store.create(&user)?;
+                writeln!(out, "Added {} \"{}\"", role, username)?;
+            }
+            UserAction::List => {
+                writeln!(out, "{:<36}  {:<6}  USERNAME", "ID", "ROLE")?;
+                for user in store.read_all()? {
+                    let role = user.role.to_string();
+                    writeln!(out, "{:<36}  {:<6}  {}", user.id, role, user.username)?;
+                }
+            }
+            UserAction::Passwd { username } => {
+                let mut user = find(store, username)?;
+                user.set_password(&password()?).map_err(invalid_input)?;
+                store.update(&user)?;
+                writeln!(out, "Changed the password of \"{}\"", username)?;
+            }
+            UserAction::SetRole { username, role } => {
+                let mut user = find(store, username)?;
+                user.role = *role;
+                store.update(&user)?;
+                writeln!(out, "\"{}\" now has the {} role", username, role)?;
+            }
+            UserAction::Remove { username } => {
+                let user = find(store, username)?;
+                store.delete(&user)?;
+                writeln!(out, "Removed user \"{}\"", username)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Command for UserCommand {
+    fn execute(&self) -> Result<(), Box<dyn FixmeError>> {
+        let users = self.cfg.user_store();
+        let mut store = crud::open_user_store(users.storage, users.storage_path.as_deref())?;
+        self.run(
+            &mut store,
+            &mut std::io::stdout().lock(),
+            &mut read_password,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crud::memory::MemoryStore;
+
+    fn run(
+        store: &mut dyn Crud<User>,
+        action: UserAction,
+        password: &str,
+    ) -> Result<String, Box<dyn FixmeError>> {
+        let mut out = Vec::new();
+        let mut password = || Ok(password.to_string());
+        UserCommand::new(Cfg::default(), action).run(store, &mut out, &mut password)?;
+        Ok(String::from_utf8(out).expect("Output is not UTF-8"))
+    }
+
+    #[test]
+    fn users_are_added_listed_and_removed() {
+        let mut store = MemoryStore::<User>::new();
+        let add = UserAction::Add {
+            username: "admin".to_string(),
+            role: Role::Viewer,
+        };
+        assert!(run(&mut store, add.clone(), "short").is_err());
+        run(&mut store, add.clone(), "correct horse").expect("Failed to add user");
+        assert!(run(&mut store, add, "correct horse").is_err());
+
+        let list = run(&mut store, UserAction::List, "").expect("Failed to list users");
+        assert!(list.lines().nth(1).unwrap().ends_with("  viewer  admin"));
+        assert!(!list.contains("argon2"));
+
+        let passwd = UserAction::Passwd {
+            username: "admin".to_string(),
+        };
+        run(&mut store, passwd, "battery staple").expect("Failed to change password");
+        assert!(store.read_all().unwrap()[0].verify_password("battery staple"));
+
+        let promote = UserAction::SetRole {
+            username: "admin".to_string(),
+            role: Role::Admin,
+        };
+        run(&mut store, promote, "").expect("Failed to change role");
+        assert_eq!(store.read_all().unwrap()[0].role, Role::Admin);
+
+        let remove = UserAction::Remove {
+            username: "admin".to_string(),
+        };
+        run(&mut store, remove.clone(), "").expect("Failed to remove user");
+        assert!(store.read_all().unwrap().is_empty());
+        assert!(run(&mut store, remove, "").is_err());
+    }
+}
@@ -0,0 +1,319 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+use super::{Crud, Identifiable};
+
+/// Hit and miss counters of a [`CachedStore`].
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CacheStats {
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct Entry<V> {
+    value: V,
+    loaded: Instant,
+}
+
+impl<V> Entry<V> {
+    fn new(value: V) -> Self {
+        Entry {
+            value,
+            loaded: Instant::now(),
+        }
+    }
+}
+
+struct CacheState<T> {
+    all: Option<Entry<Vec<T>>>,
+    by_id: HashMap<Uuid, Entry<T>>,
+}
+
+/// A read-through [`Crud`] decorator that serves `read_all` and by-id reads
+/// from memory.
+///
+/// Entries expire after `ttl`. Any write through the cache drops the cached
+/// listing and the written item. At most `max_items` items are cached: a
+/// listing longer than that is not cached at all, and the oldest by-id entry
+/// is evicted to make room for a new one.
+pub struct CachedStore<T, S> {
+    inner: S,
+    ttl: Duration,
+    max_items: usize,
+    state: Mutex<CacheState<T>>,
+    stats: Arc<CacheStats>,
+}
+
+impl<T: Clone + Identifiable, S: Crud<T>> CachedStore<T, S> {
+    pub fn new(inner: S, ttl: Duration, max_items: usize) -> Self {
+        CachedStore {
+            inner,
+            ttl,
+            max_items,
+            state: Mutex::new(CacheState {
+                all: None,
+                by_id: HashMap::new(),
+            }),
+            stats: Arc::new(CacheStats::default()),
+        }
+    }
+
+    /// Returns a handle to the counters that stays valid after the store is
+    /// moved behind a trait object.
+    pub fn stats(&self) -> Arc<CacheStats> {
+        self.stats.clone()
+    }
+
+    fn is_fresh<V>(&self, entry: &Entry<V>) -> bool {
+        entry.loaded.elapsed() < self.ttl
+    }
+
+    fn invalidate(&self, id: &Uuid) {
+        let mut state = self.state.lock().unwrap();
+        state.all = None;
+        state.by_id.remove(id);
+    }
+}
+
+impl<T, S> Crud<T> for CachedStore<T, S>
+where
+    T: Clone + Identifiable + Send,
+    S: Crud<T>,
+{
+    fn create(&mut self, item: &T) -> super::Result<()> {
+        let result = self.inner.create(item);
+        self.invalidate(&item.id());
+        result
+    }
+
+    fn create_all(&mut self, items: &[T]) -> super::Result<Vec<super::Result<()>>> {
+        let results = self.inner.create_all(items);
+        for item in items {
+            self.invalidate(&item.id());
+        }
+        results
+    }
+
+    fn read_all(&self) -> super::Result<Vec<T>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(all) = state.all.as_ref().filter(|all| self.is_fresh(all)) {
+            self.stats.hit();
+            return Ok(all.value.clone());
+        }
+        self.stats.miss();
+        let items = self.inner.read_all()?;
+        state.all = if items.len() <= self.max_items {
+            Some(Entry::new(items.clone()))
+        } else {
+            None
+        };
+        Ok(items)
+    }
+
+    fn read(&self, id: &Uuid) -> super::Result<T> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.by_id.get(id).filter(|e| self.is_fresh(e)) {
+            self.stats.hit();
+            return Ok(entry.value.clone());
+        }
+        if let Some(all) = state.all.as_ref().filter(|all| self.is_fresh(all)) {
+            if let Some(item) = all.value.iter().find(|item| item.id() == *id) {
+                self.stats.hit();
+                return Ok(item.clone());
+            }
+        }
+        self.stats.miss();
+        let item = self.inner.read(id)?;
+        if self.max_items == 0 {
+            return Ok(item);
+        }
+        if state.by_id.len() >= self.max_items {
+            let ttl = self.ttl;
+            state.by_id.retain(|_, e| e.loaded.elapsed() < ttl);
+        }
+        if state.by_id.len() >= self.max_items {
+            let oldest = state
+                .by_id
+                .iter()
+                .min_by_key(|(_, e)| e.loaded)
+                .map(|(id, _)| *id);
+            if let Some(oldest) = oldest {
+                state.by_id.remove(&oldest);
+            }
+        }
+        state.by_id.insert(*id, Entry::new(item.clone()));
+        Ok(item)
+    }
+
+    fn iter(&self) -> super::Result<Box<dyn Iterator<Item = super::Result<T>> + Send>>
+    where
+        T: Send + 'static,
+    {
+        self.inner.iter()
+    }
+
+    fn update(&mut self, item: &T) -> super::Result<()> {
+        let result = self.inner.update(item);
+        self.invalidate(&item.id());
+        result
+    }
+
+    fn delete(&mut self, item: &T) -> super::Result<()> {
+        let result = self.inner.delete(item);
+        self.invalidate(&item.id());
+        result
+    }
+
+    fn in_memory(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use common::Account;
+
+    use super::*;
+    use crate::crud::memory::MemoryStore;
+
+    /// Counts the reads that reach the wrapped store.
+    #[derive(Default)]
+    struct Counting {
+        store: MemoryStore<Account>,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl Crud<Account> for Counting {
+        fn create(&mut self, item: &Account) -> crate::crud::Result<()> {
+            self.store.create(item)
+        }
+
+        fn read_all(&self) -> crate::crud::Result<Vec<Account>> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.store.read_all()
+        }
+
+        fn read(&self, id: &Uuid) -> crate::crud::Result<Account> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.store.read(id)
+        }
+
+        fn update(&mut self, item: &Account) -> crate::crud::Result<()> {
+            self.store.update(item)
+        }
+
+        fn delete(&mut self, item: &Account) -> crate::crud::Result<()> {
+            self.store.delete(item)
+        }
+    }
+
+    fn cached(
+        ttl: Duration,
+        max_items: usize,
+    ) -> (CachedStore<Account, Counting>, Arc<AtomicUsize>) {
+        let inner = Counting::default();
+        let reads = inner.reads.clone();
+        (CachedStore::new(inner, ttl, max_items), reads)
+    }
+
+    #[test]
+    fn read_all_is_served_from_cache() {
+        let (mut store, reads) = cached(Duration::from_secs(60), 10);
+        store
+            .create(&Account::new("Test Account"))
+            .expect("Failed to create Account");
+        assert_eq!(store.read_all().unwrap().len(), 1);
+        assert_eq!(store.read_all().unwrap().len(), 1);
+        assert_eq!(reads.load(Ordering::Relaxed), 1);
+        assert_eq!((store.stats().hits(), store.stats().misses()), (1, 1));
+    }
+
+    #[test]
+    fn read_by_id_uses_cached_listing() {
+        let (mut store, reads) = cached(Duration::from_secs(60), 10);
+        let account = Account::new("Test Account");
+        store.create(&account).expect("Failed to create Account");
+        store.read_all().unwrap();
+        assert_eq!(store.read(&account.id).unwrap(), account);
+        assert_eq!(reads.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn writes_invalidate_cache() {
+        let (mut store, reads) = cached(Duration::from_secs(60), 10);
+        let mut account = Account::new("Test Account");
+        store.create(&account).expect("Failed to create Account");
+        assert_eq!(store.read(&account.id).unwrap().fullname, "Test Account");
+        account.fullname = "Modified Account".to_string();
+        store.update(&account).expect("Failed to update Account");
+        assert_eq!(
+            store.read(&account.id).unwrap().fullname,
+            "Modified Account"
+        );
+        assert_eq!(reads.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn expired_entries_are_reloaded() {
+        let (store, reads) = cached(Duration::ZERO, 10);
+        store.read_all().unwrap();
+        store.read_all().unwrap();
+        assert_eq!(reads.load(Ordering::Relaxed), 2);
+        assert_eq!(store.stats().hits(), 0);
+    }
+
+    #[test]
+    fn listing_longer_than_max_items_is_not_cached() {
+        let (mut store, reads) = cached(Duration::from_secs(60), 1);
+        for name in ["Test Account 1", "Test Account 2"] {
+            store
+                .create(&Account::new(name))
+                .expect("Failed to create Account");
+        }
+        store.read_all().unwrap();
+        store.read_all().unwrap();
+        assert_eq!(reads.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn oldest_item_is_evicted_when_full() {
+        let (mut store, reads) = cached(Duration::from_secs(60), 1);
+        let first = Account::new("Test Account 1");
+        let second = Account::new("Test Account 2");
+        store.create(&first).expect("Failed to create Account");
+        store.create(&second).expect("Failed to create Account");
+        store.read(&first.id).unwrap();
+        store.read(&second.id).unwrap();
+        store.read(&second.id).unwrap();
+        store.read(&first.id).unwrap();
+        assert_eq!(reads.load(Ordering::Relaxed), 3);
+    }
+}
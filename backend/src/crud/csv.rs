@@ -1,182 +1,418 @@
 use std::{
+    collections::HashSet,
     fs::{File, OpenOptions},
-    io::{BufRead, BufReader, Seek, Write},
-    path::Path,
+    io::{BufRead, BufReader, Write},
+    marker::PhantomData,
 };
 
-use common::Account;
+use uuid::Uuid;
 
-use super::{Crud, CrudError};
+use super::{
+    membership::{Membership, MembershipStore, Side},
+    Crud, CrudError, Entity,
+};
+use crate::auth::{user::User, Role};
 
-struct CsvAccount {
-    line: u64,
-    account: Account,
+/// Keeps `T`s in a CSV file without a header row, one `id,<fields>` record
+/// each, quoting the fields that need it. Lines written before fields were
+/// quoted, such as `<id>,Schroeter, Erich`, read as they were meant: the
+/// last field takes the rest of a record with too many values, and the
+/// first field takes all of a record with too few, as it was the only one
+/// when such lines were written. Lines without an id, written before ids
+/// were stored, hold only the fields and are given one derived from their
+/// line number and content, so they read the same until they are written
+/// back with it.
+#[derive(Debug, Clone)]
+pub struct CsvStore<T> {
+    filename: String,
+    entity: PhantomData<fn() -> T>,
 }
 
-impl From<String> for CsvAccount {
-    fn from(value: String) -> Self {
-        CsvAccount {
-            line: 0,
-            account: Account::new(&value),
+impl<T: Entity> CsvStore<T> {
+    pub fn new(filename: &str) -> Self {
+        CsvStore {
+            filename: filename.to_string(),
+            entity: PhantomData,
+        }
+    }
+
+    /// Opens the file for reading, or returns `None` if there is none yet.
+    fn reader(&self) -> super::Result<Option<csv::Reader<File>>> {
+        match File::open(&self.filename) {
+            Ok(file) => Ok(Some(
+                csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .flexible(true)
+                    .from_reader(file),
+            )),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
         }
     }
+
+    /// Reads every item to write them back, failing on an unreadable line
+    /// rather than dropping it from the file.
+    fn read_to_write(&self) -> super::Result<Vec<T>> {
+        let Some(reader) = self.reader()? else {
+            return Ok(Vec::new());
+        };
+        reader
+            .into_records()
+            .map(|record| {
+                let record = record.map_err(std::io::Error::from)?;
+                Ok(from_record(&record))
+            })
+            .collect()
+    }
+
+    /// Replaces the file with `items`, so readers that opened it keep the
+    /// old content and it is never seen half written.
+    fn write_all(&self, items: &[T]) -> super::Result<()> {
+        let tempfile_path = format!("{}.tmp", self.filename);
+        let mut tempfile = File::create(&tempfile_path)?;
+        for item in items {
+            tempfile.write_all(&record_of(item)?)?;
+        }
+        tempfile.sync_all()?;
+        std::fs::rename(&tempfile_path, &self.filename)?;
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct CsvAccountStore {
-    filename: String,
+/// The CSV line of `item`, ending in a newline.
+fn record_of<T: Entity>(item: &T) -> super::Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    let id = item.id().to_string();
+    let values = item.values();
+    writer
+        .write_record(std::iter::once(&id).chain(&values))
+        .map_err(std::io::Error::from)?;
+    writer
+        .into_inner()
+        .map_err(|err| CrudError::IO(err.into_error()))
 }
 
-impl CsvAccountStore {
-    pub fn new(filename: &str) -> Self {
-        CsvAccountStore {
-            filename: filename.to_string(),
-        }
+/// The namespace of the ids of lines written without one.
+const LEGACY_IDS: Uuid = uuid::uuid!("a0573edc-3f9b-41db-b77b-6c9809d4dd01");
+
+/// The id of a line written without one, the same on every read.
+fn legacy_id(record: &csv::StringRecord) -> Uuid {
+    let line = record.position().map_or(0, |position| position.line());
+    let content = record.iter().collect::<Vec<_>>().join(",");
+    Uuid::new_v5(&LEGACY_IDS, format!("{}:{}", line, content).as_bytes())
+}
+
+fn from_record<T: Entity>(record: &csv::StringRecord) -> T {
+    let first = record.get(0).unwrap_or_default();
+    let (id, mut values): (Uuid, Vec<String>) = match Uuid::parse_str(first) {
+        Ok(id) => (id, record.iter().skip(1).map(str::to_string).collect()),
+        Err(_) => (
+            legacy_id(record),
+            record.iter().map(str::to_string).collect(),
+        ),
+    };
+    let count = T::FIELDS.len().max(1);
+    if values.len() > count {
+        let rest = values.split_off(count - 1).join(",");
+        values.push(rest);
+    } else if values.len() < count {
+        values = vec![values.join(",")];
     }
+    T::from_values(id, values)
 }
 
-impl Crud<Account> for CsvAccountStore {
-    fn create(&mut self, account: &Account) -> super::Result<()> {
-        let mut file = if Path::new(&self.filename).exists() {
+impl<T: Entity> Crud<T> for CsvStore<T> {
+    fn create(&mut self, item: &T) -> super::Result<()> {
+        if self.read(&item.id()).is_ok() {
+            return Err(CrudError::AlreadyExists);
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.filename)?;
+        file.write_all(&record_of(item)?)?;
+        Ok(())
+    }
+
+    /// Reads the file once to check the ids of `items`, then appends them
+    /// all at once.
+    fn create_all(&mut self, items: &[T]) -> super::Result<Vec<super::Result<()>>> {
+        let mut ids = self
+            .iter()?
+            .map(|item| item.map(|item| item.id()))
+            .collect::<super::Result<HashSet<Uuid>>>()?;
+        let mut records = Vec::new();
+        let mut results = Vec::with_capacity(items.len());
+        for item in items {
+            if ids.insert(item.id()) {
+                records.extend(record_of(item)?);
+                results.push(Ok(()));
+            } else {
+                results.push(Err(CrudError::AlreadyExists));
+            }
+        }
+        if !records.is_empty() {
             OpenOptions::new()
-                .write(true)
+                .create(true)
                 .append(true)
-                .open(&self.filename)
-                .unwrap()
-        } else {
-            OpenOptions::new()
-                .create_new(true)
-                .write(true)
-                .open(&self.filename)
-                .unwrap()
-        };
-        if let Err(_) = writeln!(file, "{}", account.fullname) {
-            Err(CrudError::UnknownError)
-        } else {
-            Ok(())
+                .open(&self.filename)?
+                .write_all(&records)?;
         }
+        Ok(results)
     }
 
-    fn read_all(&self) -> super::Result<Vec<Account>> {
-        log::debug!("Reading Users from '{}'", &self.filename);
-        let file = if Path::new(&self.filename).exists() {
-            OpenOptions::new().read(true).open(&self.filename).unwrap()
-        } else {
-            return Ok(Vec::new());
+    fn read_all(&self) -> super::Result<Vec<T>> {
+        log::debug!("Reading {} from '{}'", T::PLURAL, &self.filename);
+        let items = self.iter()?.collect::<super::Result<Vec<_>>>()?;
+        log::debug!(
+            "Read {} {} from '{}'",
+            items.len(),
+            T::PLURAL,
+            &self.filename
+        );
+        Ok(items)
+    }
+
+    /// Reads the file record by record, skipping the lines that are not
+    /// UTF-8 as the store always has. Updates and deletes replace the file
+    /// rather than rewriting it, so the iterator keeps reading the items as
+    /// they were when it was created.
+    fn iter(&self) -> super::Result<Box<dyn Iterator<Item = super::Result<T>> + Send>> {
+        let Some(reader) = self.reader()? else {
+            return Ok(Box::new(std::iter::empty()));
         };
+        let filename = self.filename.clone();
+        Ok(Box::new(reader.into_records().filter_map(
+            move |record| match record {
+                Ok(record) => Some(Ok(from_record(&record))),
+                Err(err) if matches!(err.kind(), csv::ErrorKind::Utf8 { .. }) => {
+                    log::warn!("Skipping an unreadable line of '{}': {}", filename, err);
+                    None
+                }
+                Err(err) => Some(Err(std::io::Error::from(err).into())),
+            },
+        )))
+    }
 
-        let reader = BufReader::new(file);
-        let mut accounts = Vec::new();
+    fn update(&mut self, item: &T) -> super::Result<()> {
+        let mut items = self.read_to_write()?;
+        let existing = items
+            .iter_mut()
+            .find(|existing| existing.id() == item.id())
+            .ok_or(CrudError::NotFound)?;
+        *existing = item.clone();
+        self.write_all(&items)?;
+        log::debug!("Updated id='{}' in {}", item.id(), self.filename);
+        Ok(())
+    }
 
-        for line in reader.lines() {
-            if let Ok(fullname) = line {
-                accounts.push(Account::new(&fullname));
-            }
+    fn delete(&mut self, item: &T) -> super::Result<()> {
+        let mut items = self.read_to_write()?;
+        items.retain(|existing| existing.id() != item.id());
+        self.write_all(&items)
+    }
+}
+
+/// Keeps the operators who log in to the web server, one
+/// `id,username,role,password_hash` line each. Usernames hold no commas,
+/// while the hashes do, so the hash is everything after the third comma.
+/// Lines without a role, written before there were roles, are admins.
+#[derive(Debug, Clone)]
+pub struct CsvUserStore {
+    filename: String,
+}
+
+impl CsvUserStore {
+    pub fn new(filename: &str) -> Self {
+        CsvUserStore {
+            filename: filename.to_string(),
         }
-        log::debug!("Read {} Users from '{}'", accounts.len(), &self.filename);
-        Ok(accounts)
     }
-    
-    fn update(&mut self, item: &Account) -> super::Result<()> {
-        let item = update_line(&self.filename, item)?;
-        log::debug!("Updated line {} in {}", item.line, self.filename);
+
+    /// Replaces the file with `users`, so it is never seen half written.
+    fn write_all(&self, users: &[User]) -> super::Result<()> {
+        let tempfile_path = format!("{}.tmp", self.filename);
+        let mut tempfile = File::create(&tempfile_path)?;
+        for user in users {
+            writeln!(
+                tempfile,
+                "{},{},{},{}",
+                user.id, user.username, user.role, user.password_hash
+            )?;
+        }
+        tempfile.sync_all()?;
+        std::fs::rename(&tempfile_path, &self.filename)?;
         Ok(())
     }
+}
+
+fn user_from_line(line: &str) -> super::Result<User> {
+    let invalid = || {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid user line {:?}", line),
+        )
+    };
+    let mut fields = line.splitn(3, ',');
+    let (Some(id), Some(username), Some(rest)) = (fields.next(), fields.next(), fields.next())
+    else {
+        return Err(invalid().into());
+    };
+    // PHC strings start with `$`, roles never do.
+    let (role, password_hash) = match rest.starts_with('$') {
+        true => (Role::Admin, rest),
+        false => {
+            let (role, password_hash) = rest.split_once(',').ok_or_else(invalid)?;
+            (role.parse().map_err(|_| invalid())?, password_hash)
+        }
+    };
+    Ok(User {
+        id: Uuid::parse_str(id).map_err(std::io::Error::other)?,
+        username: username.to_string(),
+        role,
+        password_hash: password_hash.to_string(),
+    })
+}
+
+impl Crud<User> for CsvUserStore {
+    fn create(&mut self, user: &User) -> super::Result<()> {
+        let mut users = self.read_all()?;
+        if users
+            .iter()
+            .any(|u| u.id == user.id || u.username == user.username)
+        {
+            return Err(CrudError::AlreadyExists);
+        }
+        users.push(user.clone());
+        self.write_all(&users)
+    }
+
+    fn read_all(&self) -> super::Result<Vec<User>> {
+        let file = match File::open(&self.filename) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        BufReader::new(file)
+            .lines()
+            .map(|line| user_from_line(&line?))
+            .collect()
+    }
 
-    fn delete(&mut self, item: &Account) -> super::Result<()> {
-        delete_line(&self.filename, &item)
+    fn update(&mut self, user: &User) -> super::Result<()> {
+        let mut users = self.read_all()?;
+        let existing = users
+            .iter_mut()
+            .find(|u| u.id == user.id)
+            .ok_or(CrudError::NotFound)?;
+        *existing = user.clone();
+        self.write_all(&users)
     }
+
+    fn delete(&mut self, user: &User) -> super::Result<()> {
+        let mut users = self.read_all()?;
+        users.retain(|u| u.id != user.id);
+        self.write_all(&users)
+    }
+}
+
+/// Keeps the memberships, one `group_id,account_id` line each.
+pub struct CsvMembershipStore {
+    filename: String,
 }
 
-// fn find_entry(path: &str, uuid: Uuid) -> super::Result<CsvAccount> {
-//     let file = File::open(path)?;
-//     let reader = BufReader::new(&file);
-//     let mut line_count = 0;
-//     for line in reader.lines() {
-//         let line = line?;
-//         if line.contains(&uuid.to_string()) {
-//             return Ok(CsvAccount {
-//                 line: line_count,
-//                 account: Account::new(&line),
-//             });
-//         }
-//         line_count += 1;
-//     }
-//     Err(CrudError::NotFound)
-// }
-
-fn update_line(path: &str, account: &Account) -> super::Result<CsvAccount> {
-    // Open file in read mode
-    let file = File::open(path)?;
-    let reader = BufReader::new(&file);
-    // Create temp file to write modified content
-    let tempfile_path = format!("{}.tmp", path);
-    let mut tempfile = File::create(&tempfile_path)?;
-    // Iterate line by line, skipping the line that matches the username
-    let mut line_count = 0;
-    let mut line_updated = 0;
-    for line in reader.lines() {
-        let line = line?;
-        if !line.contains(&account.id.to_string()) {
-            writeln!(tempfile, "{}", line)?;
-        } else {
-            writeln!(tempfile, "{}", account.to_csv())?;
-            line_updated = line_count;
-            log::debug!("Updated line {}", line_count)
+impl CsvMembershipStore {
+    pub fn new(filename: &str) -> Self {
+        CsvMembershipStore {
+            filename: filename.to_string(),
+        }
+    }
+
+    /// Replaces the file with `memberships`, so it is never seen half
+    /// written.
+    fn write_all(&self, memberships: &[Membership]) -> super::Result<()> {
+        let tempfile_path = format!("{}.tmp", self.filename);
+        let mut tempfile = File::create(&tempfile_path)?;
+        for membership in memberships {
+            writeln!(tempfile, "{},{}", membership.group, membership.account)?;
         }
-        line_count += 1;
-    }
-    // Truncate the original file and copy the modified content from temp file
-    let mut file = File::create(path)?;
-    file.seek(std::io::SeekFrom::Start(0))?;
-    std::io::copy(&mut File::open(&tempfile_path)?, &mut file)?;
-    std::fs::remove_file(&tempfile_path)?;
-    Ok(CsvAccount {
-        line: line_updated,
-        account: account.to_owned(),
+        tempfile.sync_all()?;
+        std::fs::rename(&tempfile_path, &self.filename)?;
+        Ok(())
+    }
+}
+
+fn membership_from_line(line: &str) -> super::Result<Membership> {
+    let invalid = || {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid membership line {:?}", line),
+        )
+    };
+    let (group, account) = line.split_once(',').ok_or_else(invalid)?;
+    Ok(Membership {
+        group: Uuid::parse_str(group).map_err(|_| invalid())?,
+        account: Uuid::parse_str(account).map_err(|_| invalid())?,
     })
 }
 
-fn delete_line(path: &str, account: &Account) -> super::Result<()> {
-    // Open file in read mode
-    let file = File::open(path)?;
-    let reader = BufReader::new(&file);
-    // Create temp file to write modified content
-    let tempfile_path = format!("{}.tmp", path);
-    let mut tempfile = File::create(&tempfile_path)?;
-    // Iterate line by line, skipping the line that matches the uuid
-    let mut line_count = 0;
-    for line in reader.lines() {
-        let line = line?;
-        if !line.contains(&account.id.to_string()) {
-            writeln!(tempfile, "{}", line)?;
-        } else {
-            log::debug!("Deleted line {}", line_count)
+impl MembershipStore for CsvMembershipStore {
+    fn read_all(&self) -> super::Result<Vec<Membership>> {
+        let file = match File::open(&self.filename) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        BufReader::new(file)
+            .lines()
+            .map(|line| membership_from_line(&line?))
+            .collect()
+    }
+
+    fn add(&mut self, membership: &Membership) -> super::Result<()> {
+        if self.read_all()?.contains(membership) {
+            return Err(CrudError::AlreadyExists);
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.filename)?;
+        writeln!(file, "{},{}", membership.group, membership.account)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, membership: &Membership) -> super::Result<()> {
+        let mut memberships = self.read_all()?;
+        let count = memberships.len();
+        memberships.retain(|existing| existing != membership);
+        if memberships.len() == count {
+            return Err(CrudError::NotFound);
         }
-        line_count += 1;
-    }
-    // Truncate the original file and copy the modified content from temp file
-    let mut file = File::create(path)?;
-    file.seek(std::io::SeekFrom::Start(0))?;
-    std::io::copy(&mut File::open(&tempfile_path)?, &mut file)?;
-    std::fs::remove_file(&tempfile_path)?;
-    Ok(())
+        self.write_all(&memberships)
+    }
+
+    fn remove_all(&mut self, side: Side, id: &Uuid) -> super::Result<()> {
+        let mut memberships = self.read_all()?;
+        memberships.retain(|membership| membership.id_of(side) != *id);
+        self.write_all(&memberships)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::fs::File;
 
+    use common::{Account, Group};
     use tempfile::tempdir;
     use uuid::uuid;
 
-    use super::CsvAccountStore;
-
     use super::*;
 
     fn count_lines(path: &str) -> std::io::Result<usize> {
         let mut count = 0;
-        for line in std::io::BufReader::new(std::fs::File::open(&path)?).lines() {
+        for line in std::io::BufReader::new(std::fs::File::open(path)?).lines() {
             line?;
             count += 1;
         }
@@ -185,7 +421,7 @@ mod tests {
 
     fn read_line(path: &str, line_no: usize) ->std::io::Result<String> {
         let mut count = 0;
-        for line in std::io::BufReader::new(std::fs::File::open(&path)?).lines() {
+        for line in std::io::BufReader::new(std::fs::File::open(path)?).lines() {
             let line = line?;
             if count == line_no {
                 return Ok(line)
@@ -199,18 +435,40 @@ mod tests {
     fn create_creates_file_when_not_exist() {
         let dir = tempdir().expect("Failed to create temp directory");
         let csv = dir.path().join("accounts.csv");
-        let mut store = CsvAccountStore::new(csv.display().to_string().as_str());
+        let mut store = CsvStore::<Account>::new(csv.display().to_string().as_str());
         store
             .create(&Account::new("Test Account"))
             .expect("Failed to create new Account");
         assert!(csv.exists());
     }
 
+    #[test]
+    fn create_all_appends_the_items_with_new_ids() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let csv = dir.path().join("accounts.csv");
+        let mut store = CsvStore::<Account>::new(csv.display().to_string().as_str());
+        let erich = Account::new("Erich Schroeter");
+        store.create(&erich).expect("Failed to create Account");
+        let jane = Account::new("Jane Doe");
+        let results = store
+            .create_all(&[erich.clone(), jane.clone(), jane.clone()])
+            .expect("Failed to create Accounts");
+        assert!(matches!(
+            results.as_slice(),
+            [
+                Err(CrudError::AlreadyExists),
+                Ok(()),
+                Err(CrudError::AlreadyExists)
+            ]
+        ));
+        assert_eq!(store.read_all().unwrap(), vec![erich, jane]);
+    }
+
     #[test]
     fn read_all_does_not_create_file_when_not_exist() {
         let dir = tempdir().expect("Failed to create temp directory");
         let csv = dir.path().join("accounts.csv");
-        let store = CsvAccountStore::new(csv.display().to_string().as_str());
+        let store = CsvStore::<Account>::new(csv.display().to_string().as_str());
         store.read_all().expect("Failed to read Users");
         assert!(!csv.exists());
     }
@@ -219,7 +477,7 @@ mod tests {
     fn read_all_returns_none_when_file_not_exist() {
         let dir = tempdir().expect("Failed to create temp directory");
         let csv = dir.path().join("accounts.csv");
-        let store = CsvAccountStore::new(csv.display().to_string().as_str());
+        let store = CsvStore::<Account>::new(csv.display().to_string().as_str());
         let accounts = store.read_all().expect("Failed to read Users");
         assert_eq!(accounts.len(), 0);
     }
@@ -228,59 +486,119 @@ mod tests {
     fn read_all_returns_one_of_one() {
         let dir = tempdir().expect("Failed to create temp directory");
         let csv_path = dir.path().join("accounts.csv");
-        let mut csv =
-            File::create(&csv_path).expect(&format!("Failed to create {}", &csv_path.display()));
-        writeln!(csv, "Test Account").expect(&format!("Failed to write to {}", &csv_path.display()));
-        let store = CsvAccountStore::new(csv_path.display().to_string().as_str());
+        let mut csv = File::create(&csv_path)
+            .unwrap_or_else(|_| panic!("Failed to create {}", &csv_path.display()));
+        writeln!(csv, "Test Account")
+            .unwrap_or_else(|_| panic!("Failed to write to {}", &csv_path.display()));
+        let store = CsvStore::<Account>::new(csv_path.display().to_string().as_str());
         let accounts = store.read_all().expect("Failed to read Users");
         assert_eq!(accounts.len(), 1);
-        assert_eq!(accounts.get(0).unwrap().fullname, "Test Account");
+        assert_eq!(accounts.first().unwrap().fullname, "Test Account");
+        assert_eq!(store.read_all().expect("Failed to read Users"), accounts);
+    }
+
+    #[test]
+    fn read_all_skips_unreadable_lines() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let csv_path = dir.path().join("accounts.csv");
+        std::fs::write(
+            &csv_path,
+            b"Test Account 1\nTest \xff Account\nTest Account 2\n",
+        )
+        .expect("Failed to write accounts");
+        let mut store = CsvStore::<Account>::new(csv_path.display().to_string().as_str());
+        let accounts = store.read_all().expect("Failed to read Users");
+        let names: Vec<_> = accounts.iter().map(|a| a.fullname.as_str()).collect();
+        assert_eq!(names, ["Test Account 1", "Test Account 2"]);
+        assert!(store.delete(&accounts[0]).is_err());
+        let content = std::fs::read(&csv_path).expect("Failed to read accounts");
+        assert_eq!(content.iter().filter(|b| **b == b'\n').count(), 3);
+    }
+
+    #[test]
+    fn read_all_returns_created_id() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let csv = dir.path().join("accounts.csv");
+        let mut store = CsvStore::<Account>::new(csv.display().to_string().as_str());
+        let account = Account::new("Test, Account");
+        store
+            .create(&account)
+            .expect("Failed to create new Account");
+        let accounts = store.read_all().expect("Failed to read Users");
+        assert_eq!(accounts, vec![account]);
     }
 
     #[test]
     fn update_one_of_one() {
         let dir = tempdir().expect("Failed to create temp directory");
         let csv_path = dir.path().join("accounts.csv");
-        let mut csv =
-            File::create(&csv_path).expect(&format!("Failed to create {}", &csv_path.display()));
-        writeln!(csv, "67e55044-10b1-426f-9247-bb680e5fe0c8,Test Account").expect(&format!("Failed to write to {}", &csv_path.display()));
-        let mut store = CsvAccountStore::new(csv_path.display().to_string().as_str());
+        let mut csv = File::create(&csv_path)
+            .unwrap_or_else(|_| panic!("Failed to create {}", &csv_path.display()));
+        writeln!(csv, "67e55044-10b1-426f-9247-bb680e5fe0c8,Test Account")
+            .unwrap_or_else(|_| panic!("Failed to write to {}", &csv_path.display()));
+        let mut store = CsvStore::<Account>::new(csv_path.display().to_string().as_str());
         let mut user_updated = Account::new("Modified Account");
         user_updated.id = uuid::uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8");
-        store.update(&user_updated).expect("Failed to update Account");
-        let actual_line = read_line(csv_path.display().to_string().as_str(), 0).expect(&format!("Failed to read line 0 from {}", &csv_path.display()));
-        assert_eq!(actual_line, "67e55044-10b1-426f-9247-bb680e5fe0c8,Modified Account");
+        store
+            .update(&user_updated)
+            .expect("Failed to update Account");
+        let actual_line = read_line(csv_path.display().to_string().as_str(), 0)
+            .unwrap_or_else(|_| panic!("Failed to read line 0 from {}", &csv_path.display()));
+        assert_eq!(
+            actual_line,
+            "67e55044-10b1-426f-9247-bb680e5fe0c8,Modified Account,,active,,"
+        );
     }
 
     #[test]
     fn update_one_of_two() {
         let dir = tempdir().expect("Failed to create temp directory");
         let csv_path = dir.path().join("accounts.csv");
-        let mut csv =
-            File::create(&csv_path).expect(&format!("Failed to create {}", &csv_path.display()));
-        writeln!(csv, "67e55044-10b1-426f-9247-bb680e5fe0c8,Test Account 1\n67e55044-10b1-426f-9247-bb680e5fe0c9,Test Account 2").expect(&format!("Failed to write to {}", &csv_path.display()));
-        let mut store = CsvAccountStore::new(csv_path.display().to_string().as_str());
+        let mut csv = File::create(&csv_path)
+            .unwrap_or_else(|_| panic!("Failed to create {}", &csv_path.display()));
+        writeln!(csv, "67e55044-10b1-426f-9247-bb680e5fe0c8,Test Account 1\n67e55044-10b1-426f-9247-bb680e5fe0c9,Test Account 2").unwrap_or_else(|_| panic!("Failed to write to {}", &csv_path.display()));
+        let mut store = CsvStore::<Account>::new(csv_path.display().to_string().as_str());
         let mut user_updated = Account::new("Modified Account 1");
         user_updated.id = uuid::uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8");
-        store.update(&user_updated).expect("Failed to update Account");
-        let actual_line = read_line(csv_path.display().to_string().as_str(), 0).expect(&format!("Failed to read line 0 from {}", &csv_path.display()));
-        assert_eq!(actual_line, "67e55044-10b1-426f-9247-bb680e5fe0c8,Modified Account 1");
+        store
+            .update(&user_updated)
+            .expect("Failed to update Account");
+        let actual_line = read_line(csv_path.display().to_string().as_str(), 0)
+            .unwrap_or_else(|_| panic!("Failed to read line 0 from {}", &csv_path.display()));
+        assert_eq!(
+            actual_line,
+            "67e55044-10b1-426f-9247-bb680e5fe0c8,Modified Account 1,,active,,"
+        );
+    }
+
+    #[test]
+    fn update_missing_item_is_not_found() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let csv = dir.path().join("accounts.csv");
+        let mut store = CsvStore::<Account>::new(csv.display().to_string().as_str());
+        store
+            .create(&Account::new("Test Account 1"))
+            .expect("Failed to create Account");
+        let missing = Account::new("Test Account 2");
+        assert!(matches!(store.update(&missing), Err(CrudError::NotFound)));
+        assert_eq!(store.read_all().expect("Failed to read Accounts").len(), 1);
     }
 
     #[test]
     fn delete_one_of_one() {
         let dir = tempdir().expect("Failed to create temp directory");
         let csv_path = dir.path().join("accounts.csv");
-        let mut csv =
-            File::create(&csv_path).expect(&format!("Failed to create {}", &csv_path.display()));
-        writeln!(csv, "67e55044-10b1-426f-9247-bb680e5fe0c8,Test Account").expect(&format!("Failed to write to {}", &csv_path.display()));
-        let mut store = CsvAccountStore::new(csv_path.display().to_string().as_str());
+        let mut csv = File::create(&csv_path)
+            .unwrap_or_else(|_| panic!("Failed to create {}", &csv_path.display()));
+        writeln!(csv, "67e55044-10b1-426f-9247-bb680e5fe0c8,Test Account")
+            .unwrap_or_else(|_| panic!("Failed to write to {}", &csv_path.display()));
+        let mut store = CsvStore::<Account>::new(csv_path.display().to_string().as_str());
         let mut account = Account::new("Test Account");
         account.id = uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8");
         store.delete(&account).expect("Failed to delete Account");
         assert_eq!(
             count_lines(csv_path.display().to_string().as_str())
-                .expect(&format!("Failed to count lines of {}", &csv_path.display())),
+                .unwrap_or_else(|_| panic!("Failed to count lines of {}", &csv_path.display())),
             0
         );
     }
@@ -289,18 +607,198 @@ mod tests {
     fn delete_one_of_two() {
         let dir = tempdir().expect("Failed to create temp directory");
         let csv_path = dir.path().join("accounts.csv");
-        let mut csv =
-            File::create(&csv_path).expect(&format!("Failed to create {}", &csv_path.display()));
+        let mut csv = File::create(&csv_path)
+            .unwrap_or_else(|_| panic!("Failed to create {}", &csv_path.display()));
         writeln!(csv, "67e55044-10b1-426f-9247-bb680e5fe0c8,Test Account 1\n67e55044-10b1-426f-9247-bb680e5fe0c9,Test Account 2")
-            .expect(&format!("Failed to write to {}", &csv_path.display()));
-        let mut store = CsvAccountStore::new(csv_path.display().to_string().as_str());
+            .unwrap_or_else(|_| panic!("Failed to write to {}", &csv_path.display()));
+        let mut store = CsvStore::<Account>::new(csv_path.display().to_string().as_str());
         let mut account = Account::new("Test Account 1");
         account.id = uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8");
         store.delete(&account).expect("Failed to delete Account");
         assert_eq!(
             count_lines(csv_path.display().to_string().as_str())
-                .expect(&format!("Failed to count lines of {}", &csv_path.display())),
+                .unwrap_or_else(|_| panic!("Failed to count lines of {}", &csv_path.display())),
+            1
+        );
+    }
+
+    #[test]
+    fn iter_reads_accounts_as_they_were_when_created() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let csv_path = dir.path().join("accounts.csv");
+        let mut store = CsvStore::<Account>::new(csv_path.display().to_string().as_str());
+        let first = Account::new("Test Account 1");
+        let second = Account::new("Test Account 2");
+        store.create(&first).expect("Failed to create Account");
+        store.create(&second).expect("Failed to create Account");
+        let iter = store.iter().expect("Failed to iterate Accounts");
+        store.delete(&first).expect("Failed to delete Account");
+        let accounts = iter
+            .collect::<super::super::Result<Vec<_>>>()
+            .expect("Failed to read Accounts");
+        assert_eq!(accounts, vec![first, second]);
+        assert_eq!(store.read_all().expect("Failed to read Accounts").len(), 1);
+    }
+
+    #[test]
+    fn create_with_taken_id_already_exists() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let csv_path = dir.path().join("accounts.csv");
+        let mut store = CsvStore::<Account>::new(csv_path.display().to_string().as_str());
+        let account = Account::new("Test Account");
+        store.create(&account).expect("Failed to create Account");
+        assert!(matches!(
+            store.create(&account),
+            Err(CrudError::AlreadyExists)
+        ));
+        assert_eq!(
+            count_lines(csv_path.display().to_string().as_str()).unwrap(),
             1
         );
     }
+
+    #[test]
+    fn unquoted_commas_belong_to_the_last_field() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let csv_path = dir.path().join("accounts.csv");
+        std::fs::write(
+            &csv_path,
+            "67e55044-10b1-426f-9247-bb680e5fe0c8,Schroeter, Erich\n",
+        )
+        .expect("Failed to write accounts");
+        let store = CsvStore::<Account>::new(csv_path.display().to_string().as_str());
+        let accounts = store.read_all().expect("Failed to read Accounts");
+        assert_eq!(accounts[0].fullname, "Schroeter, Erich");
+    }
+
+    #[test]
+    fn accounts_are_stored_with_encoded_tags_and_attributes() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let csv_path = dir.path().join("accounts.csv");
+        let path = csv_path.display().to_string();
+        let mut store = CsvStore::<Account>::new(&path);
+        let mut account = Account::new("Schroeter, Erich");
+        account.email = Some("erich@example.com".to_string());
+        account.status = common::AccountStatus::Suspended;
+        account.tags = vec!["staff".to_string(), "emea, north".to_string()];
+        account
+            .attributes
+            .insert("vip".to_string(), common::Attribute::Bool(true));
+        store.create(&account).expect("Failed to create Account");
+        store
+            .create(&Account::new("Jane Doe"))
+            .expect("Failed to create Account");
+        assert_eq!(
+            read_line(&path, 0).unwrap(),
+            format!(
+                "{},\"Schroeter, Erich\",erich@example.com,suspended,\
+                 \"[\"\"staff\"\",\"\"emea, north\"\"]\",\"{{\"\"vip\"\":true}}\"",
+                account.id
+            )
+        );
+        assert!(read_line(&path, 1)
+            .unwrap()
+            .ends_with(",Jane Doe,,active,,"));
+        assert_eq!(
+            store.read(&account.id).expect("Failed to read Account"),
+            account
+        );
+    }
+
+    #[test]
+    fn groups_are_stored_with_every_field() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let csv_path = dir.path().join("groups.csv");
+        let mut store = CsvStore::<Group>::new(csv_path.display().to_string().as_str());
+        let mut group = Group::new("Sales, EMEA");
+        group.description = "The \"big\" ones\nand the small ones".to_string();
+        store.create(&group).expect("Failed to create Group");
+        let other = Group::new("Support");
+        store.create(&other).expect("Failed to create Group");
+        assert_eq!(
+            store.read_all().expect("Failed to read Groups"),
+            vec![group.clone(), other.clone()]
+        );
+
+        group.description.clear();
+        store.update(&group).expect("Failed to update Group");
+        assert_eq!(
+            read_line(csv_path.display().to_string().as_str(), 0).unwrap(),
+            format!("{},\"Sales, EMEA\",", group.id)
+        );
+        store.delete(&other).expect("Failed to delete Group");
+        assert_eq!(
+            store.read_all().expect("Failed to read Groups"),
+            vec![group]
+        );
+    }
+
+    #[test]
+    fn memberships_are_added_once_and_removed() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let csv_path = dir.path().join("memberships.csv");
+        let mut store = CsvMembershipStore::new(csv_path.display().to_string().as_str());
+        let (sales, support, erich) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        store
+            .add(&Membership::new(sales, erich))
+            .expect("Failed to add Membership");
+        store
+            .add(&Membership::new(support, erich))
+            .expect("Failed to add Membership");
+        assert!(matches!(
+            store.add(&Membership::new(sales, erich)),
+            Err(CrudError::AlreadyExists)
+        ));
+        assert_eq!(store.of(Side::Account, &erich).unwrap().len(), 2);
+
+        store
+            .remove(&Membership::new(sales, erich))
+            .expect("Failed to remove Membership");
+        assert!(matches!(
+            store.remove(&Membership::new(sales, erich)),
+            Err(CrudError::NotFound)
+        ));
+        store
+            .remove_all(Side::Group, &support)
+            .expect("Failed to remove Memberships");
+        assert!(store.read_all().unwrap().is_empty());
+    }
+
+    #[test]
+    fn users_keep_their_password_hash() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let csv_path = dir.path().join("users.csv");
+        let mut store = CsvUserStore::new(csv_path.display().to_string().as_str());
+        let mut user =
+            User::new("admin", "correct horse", Role::Admin).expect("Failed to create User");
+        store.create(&user).expect("Failed to store User");
+        let mut other =
+            User::new("admin", "battery staple", Role::Viewer).expect("Failed to create User");
+        assert!(matches!(
+            store.create(&other),
+            Err(CrudError::AlreadyExists)
+        ));
+        other.username = "other".to_string();
+        store.create(&other).expect("Failed to store User");
+
+        user.set_password("battery staple")
+            .expect("Failed to change password");
+        store.update(&user).expect("Failed to update User");
+        let users: Vec<User> = store.read_all().expect("Failed to read Users");
+        assert_eq!(users, vec![user.clone(), other]);
+        assert!(users[0].verify_password("battery staple"));
+
+        store.delete(&user).expect("Failed to delete User");
+        assert_eq!(
+            count_lines(csv_path.display().to_string().as_str()).unwrap(),
+            1
+        );
+
+        // Users written before there were roles are admins.
+        let legacy = format!("{},old,{}", Uuid::new_v4(), user.password_hash);
+        assert_eq!(
+            user_from_line(&legacy).expect("Failed to read User").role,
+            Role::Admin
+        );
+    }
 }
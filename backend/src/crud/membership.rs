@@ -0,0 +1,318 @@
+//! Which accounts belong to which groups.
+//!
+//! A membership refers to an account and a group, so neither may be deleted
+//! while it has memberships unless its [`OnDelete`] rule cascades. SQLite
+//! enforces the rules with foreign keys; the stores of the other strategies
+//! are wrapped in a [`RuleStore`] that checks them before each delete.
+
+use std::{
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{Crud, CrudError, Identifiable};
+
+/// An account's membership of a group.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Membership {
+    pub group: Uuid,
+    pub account: Uuid,
+}
+
+impl Membership {
+    pub fn new(group: Uuid, account: Uuid) -> Self {
+        Membership { group, account }
+    }
+
+    /// The id of the item at `side` of the membership.
+    pub fn id_of(&self, side: Side) -> Uuid {
+        match side {
+            Side::Account => self.account,
+            Side::Group => self.group,
+        }
+    }
+}
+
+/// The two kinds of items a membership refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Account,
+    Group,
+}
+
+/// What deleting an account or a group does to its memberships.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnDelete {
+    /// Refuse the delete with [`CrudError::InUse`].
+    #[default]
+    Restrict,
+    /// Delete the memberships too.
+    Cascade,
+}
+
+impl std::fmt::Display for OnDelete {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OnDelete::Restrict => write!(f, "RESTRICT"),
+            OnDelete::Cascade => write!(f, "CASCADE"),
+        }
+    }
+}
+
+/// The delete rules of both sides of the memberships.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct MembershipRules {
+    /// By default an account leaves its groups when it is deleted.
+    pub on_account_delete: OnDelete,
+    /// By default a group must be emptied before it is deleted.
+    pub on_group_delete: OnDelete,
+}
+
+impl Default for MembershipRules {
+    fn default() -> Self {
+        MembershipRules {
+            on_account_delete: OnDelete::Cascade,
+            on_group_delete: OnDelete::Restrict,
+        }
+    }
+}
+
+impl MembershipRules {
+    pub fn on_delete(&self, side: Side) -> OnDelete {
+        match side {
+            Side::Account => self.on_account_delete,
+            Side::Group => self.on_group_delete,
+        }
+    }
+}
+
+pub trait MembershipStore: Send {
+    fn read_all(&self) -> super::Result<Vec<Membership>>;
+    /// Adds `membership`, or fails with [`CrudError::AlreadyExists`].
+    fn add(&mut self, membership: &Membership) -> super::Result<()>;
+    /// Removes `membership`, or fails with [`CrudError::NotFound`].
+    fn remove(&mut self, membership: &Membership) -> super::Result<()>;
+    /// Removes every membership of the item `id` at `side`.
+    fn remove_all(&mut self, side: Side, id: &Uuid) -> super::Result<()>;
+    /// The memberships of the item `id` at `side`.
+    fn of(&self, side: Side, id: &Uuid) -> super::Result<Vec<Membership>> {
+        Ok(self
+            .read_all()?
+            .into_iter()
+            .filter(|membership| membership.id_of(side) == *id)
+            .collect())
+    }
+    /// Whether the store applies the [`MembershipRules`] itself, so that
+    /// the accounts and groups need no [`RuleStore`].
+    fn enforces_rules(&self) -> bool {
+        false
+    }
+}
+
+impl MembershipStore for Box<dyn MembershipStore> {
+    fn read_all(&self) -> super::Result<Vec<Membership>> {
+        (**self).read_all()
+    }
+
+    fn add(&mut self, membership: &Membership) -> super::Result<()> {
+        (**self).add(membership)
+    }
+
+    fn remove(&mut self, membership: &Membership) -> super::Result<()> {
+        (**self).remove(membership)
+    }
+
+    fn remove_all(&mut self, side: Side, id: &Uuid) -> super::Result<()> {
+        (**self).remove_all(side, id)
+    }
+
+    fn of(&self, side: Side, id: &Uuid) -> super::Result<Vec<Membership>> {
+        (**self).of(side, id)
+    }
+
+    fn enforces_rules(&self) -> bool {
+        (**self).enforces_rules()
+    }
+}
+
+/// A [`Crud`] decorator that applies the [`OnDelete`] rule of `side` to the
+/// memberships of each item deleted from the wrapped store.
+pub struct RuleStore<T, S> {
+    inner: S,
+    side: Side,
+    rule: OnDelete,
+    memberships: Arc<Mutex<dyn MembershipStore>>,
+    _item: PhantomData<fn(T)>,
+}
+
+impl<T, S: Crud<T>> RuleStore<T, S> {
+    pub fn new(
+        inner: S,
+        side: Side,
+        rules: MembershipRules,
+        memberships: Arc<Mutex<dyn MembershipStore>>,
+    ) -> Self {
+        RuleStore {
+            inner,
+            side,
+            rule: rules.on_delete(side),
+            memberships,
+            _item: PhantomData,
+        }
+    }
+}
+
+fn lock(
+    memberships: &Mutex<dyn MembershipStore>,
+) -> super::Result<std::sync::MutexGuard<'_, dyn MembershipStore + 'static>> {
+    memberships.lock().map_err(|_| {
+        log::error!("Membership store lock failed");
+        CrudError::UnknownError
+    })
+}
+
+/// Wraps `store` in a [`RuleStore`], unless `memberships` applies the rules
+/// itself.
+pub fn with_rules<T: Identifiable + 'static>(
+    store: Box<dyn Crud<T>>,
+    side: Side,
+    rules: MembershipRules,
+    memberships: Arc<Mutex<dyn MembershipStore>>,
+) -> super::Result<Box<dyn Crud<T>>> {
+    let enforced = memberships
+        .lock()
+        .map_err(|_| CrudError::UnknownError)?
+        .enforces_rules();
+    if enforced {
+        Ok(store)
+    } else {
+        Ok(Box::new(RuleStore::new(store, side, rules, memberships)))
+    }
+}
+
+impl<T: Identifiable, S: Crud<T>> Crud<T> for RuleStore<T, S> {
+    fn create(&mut self, item: &T) -> super::Result<()> {
+        self.inner.create(item)
+    }
+
+    fn create_all(&mut self, items: &[T]) -> super::Result<Vec<super::Result<()>>> {
+        self.inner.create_all(items)
+    }
+
+    fn read_all(&self) -> super::Result<Vec<T>> {
+        self.inner.read_all()
+    }
+
+    fn read(&self, id: &Uuid) -> super::Result<T> {
+        self.inner.read(id)
+    }
+
+    fn iter(&self) -> super::Result<Box<dyn Iterator<Item = super::Result<T>> + Send>>
+    where
+        T: Send + 'static,
+    {
+        self.inner.iter()
+    }
+
+    fn update(&mut self, item: &T) -> super::Result<()> {
+        self.inner.update(item)
+    }
+
+    fn delete(&mut self, item: &T) -> super::Result<()> {
+        let id = item.id();
+        // Held until the item is gone, so no membership can be added in
+        // between.
+        let mut memberships = lock(&self.memberships)?;
+        match self.rule {
+            OnDelete::Restrict => {
+                if !memberships.of(self.side, &id)?.is_empty() {
+                    return Err(CrudError::InUse);
+                }
+                self.inner.delete(item)
+            }
+            OnDelete::Cascade => {
+                self.inner.delete(item)?;
+                memberships.remove_all(self.side, &id)
+            }
+        }
+    }
+
+    fn in_memory(&self) -> bool {
+        self.inner.in_memory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{Account, Group};
+
+    use super::*;
+    use crate::crud::memory::MemoryStore;
+
+    fn memberships() -> Arc<Mutex<dyn MembershipStore>> {
+        Arc::new(Mutex::new(MemoryStore::<Membership>::new()))
+    }
+
+    /// An empty store of `T`s at `side` of `memberships`.
+    fn store<T: Identifiable + Clone + Send + 'static>(
+        side: Side,
+        rules: MembershipRules,
+        memberships: &Arc<Mutex<dyn MembershipStore>>,
+    ) -> Box<dyn Crud<T>> {
+        with_rules(
+            Box::new(MemoryStore::<T>::new()),
+            side,
+            rules,
+            memberships.clone(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn deletes_cascade_or_are_restricted_by_the_rules() {
+        let (rules, memberships) = (MembershipRules::default(), memberships());
+        let mut accounts = store::<Account>(Side::Account, rules, &memberships);
+        let mut groups = store::<Group>(Side::Group, rules, &memberships);
+        let account = Account::new("Erich");
+        let group = Group::new("Sales");
+        accounts.create(&account).unwrap();
+        groups.create(&group).unwrap();
+        memberships
+            .lock()
+            .unwrap()
+            .add(&Membership::new(group.id, account.id))
+            .unwrap();
+
+        assert!(matches!(groups.delete(&group), Err(CrudError::InUse)));
+        assert!(groups.read(&group.id).is_ok());
+
+        accounts.delete(&account).unwrap();
+        assert!(memberships.lock().unwrap().read_all().unwrap().is_empty());
+        groups.delete(&group).unwrap();
+    }
+
+    #[test]
+    fn restricted_accounts_keep_their_memberships() {
+        let rules = MembershipRules {
+            on_account_delete: OnDelete::Restrict,
+            on_group_delete: OnDelete::Cascade,
+        };
+        let memberships = memberships();
+        let mut accounts = store::<Account>(Side::Account, rules, &memberships);
+        let account = Account::new("Erich");
+        accounts.create(&account).unwrap();
+        let membership = Membership::new(Uuid::new_v4(), account.id);
+        memberships.lock().unwrap().add(&membership).unwrap();
+
+        assert!(matches!(accounts.delete(&account), Err(CrudError::InUse)));
+        assert_eq!(
+            memberships.lock().unwrap().read_all().unwrap(),
+            vec![membership]
+        );
+    }
+}
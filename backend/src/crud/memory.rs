@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+
+use uuid::Uuid;
+
+use super::{
+    membership::{Membership, MembershipStore, Side},
+    Crud, CrudError, Identifiable,
+};
+
+/// A store that keeps its items in memory only. Nothing survives a restart.
+#[derive(Debug, Clone)]
+pub struct MemoryStore<T> {
+    items: Vec<T>,
+}
+
+impl<T> MemoryStore<T> {
+    pub fn new() -> Self {
+        MemoryStore { items: Vec::new() }
+    }
+}
+
+impl<T> Default for MemoryStore<T> {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+impl<T: Clone + Identifiable + Send> Crud<T> for MemoryStore<T> {
+    fn create(&mut self, item: &T) -> super::Result<()> {
+        if self.items.iter().any(|stored| stored.id() == item.id()) {
+            return Err(CrudError::AlreadyExists);
+        }
+        self.items.push(item.clone());
+        Ok(())
+    }
+
+    fn create_all(&mut self, items: &[T]) -> super::Result<Vec<super::Result<()>>> {
+        let mut ids: HashSet<Uuid> = self.items.iter().map(Identifiable::id).collect();
+        Ok(items
+            .iter()
+            .map(|item| match ids.insert(item.id()) {
+                true => {
+                    self.items.push(item.clone());
+                    Ok(())
+                }
+                false => Err(CrudError::AlreadyExists),
+            })
+            .collect())
+    }
+
+    fn read_all(&self) -> super::Result<Vec<T>> {
+        Ok(self.items.clone())
+    }
+
+    fn read(&self, id: &Uuid) -> super::Result<T> {
+        self.items
+            .iter()
+            .find(|item| item.id() == *id)
+            .cloned()
+            .ok_or(CrudError::NotFound)
+    }
+
+    fn update(&mut self, item: &T) -> super::Result<()> {
+        let existing = self
+            .items
+            .iter_mut()
+            .find(|existing| existing.id() == item.id())
+            .ok_or(CrudError::NotFound)?;
+        *existing = item.clone();
+        Ok(())
+    }
+
+    fn delete(&mut self, item: &T) -> super::Result<()> {
+        self.items.retain(|existing| existing.id() != item.id());
+        Ok(())
+    }
+
+    fn in_memory(&self) -> bool {
+        true
+    }
+}
+
+impl MembershipStore for MemoryStore<Membership> {
+    fn read_all(&self) -> super::Result<Vec<Membership>> {
+        Ok(self.items.clone())
+    }
+
+    fn add(&mut self, membership: &Membership) -> super::Result<()> {
+        if self.items.contains(membership) {
+            return Err(CrudError::AlreadyExists);
+        }
+        self.items.push(*membership);
+        Ok(())
+    }
+
+    fn remove(&mut self, membership: &Membership) -> super::Result<()> {
+        let count = self.items.len();
+        self.items.retain(|existing| existing != membership);
+        match self.items.len() < count {
+            true => Ok(()),
+            false => Err(CrudError::NotFound),
+        }
+    }
+
+    fn remove_all(&mut self, side: Side, id: &Uuid) -> super::Result<()> {
+        self.items
+            .retain(|membership| membership.id_of(side) != *id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::Account;
+
+    use super::*;
+
+    #[test]
+    fn read_returns_created_item() {
+        let mut store = MemoryStore::new();
+        let account = Account::new("Test Account");
+        store.create(&account).expect("Failed to create Account");
+        assert_eq!(
+            store.read(&account.id).expect("Failed to read Account"),
+            account
+        );
+    }
+
+    #[test]
+    fn update_missing_item_is_not_found() {
+        let mut store = MemoryStore::new();
+        assert!(matches!(
+            store.update(&Account::new("Test Account")),
+            Err(CrudError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn delete_removes_item() {
+        let mut store = MemoryStore::new();
+        let account = Account::new("Test Account");
+        store.create(&account).expect("Failed to create Account");
+        store.delete(&account).expect("Failed to delete Account");
+        assert!(store
+            .read_all()
+            .expect("Failed to read Accounts")
+            .is_empty());
+    }
+
+    #[test]
+    fn create_with_taken_id_already_exists() {
+        let mut store = MemoryStore::new();
+        let account = Account::new("Test Account");
+        store.create(&account).expect("Failed to create Account");
+        assert!(matches!(
+            store.create(&account),
+            Err(CrudError::AlreadyExists)
+        ));
+        assert_eq!(store.read_all().expect("Failed to read Accounts").len(), 1);
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::{membership::MembershipStore, Crud, Identifiable};
+
+/// What [`copy_all`] did, or would do on a dry run, per item.
+#[derive(Debug, Default, PartialEq)]
+pub struct CopyStats {
+    pub total: usize,
+    pub created: usize,
+    pub updated: usize,
+    /// Items the target already held unchanged, e.g. from an interrupted run.
+    pub skipped: usize,
+}
+
+/// Copies every item of `source` into `target`, keeping ids.
+///
+/// Items the target already holds are skipped when equal and overwritten
+/// otherwise, so running the copy again resumes an interrupted one. When
+/// `dry_run` is set nothing is written. `progress` is called with the
+/// number of items handled so far and the total after each item.
+pub fn copy_all<T>(
+    source: &dyn Crud<T>,
+    target: &mut dyn Crud<T>,
+    dry_run: bool,
+    mut progress: impl FnMut(usize, usize),
+) -> super::Result<CopyStats>
+where
+    T: Identifiable + PartialEq,
+{
+    let items = source.read_all()?;
+    let existing: HashMap<Uuid, T> = target
+        .read_all()?
+        .into_iter()
+        .map(|item| (item.id(), item))
+        .collect();
+    let mut stats = CopyStats {
+        total: items.len(),
+        ..Default::default()
+    };
+    for (done, item) in items.iter().enumerate() {
+        match existing.get(&item.id()) {
+            Some(copy) if copy == item => stats.skipped += 1,
+            Some(_) => {
+                if !dry_run {
+                    target.update(item)?;
+                }
+                stats.updated += 1;
+            }
+            None => {
+                if !dry_run {
+                    target.create(item)?;
+                }
+                stats.created += 1;
+            }
+        }
+        progress(done + 1, stats.total);
+    }
+    Ok(stats)
+}
+
+/// Adds every membership of `source` that `target` does not hold yet, like
+/// [`copy_all`]. Memberships are never updated, only created or skipped.
+pub fn copy_memberships(
+    source: &dyn MembershipStore,
+    target: &mut dyn MembershipStore,
+    dry_run: bool,
+) -> super::Result<CopyStats> {
+    let memberships = source.read_all()?;
+    let existing: HashSet<(Uuid, Uuid)> = target
+        .read_all()?
+        .into_iter()
+        .map(|membership| (membership.group, membership.account))
+        .collect();
+    let mut stats = CopyStats {
+        total: memberships.len(),
+        ..Default::default()
+    };
+    for membership in &memberships {
+        if existing.contains(&(membership.group, membership.account)) {
+            stats.skipped += 1;
+        } else {
+            if !dry_run {
+                target.add(membership)?;
+            }
+            stats.created += 1;
+        }
+    }
+    Ok(stats)
+}
+
+/// Returns the hex SHA-256 of `items` in id order, so that two stores
+/// holding the same items have the same checksum.
+pub fn checksum<T: Identifiable + Serialize>(items: &[T]) -> String {
+    let mut sorted: Vec<&T> = items.iter().collect();
+    sorted.sort_by_key(|item| item.id());
+    let mut hasher = Sha256::new();
+    for item in sorted {
+        hasher.update(serde_json::to_vec(item).expect("Failed to serialize item"));
+        hasher.update(b"\n");
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use common::Account;
+
+    use super::*;
+    use crate::crud::{membership::Membership, memory::MemoryStore};
+
+    fn source() -> MemoryStore<Account> {
+        let mut store = MemoryStore::new();
+        for name in ["Test Account 1", "Test Account 2", "Test Account 3"] {
+            store
+                .create(&Account::new(name))
+                .expect("Failed to create Account");
+        }
+        store
+    }
+
+    #[test]
+    fn copy_preserves_ids_and_checksum() {
+        let source = source();
+        let mut target = MemoryStore::new();
+        let stats = copy_all(&source, &mut target, false, |_, _| {}).expect("Failed to copy");
+        assert_eq!(stats.created, 3);
+        let (from, to) = (source.read_all().unwrap(), target.read_all().unwrap());
+        assert_eq!(from, to);
+        assert_eq!(checksum(&from), checksum(&to));
+    }
+
+    #[test]
+    fn dry_run_writes_nothing() {
+        let mut target = MemoryStore::<Account>::new();
+        let stats = copy_all(&source(), &mut target, true, |_, _| {}).expect("Failed to copy");
+        assert_eq!(stats.created, 3);
+        assert!(target.read_all().unwrap().is_empty());
+    }
+
+    #[test]
+    fn memberships_are_copied_once() {
+        let group = Uuid::new_v4();
+        let mut source = MemoryStore::<Membership>::new();
+        for _ in 0..2 {
+            source
+                .add(&Membership::new(group, Uuid::new_v4()))
+                .expect("Failed to add membership");
+        }
+        let mut target = MemoryStore::<Membership>::new();
+        target
+            .add(&source.read_all().unwrap()[0])
+            .expect("Failed to add membership");
+        let stats = copy_memberships(&source, &mut target, false).expect("Failed to copy");
+        assert_eq!((stats.created, stats.skipped), (1, 1));
+        assert_eq!(target.read_all().unwrap(), source.read_all().unwrap());
+    }
+
+    #[test]
+    fn copy_resumes_after_interruption() {
+        let source = source();
+        let mut items = source.read_all().unwrap();
+        let mut target = MemoryStore::new();
+        target.create(&items[0]).expect("Failed to create Account");
+        items[1].fullname = "Stale Account".to_string();
+        target.create(&items[1]).expect("Failed to create Account");
+
+        let mut calls = Vec::new();
+        let stats = copy_all(&source, &mut target, false, |done, total| {
+            calls.push((done, total))
+        })
+        .expect("Failed to copy");
+        assert_eq!(
+            stats,
+            CopyStats {
+                total: 3,
+                created: 1,
+                updated: 1,
+                skipped: 1,
+            }
+        );
+        assert_eq!(calls, vec![(1, 3), (2, 3), (3, 3)]);
+        assert_eq!(
+            checksum(&source.read_all().unwrap()),
+            checksum(&target.read_all().unwrap())
+        );
+    }
+}
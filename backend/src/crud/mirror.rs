@@ -0,0 +1,479 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{Crud, CrudError, Identifiable};
+
+/// The most writes queued for a secondary. Further writes are dropped until
+/// it catches up, and a reconcile has to repair them.
+pub const MAX_BACKLOG: usize = 1024;
+
+/// What a [`MirroredStore`] does when a secondary store rejects a write.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Consistency {
+    /// Fail the write and undo it on the stores that already accepted it.
+    #[default]
+    Strict,
+    /// Keep the write, log the failure and retry it before the secondary's
+    /// next write. Writes the secondary can never accept, and those beyond
+    /// [`MAX_BACKLOG`], are dropped.
+    Queue,
+}
+
+#[derive(Clone, Debug)]
+enum Write<T> {
+    Create(T),
+    Update(T),
+    Delete(T),
+}
+
+impl<T: Clone + Identifiable> Write<T> {
+    fn apply(&self, store: &mut dyn Crud<T>) -> super::Result<()> {
+        match self {
+            Write::Create(item) => store.create(item),
+            Write::Update(item) => store.update(item),
+            Write::Delete(item) => store.delete(item),
+        }
+    }
+
+    /// The write that reverts this one, given the item it replaced.
+    fn undo(&self, previous: Option<&T>) -> Option<Write<T>> {
+        match self {
+            Write::Create(item) => Some(Write::Delete(item.clone())),
+            Write::Update(_) => previous.cloned().map(Write::Update),
+            Write::Delete(item) => Some(Write::Create(item.clone())),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Write::Create(item) => format!("create of {}", item.id()),
+            Write::Update(item) => format!("update of {}", item.id()),
+            Write::Delete(item) => format!("delete of {}", item.id()),
+        }
+    }
+}
+
+/// Whether a write that failed with `err` may succeed when retried. An
+/// item that exists, or is missing, stays so however often it is retried.
+fn is_transient(err: &CrudError) -> bool {
+    !matches!(
+        err,
+        CrudError::NotFound
+            | CrudError::AlreadyExists
+            | CrudError::InUse
+            | CrudError::Unsupported(_)
+            | CrudError::Forbidden(_)
+            | CrudError::UnknownField(_)
+    )
+}
+
+struct Secondary<T> {
+    name: String,
+    store: Box<dyn Crud<T>>,
+    backlog: VecDeque<Write<T>>,
+    /// Writes given up on since the last reconcile.
+    dropped: usize,
+}
+
+impl<T: Clone + Identifiable> Secondary<T> {
+    /// Queues `write` behind the earlier ones, or drops it when the backlog
+    /// is full.
+    fn queue(&mut self, write: Write<T>) {
+        if self.backlog.len() < MAX_BACKLOG {
+            self.backlog.push_back(write);
+        } else {
+            self.drop_write(&write, "the backlog is full");
+        }
+    }
+
+    /// Replays the backlog in order, stopping at the first write that may
+    /// succeed later. Writes that never will are dropped, so they do not
+    /// hold up the ones behind them.
+    fn flush(&mut self) -> super::Result<()> {
+        while let Some(write) = self.backlog.front() {
+            match write.apply(&mut *self.store) {
+                Ok(()) => {
+                    self.backlog.pop_front();
+                }
+                Err(e) if is_transient(&e) => return Err(e),
+                Err(e) => {
+                    if let Some(write) = self.backlog.pop_front() {
+                        self.drop_write(&write, e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn drop_write(&mut self, write: &Write<T>, reason: impl std::fmt::Display) {
+        self.dropped += 1;
+        log::error!(
+            "Mirror {} dropped the {}, {} writes dropped, reconcile to repair: {}",
+            self.name,
+            write.describe(),
+            self.dropped,
+            reason
+        );
+    }
+}
+
+/// How a secondary store differs from the primary, by item id.
+#[derive(Debug, Default, PartialEq)]
+pub struct Drift {
+    /// Items of the primary that the secondary lacks.
+    pub missing: Vec<Uuid>,
+    /// Items whose copy in the secondary differs from the primary.
+    pub changed: Vec<Uuid>,
+    /// Items of the secondary that the primary lacks.
+    pub extra: Vec<Uuid>,
+}
+
+impl Drift {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.changed.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// A [`Crud`] decorator that writes to a primary store and mirrors each
+/// successful write to one or more secondary stores.
+///
+/// Reads are served by the primary only. With [`Consistency::Queue`] the
+/// writes a secondary rejects are kept in memory, up to [`MAX_BACKLOG`] of
+/// them, and replayed in order. Drift left behind by a restart or by dropped
+/// writes has to be repaired with [`MirroredStore::reconcile`].
+pub struct MirroredStore<T> {
+    primary: Box<dyn Crud<T>>,
+    secondaries: Vec<Secondary<T>>,
+    consistency: Consistency,
+}
+
+impl<T> MirroredStore<T>
+where
+    T: Clone + Identifiable + PartialEq + Send,
+{
+    pub fn new(primary: Box<dyn Crud<T>>, consistency: Consistency) -> Self {
+        MirroredStore {
+            primary,
+            secondaries: Vec::new(),
+            consistency,
+        }
+    }
+
+    /// Mirrors every subsequent write to `store`, which is called `name` in
+    /// logs and reports.
+    pub fn add_secondary(&mut self, name: impl Into<String>, store: Box<dyn Crud<T>>) {
+        self.secondaries.push(Secondary {
+            name: name.into(),
+            store,
+            backlog: VecDeque::new(),
+            dropped: 0,
+        });
+    }
+
+    /// Compares every secondary with the primary and, when `repair` is set,
+    /// writes the differences to the secondary. Returns the drift found in
+    /// each secondary, by name.
+    pub fn reconcile(&mut self, repair: bool) -> super::Result<Vec<(String, Drift)>> {
+        let primary: HashMap<Uuid, T> = self
+            .primary
+            .read_all()?
+            .into_iter()
+            .map(|item| (item.id(), item))
+            .collect();
+        let mut reports = Vec::new();
+        for secondary in &mut self.secondaries {
+            let mut mirrored: HashMap<Uuid, T> = secondary
+                .store
+                .read_all()?
+                .into_iter()
+                .map(|item| (item.id(), item))
+                .collect();
+            let mut drift = Drift::default();
+            let mut repairs = Vec::new();
+            for (id, item) in &primary {
+                match mirrored.remove(id) {
+                    None => {
+                        drift.missing.push(*id);
+                        repairs.push(Write::Create(item.clone()));
+                    }
+                    Some(copy) if copy != *item => {
+                        drift.changed.push(*id);
+                        repairs.push(Write::Update(item.clone()));
+                    }
+                    Some(_) => {}
+                }
+            }
+            for (id, item) in mirrored {
+                drift.extra.push(id);
+                repairs.push(Write::Delete(item));
+            }
+            drift.missing.sort();
+            drift.changed.sort();
+            drift.extra.sort();
+            if repair {
+                for write in &repairs {
+                    write.apply(&mut *secondary.store)?;
+                }
+                // The backlog is covered by the repair.
+                secondary.backlog.clear();
+                secondary.dropped = 0;
+            }
+            reports.push((secondary.name.clone(), drift));
+        }
+        Ok(reports)
+    }
+
+    fn write(&mut self, write: Write<T>) -> super::Result<()> {
+        let previous = match (&write, self.consistency) {
+            (Write::Update(item), Consistency::Strict) => self.primary.read(&item.id()).ok(),
+            _ => None,
+        };
+        write.apply(&mut *self.primary)?;
+        match self.consistency {
+            Consistency::Strict => {
+                for i in 0..self.secondaries.len() {
+                    if let Err(e) = write.apply(&mut *self.secondaries[i].store) {
+                        log::error!(
+                            "Mirror {} rejected a write, undoing it: {}",
+                            self.secondaries[i].name,
+                            e
+                        );
+                        self.undo(&write, previous.as_ref(), i);
+                        return Err(e);
+                    }
+                }
+            }
+            Consistency::Queue => {
+                for secondary in &mut self.secondaries {
+                    secondary.queue(write.clone());
+                    if let Err(e) = secondary.flush() {
+                        log::warn!(
+                            "Mirror {} is {} writes behind: {}",
+                            secondary.name,
+                            secondary.backlog.len(),
+                            e
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reverts `write` on the primary and the first `applied` secondaries.
+    fn undo(&mut self, write: &Write<T>, previous: Option<&T>, applied: usize) {
+        let Some(undo) = write.undo(previous) else {
+            log::warn!("Cannot undo a write whose previous item is unknown");
+            return;
+        };
+        let stores = std::iter::once(&mut self.primary)
+            .chain(self.secondaries[..applied].iter_mut().map(|s| &mut s.store));
+        for store in stores {
+            if let Err(e) = undo.apply(&mut **store) {
+                log::warn!("Failed to undo a write, reconcile to repair: {}", e);
+            }
+        }
+    }
+}
+
+impl<T> Crud<T> for MirroredStore<T>
+where
+    T: Clone + Identifiable + PartialEq + Send,
+{
+    fn create(&mut self, item: &T) -> super::Result<()> {
+        self.write(Write::Create(item.clone()))
+    }
+
+    fn read_all(&self) -> super::Result<Vec<T>> {
+        self.primary.read_all()
+    }
+
+    fn read(&self, id: &Uuid) -> super::Result<T> {
+        self.primary.read(id)
+    }
+
+    fn iter(&self) -> super::Result<Box<dyn Iterator<Item = super::Result<T>> + Send>>
+    where
+        T: Send + 'static,
+    {
+        self.primary.iter()
+    }
+
+    fn update(&mut self, item: &T) -> super::Result<()> {
+        self.write(Write::Update(item.clone()))
+    }
+
+    fn delete(&mut self, item: &T) -> super::Result<()> {
+        self.write(Write::Delete(item.clone()))
+    }
+
+    fn in_memory(&self) -> bool {
+        self.primary.in_memory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    };
+
+    use common::Account;
+
+    use super::*;
+    use crate::crud::{memory::MemoryStore, CrudError};
+
+    /// A store whose writes fail while `down` is set. Clones share state,
+    /// so a test can read back the copy it handed to the mirror.
+    #[derive(Clone, Default)]
+    struct Flaky {
+        store: Arc<Mutex<MemoryStore<Account>>>,
+        down: Arc<AtomicBool>,
+    }
+
+    impl Flaky {
+        fn check(&self) -> crate::crud::Result<()> {
+            if self.down.load(Ordering::Relaxed) {
+                Err(CrudError::UnknownError)
+            } else {
+                Ok(())
+            }
+        }
+
+        fn set_down(&self, down: bool) {
+            self.down.store(down, Ordering::Relaxed);
+        }
+
+        fn items(&self) -> Vec<Account> {
+            self.store.lock().unwrap().read_all().unwrap()
+        }
+    }
+
+    impl Crud<Account> for Flaky {
+        fn create(&mut self, item: &Account) -> crate::crud::Result<()> {
+            self.check()?;
+            self.store.lock().unwrap().create(item)
+        }
+
+        fn read_all(&self) -> crate::crud::Result<Vec<Account>> {
+            self.store.lock().unwrap().read_all()
+        }
+
+        fn update(&mut self, item: &Account) -> crate::crud::Result<()> {
+            self.check()?;
+            self.store.lock().unwrap().update(item)
+        }
+
+        fn delete(&mut self, item: &Account) -> crate::crud::Result<()> {
+            self.check()?;
+            self.store.lock().unwrap().delete(item)
+        }
+    }
+
+    fn mirrored(consistency: Consistency) -> (MirroredStore<Account>, Flaky) {
+        let shadow = Flaky::default();
+        let mut store = MirroredStore::new(Box::new(MemoryStore::new()), consistency);
+        store.add_secondary("shadow", Box::new(shadow.clone()));
+        (store, shadow)
+    }
+
+    #[test]
+    fn writes_reach_every_store() {
+        let (mut store, shadow) = mirrored(Consistency::Strict);
+        let mut account = Account::new("Test Account");
+        store.create(&account).expect("Failed to create Account");
+        account.fullname = "Modified Account".to_string();
+        store.update(&account).expect("Failed to update Account");
+        assert_eq!(store.read_all().unwrap(), vec![account.clone()]);
+        assert_eq!(shadow.items(), vec![account]);
+    }
+
+    #[test]
+    fn strict_write_is_undone_when_a_secondary_fails() {
+        let (mut store, shadow) = mirrored(Consistency::Strict);
+        let mut account = Account::new("Test Account");
+        store.create(&account).expect("Failed to create Account");
+        shadow.set_down(true);
+        assert!(store.create(&Account::new("Other Account")).is_err());
+        account.fullname = "Modified Account".to_string();
+        assert!(store.update(&account).is_err());
+        assert_eq!(store.read_all().unwrap()[0].fullname, "Test Account");
+        assert_eq!(store.read_all().unwrap().len(), 1);
+        assert_eq!(shadow.items().len(), 1);
+    }
+
+    #[test]
+    fn queued_writes_are_replayed_in_order() {
+        let (mut store, shadow) = mirrored(Consistency::Queue);
+        let mut account = Account::new("Test Account");
+        shadow.set_down(true);
+        store.create(&account).expect("Failed to create Account");
+        account.fullname = "Modified Account".to_string();
+        store.update(&account).expect("Failed to update Account");
+        assert!(shadow.items().is_empty());
+        shadow.set_down(false);
+        let other = Account::new("Other Account");
+        store.create(&other).expect("Failed to create Account");
+        assert_eq!(shadow.items(), vec![account, other]);
+    }
+
+    #[test]
+    fn writes_a_secondary_never_accepts_are_dropped() {
+        let (mut store, shadow) = mirrored(Consistency::Queue);
+        let account = Account::new("Test Account");
+        shadow
+            .store
+            .lock()
+            .unwrap()
+            .create(&account)
+            .expect("Failed to create Account");
+        store.create(&account).expect("Failed to create Account");
+        let other = Account::new("Other Account");
+        store.create(&other).expect("Failed to create Account");
+        assert!(store.secondaries[0].backlog.is_empty());
+        assert_eq!(store.secondaries[0].dropped, 1);
+        assert_eq!(shadow.items(), vec![account, other]);
+    }
+
+    #[test]
+    fn backlog_is_capped() {
+        let (mut store, shadow) = mirrored(Consistency::Queue);
+        shadow.set_down(true);
+        for _ in 0..=MAX_BACKLOG {
+            store
+                .create(&Account::new("Test Account"))
+                .expect("Failed to create Account");
+        }
+        assert_eq!(store.secondaries[0].backlog.len(), MAX_BACKLOG);
+        assert_eq!(store.secondaries[0].dropped, 1);
+
+        shadow.set_down(false);
+        store.reconcile(true).expect("Failed to reconcile");
+        assert_eq!(store.secondaries[0].dropped, 0);
+        assert_eq!(shadow.items().len(), MAX_BACKLOG + 1);
+    }
+
+    #[test]
+    fn reconcile_reports_and_repairs_drift() {
+        let (mut store, shadow) = mirrored(Consistency::Queue);
+        let account = Account::new("Test Account");
+        shadow.set_down(true);
+        store.create(&account).expect("Failed to create Account");
+        shadow.set_down(false);
+
+        let reports = store.reconcile(false).expect("Failed to reconcile");
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].1.missing, vec![account.id]);
+        assert!(shadow.items().is_empty());
+
+        store.reconcile(true).expect("Failed to reconcile");
+        assert_eq!(shadow.items(), vec![account]);
+        let reports = store.reconcile(false).expect("Failed to reconcile");
+        assert!(reports[0].1.is_empty());
+    }
+}
@@ -1,15 +1,51 @@
+pub mod cache;
 #[cfg(feature = "csv")]
 pub mod csv;
+pub mod membership;
+#[cfg(any(feature = "memory", test))]
+pub mod memory;
+pub mod migrate;
+pub mod mirror;
+pub mod observer;
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
+pub mod unique;
+
+use std::str::FromStr;
+
+use common::{
+    validate::{Rules, ValidationErrors},
+    Account, Group,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+use self::{
+    membership::{MembershipRules, MembershipStore},
+    unique::UniqueRule,
+};
+use crate::auth::user::User;
 
 pub type Result<T> = std::result::Result<T, CrudError>;
 
 // #[derive(Clone, Copy, Debug, PartialEq)]
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub enum CrudError {
+    #[default]
     UnknownError,
+    NotFound,
+    /// An item with the same id, or the same value of a unique field, is
+    /// already stored.
+    AlreadyExists,
+    /// The item still has memberships, which restrict deleting it.
+    InUse,
     IO(std::io::Error),
+    /// The strategy was not enabled as a cargo feature of this build.
+    Unsupported(Strategy),
+    /// The caller's role may not do the operation.
+    Forbidden(Operation),
+    /// A [`UniqueRule`] names a field the items lack, e.g. `accounts.nickname`.
+    UnknownField(String),
     #[cfg(feature = "sqlite")]
     SqliteError(rusqlite::Error),
 }
@@ -29,21 +65,518 @@ impl From<std::io::Error> for CrudError {
 
 impl std::error::Error for CrudError {}
 
-impl Default for CrudError {
+impl std::fmt::Display for CrudError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CrudError::NotFound => write!(f, "Not found"),
+            CrudError::AlreadyExists => write!(f, "Already exists"),
+            CrudError::InUse => write!(f, "Still has memberships"),
+            CrudError::IO(err) => write!(f, "CRUD I/O error: {}", err),
+            CrudError::Unsupported(strategy) => {
+                write!(f, "Storage strategy {} is not compiled in", strategy)
+            }
+            CrudError::Forbidden(operation) => write!(f, "Forbidden to {}", operation),
+            CrudError::UnknownField(field) => write!(f, "No field {} to keep unique", field),
+            #[cfg(feature = "sqlite")]
+            CrudError::SqliteError(err) => write!(f, "CRUD SQLite error: {}", err),
+            _ => write!(f, "Generic CRUD error"),
+        }
+    }
+}
+
+/// The kinds of [`Crud`] calls, which access is granted for.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Create,
+    /// Any of [`Crud::read_all`], [`Crud::read`] and [`Crud::iter`].
+    Read,
+    Update,
+    Delete,
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operation::Create => write!(f, "create"),
+            Operation::Read => write!(f, "read"),
+            Operation::Update => write!(f, "update"),
+            Operation::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+/// The storage strategies a store can be opened with.
+///
+/// Only the strategies enabled as cargo features can be opened; the default
+/// is the first enabled one of `csv`, `sqlite` and `memory`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    Csv,
+    Sqlite,
+    Memory,
+}
+
+impl Strategy {
+    /// The file a strategy stores its items in when no path is configured.
+    pub fn default_path(&self) -> Option<&'static str> {
+        match self {
+            Strategy::Csv => Some("accounts.csv"),
+            Strategy::Sqlite => Some("accounts.sqlite"),
+            Strategy::Memory => None,
+        }
+    }
+
+    /// The file a strategy stores the operators of the web server in when no
+    /// path is configured.
+    pub fn default_user_path(&self) -> Option<&'static str> {
+        match self {
+            Strategy::Csv => Some("users.csv"),
+            Strategy::Sqlite => Some("users.sqlite"),
+            Strategy::Memory => None,
+        }
+    }
+}
+
+impl Default for Strategy {
     fn default() -> Self {
-        CrudError::UnknownError
+        if cfg!(feature = "csv") {
+            Strategy::Csv
+        } else if cfg!(feature = "sqlite") {
+            Strategy::Sqlite
+        } else {
+            Strategy::Memory
+        }
     }
 }
 
-impl std::fmt::Display for CrudError {
+impl std::fmt::Display for Strategy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Generic CRUD error")
+        match self {
+            Strategy::Csv => write!(f, "csv"),
+            Strategy::Sqlite => write!(f, "sqlite"),
+            Strategy::Memory => write!(f, "memory"),
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Strategy::Csv),
+            "sqlite" => Ok(Strategy::Sqlite),
+            "memory" => Ok(Strategy::Memory),
+            _ => Err(format!("Unknown storage strategy {}", s)),
+        }
+    }
+}
+
+/// Adds `-<suffix>` to the file name of `path`, before its extension.
+pub fn suffixed_path(path: &str, suffix: &str) -> String {
+    let path = std::path::Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, suffix, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, suffix),
+    };
+    path.with_file_name(file_name).display().to_string()
+}
+
+/// Where the `T`s are kept next to the accounts at `path`: in the same
+/// SQLite database, or in a CSV file named after the accounts' one, e.g.
+/// `accounts-groups.csv`.
+pub fn path_of<T: Entity>(strategy: Strategy, path: &str) -> String {
+    path_named(strategy, path, T::PLURAL)
+}
+
+/// Where the items called `plural` are kept next to the accounts at `path`,
+/// like [`path_of`].
+pub fn path_named(strategy: Strategy, path: &str, plural: &str) -> String {
+    match strategy {
+        Strategy::Csv if plural != Account::PLURAL => suffixed_path(path, plural),
+        _ => path.to_string(),
+    }
+}
+
+/// Opens the store of `T`s of `strategy` at `path`, or next to the accounts
+/// at the strategy's default path when `path` is `None`. The store keeps the
+/// fields of the unique `rules` unique.
+pub fn open_store<T: Entity>(
+    strategy: Strategy,
+    path: Option<&str>,
+    rules: &[UniqueRule],
+) -> Result<Box<dyn Crud<T>>> {
+    let path = match path {
+        Some(path) => path.to_string(),
+        None => strategy
+            .default_path()
+            .map(|path| path_of::<T>(strategy, path))
+            .unwrap_or_default(),
+    };
+    let path = path.as_str();
+    match strategy {
+        #[cfg(feature = "csv")]
+        Strategy::Csv => unique::with_unique(Box::new(csv::CsvStore::<T>::new(path)), rules),
+        #[cfg(feature = "sqlite")]
+        Strategy::Sqlite => {
+            let store = sqlite::SqliteStore::<T>::with_unique(path, rules)?;
+            // The rules the stored items break until they are merged.
+            let unindexed = store.unindexed().to_vec();
+            unique::with_unique(Box::new(store), &unindexed)
+        }
+        #[cfg(feature = "memory")]
+        Strategy::Memory => {
+            log::debug!("Ignoring storage path {:?} of the in-memory store", path);
+            unique::with_unique(Box::new(memory::MemoryStore::<T>::new()), rules)
+        }
+        #[allow(unreachable_patterns)]
+        _ => Err(CrudError::Unsupported(strategy)),
     }
 }
 
-pub trait Crud<T> {
+/// Opens the memberships of `strategy` at `path`, or next to the accounts at
+/// the strategy's default path when `path` is `None`. A SQLite store is
+/// created with foreign keys that apply `rules`.
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+pub fn open_membership_store(
+    strategy: Strategy,
+    path: Option<&str>,
+    rules: MembershipRules,
+) -> Result<Box<dyn MembershipStore>> {
+    let path = match path {
+        Some(path) => path.to_string(),
+        None => strategy
+            .default_path()
+            .map(|path| path_named(strategy, path, "memberships"))
+            .unwrap_or_default(),
+    };
+    match strategy {
+        #[cfg(feature = "csv")]
+        Strategy::Csv => Ok(Box::new(csv::CsvMembershipStore::new(&path))),
+        #[cfg(feature = "sqlite")]
+        Strategy::Sqlite => Ok(Box::new(sqlite::SqliteMembershipStore::new(&path, rules))),
+        #[cfg(feature = "memory")]
+        Strategy::Memory => {
+            log::debug!(
+                "Ignoring membership store path {:?} of the in-memory store",
+                path
+            );
+            Ok(Box::new(
+                memory::MemoryStore::<membership::Membership>::new(),
+            ))
+        }
+        #[allow(unreachable_patterns)]
+        _ => Err(CrudError::Unsupported(strategy)),
+    }
+}
+
+/// Opens the store of the operators who log in to the web server, like
+/// [`open_store`] opens the account store.
+pub fn open_user_store(strategy: Strategy, path: Option<&str>) -> Result<Box<dyn Crud<User>>> {
+    let path = path.or(strategy.default_user_path()).unwrap_or_default();
+    match strategy {
+        #[cfg(feature = "csv")]
+        Strategy::Csv => Ok(Box::new(csv::CsvUserStore::new(path))),
+        #[cfg(feature = "sqlite")]
+        Strategy::Sqlite => Ok(Box::new(sqlite::SqliteUserStore::new(path))),
+        #[cfg(feature = "memory")]
+        Strategy::Memory => {
+            log::debug!("Ignoring user store path {:?} of the in-memory store", path);
+            Ok(Box::new(memory::MemoryStore::<User>::new()))
+        }
+        #[allow(unreachable_patterns)]
+        _ => Err(CrudError::Unsupported(strategy)),
+    }
+}
+
+/// Items that a store can look up by id.
+pub trait Identifiable {
+    fn id(&self) -> Uuid;
+}
+
+impl Identifiable for Account {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
+impl Identifiable for Group {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// A field of an [`Entity`] besides its id.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Field {
+    /// The name of the field in JSON, forms and columns.
+    pub name: &'static str,
+    /// What the field is called on the web pages.
+    pub label: &'static str,
+}
+
+/// A page of each item of an [`Entity`] besides its own, at
+/// `/<NAME>/<id>/<path>`.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Link {
+    pub path: &'static str,
+    pub label: &'static str,
+}
+
+/// Items that the stores, routes and templates handle without knowing their
+/// type. Every field besides the id is stored as text, in the order of
+/// [`Entity::FIELDS`].
+pub trait Entity:
+    Identifiable + Clone + PartialEq + Send + Sync + Serialize + DeserializeOwned + 'static
+{
+    /// What one item is called, e.g. `account`. Its pages are under
+    /// `/<NAME>`.
+    const NAME: &'static str;
+    /// What several items are called, e.g. `accounts`. It names their SQLite
+    /// table and their JSON API under `/api/v1/<PLURAL>`.
+    const PLURAL: &'static str;
+    const FIELDS: &'static [Field];
+    /// The pages linked from the item's own, e.g. the members of a group.
+    const LINKS: &'static [Link] = &[];
+    /// The fields no two items may share a value of, unless the
+    /// configuration has [`UniqueRule`]s of its own. Empty values are not
+    /// compared.
+    const UNIQUE: &'static [&'static str] = &[];
+
+    /// The text of every field, in the order of [`Entity::FIELDS`].
+    fn values(&self) -> Vec<String>;
+
+    /// Builds an item from its id and the text of its fields, which may be
+    /// fewer than [`Entity::FIELDS`] for items stored before a field was
+    /// added.
+    fn from_values(id: Uuid, values: Vec<String>) -> Self;
+
+    /// Checks the item, returning it cleaned up or the errors of all its
+    /// invalid fields.
+    fn validate(&self, rules: &Rules) -> std::result::Result<Self, ValidationErrors>;
+
+    /// [`Entity::NAME`] at the start of a sentence, e.g. `Account`.
+    fn title() -> String {
+        let mut chars = Self::NAME.chars();
+        chars
+            .next()
+            .map(|first| first.to_uppercase().chain(chars).collect())
+            .unwrap_or_default()
+    }
+
+    /// What the item is called on the web pages: its first field.
+    fn label(&self) -> String {
+        self.values().into_iter().next().unwrap_or_default()
+    }
+}
+
+/// The JSON text of `value`, or an empty text when `value` is empty.
+fn encoded<T: Serialize>(value: &T, is_empty: bool) -> String {
+    match is_empty {
+        true => String::new(),
+        false => serde_json::to_string(value).unwrap_or_default(),
+    }
+}
+
+/// Reads the JSON text of [`encoded`], falling back to the default value
+/// when `text` is empty or invalid.
+fn decoded<T: DeserializeOwned + Default>(field: &str, text: &str) -> T {
+    if text.is_empty() {
+        return T::default();
+    }
+    serde_json::from_str(text).unwrap_or_else(|err| {
+        log::warn!("Ignoring invalid {} {:?}: {}", field, text, err);
+        T::default()
+    })
+}
+
+/// The tags and attributes of an account are stored as JSON text.
+impl Entity for Account {
+    const NAME: &'static str = "account";
+    const PLURAL: &'static str = "accounts";
+    const FIELDS: &'static [Field] = &[
+        Field {
+            name: "fullname",
+            label: "Full name",
+        },
+        Field {
+            name: "email",
+            label: "Email",
+        },
+        Field {
+            name: "status",
+            label: "Status",
+        },
+        Field {
+            name: "tags",
+            label: "Tags",
+        },
+        Field {
+            name: "attributes",
+            label: "Attributes",
+        },
+    ];
+    const UNIQUE: &'static [&'static str] = &["email"];
+
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.fullname.clone(),
+            self.email.clone().unwrap_or_default(),
+            self.status.to_string(),
+            encoded(&self.tags, self.tags.is_empty()),
+            encoded(&self.attributes, self.attributes.is_empty()),
+        ]
+    }
+
+    fn from_values(id: Uuid, values: Vec<String>) -> Self {
+        let mut values = values.into_iter();
+        let mut next = || values.next().unwrap_or_default();
+        Account {
+            id,
+            fullname: next(),
+            email: Some(next()).filter(|email| !email.is_empty()),
+            status: next().parse().unwrap_or_default(),
+            tags: decoded("tags", &next()),
+            attributes: decoded("attributes", &next()),
+        }
+    }
+
+    fn validate(&self, rules: &Rules) -> std::result::Result<Self, ValidationErrors> {
+        rules.account(self)
+    }
+}
+
+impl Entity for Group {
+    const NAME: &'static str = "group";
+    const PLURAL: &'static str = "groups";
+    const FIELDS: &'static [Field] = &[
+        Field {
+            name: "name",
+            label: "Name",
+        },
+        Field {
+            name: "description",
+            label: "Description",
+        },
+    ];
+
+    const LINKS: &'static [Link] = &[Link {
+        path: "members",
+        label: "Members",
+    }];
+
+    fn values(&self) -> Vec<String> {
+        vec![self.name.clone(), self.description.clone()]
+    }
+
+    fn from_values(id: Uuid, values: Vec<String>) -> Self {
+        let mut values = values.into_iter();
+        Group {
+            id,
+            name: values.next().unwrap_or_default(),
+            description: values.next().unwrap_or_default(),
+        }
+    }
+
+    fn validate(&self, rules: &Rules) -> std::result::Result<Self, ValidationErrors> {
+        rules.group(self)
+    }
+}
+
+pub trait Crud<T>: Send {
     fn create(&mut self, item: &T) -> Result<()>;
+    /// Creates `items` in order, returning for each whether it was created
+    /// or clashed with a stored one ([`CrudError::AlreadyExists`]). Any
+    /// other error stops the batch and is returned; the items before it stay
+    /// created.
+    ///
+    /// The default implementation creates the items one at a time; stores
+    /// that check a create against every stored item should override it to
+    /// check the whole batch at once.
+    fn create_all(&mut self, items: &[T]) -> Result<Vec<Result<()>>> {
+        items
+            .iter()
+            .map(|item| match self.create(item) {
+                Err(CrudError::AlreadyExists) => Ok(Err(CrudError::AlreadyExists)),
+                result => result.map(Ok),
+            })
+            .collect()
+    }
     fn read_all(&self) -> Result<Vec<T>>;
+    /// Returns the item with the given `id`, or [`CrudError::NotFound`].
+    ///
+    /// The default implementation scans [`Crud::read_all`]; stores that can
+    /// look items up directly should override it.
+    fn read(&self, id: &Uuid) -> Result<T>
+    where
+        T: Identifiable,
+    {
+        self.read_all()?
+            .into_iter()
+            .find(|item| item.id() == *id)
+            .ok_or(CrudError::NotFound)
+    }
+    /// Iterates the items one at a time, so that large stores can be read in
+    /// bounded memory.
+    ///
+    /// The iterator does not borrow the store: it can be drained after a
+    /// lock around the store has been released. The default implementation
+    /// iterates [`Crud::read_all`]; stores that can read items lazily should
+    /// override it.
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<T>> + Send>>
+    where
+        T: Send + 'static,
+    {
+        Ok(Box::new(self.read_all()?.into_iter().map(Ok)))
+    }
     fn update(&mut self, item: &T) -> Result<()>;
     fn delete(&mut self, item: &T) -> Result<()>;
+    /// Whether the store already holds its items in memory, so that putting
+    /// a cache in front of it gains nothing.
+    fn in_memory(&self) -> bool {
+        false
+    }
+}
+
+impl<T> Crud<T> for Box<dyn Crud<T>> {
+    fn create(&mut self, item: &T) -> Result<()> {
+        (**self).create(item)
+    }
+
+    fn create_all(&mut self, items: &[T]) -> Result<Vec<Result<()>>> {
+        (**self).create_all(items)
+    }
+
+    fn read_all(&self) -> Result<Vec<T>> {
+        (**self).read_all()
+    }
+
+    fn read(&self, id: &Uuid) -> Result<T>
+    where
+        T: Identifiable,
+    {
+        (**self).read(id)
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<T>> + Send>>
+    where
+        T: Send + 'static,
+    {
+        (**self).iter()
+    }
+
+    fn update(&mut self, item: &T) -> Result<()> {
+        (**self).update(item)
+    }
+
+    fn delete(&mut self, item: &T) -> Result<()> {
+        (**self).delete(item)
+    }
+
+    fn in_memory(&self) -> bool {
+        (**self).in_memory()
+    }
 }
@@ -0,0 +1,249 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{Crud, Identifiable};
+
+/// The kind of mutation that produced a [`Change`].
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl std::fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeKind::Created => write!(f, "created"),
+            ChangeKind::Updated => write!(f, "updated"),
+            ChangeKind::Deleted => write!(f, "deleted"),
+        }
+    }
+}
+
+/// A successful mutation of a store, as seen by a [`Listener`].
+///
+/// `seq` increases by one for every change committed through the same
+/// [`ObservedStore`], so listeners can rely on it to order changes.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Change<T> {
+    pub seq: u64,
+    pub kind: ChangeKind,
+    pub at: DateTime<Utc>,
+    pub item: T,
+}
+
+/// Receives every change committed through an [`ObservedStore`].
+///
+/// Listeners are called synchronously, in commit order, after the wrapped
+/// store has accepted the write. An error returned from a listener is logged
+/// and otherwise ignored: the write has already happened and is not undone.
+pub trait Listener<T>: Send + Sync {
+    fn on_change(&self, change: &Change<T>) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// A [`Crud`] decorator that notifies registered [`Listener`]s after each
+/// successful create, update or delete on the wrapped store.
+pub struct ObservedStore<T, S> {
+    inner: S,
+    listeners: Vec<Arc<dyn Listener<T>>>,
+    seq: u64,
+    _item: PhantomData<fn(T)>,
+}
+
+impl<T, S: Crud<T>> ObservedStore<T, S> {
+    pub fn new(inner: S) -> Self {
+        ObservedStore {
+            inner,
+            listeners: Vec::new(),
+            seq: 0,
+            _item: PhantomData,
+        }
+    }
+
+    /// Registers `listener` to be notified of every subsequent change.
+    pub fn subscribe(&mut self, listener: Arc<dyn Listener<T>>) {
+        self.listeners.push(listener);
+    }
+
+    fn notify(&mut self, kind: ChangeKind, item: &T)
+    where
+        T: Clone,
+    {
+        self.seq += 1;
+        let change = Change {
+            seq: self.seq,
+            kind,
+            at: Utc::now(),
+            item: item.clone(),
+        };
+        for listener in &self.listeners {
+            if let Err(e) = listener.on_change(&change) {
+                log::warn!("Listener failed on {} change #{}: {}", kind, change.seq, e);
+            }
+        }
+    }
+}
+
+impl<T: Clone, S: Crud<T>> Crud<T> for ObservedStore<T, S> {
+    fn create(&mut self, item: &T) -> super::Result<()> {
+        self.inner.create(item)?;
+        self.notify(ChangeKind::Created, item);
+        Ok(())
+    }
+
+    fn create_all(&mut self, items: &[T]) -> super::Result<Vec<super::Result<()>>> {
+        let results = self.inner.create_all(items)?;
+        for (item, result) in items.iter().zip(&results) {
+            if result.is_ok() {
+                self.notify(ChangeKind::Created, item);
+            }
+        }
+        Ok(results)
+    }
+
+    fn read_all(&self) -> super::Result<Vec<T>> {
+        self.inner.read_all()
+    }
+
+    fn read(&self, id: &Uuid) -> super::Result<T>
+    where
+        T: Identifiable,
+    {
+        self.inner.read(id)
+    }
+
+    fn iter(&self) -> super::Result<Box<dyn Iterator<Item = super::Result<T>> + Send>>
+    where
+        T: Send + 'static,
+    {
+        self.inner.iter()
+    }
+
+    fn update(&mut self, item: &T) -> super::Result<()> {
+        self.inner.update(item)?;
+        self.notify(ChangeKind::Updated, item);
+        Ok(())
+    }
+
+    fn delete(&mut self, item: &T) -> super::Result<()> {
+        self.inner.delete(item)?;
+        self.notify(ChangeKind::Deleted, item);
+        Ok(())
+    }
+
+    fn in_memory(&self) -> bool {
+        self.inner.in_memory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use common::Account;
+
+    use super::*;
+    use crate::crud::CrudError;
+
+    #[derive(Default)]
+    struct VecStore {
+        accounts: Vec<Account>,
+        fail: bool,
+    }
+
+    impl Crud<Account> for VecStore {
+        fn create(&mut self, item: &Account) -> crate::crud::Result<()> {
+            if self.fail {
+                return Err(CrudError::UnknownError);
+            }
+            self.accounts.push(item.clone());
+            Ok(())
+        }
+
+        fn read_all(&self) -> crate::crud::Result<Vec<Account>> {
+            Ok(self.accounts.clone())
+        }
+
+        fn update(&mut self, item: &Account) -> crate::crud::Result<()> {
+            for account in self.accounts.iter_mut().filter(|a| a.id == item.id) {
+                *account = item.clone();
+            }
+            Ok(())
+        }
+
+        fn delete(&mut self, item: &Account) -> crate::crud::Result<()> {
+            self.accounts.retain(|a| a.id != item.id);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        changes: Mutex<Vec<(u64, ChangeKind)>>,
+    }
+
+    impl Listener<Account> for Recorder {
+        fn on_change(&self, change: &Change<Account>) -> Result<(), Box<dyn std::error::Error>> {
+            self.changes.lock().unwrap().push((change.seq, change.kind));
+            Ok(())
+        }
+    }
+
+    struct Failing;
+
+    impl Listener<Account> for Failing {
+        fn on_change(&self, _: &Change<Account>) -> Result<(), Box<dyn std::error::Error>> {
+            Err("listener failed".into())
+        }
+    }
+
+    #[test]
+    fn listeners_see_changes_in_commit_order() {
+        let recorder = Arc::new(Recorder::default());
+        let mut store = ObservedStore::new(VecStore::default());
+        store.subscribe(recorder.clone());
+        let mut account = Account::new("Test Account");
+        store.create(&account).expect("Failed to create Account");
+        account.fullname = "Modified Account".to_string();
+        store.update(&account).expect("Failed to update Account");
+        store.delete(&account).expect("Failed to delete Account");
+        assert_eq!(
+            *recorder.changes.lock().unwrap(),
+            vec![
+                (1, ChangeKind::Created),
+                (2, ChangeKind::Updated),
+                (3, ChangeKind::Deleted)
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_write_does_not_notify() {
+        let recorder = Arc::new(Recorder::default());
+        let mut store = ObservedStore::new(VecStore {
+            fail: true,
+            ..Default::default()
+        });
+        store.subscribe(recorder.clone());
+        assert!(store.create(&Account::new("Test Account")).is_err());
+        assert!(recorder.changes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn failing_listener_does_not_roll_back_write() {
+        let recorder = Arc::new(Recorder::default());
+        let mut store = ObservedStore::new(VecStore::default());
+        store.subscribe(Arc::new(Failing));
+        store.subscribe(recorder.clone());
+        store
+            .create(&Account::new("Test Account"))
+            .expect("Failed to create Account");
+        assert_eq!(store.read_all().expect("Failed to read Accounts").len(), 1);
+        assert_eq!(recorder.changes.lock().unwrap().len(), 1);
+    }
+}
@@ -1,95 +1,642 @@
-use common::Account;
+use std::marker::PhantomData;
+
 use rusqlite::Connection;
 use uuid::Uuid;
 
-use super::{Crud, CrudError};
+use common::{Account, Group};
+
+use super::{
+    membership::{Membership, MembershipRules, MembershipStore, Side},
+    unique::UniqueRule,
+    Crud, CrudError, Entity,
+};
+use crate::auth::user::User;
+
+/// The statements of the table of `T`s, which is named after
+/// [`Entity::PLURAL`] and has a text column for the id and every field.
+struct Sql {
+    create_table: String,
+    /// The columns added to the table since it was first created.
+    add_columns: Vec<String>,
+    /// The name and the statement of an index of each unique field.
+    indexes: Vec<(String, String)>,
+    insert: String,
+    select_all: String,
+    select_by_id: String,
+    select_page: String,
+    update_by_id: String,
+    delete_by_id: String,
+}
+
+impl Sql {
+    /// The statements of the table of `T`s, whose fields of `rules` must be
+    /// fields of `T`.
+    fn of<T: Entity>(rules: &[UniqueRule]) -> Self {
+        let table = format!("\"{}\"", T::PLURAL);
+        let fields: Vec<String> = T::FIELDS
+            .iter()
+            .map(|field| format!("\"{}\"", field.name))
+            .collect();
+        let columns = std::iter::once("id".to_string())
+            .chain(fields.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let placeholders = (1..=fields.len() + 1)
+            .map(|i| format!("?{}", i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let assignments = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| format!("{} = ?{}", field, i + 1))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let column_defs = fields
+            .iter()
+            .map(|field| format!(",\n                {} TEXT", field))
+            .collect::<String>();
+        Sql {
+            create_table: format!(
+                "
+            CREATE TABLE IF NOT EXISTS {} (
+                id TEXT PRIMARY KEY{}
+            );
+        ",
+                table, column_defs
+            ),
+            add_columns: fields
+                .iter()
+                .map(|field| format!("ALTER TABLE {} ADD COLUMN {} TEXT", table, field))
+                .collect(),
+            indexes: rules
+                .iter()
+                .map(|rule| {
+                    let (suffix, collate) = match rule.ignore_case {
+                        true => ("_nocase", " COLLATE NOCASE"),
+                        false => ("", ""),
+                    };
+                    let name = format!("{}_{}{}", T::PLURAL, rule.field, suffix);
+                    let create_index = format!(
+                        "CREATE UNIQUE INDEX IF NOT EXISTS \"{}\" ON {} (\"{}\"{}) \
+                         WHERE \"{}\" <> ''",
+                        name, table, rule.field, collate, rule.field
+                    );
+                    (name, create_index)
+                })
+                .collect(),
+            insert: format!(
+                "INSERT INTO {} ({}) VALUES ({});",
+                table, columns, placeholders
+            ),
+            select_all: format!("SELECT {} FROM {}", columns, table),
+            select_by_id: format!("SELECT {} FROM {} WHERE id = ?1", columns, table),
+            select_page: format!(
+                "SELECT {} FROM {} WHERE id > ?1 ORDER BY id LIMIT ?2",
+                columns, table
+            ),
+            update_by_id: format!(
+                "UPDATE {} SET {} WHERE id = ?{}",
+                table,
+                assignments,
+                fields.len() + 1
+            ),
+            delete_by_id: format!("DELETE FROM {} WHERE id = ?1", table),
+        }
+    }
+}
+
+fn item_from_row<T: Entity>(row: &rusqlite::Row) -> rusqlite::Result<T> {
+    let uuid_str: String = row.get(0)?;
+    let values = (1..=T::FIELDS.len())
+        .map(|i| Ok(row.get::<_, Option<String>>(i)?.unwrap_or_default()))
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(T::from_values(
+        Uuid::parse_str(&uuid_str).expect("Failed to parse UUID"),
+        values,
+    ))
+}
+
+/// The id and the fields of `item`, in the order of the columns.
+fn params_of<T: Entity>(item: &T) -> Vec<String> {
+    std::iter::once(item.id().to_string())
+        .chain(item.values())
+        .collect()
+}
+
+/// How many items [`Pages`] reads per query.
+const PAGE_SIZE: usize = 500;
+
+/// Reads the items ordered by id, one page at a time. Each page starts
+/// after the last id of the previous one, so no read transaction is held
+/// open between pages and writers are never blocked by a slow reader.
+struct Pages<T> {
+    conn: Connection,
+    sql: String,
+    after: String,
+    page: std::vec::IntoIter<T>,
+    done: bool,
+}
+
+impl<T: Entity> Pages<T> {
+    fn next_page(&mut self) -> super::Result<()> {
+        let mut stmt = self.conn.prepare_cached(&self.sql)?;
+        let page = stmt
+            .query_map((&self.after, PAGE_SIZE as i64), item_from_row::<T>)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        self.done = page.len() < PAGE_SIZE;
+        if let Some(last) = page.last() {
+            self.after = last.id().to_string();
+        }
+        self.page = page.into_iter();
+        Ok(())
+    }
+}
+
+impl<T: Entity> Iterator for Pages<T> {
+    type Item = super::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(item) = self.page.next() {
+            return Some(Ok(item));
+        }
+        if self.done {
+            return None;
+        }
+        if let Err(err) = self.next_page() {
+            self.done = true;
+            return Some(Err(err));
+        }
+        self.page.next().map(Ok)
+    }
+}
+
+/// Keeps `T`s in a table of their own, so several entities may share a
+/// database.
+pub struct SqliteStore<T> {
+    filename: String,
+    sql: Sql,
+    /// The unique rules whose index could not be built as the stored items
+    /// break them.
+    unindexed: Vec<UniqueRule>,
+    entity: PhantomData<fn() -> T>,
+}
+
+impl<T: Entity> SqliteStore<T> {
+    /// A store that keeps the [`Entity::UNIQUE`] fields unique.
+    #[cfg(test)]
+    pub fn new(filename: &str) -> Self {
+        Self::with_unique(filename, &UniqueRule::defaults_of::<T>())
+            .expect("Entity::UNIQUE names fields of the entity")
+    }
+
+    /// A store that indexes the fields of the unique `rules`, or
+    /// [`CrudError::UnknownField`] if `T` lacks one of them. The indexes of
+    /// fields no longer unique are dropped. A rule the stored items already
+    /// break is left [`unindexed`](SqliteStore::unindexed).
+    pub fn with_unique(filename: &str, rules: &[UniqueRule]) -> super::Result<Self> {
+        for rule in rules {
+            rule.position_of::<T>()?;
+        }
+        let mut store = SqliteStore {
+            filename: filename.to_string(),
+            sql: Sql::of::<T>(rules),
+            unindexed: Vec::new(),
+            entity: PhantomData,
+        };
+        let conn = store.connect()?;
+        let indexes = conn
+            .prepare_cached(SQL_SELECT_INDEXES)?
+            .query_map([T::PLURAL], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for index in indexes {
+            if !store.sql.indexes.iter().any(|(name, _)| *name == index) {
+                conn.execute(&format!("DROP INDEX \"{}\"", index), ())?;
+            }
+        }
+        for ((name, create_index), rule) in store.sql.indexes.iter().zip(rules) {
+            match conn.execute(create_index, ()) {
+                Ok(_) => {}
+                // The stored items are left as they are, to be merged by
+                // the `dedupe` command.
+                Err(err) if is_unique_violation(&err) => {
+                    log::warn!(
+                        "Not indexing {} while some {} share a value",
+                        name,
+                        T::PLURAL
+                    );
+                    store.unindexed.push(rule.clone());
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(store)
+    }
+
+    /// The unique rules without an index, which the store does not keep;
+    /// see [`super::open_store`].
+    pub fn unindexed(&self) -> &[UniqueRule] {
+        &self.unindexed
+    }
+
+    /// Opens the database and creates the table, or adds the columns of
+    /// fields it lacks.
+    fn connect(&self) -> super::Result<Connection> {
+        let conn = Connection::open(&self.filename)?;
+        // Off by default; the memberships refer to their items by foreign
+        // keys.
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.execute(&self.sql.create_table, ())?;
+        for add_column in &self.sql.add_columns {
+            // Fails once the column is there.
+            let _ = conn.execute(add_column, ());
+        }
+        Ok(conn)
+    }
+}
+
+/// The indexes created by [`SqliteStore::with_unique`] on the table `?1`, which
+/// leaves out those SQLite creates for primary keys.
+const SQL_SELECT_INDEXES: &str =
+    "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = ?1 AND sql IS NOT NULL";
+
+/// Whether `err` is a taken primary key or unique value.
+fn is_unique_violation(err: &rusqlite::Error) -> bool {
+    matches!(
+        err,
+        rusqlite::Error::SqliteFailure(err, _)
+            if err.code == rusqlite::ErrorCode::ConstraintViolation
+    )
+}
+
+impl<T: Entity> Crud<T> for SqliteStore<T> {
+    fn create(&mut self, item: &T) -> Result<(), CrudError> {
+        let connection = self.connect()?;
+        let params = params_of(item);
+        match connection.execute(&self.sql.insert, rusqlite::params_from_iter(&params)) {
+            Ok(_) => Ok(()),
+            Err(err) if is_unique_violation(&err) => Err(CrudError::AlreadyExists),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn read_all(&self) -> Result<Vec<T>, CrudError> {
+        log::debug!("Reading all {} from '{}'", T::PLURAL, &self.filename);
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(&self.sql.select_all)?;
+        let items = stmt
+            .query_map([], item_from_row::<T>)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(items)
+    }
+
+    /// Pages through the items by id. Items written while the iterator is
+    /// being drained may or may not be included.
+    fn iter(&self) -> super::Result<Box<dyn Iterator<Item = super::Result<T>> + Send>> {
+        let conn = self.connect()?;
+        Ok(Box::new(Pages::<T> {
+            conn,
+            sql: self.sql.select_page.clone(),
+            after: String::new(),
+            page: Vec::new().into_iter(),
+            done: false,
+        }))
+    }
+
+    fn read(&self, id: &Uuid) -> super::Result<T> {
+        log::debug!("Reading {} id='{}' from '{}'", T::NAME, id, &self.filename);
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(&self.sql.select_by_id)?;
+        match stmt.query_row([id.to_string()], item_from_row::<T>) {
+            Ok(item) => Ok(item),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Err(CrudError::NotFound),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn update(&mut self, item: &T) -> super::Result<()> {
+        log::debug!(
+            "Updating {} id='{}' in '{}'",
+            T::NAME,
+            item.id(),
+            &self.filename
+        );
+        let conn = self.connect()?;
+        let mut params = item.values();
+        params.push(item.id().to_string());
+        match conn.execute(&self.sql.update_by_id, rusqlite::params_from_iter(&params)) {
+            Ok(0) => Err(CrudError::NotFound),
+            Ok(_) => Ok(()),
+            Err(err) if is_unique_violation(&err) => Err(CrudError::AlreadyExists),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn delete(&mut self, item: &T) -> super::Result<()> {
+        log::debug!(
+            "Deleting {} id='{}' from '{}'",
+            T::NAME,
+            item.id(),
+            &self.filename
+        );
+        let conn = self.connect()?;
+        match conn.execute(&self.sql.delete_by_id, [item.id().to_string()]) {
+            Ok(_) => Ok(()),
+            Err(err) if is_foreign_key_violation(&err) => Err(CrudError::InUse),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Whether `err` is a failed foreign key. `ON DELETE RESTRICT` fails with
+/// the extended code of triggers rather than of foreign keys, so the
+/// message tells.
+fn is_foreign_key_violation(err: &rusqlite::Error) -> bool {
+    matches!(
+        err,
+        rusqlite::Error::SqliteFailure(err, Some(message))
+            if err.code == rusqlite::ErrorCode::ConstraintViolation
+                && message.starts_with("FOREIGN KEY")
+    )
+}
+
+/// The `memberships` table, whose foreign keys apply `rules` to the
+/// accounts and groups of the same database.
+fn create_memberships_table(name: &str, rules: MembershipRules) -> String {
+    format!(
+        "
+            CREATE TABLE IF NOT EXISTS {} (
+                group_id TEXT NOT NULL REFERENCES groups(id) ON DELETE {},
+                account_id TEXT NOT NULL REFERENCES accounts(id) ON DELETE {},
+                PRIMARY KEY (group_id, account_id)
+            );
+        ",
+        name, rules.on_group_delete, rules.on_account_delete
+    )
+}
+const SQL_SELECT_MEMBERSHIP_RULES: &str =
+    "SELECT \"table\", on_delete FROM pragma_foreign_key_list('memberships')";
+const SQL_INSERT_MEMBERSHIP: &str =
+    "INSERT INTO memberships (group_id, account_id) VALUES (?1, ?2)";
+const SQL_SELECT_MEMBERSHIPS: &str = "SELECT group_id, account_id FROM memberships";
+const SQL_DELETE_MEMBERSHIP: &str =
+    "DELETE FROM memberships WHERE group_id = ?1 AND account_id = ?2";
+const SQL_DELETE_MEMBERSHIPS_OF_GROUP: &str = "DELETE FROM memberships WHERE group_id = ?1";
+const SQL_DELETE_MEMBERSHIPS_OF_ACCOUNT: &str = "DELETE FROM memberships WHERE account_id = ?1";
+
+fn membership_from_row(row: &rusqlite::Row) -> rusqlite::Result<Membership> {
+    let group: String = row.get(0)?;
+    let account: String = row.get(1)?;
+    Ok(Membership {
+        group: Uuid::parse_str(&group).expect("Failed to parse UUID"),
+        account: Uuid::parse_str(&account).expect("Failed to parse UUID"),
+    })
+}
+
+/// Keeps the memberships in a `memberships` table next to the `accounts`
+/// and `groups` tables, which SQLite keeps them consistent with.
+pub struct SqliteMembershipStore {
+    filename: String,
+    rules: MembershipRules,
+}
+
+impl SqliteMembershipStore {
+    pub fn new(filename: &str, rules: MembershipRules) -> Self {
+        SqliteMembershipStore {
+            filename: filename.to_string(),
+            rules,
+        }
+    }
 
-const SQL_CREATE_USER_TABLE: &str = "
-            CREATE TABLE IF NOT EXISTS accounts (
+    /// Opens the database and creates the tables. A `memberships` table
+    /// created with other rules is rebuilt, as SQLite cannot change the
+    /// foreign keys of a table.
+    fn connect(&self) -> super::Result<Connection> {
+        let conn = Connection::open(&self.filename)?;
+        conn.execute(&Sql::of::<Account>(&[]).create_table, ())?;
+        conn.execute(&Sql::of::<Group>(&[]).create_table, ())?;
+        conn.execute(&create_memberships_table("memberships", self.rules), ())?;
+        let mut stmt = conn.prepare(SQL_SELECT_MEMBERSHIP_RULES)?;
+        let stale = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+            .into_iter()
+            .any(|(table, on_delete)| {
+                let rule = match table.as_str() {
+                    "groups" => self.rules.on_group_delete,
+                    _ => self.rules.on_account_delete,
+                };
+                on_delete != rule.to_string()
+            });
+        drop(stmt);
+        if stale {
+            log::info!(
+                "Changing the delete rules of the memberships in '{}'",
+                self.filename
+            );
+            conn.execute_batch(&format!(
+                "BEGIN;
+                {}
+                INSERT INTO memberships_new SELECT group_id, account_id FROM memberships;
+                DROP TABLE memberships;
+                ALTER TABLE memberships_new RENAME TO memberships;
+                COMMIT;",
+                create_memberships_table("memberships_new", self.rules)
+            ))?;
+        }
+        conn.pragma_update(None, "foreign_keys", true)?;
+        Ok(conn)
+    }
+}
+
+impl MembershipStore for SqliteMembershipStore {
+    fn read_all(&self) -> super::Result<Vec<Membership>> {
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(SQL_SELECT_MEMBERSHIPS)?;
+        let memberships = stmt
+            .query_map([], membership_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(memberships)
+    }
+
+    fn add(&mut self, membership: &Membership) -> super::Result<()> {
+        let conn = self.connect()?;
+        match conn.execute(
+            SQL_INSERT_MEMBERSHIP,
+            [membership.group.to_string(), membership.account.to_string()],
+        ) {
+            Ok(_) => Ok(()),
+            Err(err) if is_foreign_key_violation(&err) => Err(CrudError::NotFound),
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                Err(CrudError::AlreadyExists)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn remove(&mut self, membership: &Membership) -> super::Result<()> {
+        let conn = self.connect()?;
+        let removed = conn.execute(
+            SQL_DELETE_MEMBERSHIP,
+            [membership.group.to_string(), membership.account.to_string()],
+        )?;
+        match removed {
+            0 => Err(CrudError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    fn remove_all(&mut self, side: Side, id: &Uuid) -> super::Result<()> {
+        let sql = match side {
+            Side::Account => SQL_DELETE_MEMBERSHIPS_OF_ACCOUNT,
+            Side::Group => SQL_DELETE_MEMBERSHIPS_OF_GROUP,
+        };
+        self.connect()?.execute(sql, [id.to_string()])?;
+        Ok(())
+    }
+
+    fn enforces_rules(&self) -> bool {
+        true
+    }
+}
+
+const SQL_CREATE_USERS_TABLE: &str = "
+            CREATE TABLE IF NOT EXISTS users (
                 id TEXT PRIMARY KEY,
-                fullname TEXT
+                username TEXT NOT NULL UNIQUE,
+                role TEXT NOT NULL DEFAULT 'admin',
+                password_hash TEXT NOT NULL
             );
         ";
-const SQL_INSERT_USER: &str = "INSERT INTO accounts (id, fullname) VALUES (?1, ?2);";
-// const SQL_SELECT_ALL_USERS: &str = "SELECT id, fullname FROM accounts";
-const SQL_SELECT_USERS_BY_ID: &str = "SELECT id, fullname FROM accounts WHERE id = ?1";
-const SQL_UPDATE_USER_BY_ID: &str = "UPDATE accounts SET fullname = ?1 WHERE id = ?2";
-const SQL_DELETE_USER_BY_ID: &str = "DELETE FROM accounts WHERE id = ?1";
+/// Operators added before there were roles are admins.
+const SQL_ADD_ROLE_COLUMN: &str = "ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'admin'";
+const SQL_INSERT_OPERATOR: &str =
+    "INSERT INTO users (id, username, role, password_hash) VALUES (?1, ?2, ?3, ?4);";
+const SQL_SELECT_OPERATORS: &str = "SELECT id, username, role, password_hash FROM users";
+const SQL_UPDATE_OPERATOR_BY_ID: &str =
+    "UPDATE users SET username = ?1, role = ?2, password_hash = ?3 WHERE id = ?4";
+const SQL_DELETE_OPERATOR_BY_ID: &str = "DELETE FROM users WHERE id = ?1";
 
+fn user_from_row(row: &rusqlite::Row) -> rusqlite::Result<User> {
+    let uuid_str: String = row.get(0)?;
+    let role: String = row.get(2)?;
+    Ok(User {
+        id: Uuid::parse_str(&uuid_str).expect("Failed to parse UUID"),
+        username: row.get(1)?,
+        role: role.parse().map_err(|e: String| {
+            rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, e.into())
+        })?,
+        password_hash: row.get(3)?,
+    })
+}
+
+/// Keeps the operators who log in to the web server in a `users` table,
+/// which may share its database with the accounts.
 #[derive(Debug, Clone)]
-pub struct SqliteAccountStore {
+pub struct SqliteUserStore {
     filename: String,
 }
 
-impl SqliteAccountStore {
+impl SqliteUserStore {
     pub fn new(filename: &str) -> Self {
-        SqliteAccountStore {
+        SqliteUserStore {
             filename: filename.to_string(),
         }
     }
-}
 
-impl Crud<Account> for SqliteAccountStore {
-    fn create(&mut self, account: &Account) -> Result<(), CrudError> {
-        let connection = Connection::open(&self.filename).unwrap();
-        connection.execute(SQL_CREATE_USER_TABLE, ()).unwrap();
-        connection.execute(SQL_INSERT_USER, [&account.id.to_string(), &account.fullname]).unwrap();
-        Ok(())
+    fn connect(&self) -> super::Result<Connection> {
+        let conn = Connection::open(&self.filename)?;
+        conn.execute(SQL_CREATE_USERS_TABLE, ())?;
+        // Fails once the column is there.
+        let _ = conn.execute(SQL_ADD_ROLE_COLUMN, ());
+        Ok(conn)
     }
+}
 
-    fn read_all(&self) -> Result<Vec<Account>, CrudError> {
-        log::debug!("Reading all Users from '{}'", &self.filename);
-        if let Ok(connection) = Connection::open(&self.filename) {
-            let query = "SELECT id, fullname FROM accounts";
-            if let Ok(mut stmt) = connection.prepare(query) {
-                let user_iter = stmt.query_map([], |row| {
-                    let uuid_str: String = row.get(0)?;
-                    Ok(Account {
-                        id: Uuid::parse_str(&uuid_str).expect("Failed to parse UUID"),
-                        fullname: row.get(1)?,
-                    })
-                })?;
-                let accounts = user_iter.map(|r| r.expect("Failed to SELECT Account")).collect();
-                Ok(accounts)
-            } else {
-                log::warn!("Error encountered preparing statement");
-                Ok(Vec::new())
+impl Crud<User> for SqliteUserStore {
+    fn create(&mut self, user: &User) -> super::Result<()> {
+        let conn = self.connect()?;
+        match conn.execute(
+            SQL_INSERT_OPERATOR,
+            [
+                &user.id.to_string(),
+                &user.username,
+                &user.role.to_string(),
+                &user.password_hash,
+            ],
+        ) {
+            Ok(_) => Ok(()),
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                Err(CrudError::AlreadyExists)
             }
-        } else {
-            log::warn!("Error opening SQLite connection");
-            Ok(Vec::new())
+            Err(err) => Err(err.into()),
         }
     }
 
-    fn update(&mut self, item: &Account) -> super::Result<()> {
-        log::debug!("Updating Account id='{}' from '{}'", &item.id, &self.filename);
-        let conn = Connection::open(&self.filename)?;
-        let mut stmt = conn.prepare(SQL_UPDATE_USER_BY_ID)?;
-        stmt.execute([&item.fullname, &item.id.to_string()])?;
+    fn read_all(&self) -> super::Result<Vec<User>> {
+        let conn = self.connect()?;
+        let mut stmt = conn.prepare(SQL_SELECT_OPERATORS)?;
+        let users = stmt
+            .query_map([], user_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(users)
+    }
+
+    fn update(&mut self, user: &User) -> super::Result<()> {
+        let conn = self.connect()?;
+        let updated = conn.execute(
+            SQL_UPDATE_OPERATOR_BY_ID,
+            [
+                &user.username,
+                &user.role.to_string(),
+                &user.password_hash,
+                &user.id.to_string(),
+            ],
+        )?;
+        if updated == 0 {
+            return Err(CrudError::NotFound);
+        }
         Ok(())
     }
 
-    fn delete(&mut self, item: &Account) -> super::Result<()> {
-        log::debug!("Deleting Account id='{}' from '{}'", &item.id, &self.filename);
-        let conn = Connection::open(&self.filename)?;
-        let mut stmt = conn.prepare(SQL_DELETE_USER_BY_ID)?;
-        stmt.execute([&item.id.to_string()])?;
+    fn delete(&mut self, user: &User) -> super::Result<()> {
+        let conn = self.connect()?;
+        conn.execute(SQL_DELETE_OPERATOR_BY_ID, [&user.id.to_string()])?;
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use common::{Account, Group};
     use tempfile::tempdir;
     use uuid::uuid;
 
     use super::*;
+    use crate::{
+        auth::Role,
+        crud::{membership::OnDelete, open_store, Strategy},
+    };
+
+    /// The parameters of [`Sql::insert`] for an account.
+    fn row(id: Uuid, fullname: &str) -> rusqlite::ParamsFromIter<Vec<String>> {
+        let account = Account {
+            id,
+            ..Account::new(fullname)
+        };
+        rusqlite::params_from_iter(params_of(&account))
+    }
 
     #[test]
     fn create_creates_file_when_not_exist() {
         let dir = tempdir().expect("Failed to create temp directory");
         let sqlite_path = dir.path().join("accounts.sqlite");
-        let mut store = SqliteAccountStore::new(sqlite_path.display().to_string().as_str());
+        let mut store = SqliteStore::<Account>::new(sqlite_path.display().to_string().as_str());
         store
             .create(&Account::new("Test Account"))
             .expect("Failed to create new Account");
@@ -100,7 +647,7 @@ mod tests {
     fn read_all_returns_none_when_file_not_exist() {
         let dir = tempdir().expect("Failed to create temp directory");
         let sqlite_path = dir.path().join("accounts.sqlite");
-        let store = SqliteAccountStore::new(sqlite_path.display().to_string().as_str());
+        let store = SqliteStore::<Account>::new(sqlite_path.display().to_string().as_str());
         let accounts = store.read_all().expect("Failed to read Users");
         assert_eq!(accounts.len(), 0);
     }
@@ -108,79 +655,416 @@ mod tests {
     #[test]
     fn read_all_returns_one_of_one() {
         let dir = tempdir().expect("Failed to create temp directory");
+        let sql = Sql::of::<Account>(&[]);
         let sqlite_path = dir.path().join("accounts.sqlite");
-        let conn = Connection::open(&sqlite_path).expect(&format!("Failed to open {}", &sqlite_path.display()));
-        conn.execute(SQL_CREATE_USER_TABLE, ()).expect(&format!("Failed to execute {}", SQL_CREATE_USER_TABLE));
-        conn.execute(SQL_INSERT_USER, (uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8").to_string(), "Test Account")).expect(&format!("Failed to execute {}", SQL_INSERT_USER));
-        let store = SqliteAccountStore::new(sqlite_path.display().to_string().as_str());
+        let conn = Connection::open(&sqlite_path)
+            .unwrap_or_else(|_| panic!("Failed to open {}", &sqlite_path.display()));
+        conn.execute(&sql.create_table, ())
+            .unwrap_or_else(|_| panic!("Failed to execute {}", sql.create_table));
+        conn.execute(
+            &sql.insert,
+            row(
+                uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8"),
+                "Test Account",
+            ),
+        )
+        .unwrap_or_else(|_| panic!("Failed to execute {}", sql.insert));
+        let store = SqliteStore::<Account>::new(sqlite_path.display().to_string().as_str());
         let accounts = store.read_all().expect("Failed to read Users");
         assert_eq!(accounts.len(), 1);
-        assert_eq!(accounts.get(0).unwrap().fullname, "Test Account");
+        assert_eq!(accounts.first().unwrap().fullname, "Test Account");
+    }
+
+    #[test]
+    fn read_returns_not_found_for_unknown_id() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let sqlite_path = dir.path().join("accounts.sqlite");
+        let mut store = SqliteStore::<Account>::new(sqlite_path.display().to_string().as_str());
+        let account = Account::new("Test Account");
+        store
+            .create(&account)
+            .expect("Failed to create new Account");
+        assert_eq!(
+            store.read(&account.id).expect("Failed to read Account"),
+            account
+        );
+        assert!(matches!(
+            store.read(&Uuid::new_v4()),
+            Err(CrudError::NotFound)
+        ));
     }
 
     #[test]
     fn update_one_of_one() {
         let dir = tempdir().expect("Failed to create temp directory");
+        let sql = Sql::of::<Account>(&[]);
         let sqlite_path = dir.path().join("accounts.csv");
-        let conn = Connection::open(&sqlite_path).expect(&format!("Failed to open {}", &sqlite_path.display()));
-        conn.execute(SQL_CREATE_USER_TABLE, ()).expect(&format!("Failed to execute {}", SQL_CREATE_USER_TABLE));
-        conn.execute(SQL_INSERT_USER, (uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8").to_string(), "Test Account")).expect(&format!("Failed to execute {}", SQL_INSERT_USER));
-        let mut store = SqliteAccountStore::new(sqlite_path.display().to_string().as_str());
+        let conn = Connection::open(&sqlite_path)
+            .unwrap_or_else(|_| panic!("Failed to open {}", &sqlite_path.display()));
+        conn.execute(&sql.create_table, ())
+            .unwrap_or_else(|_| panic!("Failed to execute {}", sql.create_table));
+        conn.execute(
+            &sql.insert,
+            row(
+                uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8"),
+                "Test Account",
+            ),
+        )
+        .unwrap_or_else(|_| panic!("Failed to execute {}", sql.insert));
+        let mut store = SqliteStore::<Account>::new(sqlite_path.display().to_string().as_str());
         let mut user_updated = Account::new("Modified Account");
         user_updated.id = uuid::uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8");
-        store.update(&user_updated).expect("Failed to update Account");
-        let mut stmt = conn.prepare(SQL_SELECT_USERS_BY_ID).expect(&format!("Failed to prepare {}", SQL_SELECT_USERS_BY_ID));
-        let actual_row: String = stmt.query_row([uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8").to_string()], |row| row.get(1)).expect(&format!("Failed to query {}", SQL_SELECT_USERS_BY_ID));
+        store
+            .update(&user_updated)
+            .expect("Failed to update Account");
+        let mut stmt = conn
+            .prepare(&sql.select_by_id)
+            .unwrap_or_else(|_| panic!("Failed to prepare {}", sql.select_by_id));
+        let actual_row: String = stmt
+            .query_row(
+                [uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8").to_string()],
+                |row| row.get(1),
+            )
+            .unwrap_or_else(|_| panic!("Failed to query {}", sql.select_by_id));
         assert_eq!(actual_row, "Modified Account");
     }
 
     #[test]
     fn update_one_of_two() {
         let dir = tempdir().expect("Failed to create temp directory");
+        let sql = Sql::of::<Account>(&[]);
         let sqlite_path = dir.path().join("accounts.csv");
-        let conn = Connection::open(&sqlite_path).expect(&format!("Failed to open {}", &sqlite_path.display()));
-        conn.execute(SQL_CREATE_USER_TABLE, ()).expect(&format!("Failed to execute {}", SQL_CREATE_USER_TABLE));
-        conn.execute(SQL_INSERT_USER, (uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8").to_string(), "Test Account 1")).expect(&format!("Failed to execute {}", SQL_INSERT_USER));
-        conn.execute(SQL_INSERT_USER, (uuid!("67e55044-10b1-426f-9247-bb680e5fe0c9").to_string(), "Test Account 2")).expect(&format!("Failed to execute {}", SQL_INSERT_USER));
-        let mut store = SqliteAccountStore::new(sqlite_path.display().to_string().as_str());
+        let conn = Connection::open(&sqlite_path)
+            .unwrap_or_else(|_| panic!("Failed to open {}", &sqlite_path.display()));
+        conn.execute(&sql.create_table, ())
+            .unwrap_or_else(|_| panic!("Failed to execute {}", sql.create_table));
+        conn.execute(
+            &sql.insert,
+            row(
+                uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8"),
+                "Test Account 1",
+            ),
+        )
+        .unwrap_or_else(|_| panic!("Failed to execute {}", sql.insert));
+        conn.execute(
+            &sql.insert,
+            row(
+                uuid!("67e55044-10b1-426f-9247-bb680e5fe0c9"),
+                "Test Account 2",
+            ),
+        )
+        .unwrap_or_else(|_| panic!("Failed to execute {}", sql.insert));
+        let mut store = SqliteStore::<Account>::new(sqlite_path.display().to_string().as_str());
         let mut user_updated = Account::new("Modified Account 1");
         user_updated.id = uuid::uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8");
-        store.update(&user_updated).expect("Failed to update Account");
-        let mut stmt = conn.prepare(SQL_SELECT_USERS_BY_ID).expect(&format!("Failed to prepare {}", SQL_SELECT_USERS_BY_ID));
-        let actual_user_1: String = stmt.query_row([uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8").to_string()], |row| row.get(1)).expect(&format!("Failed to query {}", SQL_SELECT_USERS_BY_ID));
+        store
+            .update(&user_updated)
+            .expect("Failed to update Account");
+        let mut stmt = conn
+            .prepare(&sql.select_by_id)
+            .unwrap_or_else(|_| panic!("Failed to prepare {}", sql.select_by_id));
+        let actual_user_1: String = stmt
+            .query_row(
+                [uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8").to_string()],
+                |row| row.get(1),
+            )
+            .unwrap_or_else(|_| panic!("Failed to query {}", sql.select_by_id));
         assert_eq!(actual_user_1, "Modified Account 1");
     }
 
+    #[test]
+    fn update_missing_item_is_not_found() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let sqlite_path = dir.path().join("accounts.sqlite");
+        let mut store = SqliteStore::<Account>::new(sqlite_path.display().to_string().as_str());
+        store
+            .create(&Account::new("Test Account 1"))
+            .expect("Failed to create Account");
+        let missing = Account::new("Test Account 2");
+        assert!(matches!(store.update(&missing), Err(CrudError::NotFound)));
+        assert_eq!(store.read_all().expect("Failed to read Accounts").len(), 1);
+    }
+
     #[test]
     fn delete_one_of_one() {
         let dir = tempdir().expect("Failed to create temp directory");
+        let sql = Sql::of::<Account>(&[]);
         let sqlite_path = dir.path().join("accounts.csv");
-        let conn = Connection::open(&sqlite_path).expect(&format!("Failed to open {}", &sqlite_path.display()));
-        conn.execute(SQL_CREATE_USER_TABLE, ()).expect(&format!("Failed to execute {}", SQL_CREATE_USER_TABLE));
-        conn.execute(SQL_INSERT_USER, (uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8").to_string(), "Test Account 1")).expect(&format!("Failed to execute {}", SQL_INSERT_USER));
-        let mut store = SqliteAccountStore::new(sqlite_path.display().to_string().as_str());
+        let conn = Connection::open(&sqlite_path)
+            .unwrap_or_else(|_| panic!("Failed to open {}", &sqlite_path.display()));
+        conn.execute(&sql.create_table, ())
+            .unwrap_or_else(|_| panic!("Failed to execute {}", sql.create_table));
+        conn.execute(
+            &sql.insert,
+            row(
+                uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8"),
+                "Test Account 1",
+            ),
+        )
+        .unwrap_or_else(|_| panic!("Failed to execute {}", sql.insert));
+        let mut store = SqliteStore::<Account>::new(sqlite_path.display().to_string().as_str());
         let mut account = Account::new("Test Account");
         account.id = uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8");
         store.delete(&account).expect("Failed to delete Account");
-        let mut stmt = conn.prepare(SQL_SELECT_USERS_BY_ID).expect(&format!("Failed to prepare {}", SQL_SELECT_USERS_BY_ID));
-        let mut result = stmt.query([uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8").to_string()]).expect(&format!("Failed to execute {}", SQL_SELECT_USERS_BY_ID));
+        let mut stmt = conn
+            .prepare(&sql.select_by_id)
+            .unwrap_or_else(|_| panic!("Failed to prepare {}", sql.select_by_id));
+        let mut result = stmt
+            .query([uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8").to_string()])
+            .unwrap_or_else(|_| panic!("Failed to execute {}", sql.select_by_id));
         assert!(result.next().expect("").is_none())
     }
 
     #[test]
     fn delete_one_of_two() {
         let dir = tempdir().expect("Failed to create temp directory");
+        let sql = Sql::of::<Account>(&[]);
         let sqlite_path = dir.path().join("accounts.csv");
-        let conn = Connection::open(&sqlite_path).expect(&format!("Failed to open {}", &sqlite_path.display()));
-        conn.execute(SQL_CREATE_USER_TABLE, ()).expect(&format!("Failed to execute {}", SQL_CREATE_USER_TABLE));
-        conn.execute(SQL_INSERT_USER, (uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8").to_string(), "Test Account 1")).expect(&format!("Failed to execute {}", SQL_INSERT_USER));
-        conn.execute(SQL_INSERT_USER, (uuid!("67e55044-10b1-426f-9247-bb680e5fe0c9").to_string(), "Test Account 2")).expect(&format!("Failed to execute {}", SQL_INSERT_USER));
-        let mut store = SqliteAccountStore::new(sqlite_path.display().to_string().as_str());
+        let conn = Connection::open(&sqlite_path)
+            .unwrap_or_else(|_| panic!("Failed to open {}", &sqlite_path.display()));
+        conn.execute(&sql.create_table, ())
+            .unwrap_or_else(|_| panic!("Failed to execute {}", sql.create_table));
+        conn.execute(
+            &sql.insert,
+            row(
+                uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8"),
+                "Test Account 1",
+            ),
+        )
+        .unwrap_or_else(|_| panic!("Failed to execute {}", sql.insert));
+        conn.execute(
+            &sql.insert,
+            row(
+                uuid!("67e55044-10b1-426f-9247-bb680e5fe0c9"),
+                "Test Account 2",
+            ),
+        )
+        .unwrap_or_else(|_| panic!("Failed to execute {}", sql.insert));
+        let mut store = SqliteStore::<Account>::new(sqlite_path.display().to_string().as_str());
         let mut account = Account::new("Test Account");
         account.id = uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8");
         store.delete(&account).expect("Failed to delete Account");
-        let mut stmt = conn.prepare(SQL_SELECT_USERS_BY_ID).expect(&format!("Failed to prepare {}", SQL_SELECT_USERS_BY_ID));
-        let mut result = stmt.query([uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8").to_string()]).expect(&format!("Failed to execute {}", SQL_SELECT_USERS_BY_ID));
+        let mut stmt = conn
+            .prepare(&sql.select_by_id)
+            .unwrap_or_else(|_| panic!("Failed to prepare {}", sql.select_by_id));
+        let mut result = stmt
+            .query([uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8").to_string()])
+            .unwrap_or_else(|_| panic!("Failed to execute {}", sql.select_by_id));
         assert!(result.next().expect("").is_none())
     }
+
+    #[test]
+    fn iter_pages_through_all_accounts() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let sqlite_path = dir.path().join("accounts.sqlite");
+        let mut store = SqliteStore::<Account>::new(sqlite_path.display().to_string().as_str());
+        assert_eq!(store.iter().expect("Failed to iterate Accounts").count(), 0);
+        let mut accounts: Vec<Account> = (0..PAGE_SIZE * 2 + 1)
+            .map(|i| Account::new(&format!("Test Account {}", i)))
+            .collect();
+        for account in &accounts {
+            store.create(account).expect("Failed to create new Account");
+        }
+        let read = store
+            .iter()
+            .expect("Failed to iterate Accounts")
+            .collect::<super::super::Result<Vec<_>>>()
+            .expect("Failed to read Accounts");
+        accounts.sort_by_key(|account| account.id.to_string());
+        assert_eq!(read, accounts);
+    }
+
+    #[test]
+    fn create_with_taken_id_already_exists() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let sqlite_path = dir.path().join("accounts.sqlite");
+        let mut store = SqliteStore::<Account>::new(sqlite_path.display().to_string().as_str());
+        let account = Account::new("Test Account");
+        store
+            .create(&account)
+            .expect("Failed to create new Account");
+        assert!(matches!(
+            store.create(&account),
+            Err(CrudError::AlreadyExists)
+        ));
+        assert_eq!(store.read_all().expect("Failed to read Users").len(), 1);
+    }
+
+    #[test]
+    fn old_tables_get_the_new_columns_and_unique_emails() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let path = dir.path().join("accounts.sqlite").display().to_string();
+        let old = Account::new("Schroeter, Erich");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(&format!(
+                "CREATE TABLE accounts (id TEXT PRIMARY KEY, fullname TEXT);
+                 INSERT INTO accounts VALUES ('{}', '{}');",
+                old.id, old.fullname
+            ))
+            .unwrap();
+        let mut store = SqliteStore::<Account>::new(&path);
+        assert_eq!(store.read(&old.id).expect("Failed to read Account"), old);
+
+        let mut account = Account::new("Erich");
+        account.email = Some("erich@example.com".to_string());
+        account.tags = vec!["staff".to_string()];
+        account
+            .attributes
+            .insert("floor".to_string(), common::Attribute::Integer(3));
+        store.create(&account).expect("Failed to create Account");
+        assert_eq!(
+            store.read(&account.id).expect("Failed to read Account"),
+            account
+        );
+        let mut copy = Account::new("Erich S.");
+        copy.email = account.email.clone();
+        assert!(matches!(store.create(&copy), Err(CrudError::AlreadyExists)));
+        let mut old = old;
+        old.email = account.email.clone();
+        assert!(matches!(store.update(&old), Err(CrudError::AlreadyExists)));
+        store
+            .create(&Account::new("No Email"))
+            .expect("Failed to create Account");
+    }
+
+    #[test]
+    fn unique_rules_are_indexed_until_they_change() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let path = dir.path().join("accounts.sqlite").display().to_string();
+        let rules = [UniqueRule {
+            field: "fullname".to_string(),
+            ignore_case: true,
+        }];
+        let mut store = SqliteStore::<Account>::with_unique(&path, &rules).unwrap();
+        store
+            .create(&Account::new("Jane Doe"))
+            .expect("Failed to create Account");
+        let copy = Account::new("JANE DOE");
+        assert!(matches!(store.create(&copy), Err(CrudError::AlreadyExists)));
+
+        // Without the rule its index is dropped, so the copy may be stored.
+        let mut store = SqliteStore::<Account>::with_unique(&path, &[]).unwrap();
+        store.create(&copy).expect("Failed to create Account");
+        // The rule cannot be indexed again while the copy is there, so it
+        // is kept by scanning the stored accounts instead.
+        let store = SqliteStore::<Account>::with_unique(&path, &rules).unwrap();
+        assert_eq!(store.unindexed(), rules);
+        let mut store = open_store(Strategy::Sqlite, Some(&path), &rules).unwrap();
+        let third = Account::new("jane doe");
+        assert!(matches!(
+            store.create(&third),
+            Err(CrudError::AlreadyExists)
+        ));
+        assert_eq!(store.read_all().expect("Failed to read Accounts").len(), 2);
+
+        let unknown = [UniqueRule::new("nickname")];
+        assert!(matches!(
+            SqliteStore::<Account>::with_unique(&path, &unknown),
+            Err(CrudError::UnknownField(_))
+        ));
+    }
+
+    #[test]
+    fn groups_share_the_database_with_accounts() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let sqlite_path = dir.path().join("accounts.sqlite");
+        let path = sqlite_path.display().to_string();
+        let mut accounts = SqliteStore::<Account>::new(&path);
+        let mut groups = SqliteStore::<Group>::new(&path);
+        let account = Account::new("Test Account");
+        accounts.create(&account).expect("Failed to create Account");
+        let mut group = Group::new("Sales");
+        groups.create(&group).expect("Failed to create Group");
+        assert!(matches!(
+            groups.create(&group),
+            Err(CrudError::AlreadyExists)
+        ));
+
+        group.description = "Everyone who sells".to_string();
+        groups.update(&group).expect("Failed to update Group");
+        assert_eq!(groups.read(&group.id).expect("Failed to read Group"), group);
+        assert_eq!(
+            accounts.read_all().expect("Failed to read Accounts"),
+            vec![account]
+        );
+        groups.delete(&group).expect("Failed to delete Group");
+        assert!(groups.read_all().expect("Failed to read Groups").is_empty());
+    }
+
+    #[test]
+    fn foreign_keys_apply_the_membership_rules() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let path = dir.path().join("accounts.sqlite").display().to_string();
+        let mut accounts = SqliteStore::<Account>::new(&path);
+        let mut groups = SqliteStore::<Group>::new(&path);
+        let account = Account::new("Test Account");
+        accounts.create(&account).expect("Failed to create Account");
+        let group = Group::new("Sales");
+        groups.create(&group).expect("Failed to create Group");
+        let mut memberships = SqliteMembershipStore::new(&path, MembershipRules::default());
+        let membership = Membership::new(group.id, account.id);
+        memberships
+            .add(&membership)
+            .expect("Failed to add Membership");
+        assert!(matches!(
+            memberships.add(&membership),
+            Err(CrudError::AlreadyExists)
+        ));
+        assert!(matches!(
+            memberships.add(&Membership::new(Uuid::new_v4(), account.id)),
+            Err(CrudError::NotFound)
+        ));
+
+        assert!(matches!(groups.delete(&group), Err(CrudError::InUse)));
+        let mut memberships = SqliteMembershipStore::new(
+            &path,
+            MembershipRules {
+                on_account_delete: OnDelete::Restrict,
+                on_group_delete: OnDelete::Cascade,
+            },
+        );
+        assert_eq!(
+            memberships.read_all().expect("Failed to read Memberships"),
+            vec![membership]
+        );
+        assert!(matches!(accounts.delete(&account), Err(CrudError::InUse)));
+        groups.delete(&group).expect("Failed to delete Group");
+        assert!(memberships
+            .read_all()
+            .expect("Failed to read Memberships")
+            .is_empty());
+        memberships
+            .remove_all(Side::Account, &account.id)
+            .expect("Failed to remove Memberships");
+    }
+
+    #[test]
+    fn users_are_unique_by_username() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let sqlite_path = dir.path().join("accounts.sqlite");
+        let mut store = SqliteUserStore::new(sqlite_path.display().to_string().as_str());
+        assert!(store.read_all().expect("Failed to read Users").is_empty());
+        let mut user =
+            User::new("admin", "correct horse", Role::Editor).expect("Failed to create User");
+        store.create(&user).expect("Failed to store User");
+        let other =
+            User::new("admin", "battery staple", Role::Viewer).expect("Failed to create User");
+        assert!(matches!(
+            store.create(&other),
+            Err(CrudError::AlreadyExists)
+        ));
+
+        user.set_password("battery staple")
+            .expect("Failed to change password");
+        user.role = Role::Admin;
+        store.update(&user).expect("Failed to update User");
+        assert_eq!(
+            store.read_all().expect("Failed to read Users"),
+            vec![user.clone()]
+        );
+        assert!(matches!(store.update(&other), Err(CrudError::NotFound)));
+        store.delete(&user).expect("Failed to delete User");
+        assert!(store.read_all().expect("Failed to read Users").is_empty());
+    }
 }
@@ -0,0 +1,277 @@
+//! Fields whose values no two items may share, such as the email of an
+//! account.
+//!
+//! The fields are named by [`UniqueRule`]s, which default to the
+//! [`Entity::UNIQUE`] fields. SQLite keeps them unique with an index; the
+//! stores of the other strategies, and SQLite stores whose items already
+//! break a rule, are wrapped in a [`UniqueStore`] that scans the stored
+//! items before each create and update, or once for a batch of creates.
+
+use std::{collections::HashSet, marker::PhantomData};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{Crud, CrudError, Entity};
+
+/// A field whose values no two items may share.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UniqueRule {
+    pub field: String,
+    /// Compare the values regardless of case, so that `Jane Doe` clashes
+    /// with `jane doe`. SQLite only folds the case of ASCII letters.
+    #[serde(default)]
+    pub ignore_case: bool,
+}
+
+impl UniqueRule {
+    pub fn new(field: &str) -> Self {
+        UniqueRule {
+            field: field.to_string(),
+            ignore_case: false,
+        }
+    }
+
+    /// The rules of the [`Entity::UNIQUE`] fields of `T`, which compare
+    /// the values as they are.
+    pub fn defaults_of<T: Entity>() -> Vec<UniqueRule> {
+        T::UNIQUE
+            .iter()
+            .map(|field| UniqueRule::new(field))
+            .collect()
+    }
+
+    /// The position of the rule's field in [`Entity::values`], or
+    /// [`CrudError::UnknownField`] if `T` has no such field.
+    pub fn position_of<T: Entity>(&self) -> super::Result<usize> {
+        T::FIELDS
+            .iter()
+            .position(|field| field.name == self.field)
+            .ok_or_else(|| CrudError::UnknownField(format!("{}.{}", T::PLURAL, self.field)))
+    }
+
+    /// What `value` is compared as, or `None` if it is empty and so not
+    /// compared at all.
+    fn key_of(&self, value: &str) -> Option<String> {
+        match (value.is_empty(), self.ignore_case) {
+            (true, _) => None,
+            (false, true) => Some(value.to_lowercase()),
+            (false, false) => Some(value.to_string()),
+        }
+    }
+}
+
+/// A [`Crud`] decorator that refuses to store an item sharing the value of
+/// a field of one of its [`UniqueRule`]s with another item, with
+/// [`CrudError::AlreadyExists`].
+pub struct UniqueStore<T, S> {
+    inner: S,
+    /// The rules with the positions of their fields in [`Entity::values`].
+    rules: Vec<(usize, UniqueRule)>,
+    _item: PhantomData<fn(T)>,
+}
+
+impl<T: Entity, S: Crud<T>> UniqueStore<T, S> {
+    pub fn new(inner: S, rules: &[UniqueRule]) -> super::Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| Ok((rule.position_of::<T>()?, rule.clone())))
+            .collect::<super::Result<_>>()?;
+        Ok(UniqueStore {
+            inner,
+            rules,
+            _item: PhantomData,
+        })
+    }
+
+    /// The compared values of the unique fields of `item`, or `None` for
+    /// the empty ones.
+    fn keys(&self, item: &T) -> Vec<Option<String>> {
+        let values = item.values();
+        self.rules
+            .iter()
+            .map(|(i, rule)| values.get(*i).and_then(|value| rule.key_of(value)))
+            .collect()
+    }
+
+    /// Fails if another item shares a key with `item`.
+    fn check(&self, item: &T) -> super::Result<()> {
+        let keys = self.keys(item);
+        if keys.iter().all(Option::is_none) {
+            return Ok(());
+        }
+        for other in self.inner.iter()? {
+            let other = other?;
+            if other.id() == item.id() {
+                continue;
+            }
+            let shared = self
+                .keys(&other)
+                .iter()
+                .zip(&keys)
+                .any(|(a, b)| a.is_some() && a == b);
+            if shared {
+                return Err(CrudError::AlreadyExists);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wraps `store` in a [`UniqueStore`] that applies `rules`, unless there
+/// are none.
+pub fn with_unique<T: Entity>(
+    store: Box<dyn Crud<T>>,
+    rules: &[UniqueRule],
+) -> super::Result<Box<dyn Crud<T>>> {
+    if rules.is_empty() {
+        Ok(store)
+    } else {
+        Ok(Box::new(UniqueStore::new(store, rules)?))
+    }
+}
+
+impl<T: Entity, S: Crud<T>> Crud<T> for UniqueStore<T, S> {
+    fn create(&mut self, item: &T) -> super::Result<()> {
+        self.check(item)?;
+        self.inner.create(item)
+    }
+
+    /// Scans the stored items once, then checks each of `items` against
+    /// them and the items before it.
+    fn create_all(&mut self, items: &[T]) -> super::Result<Vec<super::Result<()>>> {
+        let mut taken: Vec<HashSet<String>> = vec![HashSet::new(); self.rules.len()];
+        for other in self.inner.iter()? {
+            for (taken, key) in taken.iter_mut().zip(self.keys(&other?)) {
+                taken.extend(key);
+            }
+        }
+        let mut accepted = Vec::new();
+        let clashes: Vec<bool> = items
+            .iter()
+            .map(|item| {
+                let keys = self.keys(item);
+                let clash = taken
+                    .iter()
+                    .zip(&keys)
+                    .any(|(taken, key)| key.as_ref().is_some_and(|key| taken.contains(key)));
+                if !clash {
+                    for (taken, key) in taken.iter_mut().zip(keys) {
+                        taken.extend(key);
+                    }
+                    accepted.push(item.clone());
+                }
+                clash
+            })
+            .collect();
+        let mut created = self.inner.create_all(&accepted)?.into_iter();
+        Ok(clashes
+            .into_iter()
+            .map_while(|clash| match clash {
+                true => Some(Err(CrudError::AlreadyExists)),
+                false => created.next(),
+            })
+            .collect())
+    }
+
+    fn read_all(&self) -> super::Result<Vec<T>> {
+        self.inner.read_all()
+    }
+
+    fn read(&self, id: &Uuid) -> super::Result<T> {
+        self.inner.read(id)
+    }
+
+    fn iter(&self) -> super::Result<Box<dyn Iterator<Item = super::Result<T>> + Send>> {
+        self.inner.iter()
+    }
+
+    fn update(&mut self, item: &T) -> super::Result<()> {
+        self.check(item)?;
+        self.inner.update(item)
+    }
+
+    fn delete(&mut self, item: &T) -> super::Result<()> {
+        self.inner.delete(item)
+    }
+
+    fn in_memory(&self) -> bool {
+        self.inner.in_memory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::Account;
+
+    use super::*;
+    use crate::crud::memory::MemoryStore;
+
+    fn with_email(fullname: &str, email: Option<&str>) -> Account {
+        let mut account = Account::new(fullname);
+        account.email = email.map(str::to_string);
+        account
+    }
+
+    #[test]
+    fn emails_are_unique_but_may_be_left_out() {
+        let rules = UniqueRule::defaults_of::<Account>();
+        let mut store = with_unique(Box::new(MemoryStore::<Account>::new()), &rules).unwrap();
+        let mut erich = with_email("Erich", Some("erich@example.com"));
+        store.create(&erich).unwrap();
+        store.create(&with_email("Jane", None)).unwrap();
+        store.create(&with_email("John", None)).unwrap();
+
+        let copy = with_email("Erich S.", Some("erich@example.com"));
+        assert!(matches!(store.create(&copy), Err(CrudError::AlreadyExists)));
+        let mut jane = store.read_all().unwrap()[1].clone();
+        jane.email = erich.email.clone();
+        assert!(matches!(store.update(&jane), Err(CrudError::AlreadyExists)));
+
+        erich.fullname = "Erich Schroeter".to_string();
+        store.update(&erich).unwrap();
+        assert_eq!(store.read_all().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn batches_are_checked_against_the_store_and_themselves() {
+        let rules = UniqueRule::defaults_of::<Account>();
+        let mut store = with_unique(Box::new(MemoryStore::<Account>::new()), &rules).unwrap();
+        store
+            .create(&with_email("Erich", Some("erich@example.com")))
+            .unwrap();
+        let batch = [
+            with_email("Erich S.", Some("erich@example.com")),
+            with_email("Jane", Some("jane@example.com")),
+            with_email("Jane D.", Some("jane@example.com")),
+            with_email("John", None),
+        ];
+        let results = store.create_all(&batch).unwrap();
+        assert_eq!(
+            results.iter().map(Result::is_ok).collect::<Vec<_>>(),
+            vec![false, true, false, true]
+        );
+        assert!(matches!(results[0], Err(CrudError::AlreadyExists)));
+        assert_eq!(store.read_all().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn names_may_be_unique_regardless_of_case() {
+        let rules = [UniqueRule {
+            field: "fullname".to_string(),
+            ignore_case: true,
+        }];
+        let mut store = with_unique(Box::new(MemoryStore::<Account>::new()), &rules).unwrap();
+        store.create(&Account::new("Jane Doe")).unwrap();
+        let copy = Account::new("JANE doe");
+        assert!(matches!(store.create(&copy), Err(CrudError::AlreadyExists)));
+        store.create(&Account::new("Jane Do")).unwrap();
+
+        let unknown = [UniqueRule::new("nickname")];
+        let store = with_unique(Box::new(MemoryStore::<Account>::new()), &unknown);
+        assert!(matches!(
+            store,
+            Err(CrudError::UnknownField(field)) if field == "accounts.nickname"
+        ));
+    }
+}
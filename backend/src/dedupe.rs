@@ -0,0 +1,297 @@
+//! Finding accounts that are likely the same person under slightly
+//! different names, and merging them into one.
+
+use std::sync::{Mutex, MutexGuard};
+
+use common::Account;
+
+use crate::crud::{
+    self,
+    membership::{Membership, MembershipStore, Side},
+    Crud, CrudError,
+};
+
+/// Two accounts whose names are alike.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    /// The account stored first.
+    pub first: Account,
+    pub second: Account,
+    /// How alike the names are, from 0 to 1.
+    pub score: f64,
+}
+
+/// The name as it is compared: lowercased, without punctuation and with
+/// its words sorted, so that `Schroeter, Erich` is `erich schroeter`.
+pub fn normalize(name: &str) -> String {
+    let name: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+    let mut words: Vec<_> = name.split_whitespace().collect();
+    words.sort_unstable();
+    words.join(" ")
+}
+
+/// How alike two normalized names are, from 0 for nothing in common to 1
+/// for the same: one less the share of the longer name that has to be
+/// edited to turn one into the other.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let longer = a.len().max(b.len());
+    if longer == 0 {
+        return 1.0;
+    }
+    1.0 - distance(&a, &b) as f64 / longer as f64
+}
+
+/// The Levenshtein distance of `a` and `b`: the fewest characters to insert,
+/// delete or replace to turn one into the other.
+fn distance(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The pairs of `accounts` whose names score at least `min_score`, the
+/// most alike first. Every account is compared with every other one.
+pub fn candidates(accounts: &[Account], min_score: f64) -> Vec<Candidate> {
+    let names: Vec<String> = accounts.iter().map(|a| normalize(&a.fullname)).collect();
+    let mut candidates = Vec::new();
+    for (i, first) in accounts.iter().enumerate() {
+        for (j, second) in accounts.iter().enumerate().skip(i + 1) {
+            let score = similarity(&names[i], &names[j]);
+            if score >= min_score {
+                candidates.push(Candidate {
+                    first: first.clone(),
+                    second: second.clone(),
+                    score,
+                });
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    candidates
+}
+
+/// `keep` with what `other` adds to it: the email if `keep` has none, the
+/// tags it lacks and the attributes it does not set. The other fields of
+/// `keep` are kept.
+pub fn merged(keep: &Account, other: &Account) -> Account {
+    let mut account = keep.clone();
+    if account.email.is_none() {
+        account.email = other.email.clone();
+    }
+    for tag in &other.tags {
+        if !account.has_tag(tag) {
+            account.tags.push(tag.clone());
+        }
+    }
+    for (name, value) in &other.attributes {
+        account
+            .attributes
+            .entry(name.clone())
+            .or_insert_with(|| value.clone());
+    }
+    account
+}
+
+/// Merges `other` into `keep`: moves the memberships of `other` to `keep`,
+/// deletes `other` and stores `keep` [`merged`] with it. Returns the
+/// merged account. If `keep` cannot be stored, `other` and its memberships
+/// are put back.
+pub fn merge(
+    store: &mut dyn Crud<Account>,
+    memberships: &Mutex<dyn MembershipStore>,
+    keep: &Account,
+    other: &Account,
+) -> crud::Result<Account> {
+    // Unlocked again before the delete, which may apply the membership
+    // rules.
+    let (added, removed) = move_memberships(memberships, other, keep)?;
+    let account = merged(keep, other);
+    // Deleted first, so that `keep` may take over its unique values.
+    let stored = store.delete(other).and_then(|()| {
+        store.update(&account).inspect_err(|_| {
+            if let Err(err) = store.create(other) {
+                log::error!(
+                    "Failed to restore {} after a failed merge: {}",
+                    other.id,
+                    err
+                );
+            }
+        })
+    });
+    if let Err(err) = stored {
+        let mut memberships = lock(memberships)?;
+        for membership in &added {
+            memberships.remove(membership)?;
+        }
+        for membership in &removed {
+            memberships.add(membership)?;
+        }
+        return Err(err);
+    }
+    Ok(account)
+}
+
+fn lock(
+    memberships: &Mutex<dyn MembershipStore>,
+) -> crud::Result<MutexGuard<'_, dyn MembershipStore + 'static>> {
+    memberships.lock().map_err(|_| {
+        log::error!("Membership store lock failed");
+        CrudError::UnknownError
+    })
+}
+
+/// Moves the memberships of `other` to `keep`. Returns those added to
+/// `keep`, which leaves out the groups it was in already, and those
+/// removed from `other`.
+fn move_memberships(
+    memberships: &Mutex<dyn MembershipStore>,
+    other: &Account,
+    keep: &Account,
+) -> crud::Result<(Vec<Membership>, Vec<Membership>)> {
+    let mut memberships = lock(memberships)?;
+    let (mut added, mut removed) = (Vec::new(), Vec::new());
+    for membership in memberships.of(Side::Account, &other.id)? {
+        let moved = Membership::new(membership.group, keep.id);
+        match memberships.add(&moved) {
+            Ok(()) => added.push(moved),
+            Err(CrudError::AlreadyExists) => {}
+            Err(err) => return Err(err),
+        }
+        memberships.remove(&membership)?;
+        removed.push(membership);
+    }
+    Ok((added, removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use common::Attribute;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::crud::{memory::MemoryStore, unique, unique::UniqueRule};
+
+    #[test]
+    fn names_are_compared_regardless_of_case_punctuation_and_order() {
+        assert_eq!(normalize("Schroeter, Erich"), "erich schroeter");
+        assert_eq!(normalize("  ERICH   schroeter. "), "erich schroeter");
+        assert_eq!(similarity("erich schroeter", "erich schroeter"), 1.0);
+        assert_eq!(similarity("john smith", "jon smith"), 0.9);
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("abc", ""), 0.0);
+        assert!(similarity("doe jane", "doe john") < 0.7);
+    }
+
+    #[test]
+    fn candidates_are_the_alike_pairs_best_first() {
+        let accounts = [
+            Account::new("John Smith"),
+            Account::new("Erich Schroeter"),
+            Account::new("Jane Doe"),
+            Account::new("Jon Smith"),
+            Account::new("Schroeter, Erich"),
+        ];
+        let pairs: Vec<_> = candidates(&accounts, 0.85)
+            .into_iter()
+            .map(|c| (c.first.fullname, c.second.fullname, c.score))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                (
+                    "Erich Schroeter".to_string(),
+                    "Schroeter, Erich".to_string(),
+                    1.0
+                ),
+                ("John Smith".to_string(), "Jon Smith".to_string(), 0.9),
+            ]
+        );
+    }
+
+    #[test]
+    fn merging_moves_memberships_and_fills_in_fields() {
+        let rules = UniqueRule::defaults_of::<Account>();
+        let mut store = unique::with_unique(Box::new(MemoryStore::new()), &rules).unwrap();
+        let memberships: Arc<Mutex<dyn MembershipStore>> =
+            Arc::new(Mutex::new(MemoryStore::<Membership>::new()));
+        let mut keep = Account::new("Erich Schroeter");
+        keep.tags = vec!["staff".to_string()];
+        keep.attributes
+            .insert("floor".to_string(), Attribute::Integer(3));
+        let mut other = Account::new("Schroeter, Erich");
+        other.email = Some("erich@example.com".to_string());
+        other.tags = vec!["sales".to_string(), "staff".to_string()];
+        other
+            .attributes
+            .insert("floor".to_string(), Attribute::Integer(4));
+        other
+            .attributes
+            .insert("desk".to_string(), Attribute::Text("B2".to_string()));
+        store.create(&keep).unwrap();
+        store.create(&other).unwrap();
+        let (sales, staff) = (Uuid::new_v4(), Uuid::new_v4());
+        {
+            let mut memberships = memberships.lock().unwrap();
+            memberships.add(&Membership::new(sales, other.id)).unwrap();
+            memberships.add(&Membership::new(staff, other.id)).unwrap();
+            memberships.add(&Membership::new(staff, keep.id)).unwrap();
+        }
+
+        let account = merge(&mut store, &*memberships, &keep, &other).unwrap();
+        assert_eq!(account.fullname, "Erich Schroeter");
+        assert_eq!(account.email.as_deref(), Some("erich@example.com"));
+        assert_eq!(account.tags, vec!["staff", "sales"]);
+        assert_eq!(account.attributes["floor"], Attribute::Integer(3));
+        assert_eq!(
+            account.attributes["desk"],
+            Attribute::Text("B2".to_string())
+        );
+        assert_eq!(store.read_all().unwrap(), vec![account]);
+        let mut moved = memberships.lock().unwrap().read_all().unwrap();
+        moved.sort_by_key(|membership| membership.group);
+        let mut expected = vec![
+            Membership::new(sales, keep.id),
+            Membership::new(staff, keep.id),
+        ];
+        expected.sort_by_key(|membership| membership.group);
+        assert_eq!(moved, expected);
+    }
+
+    #[test]
+    fn failed_merges_put_the_other_account_back() {
+        let mut store = MemoryStore::new();
+        let memberships: Arc<Mutex<dyn MembershipStore>> =
+            Arc::new(Mutex::new(MemoryStore::<Membership>::new()));
+        let mut other = Account::new("Schroeter, Erich");
+        other.email = Some("erich@example.com".to_string());
+        store.create(&other).unwrap();
+        let group = Uuid::new_v4();
+        let membership = Membership::new(group, other.id);
+        memberships.lock().unwrap().add(&membership).unwrap();
+
+        // Deleted since it was read, so it cannot be updated.
+        let keep = Account::new("Erich Schroeter");
+        let merged = merge(&mut store, &*memberships, &keep, &other);
+        assert!(matches!(merged, Err(CrudError::NotFound)));
+        assert_eq!(store.read_all().unwrap(), vec![other]);
+        assert_eq!(
+            memberships.lock().unwrap().read_all().unwrap(),
+            vec![membership]
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use chrono::Utc;
+use tokio::sync::broadcast;
+
+use crate::crud::observer::{Change, Listener};
+
+/// Default number of changes a subscriber may fall behind before it starts
+/// missing events, and number of changes kept for replay.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// A channel-based fan-out of store changes.
+///
+/// Register the bus as a [`Listener`] on an `ObservedStore` and every
+/// subsystem that needs to react to changes (caches, webhooks, live UI, ...)
+/// can [`subscribe`](EventBus::subscribe) to it without the store knowing
+/// about them. Changes are delivered to each subscriber in commit order.
+/// A subscriber that falls more than `capacity` changes behind is told how
+/// many it missed by its receiver, rather than slowing down writers.
+///
+/// The most recent `capacity` changes are also kept so that a subscriber
+/// which disconnected can [`resume`](EventBus::resume) where it left off.
+pub struct EventBus<T> {
+    sender: broadcast::Sender<Change<T>>,
+    history: Mutex<VecDeque<Change<T>>>,
+    capacity: usize,
+    /// When the bus was created, in milliseconds since the Unix epoch.
+    /// Sequence numbers start over with every bus, so the ids of its
+    /// events carry it to tell them from those of an earlier run.
+    epoch: i64,
+}
+
+/// The result of [`EventBus::resume`].
+pub struct Resume<T> {
+    /// Buffered changes committed after the requested sequence number.
+    pub missed: Vec<Change<T>>,
+    /// `false` when some changes after the requested sequence number are no
+    /// longer buffered, so `missed` has a gap at its start.
+    pub complete: bool,
+    /// The sequence number `missed` follows: the requested one, or 0 if it
+    /// was never handed out by this bus.
+    pub after: u64,
+    /// Receives every change committed after the last one in `missed`.
+    pub receiver: broadcast::Receiver<Change<T>>,
+}
+
+impl<T: Clone> EventBus<T> {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        EventBus {
+            sender,
+            history: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            epoch: Utc::now().timestamp_millis(),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Change<T>> {
+        self.sender.subscribe()
+    }
+
+    /// Returns the sequence number of the most recent change, or 0 if none
+    /// has been published yet.
+    pub fn last_seq(&self) -> u64 {
+        let history = self.history.lock().unwrap();
+        history.back().map(|c| c.seq).unwrap_or_default()
+    }
+
+    /// The id of the event of the change with sequence number `seq`.
+    pub fn event_id(&self, seq: u64) -> String {
+        format!("{}-{}", self.epoch, seq)
+    }
+
+    /// The sequence number of an [`event_id`](EventBus::event_id) of this
+    /// bus, or `None` for one of an earlier run or no event id at all.
+    pub fn seq_of(&self, event_id: &str) -> Option<u64> {
+        let (epoch, seq) = event_id.trim().split_once('-')?;
+        match epoch.parse::<i64>() {
+            Ok(epoch) if epoch == self.epoch => seq.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the buffered changes of the items that `matches`, oldest
+    /// first. Older changes, and those made before the server started, are
+    /// not known.
+    pub fn history(&self, matches: impl Fn(&T) -> bool) -> Vec<Change<T>> {
+        let history = self.history.lock().unwrap();
+        history
+            .iter()
+            .filter(|c| matches(&c.item))
+            .cloned()
+            .collect()
+    }
+
+    /// Subscribes to changes committed after sequence number `after`,
+    /// replaying any that are still buffered. A sequence number beyond the
+    /// last one was handed out before a restart, so every buffered change
+    /// is replayed after a gap.
+    pub fn resume(&self, after: u64) -> Resume<T> {
+        let history = self.history.lock().unwrap();
+        // Holding the history lock keeps publishers out, so nothing can be
+        // committed between taking the snapshot and subscribing.
+        let receiver = self.sender.subscribe();
+        let last_seq = history.back().map(|c| c.seq).unwrap_or_default();
+        let (after, complete) = if after > last_seq {
+            (0, false)
+        } else {
+            let complete = history
+                .front()
+                .is_none_or(|oldest| oldest.seq <= after.saturating_add(1));
+            (after, complete)
+        };
+        let missed: Vec<Change<T>> = history.iter().filter(|c| c.seq > after).cloned().collect();
+        Resume {
+            missed,
+            complete,
+            after,
+            receiver,
+        }
+    }
+}
+
+impl<T: Clone> Default for EventBus<T> {
+    fn default() -> Self {
+        EventBus::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl<T: Clone + Send + Sync> Listener<T> for EventBus<T> {
+    fn on_change(&self, change: &Change<T>) -> Result<(), Box<dyn std::error::Error>> {
+        let mut history = self.history.lock().unwrap();
+        if history.len() == self.capacity {
+            history.pop_front();
+        }
+        history.push_back(change.clone());
+        // Sending only fails when nobody is subscribed, which is not an error.
+        let _ = self.sender.send(change.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use common::Account;
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    use super::*;
+    use crate::crud::observer::ChangeKind;
+
+    fn change(seq: u64) -> Change<Account> {
+        Change {
+            seq,
+            kind: ChangeKind::Created,
+            at: Utc::now(),
+            item: Account::new("Test Account"),
+        }
+    }
+
+    #[test]
+    fn publishing_without_subscribers_succeeds() {
+        let bus = EventBus::default();
+        assert!(bus.on_change(&change(1)).is_ok());
+    }
+
+    #[test]
+    fn subscribers_receive_changes_in_order() {
+        let bus = EventBus::default();
+        let mut first = bus.subscribe();
+        let mut second = bus.subscribe();
+        for seq in 1..=3 {
+            bus.on_change(&change(seq))
+                .expect("Failed to publish change");
+        }
+        for rx in [&mut first, &mut second] {
+            let seqs: Vec<u64> = (0..3).map(|_| rx.try_recv().unwrap().seq).collect();
+            assert_eq!(seqs, vec![1, 2, 3]);
+            assert_eq!(rx.try_recv().unwrap_err(), TryRecvError::Empty);
+        }
+    }
+
+    #[test]
+    fn slow_subscriber_is_told_how_many_it_missed() {
+        let bus = EventBus::new(2);
+        let mut rx = bus.subscribe();
+        for seq in 1..=3 {
+            bus.on_change(&change(seq))
+                .expect("Failed to publish change");
+        }
+        assert_eq!(rx.try_recv().unwrap_err(), TryRecvError::Lagged(1));
+        assert_eq!(rx.try_recv().unwrap().seq, 2);
+    }
+
+    #[test]
+    fn resume_replays_changes_after_sequence_number() {
+        let bus = EventBus::default();
+        for seq in 1..=3 {
+            bus.on_change(&change(seq))
+                .expect("Failed to publish change");
+        }
+        let mut resume = bus.resume(1);
+        let seqs: Vec<u64> = resume.missed.iter().map(|c| c.seq).collect();
+        assert_eq!(seqs, vec![2, 3]);
+        assert!(resume.complete);
+        bus.on_change(&change(4)).expect("Failed to publish change");
+        assert_eq!(resume.receiver.try_recv().unwrap().seq, 4);
+    }
+
+    #[test]
+    fn resume_reports_gap_when_changes_were_evicted() {
+        let bus = EventBus::new(2);
+        for seq in 1..=4 {
+            bus.on_change(&change(seq))
+                .expect("Failed to publish change");
+        }
+        assert_eq!(bus.last_seq(), 4);
+        let resume = bus.resume(1);
+        let seqs: Vec<u64> = resume.missed.iter().map(|c| c.seq).collect();
+        assert_eq!(seqs, vec![3, 4]);
+        assert!(!resume.complete);
+        assert!(bus.resume(2).complete);
+    }
+
+    #[test]
+    fn resume_reports_gap_after_a_restart() {
+        let bus = EventBus::default();
+        for seq in 1..=2 {
+            bus.on_change(&change(seq))
+                .expect("Failed to publish change");
+        }
+        for after in [3, u64::MAX] {
+            let resume = bus.resume(after);
+            let seqs: Vec<u64> = resume.missed.iter().map(|c| c.seq).collect();
+            assert_eq!(seqs, vec![1, 2]);
+            assert!(!resume.complete);
+            assert_eq!(resume.after, 0);
+        }
+        assert!(!EventBus::<Account>::default().resume(1).complete);
+    }
+
+    #[test]
+    fn event_ids_of_an_earlier_run_are_not_known() {
+        let bus = EventBus::<Account>::default();
+        assert_eq!(bus.seq_of(&bus.event_id(42)), Some(42));
+        assert_eq!(bus.seq_of(&format!("{}-42", bus.epoch - 1)), None);
+        assert_eq!(bus.seq_of("42"), None);
+        assert_eq!(bus.seq_of("18446744073709551615"), None);
+    }
+
+    #[test]
+    fn history_of_an_item() {
+        let bus = EventBus::default();
+        let account = Account::new("Test Account");
+        for seq in 1..=3 {
+            let mut change = change(seq);
+            if seq != 2 {
+                change.item = account.clone();
+            }
+            bus.on_change(&change).expect("Failed to publish change");
+        }
+        let seqs: Vec<u64> = bus
+            .history(|item| item.id == account.id)
+            .iter()
+            .map(|c| c.seq)
+            .collect();
+        assert_eq!(seqs, vec![1, 3]);
+    }
+}
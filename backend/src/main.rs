@@ -1,16 +1,34 @@
+mod auth;
 mod cfg;
 mod command;
 mod crud;
+mod dedupe;
+mod event;
 mod route;
+mod tenant;
+mod transfer;
+mod webhook;
 
-#[cfg(all(feature = "csv", feature = "sqlite"))]
-compile_error!("feature \"csv\" and feature \"sqlite\" cannot be enabled at the same time");
+#[cfg(not(any(feature = "csv", feature = "sqlite", feature = "memory")))]
+compile_error!("at least one of the features \"csv\", \"sqlite\" and \"memory\" must be enabled");
 
+use auth::{Role, Scope};
 use cfg::default_config_path;
-use clap::{value_parser, Arg};
+use clap::{value_parser, Arg, ArgAction};
+use command::{
+    account::{AccountCommand, OutputFormat},
+    dedupe::DedupeCommand,
+    reconcile::ReconcileCommand,
+    token::TokenCommand,
+    transfer::{ExportCommand, ImportCommand},
+    user::UserCommand,
+    Command,
+};
+use common::AccountStatus;
 use cor_args::{ArgHandler, ConfigHandler, DefaultHandler, EnvHandler, FileHandler, Handler};
 use log::LevelFilter;
 use std::path::PathBuf;
+use transfer::{Conflict, Format};
 
 pub const APP_NAME: &str = "example-rust-crud-strategy-pattern";
 pub const APP_PREFIX: &str = "example-rust-crud-strategy-pattern_";
@@ -60,19 +78,13 @@ fn setup_logging(verbosity: &str) {
 //     HttpResponse::Ok().body("Help text")
 // }
 
-// async fn upload(mut payload: Multipart) -> Result<HttpResponse, Error> {
-//     while let Ok(Some(mut field)) = payload.try_next().await {
-//         let content_disposition = field.content_disposition().unwrap();
-//         let filename = content_disposition.get_filename().unwrap();
-//         let filepath = format!("./tmp/{}", sanitize_filename::sanitize(&filename));
-//         let mut f = web::block(|| std::fs::File::create(filepath)).await.unwrap();
-//         while let Some(chunk) = field.next().await {
-//             let data = chunk.unwrap();
-//             f = web::block(move || f.write_all(&data).map(|_| f)).await?;
-//         }
-//     }
-//     Ok(HttpResponse::Ok().into())
-// }
+/// Points a command that works on one store at a tenant's store.
+fn tenant_arg() -> Arg {
+    Arg::new("tenant")
+        .long("tenant")
+        .value_name("NAME")
+        .help("Use the store of this tenant [default: the default tenant's]")
+}
 
 struct App {
     args: clap::Command,
@@ -90,9 +102,9 @@ impl App {
                         .short('c')
                         .long("config")
                         .value_name("FILE")
+                        .global(true)
                         // .default_value(&default_config_path_value)
-                        .help("Sets a custom config file")
-                        .value_parser(value_parser!(PathBuf)),
+                        .help("Sets a custom config file"),
                 )
                 .arg(
                     Arg::new("verbosity")
@@ -139,6 +151,260 @@ impl App {
                                 .value_name("DIR")
                                 .help("Directory path to where HTML templates are stored"),
                         ),
+                )
+                .subcommand(
+                    clap::Command::new("reconcile")
+                        .about("Repair drift between the account store and its mirrors")
+                        .arg(tenant_arg())
+                        .arg(
+                            Arg::new("dry_run")
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help("Only report the differences"),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("migrate-store")
+                        .about("Copy every account, group and membership from one store into another")
+                        .arg(
+                            Arg::new("from")
+                                .long("from")
+                                .value_name("STORE")
+                                .help("The store to copy from, e.g. csv:accounts.csv [default: the configured store]"),
+                        )
+                        .arg(
+                            Arg::new("to")
+                                .long("to")
+                                .value_name("STORE")
+                                .required(true)
+                                .help("The store to copy to, e.g. sqlite:accounts.sqlite"),
+                        )
+                        .arg(
+                            Arg::new("dry_run")
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help("Only report what would be copied"),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("account")
+                        .about("Manage accounts in the configured store")
+                        .subcommand_required(true)
+                        .arg(tenant_arg().global(true))
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .short('f')
+                                .global(true)
+                                .value_name("FORMAT")
+                                .value_parser(value_parser!(OutputFormat))
+                                .default_value("table")
+                                .help("The output format"),
+                        )
+                        .subcommand(
+                            clap::Command::new("add")
+                                .about("Add an account")
+                                .arg(Arg::new("fullname").required(true).value_name("NAME"))
+                                .arg(
+                                    Arg::new("email")
+                                        .long("email")
+                                        .value_name("EMAIL")
+                                        .help("The email of the account, unique among the accounts"),
+                                )
+                                .arg(
+                                    Arg::new("tag")
+                                        .long("tag")
+                                        .value_name("TAG")
+                                        .action(ArgAction::Append)
+                                        .help("A tag of the account; may be given more than once"),
+                                ),
+                        )
+                        .subcommand(
+                            clap::Command::new("list")
+                                .about("List all accounts, or those matching the filters")
+                                .arg(
+                                    Arg::new("tag")
+                                        .long("tag")
+                                        .value_name("TAG")
+                                        .help("Only the accounts with this tag"),
+                                )
+                                .arg(
+                                    Arg::new("status")
+                                        .long("status")
+                                        .value_name("STATUS")
+                                        .value_parser(str::parse::<AccountStatus>)
+                                        .help("Only the accounts with this status"),
+                                ),
+                        )
+                        .subcommand(
+                            clap::Command::new("show")
+                                .about("Show an account")
+                                .arg(Arg::new("id").required(true).value_name("ID")),
+                        )
+                        .subcommand(
+                            clap::Command::new("rename")
+                                .about("Change the full name of an account")
+                                .arg(Arg::new("id").required(true).value_name("ID"))
+                                .arg(Arg::new("fullname").required(true).value_name("NAME")),
+                        )
+                        .subcommand(
+                            clap::Command::new("rm")
+                                .about("Remove an account")
+                                .arg(Arg::new("id").required(true).value_name("ID")),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("dedupe")
+                        .about("Find accounts with alike names and merge them")
+                        .arg(tenant_arg())
+                        .arg(
+                            Arg::new("min_score")
+                                .long("min-score")
+                                .value_name("PERCENT")
+                                .value_parser(value_parser!(u8).range(1..=100))
+                                .default_value("85")
+                                .help("How alike two names must be to be listed"),
+                        )
+                        .arg(
+                            Arg::new("dry_run")
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help("Only list the alike accounts"),
+                        )
+                        .arg(
+                            Arg::new("yes")
+                                .long("yes")
+                                .short('y')
+                                .action(ArgAction::SetTrue)
+                                .conflicts_with("dry_run")
+                                .help("Merge each pair into the account stored first without asking"),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("token")
+                        .about("Manage the API tokens of the token file")
+                        .subcommand_required(true)
+                        .subcommand(
+                            clap::Command::new("create")
+                                .about("Create a token and print its secret")
+                                .arg(Arg::new("name").required(true).value_name("NAME"))
+                                .arg(
+                                    Arg::new("scope")
+                                        .long("scope")
+                                        .short('s')
+                                        .value_name("SCOPE")
+                                        .value_parser(value_parser!(Scope))
+                                        .action(ArgAction::Append)
+                                        .help("What the token may be used for [default: read]"),
+                                )
+                                .arg(
+                                    Arg::new("expires_in")
+                                        .long("expires-in")
+                                        .value_name("DAYS")
+                                        .value_parser(value_parser!(i64).range(1..))
+                                        .help("Refuse the token after this many days [default: never]"),
+                                ),
+                        )
+                        .subcommand(clap::Command::new("list").about("List the tokens"))
+                        .subcommand(
+                            clap::Command::new("revoke")
+                                .about("Revoke a token")
+                                .arg(Arg::new("name").required(true).value_name("NAME")),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("user")
+                        .about("Manage the operators who log in to the HTML pages")
+                        .subcommand_required(true)
+                        .subcommand(
+                            clap::Command::new("add")
+                                .about("Add an operator; the password is asked for, or read from stdin")
+                                .arg(Arg::new("username").required(true).value_name("USERNAME"))
+                                .arg(
+                                    Arg::new("role")
+                                        .long("role")
+                                        .short('r')
+                                        .value_name("ROLE")
+                                        .value_parser(value_parser!(Role))
+                                        .default_value("viewer")
+                                        .help("What the operator may do"),
+                                ),
+                        )
+                        .subcommand(clap::Command::new("list").about("List the operators"))
+                        .subcommand(
+                            clap::Command::new("passwd")
+                                .about("Change the password of an operator")
+                                .arg(Arg::new("username").required(true).value_name("USERNAME")),
+                        )
+                        .subcommand(
+                            clap::Command::new("role")
+                                .about("Change the role of an operator")
+                                .arg(Arg::new("username").required(true).value_name("USERNAME"))
+                                .arg(
+                                    Arg::new("role")
+                                        .required(true)
+                                        .value_name("ROLE")
+                                        .value_parser(value_parser!(Role)),
+                                ),
+                        )
+                        .subcommand(
+                            clap::Command::new("rm")
+                                .about("Remove an operator")
+                                .arg(Arg::new("username").required(true).value_name("USERNAME")),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("import")
+                        .about("Load accounts from a CSV, JSON, JSON Lines or YAML file")
+                        .arg(tenant_arg())
+                        .arg(
+                            Arg::new("file")
+                                .required(true)
+                                .value_name("FILE")
+                                .help("The file to import, or - for stdin"),
+                        )
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .short('f')
+                                .value_name("FORMAT")
+                                .value_parser(value_parser!(Format))
+                                .help("The input format [default: detected from the file]"),
+                        )
+                        .arg(
+                            Arg::new("on_conflict")
+                                .long("on-conflict")
+                                .value_name("POLICY")
+                                .value_parser(value_parser!(Conflict))
+                                .default_value("skip")
+                                .help("What to do with accounts whose id is taken"),
+                        )
+                        .arg(
+                            Arg::new("rejected")
+                                .long("rejected")
+                                .value_name("FILE")
+                                .help("Write the rejected rows to this CSV file"),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("export")
+                        .about("Write every account to a CSV, JSON, JSON Lines or YAML file")
+                        .arg(tenant_arg())
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .short('f')
+                                .value_name("FORMAT")
+                                .value_parser(value_parser!(Format))
+                                .help("The output format [default: detected from the file, or csv]"),
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .long("output")
+                                .short('o')
+                                .value_name("FILE")
+                                .help("The file to write to [default: stdout]"),
+                        ),
                 ),
         }
     }
@@ -182,17 +448,44 @@ impl App {
 
         match matches.subcommand() {
             Some(("serve", sub_m)) => command::serve::serve(sub_m),
+            Some(("reconcile", sub_m)) => ReconcileCommand::from_matches(sub_m)
+                .execute()
+                .map_err(|e| e as Box<dyn std::error::Error>)?,
+            Some(("migrate-store", sub_m)) => command::migrate_store::migrate_store(sub_m)?,
+            Some(("account", sub_m)) => {
+                AccountCommand::from_matches(sub_m)
+                    .and_then(|command| command.execute())
+                    .map_err(|e| e as Box<dyn std::error::Error>)?;
+            }
+            Some(("dedupe", sub_m)) => DedupeCommand::from_matches(sub_m)
+                .execute()
+                .map_err(|e| e as Box<dyn std::error::Error>)?,
+            Some(("token", sub_m)) => TokenCommand::from_matches(sub_m)
+                .execute()
+                .map_err(|e| e as Box<dyn std::error::Error>)?,
+            Some(("user", sub_m)) => UserCommand::from_matches(sub_m)
+                .execute()
+                .map_err(|e| e as Box<dyn std::error::Error>)?,
+            Some(("import", sub_m)) => ImportCommand::from_matches(sub_m)
+                .execute()
+                .map_err(|e| e as Box<dyn std::error::Error>)?,
+            Some(("export", sub_m)) => ExportCommand::from_matches(sub_m)
+                .execute()
+                .map_err(|e| e as Box<dyn std::error::Error>)?,
             subcommand => eprintln!("Invalid subcommand {:?}", subcommand),
         }
         Ok(())
     }
 
     pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.run_with_args(std::env::args().into_iter())
+        self.run_with_args(std::env::args())
     }
 }
 
 // #[actix_web::main]
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    App::new().run()
+fn main() {
+    if let Err(e) = App::new().run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
 }
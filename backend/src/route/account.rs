@@ -1,45 +1,440 @@
-use std::sync::Mutex;
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
 
-use actix_web::{web, HttpResponse, Responder};
-use common::Account;
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder, ResponseError};
+use chrono::{DateTime, Utc};
+use common::{Account, AccountFilter, AccountStatus, NewAccount, Page};
+use serde::Deserialize;
 use tera::Context;
+use tokio::sync::broadcast::{self, error::RecvError};
+use utoipa::IntoParams;
+use uuid::Uuid;
 
-use crate::crud::Crud;
+use crate::{
+    auth::{self, policy::Caller},
+    cfg::Cfg,
+    crud::{
+        observer::{Change, ChangeKind},
+        Crud, CrudError,
+    },
+    event::EventBus,
+    tenant,
+};
 
-use super::{BACKEND_STRATEGY, VERSION};
+use super::{
+    api::{self, Representation},
+    flash::Flash,
+    VERSION,
+};
 
+/// Lists the accounts as a page, or as a page of JSON when negotiated (see
+/// [`api::negotiate`]).
+#[utoipa::path(
+    get,
+    path = "/account",
+    tag = "accounts",
+    summary = "List accounts as HTML or JSON",
+    operation_id = "list_accounts_page",
+    description = "Lists the accounts that match the filter as HTML, or one page of them as \
+        JSON when asked with `Accept: application/json`, `?format=json` or at `/account.json`.",
+    params(
+        api::PageQuery,
+        AccountFilter,
+        ("format" = Option<String>, Query, description = "`json` to get JSON"),
+    ),
+    responses(
+        (status = 200, description = "The accounts", content(
+            (Page<Account> = "application/json"),
+            (String = "text/html"),
+        )),
+        (status = 403, description = "The caller may not read accounts", body = api::ErrorBody),
+    )
+)]
 pub async fn list_accounts(
+    req: HttpRequest,
     tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
     storage: web::Data<Mutex<dyn Crud<Account>>>,
+    events: web::Data<EventBus<Account>>,
+    caller: Caller,
+    query: web::Query<api::PageQuery>,
 ) -> impl Responder {
-    if let Ok(storage) = storage.lock() {
-        let accounts = storage.read_all().unwrap_or_default();
+    let filter = match web::Query::<AccountFilter>::from_query(req.query_string()) {
+        Ok(filter) => filter,
+        Err(err) => {
+            return api::ApiError::new(actix_web::http::StatusCode::BAD_REQUEST, err)
+                .error_response()
+        }
+    };
+    if api::negotiate(&req) == Representation::Json {
+        return match api::list_accounts(storage, caller, query, filter).await {
+            Ok(response) => response,
+            Err(err) => err.error_response(),
+        };
+    }
+    if let Ok(mut storage) = storage.lock() {
+        let accounts: Vec<Account> = match caller.guard(&mut *storage).read_all() {
+            Ok(accounts) => accounts
+                .into_iter()
+                .filter(|account| filter.matches(account))
+                .collect(),
+            Err(err @ CrudError::Forbidden(_)) => return api::ApiError::from(err).error_response(),
+            Err(_) => Vec::new(),
+        };
+        // Read while holding the storage lock so no change can slip in
+        // between the listing and the point live updates resume from.
+        let last_event_id = events.event_id(events.last_seq());
         let mut ctx = Context::new();
         ctx.insert("version", &VERSION);
-        ctx.insert("backend", &BACKEND_STRATEGY);
+        ctx.insert("backend", &cfg.backend());
         ctx.insert("title", "Index Page");
         ctx.insert("accounts", &accounts);
+        ctx.insert("filter", &*filter);
+        ctx.insert("statuses", &AccountStatus::ALL);
+        ctx.insert("user", &auth::identity(&req));
+        ctx.insert("base", &tenant::base(&req));
+        ctx.insert("last_event_id", &last_event_id);
+        let mut response = HttpResponse::Ok();
+        ctx.insert("flash", &Flash::take(&req, &mut response));
         let s = tmpl.render("accounts.html", &ctx).unwrap();
-        HttpResponse::Ok().body(s)
+        response.body(s)
     } else {
         HttpResponse::InternalServerError().finish()
     }
 }
 
+/// Creates an account under an id assigned by the server.
+#[utoipa::path(
+    post,
+    path = "/account/create",
+    tag = "accounts",
+    summary = "Create an account",
+    operation_id = "create_account_page",
+    request_body = NewAccount,
+    responses(
+        (status = 200, description = "The account was created", body = Account),
+        (status = 403, description = "The caller may not create accounts", body = api::ErrorBody),
+        (status = 409, description = "The assigned id or a unique value is taken", body = api::ErrorBody),
+        (status = 400, description = "The body is not an account", body = String),
+        (status = 422, description = "Some fields are invalid", body = api::ErrorBody),
+        (status = 500, description = "The account could not be stored"),
+    )
+)]
 pub async fn create_account(
-    account: web::Json<Account>,
+    cfg: web::Data<Cfg>,
+    account: web::Json<NewAccount>,
     storage: web::Data<Mutex<dyn Crud<Account>>>,
+    caller: Caller,
 ) -> impl Responder {
-    let response = &account.0;
+    let account = match cfg.validation.new_account(&account) {
+        Ok(account) => account.into_account(cfg.id_version.generate()),
+        Err(errors) => return api::ApiError::from(errors).error_response(),
+    };
     if let Ok(mut storage) = storage.lock() {
-        if let Ok(_) = storage.create(&account) {
-            HttpResponse::Ok().json(response)
-        } else {
-            log::error!("[C]RUD failed");
-            HttpResponse::InternalServerError().finish()
+        match caller.guard(&mut *storage).create(&account) {
+            Ok(()) => HttpResponse::Ok().json(account),
+            Err(err @ (CrudError::AlreadyExists | CrudError::Forbidden(_))) => {
+                api::ApiError::from(err).error_response()
+            }
+            Err(_) => {
+                log::error!("[C]RUD failed");
+                HttpResponse::InternalServerError().finish()
+            }
         }
     } else {
         log::error!("Storage lock failed");
         HttpResponse::InternalServerError().finish()
     }
 }
+
+/// When an account was created: taken from its change history if the
+/// server saw it happen, or from a UUIDv7 id, which records its creation
+/// time.
+fn created_at(account: &Account, history: &[Change<Account>]) -> Option<DateTime<Utc>> {
+    match history.iter().find(|c| c.kind == ChangeKind::Created) {
+        Some(created) => Some(created.at),
+        None => {
+            let (secs, nanos) = account.id.get_timestamp()?.to_unix();
+            DateTime::from_timestamp(secs as i64, nanos)
+        }
+    }
+}
+
+/// Shows an account with its recent changes, or the account as JSON when
+/// negotiated (see [`api::negotiate`]).
+#[utoipa::path(
+    get,
+    path = "/account/{id}",
+    tag = "accounts",
+    summary = "Show an account as HTML or JSON",
+    operation_id = "get_account_page",
+    params(
+        ("id" = Uuid, Path, description = "The id of the account"),
+        ("format" = Option<String>, Query, description = "`json` to get JSON"),
+    ),
+    responses(
+        (status = 200, description = "The account", content(
+            (Account = "application/json"),
+            (String = "text/html"),
+        )),
+        (status = 403, description = "The caller may not read accounts", body = api::ErrorBody),
+        (status = 404, description = "No account has this id", body = api::ErrorBody),
+    )
+)]
+pub async fn show_account(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    events: web::Data<EventBus<Account>>,
+    caller: Caller,
+    id: web::Path<Uuid>,
+) -> impl Responder {
+    if api::negotiate(&req) == Representation::Json {
+        return match api::get_account(storage, caller, id).await {
+            Ok(response) => response,
+            Err(err) => err.error_response(),
+        };
+    }
+    let account = match storage.lock() {
+        Ok(mut storage) => caller.guard(&mut *storage).read(&id),
+        Err(_) => {
+            log::error!("Storage lock failed");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let account = match account {
+        Ok(account) => account,
+        Err(err) => return api::ApiError::from(err).error_response(),
+    };
+    let history = events.history(|item| item.id == account.id);
+    let mut ctx = Context::new();
+    ctx.insert("version", &VERSION);
+    ctx.insert("backend", &cfg.backend());
+    ctx.insert("title", &account.fullname);
+    ctx.insert("account", &account);
+    ctx.insert("user", &auth::identity(&req));
+    ctx.insert("base", &tenant::base(&req));
+    ctx.insert("created", &created_at(&account, &history));
+    ctx.insert("changed", &history.last().map(|c| c.at));
+    ctx.insert("history", &history.iter().rev().collect::<Vec<_>>());
+    let mut response = HttpResponse::Ok();
+    ctx.insert("flash", &Flash::take(&req, &mut response));
+    let s = tmpl.render("account.html", &ctx).unwrap();
+    response.body(s)
+}
+
+/// How long an idle event stream waits before sending a keep-alive comment.
+const EVENTS_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventsQuery {
+    /// The last event received, like the `Last-Event-ID` header.
+    last_event_id: Option<String>,
+}
+
+struct EventStream {
+    events: web::Data<EventBus<Account>>,
+    pending: VecDeque<Change<Account>>,
+    receiver: broadcast::Receiver<Change<Account>>,
+    last_seq: u64,
+    reset: bool,
+}
+
+fn sse_frame(events: &EventBus<Account>, change: &Change<Account>) -> web::Bytes {
+    let data = serde_json::to_string(change).expect("Failed to serialize Change");
+    web::Bytes::from(format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        events.event_id(change.seq),
+        change.kind,
+        data
+    ))
+}
+
+/// Streams account changes as Server-Sent Events.
+///
+/// Clients resume after a disconnect by sending the `Last-Event-ID` header
+/// (or the `last_event_id` query parameter). Changes still in the replay
+/// buffer are sent first; if some were already evicted, or the id is from
+/// before the server restarted, a `reset` event tells the client to reload
+/// the full list.
+#[utoipa::path(
+    get,
+    path = "/account/events",
+    tag = "accounts",
+    summary = "Stream account changes",
+    params(
+        EventsQuery,
+        ("Last-Event-ID" = Option<String>, Header, description = "The last event received"),
+    ),
+    responses(
+        (status = 200, description = "`created`, `updated`, `deleted` and `reset` events",
+            content_type = "text/event-stream", body = String),
+    )
+)]
+pub async fn account_events(
+    req: HttpRequest,
+    query: web::Query<EventsQuery>,
+    events: web::Data<EventBus<Account>>,
+) -> impl Responder {
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| query.last_event_id.clone());
+    let state = match last_event_id {
+        Some(id) => {
+            // An id from before a restart replays every change after a reset.
+            let seq = events.seq_of(&id);
+            let resume = events.resume(seq.unwrap_or_default());
+            EventStream {
+                events: events.clone(),
+                pending: resume.missed.into(),
+                receiver: resume.receiver,
+                last_seq: resume.after,
+                reset: seq.is_none() || !resume.complete,
+            }
+        }
+        None => EventStream {
+            events: events.clone(),
+            pending: VecDeque::new(),
+            receiver: events.subscribe(),
+            last_seq: 0,
+            reset: false,
+        },
+    };
+    let stream = futures_util::stream::unfold(state, |mut s| async move {
+        if s.reset {
+            s.reset = false;
+            let frame = web::Bytes::from_static(b"event: reset\ndata: {}\n\n");
+            return Some((Ok::<_, actix_web::Error>(frame), s));
+        }
+        if let Some(change) = s.pending.pop_front() {
+            s.last_seq = change.seq;
+            return Some((Ok(sse_frame(&s.events, &change)), s));
+        }
+        loop {
+            match tokio::time::timeout(EVENTS_KEEP_ALIVE, s.receiver.recv()).await {
+                Err(_) => return Some((Ok(web::Bytes::from_static(b": keep-alive\n\n")), s)),
+                Ok(Ok(change)) if change.seq <= s.last_seq => continue,
+                Ok(Ok(change)) => {
+                    s.last_seq = change.seq;
+                    return Some((Ok(sse_frame(&s.events, &change)), s));
+                }
+                Ok(Err(RecvError::Lagged(missed))) => {
+                    // End the stream; the client reconnects with its
+                    // Last-Event-ID and is replayed from the buffer.
+                    log::warn!("Event stream fell {} changes behind, closing", missed);
+                    return None;
+                }
+                Ok(Err(RecvError::Closed)) => return None,
+            }
+        }
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, init_service, read_body, TestRequest},
+        App,
+    };
+    use common::IdVersion;
+
+    use super::*;
+    use crate::{
+        cfg::default_template_glob,
+        crud::{memory::MemoryStore, observer::ObservedStore},
+    };
+
+    #[test]
+    fn creation_time_is_read_from_uuid_v7() {
+        let account = NewAccount {
+            fullname: "Test Account".to_string(),
+            ..NewAccount::default()
+        }
+        .into_account(IdVersion::V7.generate());
+        let created = created_at(&account, &[]).expect("No creation time");
+        assert!((Utc::now() - created).num_seconds() < 5);
+        assert_eq!(created_at(&Account::new("Test Account"), &[]), None);
+    }
+
+    #[actix_web::test]
+    async fn filter_is_not_rendered_into_the_script() {
+        let storage: Arc<Mutex<dyn Crud<Account>>> =
+            Arc::new(Mutex::new(MemoryStore::<Account>::new()));
+        let tera = tera::Tera::new(&default_template_glob()).expect("Failed to load templates");
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(tera))
+                .app_data(web::Data::new(Cfg::default()))
+                .app_data(web::Data::from(storage))
+                .app_data(web::Data::new(EventBus::<Account>::default()))
+                .route("/account", web::get().to(list_accounts)),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/account?tag=%3C%2Fscript%3E%3Cscript%3Ealert(1)%3C%2Fscript%3E")
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert!(!body.contains("<script>alert(1)"));
+        assert_eq!(body.matches("</script>").count(), 1);
+        assert!(body.contains("&lt;&#x2F;script&gt;&lt;script&gt;alert(1)"));
+    }
+
+    #[actix_web::test]
+    async fn account_is_shown_with_its_history() {
+        let events = Arc::new(EventBus::default());
+        let mut store = ObservedStore::new(MemoryStore::<Account>::new());
+        store.subscribe(events.clone());
+        let mut account = Account::new("Erich");
+        store.create(&account).expect("Failed to create Account");
+        account.fullname = "Erich Schroeter".to_string();
+        store.update(&account).expect("Failed to update Account");
+        let storage: Arc<Mutex<dyn Crud<Account>>> = Arc::new(Mutex::new(store));
+        let tera = tera::Tera::new(&default_template_glob()).expect("Failed to load templates");
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(tera))
+                .app_data(web::Data::new(Cfg::default()))
+                .app_data(web::Data::from(storage))
+                .app_data(web::Data::from(events))
+                .route("/account/{id}", web::get().to(show_account)),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri(&format!("/account/{}", account.id))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains("<h1>Erich Schroeter</h1>"));
+        assert!(body.contains("<td>created</td><td>Erich</td>"));
+        assert!(body.contains(&format!("/account/{}/edit", account.id)));
+
+        let req = TestRequest::get()
+            .uri(&format!("/account/{}?format=json", account.id))
+            .to_request();
+        let response = call_service(&app, req).await;
+        let shown: Account = actix_web::test::read_body_json(response).await;
+        assert_eq!(shown, account);
+
+        let req = TestRequest::get()
+            .uri(&format!("/account/{}", Uuid::new_v4()))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,693 @@
+use std::sync::Mutex;
+
+use actix_web::{
+    http::{
+        header::{self, Header},
+        StatusCode,
+    },
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use common::{
+    validate::{FieldError, Rules, ValidationErrors},
+    Account, AccountFilter, AccountUpdate, Group, NewAccount, Page,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    auth::policy::Caller,
+    cfg::Cfg,
+    crud::{Crud, CrudError, Entity},
+    tenant,
+};
+
+/// Items per page when `per_page` is not given.
+const DEFAULT_PER_PAGE: usize = 50;
+/// The largest `per_page` a client can ask for.
+const MAX_PER_PAGE: usize = 500;
+
+/// How a response is represented.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Representation {
+    Html,
+    Json,
+}
+
+#[derive(Deserialize)]
+struct FormatQuery {
+    format: Option<String>,
+}
+
+/// Picks JSON over HTML when the path ends in `.json`, the query asks for
+/// `format=json` or the `Accept` header ranks `application/json` before
+/// `text/html`.
+pub fn negotiate(req: &HttpRequest) -> Representation {
+    if req.path().ends_with(".json") {
+        return Representation::Json;
+    }
+    if let Ok(query) = web::Query::<FormatQuery>::from_query(req.query_string()) {
+        if let Some(format) = &query.format {
+            return if format.eq_ignore_ascii_case("json") {
+                Representation::Json
+            } else {
+                Representation::Html
+            };
+        }
+    }
+    let accept = match header::Accept::parse(req) {
+        Ok(accept) => accept,
+        Err(_) => return Representation::Html,
+    };
+    for mime in accept.ranked() {
+        match mime.essence_str() {
+            "application/json" => return Representation::Json,
+            "text/html" | "*/*" => return Representation::Html,
+            _ => {}
+        }
+    }
+    Representation::Html
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    /// The page to return, starting at 1.
+    #[param(minimum = 1, default = 1)]
+    page: Option<usize>,
+    /// Items per page.
+    #[param(minimum = 1, maximum = 500, default = 50)]
+    per_page: Option<usize>,
+}
+
+/// Reads the page of items that `query` asks for.
+pub fn read_page<T: Send + 'static>(
+    store: &dyn Crud<T>,
+    query: &PageQuery,
+) -> crate::crud::Result<Page<T>> {
+    read_matching_page(store, query, |_| true)
+}
+
+/// Reads the page of the items `keep` returns true for that `query` asks
+/// for.
+pub fn read_matching_page<T: Send + 'static>(
+    store: &dyn Crud<T>,
+    query: &PageQuery,
+    keep: impl Fn(&T) -> bool,
+) -> crate::crud::Result<Page<T>> {
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    let items = store
+        .iter()?
+        .filter(|item| item.as_ref().map_or(true, &keep));
+    Page::collect(items, query.page.unwrap_or(1), per_page)
+}
+
+/// The body of every JSON error response.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    #[schema(example = "Account not found")]
+    error: String,
+    /// The invalid fields of a payload, answered with 422.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<FieldError>,
+}
+
+/// A JSON error response, `{"error": "..."}`.
+pub fn error(status: StatusCode, message: &str) -> HttpResponse {
+    HttpResponse::build(status).json(ErrorBody {
+        error: message.to_string(),
+        fields: Vec::new(),
+    })
+}
+
+/// An error of an API handler, answered with an [`ErrorBody`].
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+    fields: Vec<FieldError>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl ToString) -> Self {
+        ApiError {
+            status,
+            message: message.to_string(),
+            fields: Vec::new(),
+        }
+    }
+
+    /// Answers a `T` whose fields are invalid.
+    pub fn invalid<T: Entity>(errors: ValidationErrors) -> Self {
+        ApiError {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            message: format!("Invalid {}", T::NAME),
+            fields: errors.fields,
+        }
+    }
+
+    /// Answers a failed call to the store of `T`s.
+    pub fn from_store<T: Entity>(err: CrudError) -> Self {
+        match err {
+            CrudError::NotFound => {
+                ApiError::new(StatusCode::NOT_FOUND, format!("{} not found", T::title()))
+            }
+            CrudError::AlreadyExists => ApiError::new(
+                StatusCode::CONFLICT,
+                format!("{} already exists", T::title()),
+            ),
+            CrudError::InUse => ApiError::new(
+                StatusCode::CONFLICT,
+                format!("{} still has memberships", T::title()),
+            ),
+            CrudError::Forbidden(operation) => ApiError::new(
+                StatusCode::FORBIDDEN,
+                format!("You may not {} {}", operation, T::PLURAL),
+            ),
+            err => {
+                log::error!("CRUD failed: {}", err);
+                ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Storage failed")
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(ErrorBody {
+            error: self.message.clone(),
+            fields: self.fields.clone(),
+        })
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        ApiError::invalid::<Account>(errors)
+    }
+}
+
+impl From<CrudError> for ApiError {
+    fn from(err: CrudError) -> Self {
+        ApiError::from_store::<Account>(err)
+    }
+}
+
+/// Runs `op` on the locked store of `T`s on behalf of `caller`.
+pub(super) fn with_store<T: Entity, R>(
+    storage: &Mutex<dyn Crud<T>>,
+    caller: &Caller,
+    op: impl FnOnce(&mut dyn Crud<T>) -> crate::crud::Result<R>,
+) -> Result<R, ApiError> {
+    let mut storage = storage.lock().map_err(|_| {
+        log::error!("Storage lock failed");
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Storage lock failed")
+    })?;
+    op(&mut caller.guard(&mut *storage)).map_err(ApiError::from_store::<T>)
+}
+
+/// List accounts
+///
+/// Returns one page of the accounts that match the filter, with the number
+/// of matching accounts and pages.
+#[utoipa::path(
+    get,
+    path = "/api/v1/accounts",
+    tag = "api",
+    params(PageQuery, AccountFilter),
+    responses(
+        (status = 200, description = "A page of accounts", body = Page<Account>),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+        (status = 403, description = "The caller may not read accounts", body = ErrorBody),
+    )
+)]
+pub async fn list_accounts(
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    caller: Caller,
+    query: web::Query<PageQuery>,
+    filter: web::Query<AccountFilter>,
+) -> Result<HttpResponse, ApiError> {
+    let page = with_store(&storage, &caller, |store| {
+        read_matching_page(store, &query, |account| filter.matches(account))
+    })?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// Create an account
+///
+/// The server assigns the id of the account.
+#[utoipa::path(
+    post,
+    path = "/api/v1/accounts",
+    tag = "api",
+    request_body = NewAccount,
+    responses(
+        (status = 201, description = "The account was created", body = Account,
+            headers(("Location" = String, description = "The URL of the account"))),
+        (status = 400, description = "The body is not an account", body = ErrorBody),
+        (status = 403, description = "The caller may not create accounts", body = ErrorBody),
+        (status = 409, description = "The assigned id or a unique value, such as the email, is taken", body = ErrorBody),
+        (status = 422, description = "Some fields are invalid", body = ErrorBody),
+    )
+)]
+pub async fn create_account(
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    caller: Caller,
+    account: web::Json<NewAccount>,
+) -> Result<HttpResponse, ApiError> {
+    let account = cfg
+        .validation
+        .new_account(&account)?
+        .into_account(cfg.id_version.generate());
+    with_store(&storage, &caller, |store| store.create(&account))?;
+    let base = cfg
+        .tenant
+        .as_deref()
+        .map(tenant::prefix)
+        .unwrap_or_default();
+    Ok(HttpResponse::Created()
+        .insert_header((
+            header::LOCATION,
+            format!("{}/api/v1/accounts/{}", base, account.id),
+        ))
+        .json(account))
+}
+
+/// Get an account
+#[utoipa::path(
+    get,
+    path = "/api/v1/accounts/{id}",
+    tag = "api",
+    params(("id" = Uuid, Path, description = "The id of the account")),
+    responses(
+        (status = 200, description = "The account", body = Account),
+        (status = 403, description = "The caller may not read accounts", body = ErrorBody),
+        (status = 404, description = "No account has this id", body = ErrorBody),
+    )
+)]
+pub async fn get_account(
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    caller: Caller,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let account = with_store(&storage, &caller, |store| store.read(&id))?;
+    Ok(HttpResponse::Ok().json(account))
+}
+
+/// Change an account
+#[utoipa::path(
+    put,
+    path = "/api/v1/accounts/{id}",
+    tag = "api",
+    params(("id" = Uuid, Path, description = "The id of the account")),
+    request_body = AccountUpdate,
+    responses(
+        (status = 200, description = "The account as stored", body = Account),
+        (status = 400, description = "The body is not an account", body = ErrorBody),
+        (status = 403, description = "The caller may not change accounts", body = ErrorBody),
+        (status = 404, description = "No account has this id", body = ErrorBody),
+        (status = 409, description = "Another account has one of the unique values, such as the email", body = ErrorBody),
+        (status = 422, description = "Some fields are invalid", body = ErrorBody),
+    )
+)]
+pub async fn update_account(
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    caller: Caller,
+    id: web::Path<Uuid>,
+    update: web::Json<AccountUpdate>,
+) -> Result<HttpResponse, ApiError> {
+    let update = cfg.validation.account_update(&update)?;
+    let account = with_store(&storage, &caller, |store| {
+        let account = update.apply(store.read(&id)?);
+        store.update(&account)?;
+        Ok(account)
+    })?;
+    Ok(HttpResponse::Ok().json(account))
+}
+
+/// Delete an account
+#[utoipa::path(
+    delete,
+    path = "/api/v1/accounts/{id}",
+    tag = "api",
+    params(("id" = Uuid, Path, description = "The id of the account")),
+    responses(
+        (status = 204, description = "The account was deleted"),
+        (status = 403, description = "The caller may not delete accounts", body = ErrorBody),
+        (status = 404, description = "No account has this id", body = ErrorBody),
+        (status = 409, description = "The account belongs to groups, which `memberships.on_account_delete: restrict` keeps it from leaving", body = ErrorBody),
+    )
+)]
+pub async fn delete_account(
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    caller: Caller,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    with_store(&storage, &caller, |store| {
+        let account = store.read(&id)?;
+        store.delete(&account)
+    })?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Get the validation rules
+///
+/// The rules that account payloads are checked against, for clients that
+/// check their input before sending it.
+#[utoipa::path(
+    get,
+    path = "/api/v1/rules",
+    tag = "api",
+    responses((status = 200, description = "The validation rules", body = Rules))
+)]
+pub async fn get_rules(cfg: web::Data<Cfg>) -> HttpResponse {
+    HttpResponse::Ok().json(&cfg.validation)
+}
+
+/// Mounts the version 1 API.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api/v1")
+            .app_data(
+                web::JsonConfig::default()
+                    .error_handler(|err, _| ApiError::new(StatusCode::BAD_REQUEST, err).into()),
+            )
+            .app_data(web::PathConfig::default().error_handler(|_, _| {
+                ApiError::new(StatusCode::NOT_FOUND, "Not found: invalid id").into()
+            }))
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|err, _| ApiError::new(StatusCode::BAD_REQUEST, err).into()),
+            )
+            .route("/rules", web::get().to(get_rules))
+            .route("/accounts", web::get().to(list_accounts))
+            .route("/accounts", web::post().to(create_account))
+            .service(
+                web::resource("/accounts/{id}")
+                    .app_data(web::PathConfig::default().error_handler(|_, _| {
+                        ApiError::new(StatusCode::NOT_FOUND, "Account not found").into()
+                    }))
+                    .route(web::get().to(get_account))
+                    .route(web::put().to(update_account))
+                    .route(web::delete().to(delete_account)),
+            )
+            .configure(super::membership::configure_api)
+            .configure(super::entity::configure_api::<Group>),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        middleware::from_fn,
+        test::{call_and_read_body_json, call_service, init_service, TestRequest},
+        App,
+    };
+
+    use super::*;
+    use crate::{
+        auth::{audit, authenticate, policy::Policy, user::User, Auth, Role},
+        cfg::AuthCfg,
+        crud::memory::MemoryStore,
+    };
+
+    fn negotiated(req: TestRequest) -> Representation {
+        negotiate(&req.to_http_request())
+    }
+
+    #[test]
+    fn json_is_negotiated_by_suffix_query_or_accept() {
+        assert_eq!(
+            negotiated(TestRequest::get().uri("/account")),
+            Representation::Html
+        );
+        assert_eq!(
+            negotiated(TestRequest::get().uri("/account.json")),
+            Representation::Json
+        );
+        assert_eq!(
+            negotiated(TestRequest::get().uri("/account?format=json")),
+            Representation::Json
+        );
+        assert_eq!(
+            negotiated(
+                TestRequest::get()
+                    .uri("/account")
+                    .insert_header((header::ACCEPT, "text/html;q=0.8, application/json"))
+            ),
+            Representation::Json
+        );
+        assert_eq!(
+            negotiated(
+                TestRequest::get()
+                    .uri("/account")
+                    .insert_header((header::ACCEPT, "text/html,application/xhtml+xml,*/*;q=0.8"))
+            ),
+            Representation::Html
+        );
+    }
+
+    #[actix_web::test]
+    async fn accounts_are_listed_in_pages() {
+        let mut store = MemoryStore::new();
+        for i in 0..7 {
+            store
+                .create(&Account::new(&format!("Account {}", i)))
+                .expect("Failed to create Account");
+        }
+        let storage: std::sync::Arc<Mutex<dyn Crud<Account>>> =
+            std::sync::Arc::new(Mutex::new(store));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Cfg::default()))
+                .app_data(web::Data::from(storage))
+                .configure(configure),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/api/v1/accounts?page=2&per_page=3")
+            .to_request();
+        let page: Page<Account> = call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            (page.page, page.per_page, page.total, page.pages),
+            (2, 3, 7, 3)
+        );
+        assert_eq!(page.items.len(), 3);
+
+        let req = TestRequest::get()
+            .uri(&format!("/api/v1/accounts/{}", Uuid::new_v4()))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let req = TestRequest::get()
+            .uri("/api/v1/accounts/not-a-uuid")
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value = actix_web::test::read_body_json(response).await;
+        assert_eq!(body["error"], "Account not found");
+    }
+
+    #[actix_web::test]
+    async fn invalid_account_is_rejected_with_field_errors() {
+        let storage: std::sync::Arc<Mutex<dyn Crud<Account>>> =
+            std::sync::Arc::new(Mutex::new(MemoryStore::new()));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Cfg::default()))
+                .app_data(web::Data::from(storage.clone()))
+                .configure(configure),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/api/v1/accounts")
+            .set_json(NewAccount {
+                fullname: " \t ".to_string(),
+                ..NewAccount::default()
+            })
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = actix_web::test::read_body_json(response).await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": "Invalid account",
+                "fields": [{"field": "fullname", "message": "must not be empty"}]
+            })
+        );
+
+        let req = TestRequest::post()
+            .uri("/api/v1/accounts")
+            .set_json(NewAccount {
+                fullname: "  Erich Schroeter ".to_string(),
+                ..NewAccount::default()
+            })
+            .to_request();
+        let created: Account = call_and_read_body_json(&app, req).await;
+        assert_eq!(created.fullname, "Erich Schroeter");
+        assert_eq!(storage.lock().unwrap().read_all().unwrap(), vec![created]);
+    }
+
+    #[actix_web::test]
+    async fn accounts_are_filtered_and_keep_their_emails_unique() {
+        let store = crate::crud::unique::with_unique(
+            Box::new(MemoryStore::new()),
+            &Cfg::default().unique_rules::<Account>(),
+        )
+        .unwrap();
+        let storage: std::sync::Arc<Mutex<dyn Crud<Account>>> =
+            std::sync::Arc::new(Mutex::new(store));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Cfg::default()))
+                .app_data(web::Data::from(storage.clone()))
+                .configure(configure),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/api/v1/accounts")
+            .set_json(serde_json::json!({
+                "fullname": "Erich",
+                "email": "Erich@Example.com",
+                "tags": ["staff"],
+                "attributes": {"floor": 3, "vip": true}
+            }))
+            .to_request();
+        let erich: Account = call_and_read_body_json(&app, req).await;
+        assert_eq!(erich.email.as_deref(), Some("erich@example.com"));
+        assert_eq!(erich.attributes["floor"], common::Attribute::Integer(3));
+        let req = TestRequest::post()
+            .uri("/api/v1/accounts")
+            .set_json(serde_json::json!({"fullname": "Jane", "status": "suspended"}))
+            .to_request();
+        let jane: Account = call_and_read_body_json(&app, req).await;
+
+        let req = TestRequest::put()
+            .uri(&format!("/api/v1/accounts/{}", jane.id))
+            .set_json(serde_json::json!({"fullname": "Jane", "email": "erich@example.com"}))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::CONFLICT);
+        let req = TestRequest::put()
+            .uri(&format!("/api/v1/accounts/{}", jane.id))
+            .set_json(serde_json::json!({"fullname": "Jane Doe"}))
+            .to_request();
+        let changed: Account = call_and_read_body_json(&app, req).await;
+        assert_eq!(changed.status, common::AccountStatus::Suspended);
+
+        for (query, expected) in [
+            ("tag=staff", vec!["Erich"]),
+            ("status=suspended", vec!["Jane Doe"]),
+            ("tag=staff&status=suspended", vec![]),
+            ("tag=&status=", vec!["Erich", "Jane Doe"]),
+        ] {
+            let req = TestRequest::get()
+                .uri(&format!("/api/v1/accounts?{}", query))
+                .to_request();
+            let page: Page<Account> = call_and_read_body_json(&app, req).await;
+            let names: Vec<_> = page.items.iter().map(|a| a.fullname.as_str()).collect();
+            assert_eq!(names, expected, "{}", query);
+            assert_eq!(page.total, expected.len(), "{}", query);
+        }
+        let req = TestRequest::get()
+            .uri("/api/v1/accounts?status=gone")
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[actix_web::test]
+    async fn contractors_may_view_but_not_delete_accounts() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let audit_path = dir.path().join("audit.jsonl");
+        let cfg = AuthCfg {
+            enabled: true,
+            policy: Policy {
+                delete: Role::Admin,
+                ..Policy::default()
+            },
+            audit_path: Some(audit_path.display().to_string()),
+            ..AuthCfg::default()
+        };
+        let mut users: Box<dyn Crud<User>> = Box::new(MemoryStore::<User>::new());
+        let contractor = User::new("contractor", "correct horse", Role::Editor).unwrap();
+        let viewer = User::new("viewer", "correct horse", Role::Viewer).unwrap();
+        users.create(&contractor).unwrap();
+        users.create(&viewer).unwrap();
+        let auth = web::Data::new(Auth::new(&cfg, users));
+        let contractor = auth.sessions.start(contractor.id, &contractor.username);
+        let viewer = auth.sessions.start(viewer.id, &viewer.username);
+
+        let account = Account::new("Test Account");
+        let mut store = MemoryStore::new();
+        store.create(&account).expect("Failed to create Account");
+        let storage: std::sync::Arc<Mutex<dyn Crud<Account>>> =
+            std::sync::Arc::new(Mutex::new(store));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Cfg::default()))
+                .app_data(web::Data::from(storage.clone()))
+                .app_data(auth)
+                .wrap(from_fn(authenticate))
+                .configure(configure),
+        )
+        .await;
+
+        let uri = format!("/api/v1/accounts/{}", account.id);
+        for cookie in [&contractor, &viewer] {
+            let req = TestRequest::get().uri(&uri).cookie(cookie.clone());
+            let shown: Account = call_and_read_body_json(&app, req.to_request()).await;
+            assert_eq!(shown, account);
+            let req = TestRequest::delete().uri(&uri).cookie(cookie.clone());
+            let response = call_service(&app, req.to_request()).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+        assert_eq!(
+            storage.lock().unwrap().read_all().unwrap(),
+            vec![account.clone()]
+        );
+
+        let denied: Vec<_> = audit::read(&audit_path)
+            .into_iter()
+            .map(|entry| (entry.who, entry.role, entry.action, entry.target))
+            .collect();
+        assert_eq!(
+            denied,
+            vec![
+                (
+                    "contractor".to_string(),
+                    Role::Editor,
+                    "delete".to_string(),
+                    Some(account.id)
+                ),
+                (
+                    "viewer".to_string(),
+                    Role::Viewer,
+                    format!("DELETE {}", uri),
+                    None
+                ),
+            ]
+        );
+    }
+}
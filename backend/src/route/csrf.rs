@@ -0,0 +1,81 @@
+//! Protection of the HTML forms against cross-site request forgery.
+//!
+//! A page with a form hands out a random token, both as a cookie and as a
+//! hidden field of the form. A submission is only accepted when the two
+//! match, which a page on another site cannot arrange because it can neither
+//! read the cookie nor choose what the browser sends in it.
+
+use actix_web::{cookie::Cookie, HttpRequest};
+use uuid::Uuid;
+
+/// The name of the cookie and of the form field holding the token.
+pub const TOKEN: &str = "csrf_token";
+
+/// The token of a form page.
+pub struct CsrfToken {
+    value: String,
+    fresh: bool,
+}
+
+impl CsrfToken {
+    /// Reuses the token of the client, so that forms open in several tabs
+    /// stay valid, or makes a new one.
+    pub fn from_request(req: &HttpRequest) -> Self {
+        match req.cookie(TOKEN) {
+            Some(cookie) if !cookie.value().is_empty() => CsrfToken {
+                value: cookie.value().to_string(),
+                fresh: false,
+            },
+            _ => CsrfToken {
+                value: Uuid::new_v4().simple().to_string(),
+                fresh: true,
+            },
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The cookie to send along with the page, if the client has none yet.
+    pub fn cookie(&self) -> Option<Cookie<'static>> {
+        self.fresh.then(|| {
+            Cookie::build(TOKEN, self.value.clone())
+                .path("/")
+                .http_only(true)
+                .same_site(actix_web::cookie::SameSite::Strict)
+                .finish()
+        })
+    }
+}
+
+/// Whether `submitted` is the token of the client that submitted the form.
+pub fn verify(req: &HttpRequest, submitted: &str) -> bool {
+    match req.cookie(TOKEN) {
+        Some(cookie) => !submitted.is_empty() && cookie.value() == submitted,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn token_is_reused_and_verified() {
+        let req = TestRequest::get().to_http_request();
+        let token = CsrfToken::from_request(&req);
+        let cookie = token.cookie().expect("New token has no cookie");
+        assert!(!verify(&req, token.value()));
+
+        let req = TestRequest::post().cookie(cookie).to_http_request();
+        let reused = CsrfToken::from_request(&req);
+        assert_eq!(reused.value(), token.value());
+        assert!(reused.cookie().is_none());
+        assert!(verify(&req, token.value()));
+        assert!(!verify(&req, "forged"));
+        assert!(!verify(&req, ""));
+    }
+}
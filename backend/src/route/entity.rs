@@ -0,0 +1,593 @@
+//! Pages and a JSON API for any [`Entity`], built from its fields alone.
+//!
+//! The pages of `T` are under `/<NAME>` and its API under
+//! `/api/v1/<PLURAL>`, like the ones of accounts but without their events,
+//! history, import and export. Forms are checked for their CSRF token, and
+//! every store call is made on behalf of the [`Caller`].
+
+use std::{collections::HashMap, sync::Mutex};
+
+use actix_web::{error, http::header, http::StatusCode, web, HttpRequest, HttpResponse};
+use common::validate::FieldError;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use super::{
+    api::{self, ApiError, PageQuery},
+    flash::Flash,
+    form::{check_csrf, redirect_to, render, with_store, Page},
+};
+use crate::{
+    auth::policy::Caller,
+    cfg::Cfg,
+    crud::{Crud, CrudError, Entity, Field, Link},
+    tenant,
+};
+
+/// What the templates are told about `T`.
+#[derive(Serialize)]
+struct Kind {
+    name: &'static str,
+    plural: &'static str,
+    title: String,
+    fields: &'static [Field],
+    links: &'static [Link],
+}
+
+impl Kind {
+    fn of<T: Entity>() -> Self {
+        Kind {
+            name: T::NAME,
+            plural: T::PLURAL,
+            title: T::title(),
+            fields: T::FIELDS,
+            links: T::LINKS,
+        }
+    }
+}
+
+/// A field of an item with its value, as the templates show it.
+#[derive(Serialize)]
+struct Cell {
+    name: &'static str,
+    label: &'static str,
+    value: String,
+}
+
+/// An item as the templates show it.
+#[derive(Serialize)]
+struct Row {
+    id: Uuid,
+    label: String,
+    cells: Vec<Cell>,
+}
+
+impl Row {
+    fn of<T: Entity>(item: &T) -> Self {
+        Row {
+            id: item.id(),
+            label: item.label(),
+            cells: cells::<T>(item.values()),
+        }
+    }
+}
+
+fn cells<T: Entity>(values: Vec<String>) -> Vec<Cell> {
+    let mut values = values.into_iter();
+    T::FIELDS
+        .iter()
+        .map(|field| Cell {
+            name: field.name,
+            label: field.label,
+            value: values.next().unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Redirects to the list of `T`s, which shows `flash`.
+fn redirect<T: Entity>(req: &HttpRequest, flash: Flash) -> HttpResponse {
+    redirect_to(req, &format!("/{}", T::NAME), flash)
+}
+
+/// Turns a store error into a redirect with a message, or an error page.
+fn store_error<T: Entity>(req: &HttpRequest, err: CrudError) -> actix_web::Result<HttpResponse> {
+    match err {
+        CrudError::NotFound => Ok(redirect::<T>(
+            req,
+            Flash::error(format!("The {} no longer exists.", T::NAME)),
+        )),
+        CrudError::AlreadyExists => Ok(redirect::<T>(
+            req,
+            Flash::error(format!(
+                "A {} with the same id or unique value already exists.",
+                T::NAME
+            )),
+        )),
+        CrudError::InUse => Ok(redirect::<T>(
+            req,
+            Flash::error(format!("The {} still has memberships.", T::NAME)),
+        )),
+        CrudError::Forbidden(operation) => Err(error::ErrorForbidden(format!(
+            "You may not {} {}.",
+            operation,
+            T::PLURAL
+        ))),
+        err => {
+            log::error!("{} form failed: {}", T::title(), err);
+            Err(error::ErrorInternalServerError(format!(
+                "The {} could not be stored",
+                T::NAME
+            )))
+        }
+    }
+}
+
+/// Lists every `T`.
+pub async fn list<T: Entity>(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<T>>>,
+) -> actix_web::Result<HttpResponse> {
+    let items = match with_store(&req, &storage, |store| store.read_all()) {
+        Ok(items) => items,
+        Err(err) => return store_error::<T>(&req, err),
+    };
+    let title = format!("All {}", T::PLURAL);
+    let page = Page {
+        template: "entities.html",
+        title: &title,
+        status: StatusCode::OK,
+    };
+    Ok(render(&req, &tmpl, &cfg, page, |ctx| {
+        ctx.insert("kind", &Kind::of::<T>());
+        ctx.insert("items", &items.iter().map(Row::of).collect::<Vec<_>>());
+    }))
+}
+
+/// Shows a `T`.
+pub async fn show<T: Entity>(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<T>>>,
+    id: web::Path<Uuid>,
+) -> actix_web::Result<HttpResponse> {
+    let item = match with_store(&req, &storage, |store| store.read(&id)) {
+        Ok(item) => item,
+        Err(CrudError::NotFound) => {
+            return Err(error::ErrorNotFound(format!("{} not found", T::title())))
+        }
+        Err(err) => return store_error::<T>(&req, err),
+    };
+    let label = item.label();
+    let page = Page {
+        template: "entity.html",
+        title: &label,
+        status: StatusCode::OK,
+    };
+    Ok(render(&req, &tmpl, &cfg, page, |ctx| {
+        ctx.insert("kind", &Kind::of::<T>());
+        ctx.insert("item", &Row::of(&item));
+    }))
+}
+
+/// Renders the create form, or the edit form of the item `id`, filled in
+/// with `values`.
+fn render_form<T: Entity>(
+    req: &HttpRequest,
+    tmpl: &tera::Tera,
+    cfg: &Cfg,
+    id: Option<Uuid>,
+    values: Vec<String>,
+    errors: &[FieldError],
+) -> HttpResponse {
+    let title = match id {
+        Some(_) => format!("Edit {}", T::title()),
+        None => format!("New {}", T::title()),
+    };
+    let page = Page {
+        template: "entity_form.html",
+        title: &title,
+        status: if errors.is_empty() {
+            StatusCode::OK
+        } else {
+            StatusCode::UNPROCESSABLE_ENTITY
+        },
+    };
+    render(req, tmpl, cfg, page, |ctx| {
+        ctx.insert("kind", &Kind::of::<T>());
+        ctx.insert("id", &id);
+        ctx.insert("cells", &cells::<T>(values));
+        ctx.insert("errors", errors);
+        if !errors.is_empty() {
+            ctx.insert(
+                "flash",
+                &Flash::error(format!(
+                    "The {} was not saved. Correct the fields below.",
+                    T::NAME
+                )),
+            );
+        }
+    })
+}
+
+/// The values of the fields of `T` in a submitted form.
+fn submitted<T: Entity>(form: &HashMap<String, String>) -> Vec<String> {
+    T::FIELDS
+        .iter()
+        .map(|field| form.get(field.name).cloned().unwrap_or_default())
+        .collect()
+}
+
+fn csrf_token(form: &HashMap<String, String>) -> &str {
+    form.get("csrf_token")
+        .map(String::as_str)
+        .unwrap_or_default()
+}
+
+/// Shows the form to create a `T`.
+pub async fn new_form<T: Entity>(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+) -> HttpResponse {
+    render_form::<T>(&req, &tmpl, &cfg, None, Vec::new(), &[])
+}
+
+/// Creates a `T` from the submitted form.
+pub async fn create<T: Entity>(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<T>>>,
+    form: web::Form<HashMap<String, String>>,
+) -> actix_web::Result<HttpResponse> {
+    check_csrf(&req, csrf_token(&form))?;
+    let values = submitted::<T>(&form);
+    let item = T::from_values(cfg.id_version.generate(), values.clone());
+    let item = match item.validate(&cfg.validation) {
+        Ok(item) => item,
+        Err(errors) => {
+            return Ok(render_form::<T>(
+                &req,
+                &tmpl,
+                &cfg,
+                None,
+                values,
+                &errors.fields,
+            ))
+        }
+    };
+    match with_store(&req, &storage, |store| store.create(&item)) {
+        Ok(()) => Ok(redirect::<T>(
+            &req,
+            Flash::success(format!("{} \"{}\" was created.", T::title(), item.label())),
+        )),
+        Err(err) => store_error::<T>(&req, err),
+    }
+}
+
+/// Shows the form to edit a `T`.
+pub async fn edit_form<T: Entity>(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<T>>>,
+    id: web::Path<Uuid>,
+) -> actix_web::Result<HttpResponse> {
+    match with_store(&req, &storage, |store| store.read(&id)) {
+        Ok(item) => Ok(render_form::<T>(
+            &req,
+            &tmpl,
+            &cfg,
+            Some(item.id()),
+            item.values(),
+            &[],
+        )),
+        Err(err) => store_error::<T>(&req, err),
+    }
+}
+
+/// Changes a `T` from the submitted form.
+pub async fn update<T: Entity>(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<T>>>,
+    id: web::Path<Uuid>,
+    form: web::Form<HashMap<String, String>>,
+) -> actix_web::Result<HttpResponse> {
+    check_csrf(&req, csrf_token(&form))?;
+    let values = submitted::<T>(&form);
+    let item = match T::from_values(*id, values.clone()).validate(&cfg.validation) {
+        Ok(item) => item,
+        Err(errors) => {
+            return Ok(render_form::<T>(
+                &req,
+                &tmpl,
+                &cfg,
+                Some(*id),
+                values,
+                &errors.fields,
+            ))
+        }
+    };
+    let updated = with_store(&req, &storage, |store| {
+        store.read(&id)?;
+        store.update(&item)
+    });
+    match updated {
+        Ok(()) => Ok(redirect::<T>(
+            &req,
+            Flash::success(format!("{} \"{}\" was saved.", T::title(), item.label())),
+        )),
+        Err(err) => store_error::<T>(&req, err),
+    }
+}
+
+/// Asks to confirm deleting a `T`.
+pub async fn confirm_delete<T: Entity>(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<T>>>,
+    id: web::Path<Uuid>,
+) -> actix_web::Result<HttpResponse> {
+    match with_store(&req, &storage, |store| store.read(&id)) {
+        Ok(item) => {
+            let title = format!("Delete {}", T::title());
+            let page = Page {
+                template: "entity_delete.html",
+                title: &title,
+                status: StatusCode::OK,
+            };
+            Ok(render(&req, &tmpl, &cfg, page, |ctx| {
+                ctx.insert("kind", &Kind::of::<T>());
+                ctx.insert("item", &Row::of(&item));
+            }))
+        }
+        Err(err) => store_error::<T>(&req, err),
+    }
+}
+
+/// Deletes a `T` once confirmed.
+pub async fn delete<T: Entity>(
+    req: HttpRequest,
+    storage: web::Data<Mutex<dyn Crud<T>>>,
+    id: web::Path<Uuid>,
+    form: web::Form<HashMap<String, String>>,
+) -> actix_web::Result<HttpResponse> {
+    check_csrf(&req, csrf_token(&form))?;
+    let deleted = with_store(&req, &storage, |store| {
+        let item = store.read(&id)?;
+        store.delete(&item)?;
+        Ok(item)
+    });
+    match deleted {
+        Ok(item) => Ok(redirect::<T>(
+            &req,
+            Flash::success(format!("{} \"{}\" was deleted.", T::title(), item.label())),
+        )),
+        Err(err) => store_error::<T>(&req, err),
+    }
+}
+
+/// Mounts the pages of `T` under `/<NAME>`.
+pub fn configure<T: Entity>(cfg: &mut web::ServiceConfig) {
+    let path = format!("/{}", T::NAME);
+    cfg.route(&path, web::get().to(list::<T>))
+        .route(&format!("{}/new", path), web::get().to(new_form::<T>))
+        .route(&format!("{}/new", path), web::post().to(create::<T>))
+        .route(&format!("{}/{{id}}", path), web::get().to(show::<T>))
+        .route(
+            &format!("{}/{{id}}/edit", path),
+            web::get().to(edit_form::<T>),
+        )
+        .route(
+            &format!("{}/{{id}}/edit", path),
+            web::post().to(update::<T>),
+        )
+        .route(
+            &format!("{}/{{id}}/delete", path),
+            web::get().to(confirm_delete::<T>),
+        )
+        .route(
+            &format!("{}/{{id}}/delete", path),
+            web::post().to(delete::<T>),
+        );
+}
+
+/// Reads a JSON object of the fields of a `T` with the id `id`. Fields that
+/// are left out keep the values of `base`, if any.
+fn item_from_json<T: Entity>(
+    id: Uuid,
+    base: Option<&T>,
+    body: Map<String, Value>,
+) -> Result<T, ApiError> {
+    let mut object = match base.map(serde_json::to_value) {
+        Some(Ok(Value::Object(object))) => object,
+        _ => Map::new(),
+    };
+    object.extend(body);
+    object.insert("id".to_string(), Value::String(id.to_string()));
+    serde_json::from_value(Value::Object(object))
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))
+}
+
+/// Lists a page of `T`s.
+pub async fn api_list<T: Entity>(
+    storage: web::Data<Mutex<dyn Crud<T>>>,
+    caller: Caller,
+    query: web::Query<PageQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let page = api::with_store(&storage, &caller, |store| api::read_page(store, &query))?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// Creates a `T` with an id the server assigns.
+pub async fn api_create<T: Entity>(
+    req: HttpRequest,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<T>>>,
+    caller: Caller,
+    body: web::Json<Map<String, Value>>,
+) -> Result<HttpResponse, ApiError> {
+    let item: T = item_from_json(cfg.id_version.generate(), None, body.into_inner())?;
+    let item = item
+        .validate(&cfg.validation)
+        .map_err(ApiError::invalid::<T>)?;
+    api::with_store(&storage, &caller, |store| store.create(&item))?;
+    let location = format!("{}/api/v1/{}/{}", tenant::base(&req), T::PLURAL, item.id());
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, location))
+        .json(item))
+}
+
+/// Gets a `T`.
+pub async fn api_get<T: Entity>(
+    storage: web::Data<Mutex<dyn Crud<T>>>,
+    caller: Caller,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let item = api::with_store(&storage, &caller, |store| store.read(&id))?;
+    Ok(HttpResponse::Ok().json(item))
+}
+
+/// Changes the fields of a `T` that the body has.
+pub async fn api_update<T: Entity>(
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<T>>>,
+    caller: Caller,
+    id: web::Path<Uuid>,
+    body: web::Json<Map<String, Value>>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    let item = api::with_store(&storage, &caller, |store| {
+        let stored = store.read(&id)?;
+        Ok(item_from_json::<T>(*id, Some(&stored), body))
+    })??;
+    let item = item
+        .validate(&cfg.validation)
+        .map_err(ApiError::invalid::<T>)?;
+    api::with_store(&storage, &caller, |store| store.update(&item))?;
+    Ok(HttpResponse::Ok().json(item))
+}
+
+/// Deletes a `T`.
+pub async fn api_delete<T: Entity>(
+    storage: web::Data<Mutex<dyn Crud<T>>>,
+    caller: Caller,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    api::with_store(&storage, &caller, |store| {
+        let item = store.read(&id)?;
+        store.delete(&item)
+    })?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Mounts the API of `T` under `/<PLURAL>` of the API scope.
+pub fn configure_api<T: Entity>(cfg: &mut web::ServiceConfig) {
+    let not_found = |_, _: &HttpRequest| {
+        ApiError::new(StatusCode::NOT_FOUND, format!("{} not found", T::title())).into()
+    };
+    cfg.service(
+        web::resource(format!("/{}", T::PLURAL))
+            .route(web::get().to(api_list::<T>))
+            .route(web::post().to(api_create::<T>)),
+    )
+    .service(
+        web::resource(format!("/{}/{{id}}", T::PLURAL))
+            .app_data(web::PathConfig::default().error_handler(not_found))
+            .route(web::get().to(api_get::<T>))
+            .route(web::put().to(api_update::<T>))
+            .route(web::delete().to(api_delete::<T>)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{
+        test::{call_and_read_body_json, call_service, init_service, read_body, TestRequest},
+        App,
+    };
+    use common::Group;
+
+    use super::*;
+    use crate::{cfg::default_template_glob, crud::memory::MemoryStore, route::csrf};
+
+    #[actix_web::test]
+    async fn groups_get_pages_and_an_api() {
+        let tera = tera::Tera::new(&default_template_glob()).expect("Failed to load templates");
+        let storage: Arc<Mutex<dyn Crud<Group>>> =
+            Arc::new(Mutex::new(MemoryStore::<Group>::new()));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(tera))
+                .app_data(web::Data::new(Cfg::default()))
+                .app_data(web::Data::from(storage.clone()))
+                .configure(configure::<Group>)
+                .service(web::scope("/api/v1").configure(configure_api::<Group>)),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/api/v1/groups")
+            .set_json(serde_json::json!({"name": " Sales ", "description": "Who sells"}))
+            .to_request();
+        let group: Group = call_and_read_body_json(&app, req).await;
+        assert_eq!(group.name, "Sales");
+        let req = TestRequest::put()
+            .uri(&format!("/api/v1/groups/{}", group.id))
+            .set_json(serde_json::json!({"name": ""}))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let req = TestRequest::get().uri("/api/v1/groups/nope").to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = call_service(&app, TestRequest::get().uri("/group/new").to_request()).await;
+        let csrf = response
+            .response()
+            .cookies()
+            .find(|cookie| cookie.name() == csrf::TOKEN)
+            .expect("No CSRF cookie")
+            .into_owned();
+        let token = csrf.value().to_string();
+        let req = TestRequest::post()
+            .uri(&format!("/group/{}/edit", group.id))
+            .cookie(csrf.clone())
+            .set_form([
+                ("csrf_token", token.as_str()),
+                ("name", "Sales"),
+                ("description", "Everyone who sells"),
+            ])
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            storage.lock().unwrap().read(&group.id).unwrap().description,
+            "Everyone who sells"
+        );
+
+        let req = TestRequest::get().uri("/group").to_request();
+        let body = String::from_utf8(read_body(call_service(&app, req).await).await.to_vec())
+            .expect("Page is not UTF-8");
+        assert!(body.contains("Everyone who sells"));
+        assert!(body.contains(&format!("/group/{}/edit", group.id)));
+
+        let req = TestRequest::post()
+            .uri(&format!("/group/{}/delete", group.id))
+            .cookie(csrf)
+            .set_form([("csrf_token", token.as_str())])
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert!(storage.lock().unwrap().read_all().unwrap().is_empty());
+    }
+}
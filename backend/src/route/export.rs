@@ -0,0 +1,209 @@
+use std::{io::Write, sync::Mutex};
+
+use actix_web::{error, http::header, web, HttpResponse};
+use common::Account;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use utoipa::IntoParams;
+
+use super::api;
+use crate::{
+    auth::policy::Caller,
+    crud::Crud,
+    transfer::{AccountWriter, Format},
+};
+
+/// Bytes gathered before a chunk is sent to the client.
+const CHUNK_SIZE: usize = 8 * 1024;
+/// Chunks buffered between the writer and the response, which bounds the
+/// memory an export takes however large the store is.
+const CHUNK_BUFFER: usize = 16;
+
+type Chunk = Result<web::Bytes, std::io::Error>;
+
+/// Sends what is written to it as chunks of the response body. Writing
+/// blocks while the client is slower than the store, and fails once the
+/// client has gone away.
+struct ChunkWriter {
+    chunks: mpsc::Sender<Chunk>,
+    buf: Vec<u8>,
+}
+
+impl ChunkWriter {
+    fn send(&mut self) -> std::io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = web::Bytes::from(std::mem::replace(
+            &mut self.buf,
+            Vec::with_capacity(CHUNK_SIZE),
+        ));
+        self.chunks
+            .blocking_send(Ok(chunk))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Download cancelled"))
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.send()
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    /// `csv` (default), `json`, `jsonl` or `yaml`.
+    format: Option<String>,
+    /// Only accounts whose full name contains this text, ignoring case.
+    q: Option<String>,
+}
+
+/// Writes the accounts that `matches` to `out` in `format`.
+fn write_accounts(
+    accounts: impl Iterator<Item = crate::crud::Result<Account>>,
+    matches: impl Fn(&Account) -> bool,
+    out: impl Write,
+    format: Format,
+) -> std::io::Result<()> {
+    let mut writer = AccountWriter::new(out, format)?;
+    for account in accounts {
+        let account = account.map_err(std::io::Error::other)?;
+        if matches(&account) {
+            writer.write(&account)?;
+        }
+    }
+    writer.finish()?.flush()
+}
+
+/// Streams all accounts, or those matching `q`, as a download.
+///
+/// The accounts are read one at a time from [`Crud::iter`] on a blocking
+/// thread, and the storage lock is only held while the iterator is created.
+#[utoipa::path(
+    get,
+    path = "/account/export",
+    tag = "accounts",
+    summary = "Download accounts",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "The accounts as a download", content(
+            (String = "text/csv"),
+            ([Account] = "application/json"),
+            (Account = "application/x-ndjson"),
+            (String = "application/yaml"),
+        ), headers(("Content-Disposition" = String, description = "The name of the download"))),
+        (status = 400, description = "Unknown format", body = String),
+        (status = 403, description = "The caller may not read accounts", body = api::ErrorBody),
+    )
+)]
+pub async fn export_accounts(
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    caller: Caller,
+    query: web::Query<ExportQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let format = match &query.format {
+        Some(format) => {
+            <Format as clap::ValueEnum>::from_str(format, true).map_err(error::ErrorBadRequest)?
+        }
+        None => Format::Csv,
+    };
+    let filter = query.q.as_deref().unwrap_or("").to_lowercase();
+    let accounts = caller
+        .guard(
+            &mut *storage
+                .lock()
+                .map_err(|_| error::ErrorInternalServerError("Storage lock failed"))?,
+        )
+        .iter()
+        .map_err(api::ApiError::from)?;
+
+    let (sender, chunks) = mpsc::channel(CHUNK_BUFFER);
+    actix_web::rt::task::spawn_blocking(move || {
+        let out = ChunkWriter {
+            chunks: sender.clone(),
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        };
+        let matches = |account: &Account| account.fullname.to_lowercase().contains(&filter);
+        if let Err(err) = write_accounts(accounts, matches, out, format) {
+            log::warn!("Export failed: {}", err);
+            // Fails the response, so the client sees an incomplete download.
+            sender.blocking_send(Err(err)).ok();
+        }
+    });
+    let body = futures_util::stream::unfold(chunks, |mut chunks| async move {
+        chunks.recv().await.map(|chunk| (chunk, chunks))
+    });
+
+    let filename = format!(
+        "accounts-{}.{}",
+        chrono::Local::now().format("%Y-%m-%d"),
+        format
+    );
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(header::ContentDisposition::attachment(filename))
+        .streaming(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filtered_accounts_are_sent_in_chunks() {
+        let accounts: Vec<Account> = (0..1000)
+            .map(|i| Account::new(&format!("Account {}", i)))
+            .collect();
+        let (sender, mut chunks) = mpsc::channel(1024);
+        let out = ChunkWriter {
+            chunks: sender,
+            buf: Vec::new(),
+        };
+        write_accounts(
+            accounts.clone().into_iter().map(Ok),
+            |account| account.fullname.ends_with('7'),
+            out,
+            Format::Jsonl,
+        )
+        .expect("Failed to write accounts");
+
+        let mut body = Vec::new();
+        while let Ok(chunk) = chunks.try_recv() {
+            let chunk = chunk.expect("Failed to send chunk");
+            assert!(chunk.len() <= CHUNK_SIZE + 100);
+            body.extend_from_slice(&chunk);
+        }
+        let written: Vec<Account> = String::from_utf8(body)
+            .expect("Export is not UTF-8")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Failed to parse line"))
+            .collect();
+        assert_eq!(written.len(), 100);
+        assert!(written.iter().all(|account| accounts.contains(account)));
+    }
+
+    #[test]
+    fn cancelled_download_stops_writing() {
+        let (sender, chunks) = mpsc::channel(1);
+        drop(chunks);
+        let out = ChunkWriter {
+            chunks: sender,
+            buf: Vec::new(),
+        };
+        let accounts = (0..1000).map(|i| Ok(Account::new(&format!("Account {}", i))));
+        let result = write_accounts(accounts, |_| true, out, Format::Jsonl);
+        assert_eq!(
+            result.map_err(|e| e.kind()),
+            Err(std::io::ErrorKind::BrokenPipe)
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! One-time messages shown on the page a form redirects to.
+//!
+//! The message travels in a cookie that the next page takes and removes.
+
+use actix_web::{
+    cookie::{time::Duration, Cookie, SameSite},
+    http::header,
+    HttpRequest, HttpResponseBuilder,
+};
+use serde::{Deserialize, Serialize};
+
+const COOKIE: &str = "flash";
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Success,
+    Error,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Flash {
+    pub level: Level,
+    pub message: String,
+}
+
+impl Flash {
+    pub fn success(message: impl Into<String>) -> Self {
+        Flash {
+            level: Level::Success,
+            message: message.into(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Flash {
+            level: Level::Error,
+            message: message.into(),
+        }
+    }
+
+    /// Adds the message to `response`, to be shown by the next page.
+    pub fn set(&self, response: &mut HttpResponseBuilder) {
+        let value = serde_json::to_string(self).expect("Failed to serialize Flash");
+        let cookie = Cookie::build(COOKIE, value)
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .finish();
+        // Requests decode cookies, but responses do not encode them.
+        response.append_header((header::SET_COOKIE, cookie.encoded().to_string()));
+    }
+
+    /// Takes the message sent to this page, if any, removing it from the
+    /// client with `response`.
+    pub fn take(req: &HttpRequest, response: &mut HttpResponseBuilder) -> Option<Flash> {
+        let cookie = req.cookie(COOKIE)?;
+        response.cookie(
+            Cookie::build(COOKIE, "")
+                .path("/")
+                .max_age(Duration::ZERO)
+                .finish(),
+        );
+        serde_json::from_str(cookie.value()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test::TestRequest, HttpResponse};
+
+    use super::*;
+
+    #[test]
+    fn message_is_passed_to_next_request_once() {
+        let flash = Flash::success("Account \"Jörg; Müller\" was created.");
+        let mut response = HttpResponse::SeeOther();
+        flash.set(&mut response);
+        let response = response.finish();
+        let cookie = response.cookies().next().expect("No flash cookie");
+
+        let req = TestRequest::get()
+            .cookie(cookie.into_owned())
+            .to_http_request();
+        let mut response = HttpResponse::Ok();
+        assert_eq!(Flash::take(&req, &mut response), Some(flash));
+        let removal = response.finish();
+        let removal = removal
+            .cookies()
+            .next()
+            .expect("Flash cookie is not removed");
+        assert_eq!(removal.max_age(), Some(Duration::ZERO));
+
+        let req = TestRequest::get().to_http_request();
+        assert_eq!(Flash::take(&req, &mut HttpResponse::Ok()), None);
+    }
+}
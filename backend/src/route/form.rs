@@ -0,0 +1,552 @@
+//! The HTML forms to create, edit and delete accounts.
+//!
+//! Submissions are form-urlencoded and checked for their [`csrf`] token.
+//! After a change the browser is redirected to the list of accounts, which
+//! shows a [`Flash`] message about it; invalid input shows the form again
+//! with what was wrong.
+
+use std::sync::Mutex;
+
+use actix_web::{error, http::header, http::StatusCode, web, HttpRequest, HttpResponse};
+use common::{
+    validate::FieldError, Account, AccountStatus, AccountUpdate, Attribute, Attributes, NewAccount,
+};
+use serde::{Deserialize, Serialize};
+use tera::Context;
+use uuid::Uuid;
+
+use super::{
+    csrf::{self, CsrfToken},
+    flash::Flash,
+    VERSION,
+};
+use crate::{
+    auth::{self, policy::Caller},
+    cfg::Cfg,
+    crud::{Crud, CrudError, Identifiable},
+    tenant,
+};
+
+/// The fields of the account form. Tags are separated by commas, and each
+/// line of `attributes` holds one `name = value`.
+#[derive(Default, Deserialize, Serialize)]
+pub struct AccountForm {
+    #[serde(skip_serializing)]
+    csrf_token: String,
+    fullname: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    status: AccountStatus,
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    attributes: String,
+}
+
+impl AccountForm {
+    /// The form filled in with `account`.
+    fn of(account: &Account) -> Self {
+        AccountForm {
+            csrf_token: String::new(),
+            fullname: account.fullname.clone(),
+            email: account.email.clone().unwrap_or_default(),
+            status: account.status,
+            tags: account.tags.join(", "),
+            attributes: account
+                .attributes
+                .iter()
+                .map(|(name, value)| format!("{} = {}\n", name, value))
+                .collect(),
+        }
+    }
+
+    fn tags(&self) -> Vec<String> {
+        self.tags.split(',').map(str::to_string).collect()
+    }
+
+    fn attributes(&self) -> Result<Attributes, FieldError> {
+        let mut attributes = Attributes::new();
+        for (i, line) in self.attributes.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(FieldError {
+                    field: "attributes".to_string(),
+                    message: format!("on line {} needs a \"=\"", i + 1),
+                });
+            };
+            attributes.insert(name.to_string(), Attribute::parse(value));
+        }
+        Ok(attributes)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeleteForm {
+    csrf_token: String,
+}
+
+/// Fails a submission whose CSRF token does not match the cookie.
+pub(super) fn check_csrf(req: &HttpRequest, token: &str) -> actix_web::Result<()> {
+    if csrf::verify(req, token) {
+        Ok(())
+    } else {
+        Err(error::ErrorForbidden(
+            "The form has expired or was sent from another site. Reload it and try again.",
+        ))
+    }
+}
+
+/// Redirects to the list of accounts, which shows `flash`.
+fn redirect(req: &HttpRequest, flash: Flash) -> HttpResponse {
+    redirect_to(req, "/account", flash)
+}
+
+/// Redirects to `path` of the tenant serving `req`, which shows `flash`.
+pub(super) fn redirect_to(req: &HttpRequest, path: &str, flash: Flash) -> HttpResponse {
+    let mut response = HttpResponse::SeeOther();
+    response.insert_header((header::LOCATION, format!("{}{}", tenant::base(req), path)));
+    flash.set(&mut response);
+    response.finish()
+}
+
+/// Runs `f` on the locked store on behalf of the sender of `req`.
+pub(super) fn with_store<T: Identifiable, R>(
+    req: &HttpRequest,
+    storage: &Mutex<dyn Crud<T>>,
+    f: impl FnOnce(&mut dyn Crud<T>) -> crate::crud::Result<R>,
+) -> crate::crud::Result<R> {
+    let caller = Caller::of(req);
+    let mut storage = storage.lock().map_err(|_| {
+        log::error!("Storage lock failed");
+        CrudError::UnknownError
+    })?;
+    f(&mut caller.guard(&mut *storage))
+}
+
+/// Turns a store error into a redirect with a message, or an error page.
+fn store_error(req: &HttpRequest, err: CrudError) -> actix_web::Result<HttpResponse> {
+    match err {
+        CrudError::NotFound => Ok(redirect(req, Flash::error("The account no longer exists."))),
+        CrudError::AlreadyExists => Ok(redirect(
+            req,
+            Flash::error("An account with the same id or unique value already exists."),
+        )),
+        CrudError::InUse => Ok(redirect(
+            req,
+            Flash::error("The account still belongs to groups."),
+        )),
+        CrudError::Forbidden(operation) => Err(error::ErrorForbidden(format!(
+            "You may not {} accounts.",
+            operation
+        ))),
+        err => {
+            log::error!("Account form failed: {}", err);
+            Err(error::ErrorInternalServerError(
+                "The account could not be stored",
+            ))
+        }
+    }
+}
+
+pub(super) struct Page<'a> {
+    pub template: &'a str,
+    pub title: &'a str,
+    pub status: StatusCode,
+}
+
+/// Renders a page with a form, handing out the CSRF token it submits.
+pub(super) fn render(
+    req: &HttpRequest,
+    tmpl: &tera::Tera,
+    cfg: &Cfg,
+    page: Page,
+    fill: impl FnOnce(&mut Context),
+) -> HttpResponse {
+    let csrf = CsrfToken::from_request(req);
+    let mut ctx = Context::new();
+    ctx.insert("version", &VERSION);
+    ctx.insert("backend", &cfg.backend());
+    ctx.insert("title", page.title);
+    ctx.insert("csrf_token", csrf.value());
+    ctx.insert("user", &auth::identity(req));
+    ctx.insert("base", &tenant::base(req));
+    let mut response = HttpResponse::build(page.status);
+    ctx.insert("flash", &Flash::take(req, &mut response));
+    fill(&mut ctx);
+    if let Some(cookie) = csrf.cookie() {
+        response.cookie(cookie);
+    }
+    let s = tmpl.render(page.template, &ctx).unwrap();
+    response.content_type("text/html; charset=utf-8").body(s)
+}
+
+/// Renders the create form, or the edit form of `account`.
+fn render_form(
+    req: &HttpRequest,
+    tmpl: &tera::Tera,
+    cfg: &Cfg,
+    account: Option<&Account>,
+    form: &AccountForm,
+    errors: &[FieldError],
+) -> HttpResponse {
+    let page = Page {
+        template: "account_form.html",
+        title: if account.is_some() {
+            "Edit Account"
+        } else {
+            "New Account"
+        },
+        status: if errors.is_empty() {
+            StatusCode::OK
+        } else {
+            StatusCode::UNPROCESSABLE_ENTITY
+        },
+    };
+    render(req, tmpl, cfg, page, |ctx| {
+        ctx.insert("account", &account);
+        ctx.insert("form", form);
+        ctx.insert("statuses", &AccountStatus::ALL);
+        ctx.insert("errors", errors);
+        if !errors.is_empty() {
+            ctx.insert(
+                "flash",
+                &Flash::error("The account was not saved. Correct the fields below."),
+            );
+        }
+    })
+}
+
+/// Shows the form to create an account.
+pub async fn new_account(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+) -> HttpResponse {
+    render_form(&req, &tmpl, &cfg, None, &AccountForm::default(), &[])
+}
+
+/// Creates an account from the submitted form.
+pub async fn create_account(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    form: web::Form<AccountForm>,
+) -> actix_web::Result<HttpResponse> {
+    check_csrf(&req, &form.csrf_token)?;
+    let new = form
+        .attributes()
+        .map_err(|error| vec![error])
+        .and_then(|attributes| {
+            let new = NewAccount {
+                fullname: form.fullname.clone(),
+                email: Some(form.email.clone()),
+                status: form.status,
+                tags: form.tags(),
+                attributes,
+            };
+            cfg.validation
+                .new_account(&new)
+                .map_err(|errors| errors.fields)
+        });
+    let account = match new {
+        Ok(new) => new.into_account(cfg.id_version.generate()),
+        Err(errors) => return Ok(render_form(&req, &tmpl, &cfg, None, &form, &errors)),
+    };
+    match with_store(&req, &storage, |store| store.create(&account)) {
+        Ok(()) => Ok(redirect(
+            &req,
+            Flash::success(format!("Account \"{}\" was created.", account.fullname)),
+        )),
+        Err(err) => store_error(&req, err),
+    }
+}
+
+/// Shows the form to edit an account.
+pub async fn edit_account(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    id: web::Path<Uuid>,
+) -> actix_web::Result<HttpResponse> {
+    match with_store(&req, &storage, |store| store.read(&id)) {
+        Ok(account) => Ok(render_form(
+            &req,
+            &tmpl,
+            &cfg,
+            Some(&account),
+            &AccountForm::of(&account),
+            &[],
+        )),
+        Err(err) => store_error(&req, err),
+    }
+}
+
+/// Changes an account from the submitted form.
+pub async fn update_account(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    id: web::Path<Uuid>,
+    form: web::Form<AccountForm>,
+) -> actix_web::Result<HttpResponse> {
+    check_csrf(&req, &form.csrf_token)?;
+    let update = form
+        .attributes()
+        .map_err(|error| vec![error])
+        .and_then(|attributes| {
+            let update = AccountUpdate {
+                fullname: form.fullname.clone(),
+                email: Some(form.email.clone()),
+                status: Some(form.status),
+                tags: Some(form.tags()),
+                attributes: Some(attributes),
+            };
+            cfg.validation
+                .account_update(&update)
+                .map_err(|errors| errors.fields)
+        });
+    let update = match update {
+        Ok(update) => update,
+        Err(errors) => {
+            return match with_store(&req, &storage, |store| store.read(&id)) {
+                Ok(account) => Ok(render_form(
+                    &req,
+                    &tmpl,
+                    &cfg,
+                    Some(&account),
+                    &form,
+                    &errors,
+                )),
+                Err(err) => store_error(&req, err),
+            }
+        }
+    };
+    let updated = with_store(&req, &storage, |store| {
+        let account = update.apply(store.read(&id)?);
+        store.update(&account)?;
+        Ok(account)
+    });
+    match updated {
+        Ok(account) => Ok(redirect(
+            &req,
+            Flash::success(format!("Account \"{}\" was saved.", account.fullname)),
+        )),
+        Err(err) => store_error(&req, err),
+    }
+}
+
+/// Asks to confirm deleting an account.
+pub async fn confirm_delete(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    id: web::Path<Uuid>,
+) -> actix_web::Result<HttpResponse> {
+    match with_store(&req, &storage, |store| store.read(&id)) {
+        Ok(account) => {
+            let page = Page {
+                template: "account_delete.html",
+                title: "Delete Account",
+                status: StatusCode::OK,
+            };
+            Ok(render(&req, &tmpl, &cfg, page, |ctx| {
+                ctx.insert("account", &account)
+            }))
+        }
+        Err(err) => store_error(&req, err),
+    }
+}
+
+/// Deletes an account once confirmed.
+pub async fn delete_account(
+    req: HttpRequest,
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    id: web::Path<Uuid>,
+    form: web::Form<DeleteForm>,
+) -> actix_web::Result<HttpResponse> {
+    check_csrf(&req, &form.csrf_token)?;
+    let deleted = with_store(&req, &storage, |store| {
+        let account = store.read(&id)?;
+        store.delete(&account)?;
+        Ok(account)
+    });
+    match deleted {
+        Ok(account) => Ok(redirect(
+            &req,
+            Flash::success(format!("Account \"{}\" was deleted.", account.fullname)),
+        )),
+        Err(err) => store_error(&req, err),
+    }
+}
+
+/// Mounts the forms under `/account`.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/account/new", web::get().to(new_account))
+        .route("/account/new", web::post().to(create_account))
+        .route("/account/{id}/edit", web::get().to(edit_account))
+        .route("/account/{id}/edit", web::post().to(update_account))
+        .route("/account/{id}/delete", web::get().to(confirm_delete))
+        .route("/account/{id}/delete", web::post().to(delete_account));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{
+        cookie::Cookie,
+        test::{call_service, init_service, read_body, TestRequest},
+        App,
+    };
+
+    use super::*;
+    use crate::{cfg::default_template_glob, crud::memory::MemoryStore};
+
+    fn app_data() -> (web::Data<tera::Tera>, Arc<Mutex<dyn Crud<Account>>>) {
+        let tera = tera::Tera::new(&default_template_glob()).expect("Failed to load templates");
+        let storage: Arc<Mutex<dyn Crud<Account>>> =
+            Arc::new(Mutex::new(MemoryStore::<Account>::new()));
+        (web::Data::new(tera), storage)
+    }
+
+    #[actix_web::test]
+    async fn account_is_created_edited_and_deleted() {
+        let (tera, storage) = app_data();
+        let app = init_service(
+            App::new()
+                .app_data(tera)
+                .app_data(web::Data::new(Cfg::default()))
+                .app_data(web::Data::from(storage.clone()))
+                .configure(configure),
+        )
+        .await;
+
+        let response =
+            call_service(&app, TestRequest::get().uri("/account/new").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let csrf = response
+            .response()
+            .cookies()
+            .find(|cookie| cookie.name() == csrf::TOKEN)
+            .expect("No CSRF cookie")
+            .into_owned();
+        let token = csrf.value().to_string();
+
+        let req = TestRequest::post()
+            .uri("/account/new")
+            .cookie(csrf.clone())
+            .set_form([
+                ("csrf_token", token.as_str()),
+                ("fullname", " Erich "),
+                ("attributes", "floor"),
+            ])
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains("on line 1 needs a"));
+
+        let req = TestRequest::post()
+            .uri("/account/new")
+            .cookie(csrf.clone())
+            .set_form([
+                ("csrf_token", token.as_str()),
+                ("fullname", " Erich "),
+                ("email", "Erich@Example.com"),
+                ("status", "suspended"),
+                ("tags", "staff, emea,"),
+                ("attributes", "floor = 3\r\nvip = true\r\n"),
+            ])
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert!(response.response().cookies().any(|c| c.name() == "flash"));
+        let account = storage.lock().unwrap().read_all().unwrap().remove(0);
+        assert_eq!(account.fullname, "Erich");
+        assert_eq!(account.email.as_deref(), Some("erich@example.com"));
+        assert_eq!(account.status, AccountStatus::Suspended);
+        assert_eq!(account.tags, vec!["staff", "emea"]);
+        assert_eq!(account.attributes["floor"], Attribute::Integer(3));
+        assert_eq!(account.attributes["vip"], Attribute::Bool(true));
+
+        let req = TestRequest::get()
+            .uri(&format!("/account/{}/edit", account.id))
+            .to_request();
+        let body =
+            String::from_utf8(read_body(call_service(&app, req).await).await.to_vec()).unwrap();
+        assert!(body.contains("value=\"staff, emea\""));
+        assert!(body.contains("floor = 3\nvip = true"));
+
+        let req = TestRequest::post()
+            .uri(&format!("/account/{}/edit", account.id))
+            .cookie(csrf.clone())
+            .set_form([("csrf_token", token.as_str()), ("fullname", "")])
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains("must not be empty"));
+
+        let req = TestRequest::post()
+            .uri(&format!("/account/{}/edit", account.id))
+            .cookie(csrf.clone())
+            .set_form([
+                ("csrf_token", token.as_str()),
+                ("fullname", "Erich Schroeter"),
+            ])
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            storage.lock().unwrap().read(&account.id).unwrap().fullname,
+            "Erich Schroeter"
+        );
+
+        let req = TestRequest::get()
+            .uri(&format!("/account/{}/delete", account.id))
+            .to_request();
+        let body =
+            String::from_utf8(read_body(call_service(&app, req).await).await.to_vec()).unwrap();
+        assert!(body.contains("Erich Schroeter"));
+        let req = TestRequest::post()
+            .uri(&format!("/account/{}/delete", account.id))
+            .cookie(csrf)
+            .set_form([("csrf_token", token.as_str())])
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert!(storage.lock().unwrap().read_all().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn forged_submission_is_forbidden() {
+        let (tera, storage) = app_data();
+        let app = init_service(
+            App::new()
+                .app_data(tera)
+                .app_data(web::Data::new(Cfg::default()))
+                .app_data(web::Data::from(storage.clone()))
+                .configure(configure),
+        )
+        .await;
+
+        for cookie in [None, Some(Cookie::new(csrf::TOKEN, "other"))] {
+            let mut req = TestRequest::post()
+                .uri("/account/new")
+                .set_form([("csrf_token", "forged"), ("fullname", "Mallory")]);
+            if let Some(cookie) = cookie {
+                req = req.cookie(cookie);
+            }
+            let response = call_service(&app, req.to_request()).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+        assert!(storage.lock().unwrap().read_all().unwrap().is_empty());
+    }
+}
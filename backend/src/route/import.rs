@@ -0,0 +1,562 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_multipart::{Field, Multipart};
+use actix_web::{error, http::StatusCode, web, HttpRequest, HttpResponse, Responder};
+use common::{validate::Rules, Account};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use tera::Context;
+use tokio::sync::mpsc;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{
+    api::{self, Representation},
+    VERSION,
+};
+use crate::{
+    auth::{self, policy::Caller},
+    cfg::Cfg,
+    crud::Crud,
+    tenant,
+    transfer::{self, Conflict, Format, Record, Rejected},
+};
+
+/// How long a previewed upload waits to be committed.
+const STAGING_TTL: Duration = Duration::from_secs(30 * 60);
+/// Reading stops after this many rows, to bound the memory an upload takes.
+const MAX_ROWS: usize = 100_000;
+/// How many valid rows the preview lists.
+const PREVIEW_ROWS: usize = 20;
+/// Upload chunks buffered between the request and the parser.
+const CHUNK_BUFFER: usize = 16;
+
+/// The valid rows of an upload, held between its preview and its commit.
+struct Staged {
+    rows: Vec<(usize, Record)>,
+    uploaded: Instant,
+}
+
+/// Uploads that were previewed but not committed yet.
+#[derive(Default)]
+pub struct ImportStaging {
+    uploads: Mutex<HashMap<Uuid, Staged>>,
+}
+
+impl ImportStaging {
+    fn stage(&self, rows: Vec<(usize, Record)>) -> Uuid {
+        let token = Uuid::new_v4();
+        let mut uploads = self.uploads.lock().unwrap();
+        uploads.retain(|_, staged| staged.uploaded.elapsed() < STAGING_TTL);
+        uploads.insert(
+            token,
+            Staged {
+                rows,
+                uploaded: Instant::now(),
+            },
+        );
+        token
+    }
+
+    fn take(&self, token: &Uuid) -> Option<Staged> {
+        let mut uploads = self.uploads.lock().unwrap();
+        uploads
+            .remove(token)
+            .filter(|staged| staged.uploaded.elapsed() < STAGING_TTL)
+    }
+}
+
+/// Reads the chunks of an upload, sent from the request handler, as a
+/// blocking [`Read`] for the parsers.
+struct ChunkReader {
+    chunks: mpsc::Receiver<web::Bytes>,
+    chunk: web::Bytes,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.chunk = chunk,
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk.split_to(n));
+        Ok(n)
+    }
+}
+
+/// The outcome of parsing and validating an upload.
+#[derive(Default)]
+struct Parsed {
+    valid: Vec<(usize, Record)>,
+    rejected: Vec<Rejected>,
+}
+
+fn parse(input: ChunkReader, format: Format, rules: &Rules) -> std::io::Result<Parsed> {
+    let mut parsed = Parsed::default();
+    let input = std::io::BufReader::new(input);
+    for (row, record) in transfer::read_records(input, format)? {
+        if row > MAX_ROWS {
+            parsed.rejected.push(Rejected {
+                row,
+                record: None,
+                reason: format!("only the first {} rows can be uploaded", MAX_ROWS),
+            });
+            break;
+        }
+        let record = match record {
+            Ok(record) => record,
+            Err(reason) => {
+                parsed.rejected.push(Rejected {
+                    row,
+                    record: None,
+                    reason,
+                });
+                continue;
+            }
+        };
+        match transfer::validate(&record, rules) {
+            Ok(_) => parsed.valid.push((row, record)),
+            Err(reason) => parsed.rejected.push(Rejected {
+                row,
+                record: Some(record),
+                reason,
+            }),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Parses the `file` field of an upload as it arrives.
+async fn parse_field(mut field: Field, rules: Rules) -> actix_web::Result<Parsed> {
+    let first = field.try_next().await?.unwrap_or_default();
+    let format = field
+        .content_disposition()
+        .get_filename()
+        .and_then(|name| Format::from_path(std::path::Path::new(name)))
+        .unwrap_or_else(|| Format::sniff(&first));
+    if !matches!(format, Format::Csv | Format::Json | Format::Jsonl) {
+        return Err(error::ErrorUnsupportedMediaType(
+            "Upload a CSV or JSON file",
+        ));
+    }
+    let (sender, chunks) = mpsc::channel(CHUNK_BUFFER);
+    let parser = web::block(move || {
+        parse(
+            ChunkReader {
+                chunks,
+                chunk: web::Bytes::new(),
+            },
+            format,
+            &rules,
+        )
+    });
+    let forward = async move {
+        sender.send(first).await.ok();
+        while let Some(chunk) = field.try_next().await? {
+            // The parser stops early on a malformed document.
+            if sender.send(chunk).await.is_err() {
+                break;
+            }
+        }
+        Ok::<_, actix_web::Error>(())
+    };
+    let (parsed, forwarded) = futures_util::join!(parser, forward);
+    forwarded?;
+    parsed?.map_err(error::ErrorBadRequest)
+}
+
+async fn read_text(mut field: Field) -> actix_web::Result<String> {
+    let mut text = Vec::new();
+    while let Some(chunk) = field.try_next().await? {
+        text.extend_from_slice(&chunk);
+        if text.len() > 1024 {
+            return Err(error::ErrorPayloadTooLarge("Form field is too long"));
+        }
+    }
+    String::from_utf8(text).map_err(error::ErrorBadRequest)
+}
+
+fn parse_conflict(value: &str) -> actix_web::Result<Conflict> {
+    <Conflict as clap::ValueEnum>::from_str(value, true).map_err(error::ErrorBadRequest)
+}
+
+/// The form of an upload, as documented in the OpenAPI document.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct UploadForm {
+    /// A CSV, JSON or JSON Lines file.
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+    /// What to do with accounts whose id is taken.
+    #[schema(example = "skip")]
+    on_conflict: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PreviewRow {
+    row: usize,
+    id: Option<String>,
+    fullname: Option<String>,
+    email: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RejectedRow {
+    row: usize,
+    reason: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Preview {
+    token: Uuid,
+    on_conflict: String,
+    valid: usize,
+    conflicts: usize,
+    rows: Vec<PreviewRow>,
+    /// Valid rows left out of `rows`.
+    more: usize,
+    rejected: Vec<RejectedRow>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Summary {
+    created: usize,
+    updated: usize,
+    skipped: usize,
+    rejected: Vec<RejectedRow>,
+    failed_at: Option<usize>,
+}
+
+fn render(
+    req: &HttpRequest,
+    tmpl: &tera::Tera,
+    cfg: &Cfg,
+    preview: Option<&Preview>,
+    summary: Option<&Summary>,
+) -> HttpResponse {
+    if api::negotiate(req) == Representation::Json {
+        return match (preview, summary) {
+            (Some(preview), _) => HttpResponse::Ok().json(preview),
+            (_, Some(summary)) => HttpResponse::Ok().json(summary),
+            (None, None) => api::error(StatusCode::NOT_ACCEPTABLE, "The upload form is HTML"),
+        };
+    }
+    let mut ctx = Context::new();
+    ctx.insert("version", &VERSION);
+    ctx.insert("backend", &cfg.backend());
+    ctx.insert("title", "Import Accounts");
+    ctx.insert("user", &auth::identity(req));
+    ctx.insert("base", &tenant::base(req));
+    ctx.insert("preview", &preview);
+    ctx.insert("summary", &summary);
+    let s = tmpl.render("import.html", &ctx).unwrap();
+    HttpResponse::Ok().body(s)
+}
+
+fn rejected_rows(rejected: &[Rejected]) -> Vec<RejectedRow> {
+    rejected
+        .iter()
+        .map(|r| RejectedRow {
+            row: r.row,
+            reason: r.reason.clone(),
+        })
+        .collect()
+}
+
+/// Shows the upload form.
+pub async fn import_form(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+) -> impl Responder {
+    render(&req, &tmpl, &cfg, None, None)
+}
+
+/// Parses and validates an uploaded CSV or JSON file and shows a preview.
+///
+/// Nothing is written yet: the valid rows are staged until they are
+/// committed with [`commit_import`].
+#[utoipa::path(
+    post,
+    path = "/account/import",
+    tag = "accounts",
+    summary = "Preview an upload of accounts",
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "The preview, as HTML or JSON when negotiated", content(
+            (Preview = "application/json"),
+            (String = "text/html"),
+        )),
+        (status = 400, description = "No file was uploaded or it could not be read", body = String),
+        (status = 403, description = "The caller may not read accounts", body = api::ErrorBody),
+        (status = 415, description = "The file is neither CSV nor JSON", body = String),
+    )
+)]
+pub async fn import_accounts(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    staging: web::Data<ImportStaging>,
+    caller: Caller,
+    mut payload: Multipart,
+) -> actix_web::Result<HttpResponse> {
+    let mut conflict = Conflict::default();
+    let mut parsed = None;
+    while let Some(field) = payload.try_next().await? {
+        match field.name() {
+            "file" => parsed = Some(parse_field(field, cfg.validation.clone()).await?),
+            "on_conflict" => conflict = parse_conflict(&read_text(field).await?)?,
+            _ => {
+                read_text(field).await?;
+            }
+        }
+    }
+    let parsed = parsed.ok_or_else(|| error::ErrorBadRequest("No file uploaded"))?;
+
+    let existing: HashSet<Uuid> = caller
+        .guard(
+            &mut *storage
+                .lock()
+                .map_err(|_| error::ErrorInternalServerError("Storage lock failed"))?,
+        )
+        .read_all()
+        .map_err(api::ApiError::from)?
+        .iter()
+        .map(|account| account.id)
+        .collect();
+    let conflicts = parsed
+        .valid
+        .iter()
+        .filter_map(|(_, record)| transfer::validate(record, &cfg.validation).ok()?.0)
+        .filter(|id| existing.contains(id))
+        .count();
+    let rows = parsed
+        .valid
+        .iter()
+        .take(PREVIEW_ROWS)
+        .map(|(row, record)| PreviewRow {
+            row: *row,
+            id: record.id.clone().filter(|id| !id.trim().is_empty()),
+            fullname: record.fullname.clone(),
+            email: record.email.clone(),
+        })
+        .collect();
+    let preview = Preview {
+        valid: parsed.valid.len(),
+        more: parsed.valid.len().saturating_sub(PREVIEW_ROWS),
+        conflicts,
+        rows,
+        rejected: rejected_rows(&parsed.rejected),
+        on_conflict: clap::ValueEnum::to_possible_value(&conflict)
+            .expect("Conflict has a name")
+            .get_name()
+            .to_string(),
+        token: staging.stage(parsed.valid),
+    };
+    Ok(render(&req, &tmpl, &cfg, Some(&preview), None))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CommitForm {
+    /// The token of the preview.
+    token: Uuid,
+    on_conflict: String,
+}
+
+/// Writes the rows of a previewed upload in one batch, holding the storage
+/// lock so no other write interleaves with it.
+#[utoipa::path(
+    post,
+    path = "/account/import/commit",
+    tag = "accounts",
+    summary = "Import a previewed upload",
+    request_body(content = CommitForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "What was imported, as HTML or JSON when negotiated", content(
+            (Summary = "application/json"),
+            (String = "text/html"),
+        )),
+        (status = 403, description = "The caller may not change accounts", body = api::ErrorBody),
+        (status = 410, description = "The preview expired", body = String),
+    )
+)]
+pub async fn commit_import(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    staging: web::Data<ImportStaging>,
+    caller: Caller,
+    form: web::Form<CommitForm>,
+) -> actix_web::Result<HttpResponse> {
+    let conflict = parse_conflict(&form.on_conflict)?;
+    let staged = staging
+        .take(&form.token)
+        .ok_or_else(|| error::ErrorGone("The upload expired, upload the file again"))?;
+    let rows = staged
+        .rows
+        .into_iter()
+        .map(|(row, record)| (row, Ok(record)));
+    // The import writes up to MAX_ROWS accounts, so it runs off the
+    // executor rather than holding the lock in the handler.
+    let import_cfg = cfg.clone();
+    let report = web::block(move || {
+        let mut storage = storage.lock().map_err(|_| {
+            api::ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Storage lock failed")
+        })?;
+        transfer::import(
+            &mut caller.guard(&mut *storage),
+            rows,
+            conflict,
+            &import_cfg.validation,
+            import_cfg.id_version,
+        )
+        .map_err(api::ApiError::from)
+    })
+    .await??;
+    let summary = Summary {
+        created: report.created,
+        updated: report.updated,
+        skipped: report.skipped,
+        rejected: rejected_rows(&report.rejected),
+        failed_at: report.failed_at,
+    };
+    Ok(render(&req, &tmpl, &cfg, None, Some(&summary)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{
+        http::header,
+        test::{call_service, init_service, read_body_json, TestRequest},
+        App,
+    };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{cfg::default_template_glob, crud::memory::MemoryStore};
+
+    fn reader(content: &str, chunk_size: usize) -> ChunkReader {
+        let (sender, chunks) = mpsc::channel(content.len() / chunk_size + 1);
+        for chunk in content.as_bytes().chunks(chunk_size) {
+            sender
+                .blocking_send(web::Bytes::copy_from_slice(chunk))
+                .expect("Failed to send chunk");
+        }
+        ChunkReader {
+            chunks,
+            chunk: web::Bytes::new(),
+        }
+    }
+
+    #[test]
+    fn upload_split_into_chunks_is_parsed() {
+        let csv = "id,fullname\n,Test Account\nnot-a-uuid,Other Account\n,\"Doe, Jane\"\n";
+        let parsed =
+            parse(reader(csv, 5), Format::Csv, &Rules::default()).expect("Failed to parse upload");
+        assert_eq!(
+            parsed
+                .valid
+                .iter()
+                .map(|(_, r)| r.fullname.clone().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["Test Account", "Doe, Jane"]
+        );
+        assert_eq!(parsed.rejected.len(), 1);
+        assert_eq!(parsed.rejected[0].row, 2);
+    }
+
+    #[test]
+    fn staged_upload_can_only_be_committed_once() {
+        let staging = ImportStaging::default();
+        let token = staging.stage(vec![(1, Record::default())]);
+        assert!(staging.take(&token).is_some());
+        assert!(staging.take(&token).is_none());
+    }
+
+    #[actix_web::test]
+    async fn upload_is_previewed_then_committed_once() {
+        let storage: Arc<Mutex<dyn Crud<Account>>> =
+            Arc::new(Mutex::new(MemoryStore::<Account>::new()));
+        let staging = web::Data::new(ImportStaging::default());
+        let tera = tera::Tera::new(&default_template_glob()).expect("Failed to load templates");
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(tera))
+                .app_data(web::Data::new(Cfg::default()))
+                .app_data(web::Data::from(storage.clone()))
+                .app_data(staging.clone())
+                .route("/account/import", web::post().to(import_accounts))
+                .route("/account/import/commit", web::post().to(commit_import)),
+        )
+        .await;
+
+        let boundary = "upload-boundary";
+        let body = format!(
+            "--{b}\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"accounts.csv\"\r\n\
+             Content-Type: text/csv\r\n\r\n\
+             id,fullname\n,Test Account\nnot-a-uuid,Other Account\n\r\n\
+             --{b}--\r\n",
+            b = boundary
+        );
+        let req = TestRequest::post()
+            .uri("/account/import?format=json")
+            .insert_header((
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", boundary),
+            ))
+            .set_payload(body)
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let preview: Value = read_body_json(response).await;
+        assert_eq!(preview["valid"], 1);
+        assert_eq!(preview["rejected"][0]["row"], 2);
+        assert!(storage.lock().unwrap().read_all().unwrap().is_empty());
+
+        let commit = |token: &str| {
+            TestRequest::post()
+                .uri("/account/import/commit?format=json")
+                .set_form([("token", token), ("on_conflict", "skip")])
+                .to_request()
+        };
+        let token = preview["token"].as_str().expect("No token").to_string();
+        let response = call_service(&app, commit(&token)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let summary: Value = read_body_json(response).await;
+        assert_eq!(summary["created"], 1);
+        let accounts = storage.lock().unwrap().read_all().unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].fullname, "Test Account");
+
+        let response = call_service(&app, commit(&token)).await;
+        assert_eq!(response.status(), StatusCode::GONE);
+
+        // The monotonic clock may not reach back a whole TTL on a fresh boot.
+        let Some(uploaded) = Instant::now().checked_sub(STAGING_TTL) else {
+            return;
+        };
+        let expired = staging.stage(vec![(1, Record::default())]);
+        staging
+            .uploads
+            .lock()
+            .unwrap()
+            .get_mut(&expired)
+            .expect("Upload not staged")
+            .uploaded = uploaded;
+        let response = call_service(&app, commit(&expired.to_string())).await;
+        assert_eq!(response.status(), StatusCode::GONE);
+    }
+}
@@ -1,13 +1,21 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use tera::Context;
 
-use super::{BACKEND_STRATEGY, VERSION};
+use super::VERSION;
+use crate::{auth, cfg::Cfg, tenant};
 
-pub async fn index(tmpl: web::Data<tera::Tera>) -> impl Responder {
+pub async fn index(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+) -> impl Responder {
     let mut ctx = Context::new();
     ctx.insert("version", &VERSION);
-    ctx.insert("backend", &BACKEND_STRATEGY);
+    ctx.insert("backend", &cfg.backend());
     ctx.insert("title", "Index Page");
+    ctx.insert("user", &auth::identity(&req));
+    ctx.insert("base", &tenant::base(&req));
+    ctx.insert("tenants", &cfg.tenants.keys().collect::<Vec<_>>());
     let s = tmpl.render("index.html", &ctx).unwrap();
     HttpResponse::Ok().body(s)
 }
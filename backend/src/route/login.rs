@@ -0,0 +1,292 @@
+//! Logging operators in and out of the HTML pages.
+//!
+//! A successful login starts a [`Session`](crate::auth::session::Session)
+//! and returns the browser to the page it was sent away from.
+
+use actix_web::{error, http::header, http::StatusCode, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use super::{
+    flash::Flash,
+    form::{self, Page},
+};
+use crate::{
+    auth::{session, Auth},
+    cfg::Cfg,
+};
+
+#[derive(Deserialize)]
+pub struct LoginQuery {
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    csrf_token: String,
+    username: String,
+    password: String,
+    #[serde(default)]
+    next: String,
+}
+
+#[derive(Deserialize)]
+pub struct LogoutForm {
+    csrf_token: String,
+}
+
+/// Where to go after logging in: `next` if it is a path on this server.
+/// Browsers drop tabs and line breaks from URLs, so `/\t/evil.example` is
+/// another host to them; paths with any whitespace or control character
+/// are refused.
+fn next_page(next: Option<&str>) -> &str {
+    match next {
+        Some(next)
+            if next.starts_with('/')
+                && !next.starts_with("//")
+                && !next.contains('\\')
+                && !next.chars().any(|c| c.is_whitespace() || c.is_control()) =>
+        {
+            next
+        }
+        _ => "/account",
+    }
+}
+
+/// Renders the login form, after a failed attempt when `failed` is set.
+fn render_login(
+    req: &HttpRequest,
+    tmpl: &tera::Tera,
+    cfg: &Cfg,
+    username: &str,
+    next: &str,
+    failed: bool,
+) -> HttpResponse {
+    let page = Page {
+        template: "login.html",
+        title: "Log in",
+        status: if failed {
+            StatusCode::UNAUTHORIZED
+        } else {
+            StatusCode::OK
+        },
+    };
+    form::render(req, tmpl, cfg, page, |ctx| {
+        ctx.insert("username", username);
+        ctx.insert("next", next);
+        if failed {
+            ctx.insert("flash", &Flash::error("Wrong username or password."));
+        }
+    })
+}
+
+/// Shows the login form.
+pub async fn login_form(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    query: web::Query<LoginQuery>,
+) -> HttpResponse {
+    render_login(
+        &req,
+        &tmpl,
+        &cfg,
+        "",
+        next_page(query.next.as_deref()),
+        false,
+    )
+}
+
+/// Starts a session if the username and password match.
+pub async fn login(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    auth: web::Data<Auth>,
+    form: web::Form<LoginForm>,
+) -> actix_web::Result<HttpResponse> {
+    form::check_csrf(&req, &form.csrf_token)?;
+    let form = form.into_inner();
+    let next = next_page(Some(&form.next)).to_string();
+    // Hashing the password takes a while, so it is kept off the workers.
+    let verifier = auth.clone();
+    let (username, password) = (form.username.clone(), form.password);
+    let user = web::block(move || verifier.verify_user(&username, &password))
+        .await?
+        .map_err(|err| {
+            log::error!("Failed to read users: {}", err);
+            error::ErrorInternalServerError("The users could not be read")
+        })?;
+    let Some(user) = user else {
+        log::warn!("Failed login as {:?}", form.username);
+        return Ok(render_login(&req, &tmpl, &cfg, &form.username, &next, true));
+    };
+    log::info!("{} logged in", user.username);
+    // A session id the browser already had is never reused.
+    if let Some(old) = req.cookie(session::COOKIE) {
+        auth.sessions.end(old.value());
+    }
+    Ok(HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, next))
+        .cookie(auth.sessions.start(user.id, &user.username))
+        .finish())
+}
+
+/// Asks to confirm logging out.
+pub async fn logout_form(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+) -> HttpResponse {
+    let page = Page {
+        template: "logout.html",
+        title: "Log out",
+        status: StatusCode::OK,
+    };
+    form::render(&req, &tmpl, &cfg, page, |_| {})
+}
+
+/// Ends the session and returns to the login page.
+pub async fn logout(
+    req: HttpRequest,
+    auth: web::Data<Auth>,
+    form: web::Form<LogoutForm>,
+) -> actix_web::Result<HttpResponse> {
+    form::check_csrf(&req, &form.csrf_token)?;
+    let mut response = HttpResponse::SeeOther();
+    response.insert_header((header::LOCATION, "/login"));
+    if let Some(cookie) = req.cookie(session::COOKIE) {
+        response.cookie(auth.sessions.end(cookie.value()));
+    }
+    Flash::success("You are logged out.").set(&mut response);
+    Ok(response.finish())
+}
+
+/// Mounts `/login` and `/logout`.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/login", web::get().to(login_form))
+        .route("/login", web::post().to(login))
+        .route("/logout", web::get().to(logout_form))
+        .route("/logout", web::post().to(logout));
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        middleware::from_fn,
+        test::{call_service, init_service, read_body, TestRequest},
+        App,
+    };
+
+    use super::*;
+    use crate::{
+        auth::{authenticate, user::User, Role},
+        cfg::{default_template_glob, AuthCfg},
+        crud::{memory::MemoryStore, Crud},
+        route::csrf,
+    };
+
+    #[test]
+    fn only_local_pages_are_returned_to() {
+        assert_eq!(next_page(Some("/account/new")), "/account/new");
+        assert_eq!(next_page(Some("//evil.example")), "/account");
+        assert_eq!(next_page(Some("/\\evil.example")), "/account");
+        assert_eq!(next_page(Some("/\t/evil.example")), "/account");
+        assert_eq!(next_page(Some("/\n/evil.example")), "/account");
+        assert_eq!(next_page(Some("/ /evil.example")), "/account");
+        assert_eq!(next_page(Some("https://evil.example")), "/account");
+        assert_eq!(next_page(None), "/account");
+    }
+
+    #[actix_web::test]
+    async fn operator_logs_in_and_out() {
+        let mut users: Box<dyn Crud<User>> = Box::new(MemoryStore::<User>::new());
+        users
+            .create(&User::new("admin", "correct horse", Role::Admin).unwrap())
+            .unwrap();
+        let cfg = AuthCfg {
+            enabled: true,
+            ..AuthCfg::default()
+        };
+        let tera = tera::Tera::new(&default_template_glob()).expect("Failed to load templates");
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(tera))
+                .app_data(web::Data::new(Cfg::default()))
+                .app_data(web::Data::new(Auth::new(&cfg, users)))
+                .wrap(from_fn(authenticate))
+                .configure(configure)
+                .route("/account", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let response = call_service(
+            &app,
+            TestRequest::get().uri("/login?next=/account").to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let csrf = response
+            .response()
+            .cookies()
+            .find(|cookie| cookie.name() == csrf::TOKEN)
+            .expect("No CSRF cookie")
+            .into_owned();
+        let token = csrf.value().to_string();
+
+        let login = |password: &'static str| {
+            TestRequest::post()
+                .uri("/login")
+                .cookie(csrf.clone())
+                .set_form([
+                    ("csrf_token", token.as_str()),
+                    ("username", "admin"),
+                    ("password", password),
+                    ("next", "/account"),
+                ])
+                .to_request()
+        };
+        let response = call_service(&app, login("battery staple")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains("Wrong username or password"));
+
+        let response = call_service(&app, login("correct horse")).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "/account"
+        );
+        let session = response
+            .response()
+            .cookies()
+            .find(|cookie| cookie.name() == session::COOKIE)
+            .expect("No session cookie")
+            .into_owned();
+        assert_eq!(session.http_only(), Some(true));
+        assert_eq!(session.secure(), Some(true));
+        let req = TestRequest::get()
+            .uri("/account")
+            .cookie(session.clone())
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = TestRequest::post()
+            .uri("/logout")
+            .cookie(csrf.clone())
+            .cookie(session.clone())
+            .set_form([("csrf_token", token.as_str())])
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::SEE_OTHER
+        );
+        let req = TestRequest::get()
+            .uri("/account")
+            .cookie(session)
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
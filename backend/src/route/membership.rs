@@ -0,0 +1,486 @@
+//! The members of groups: pages under `/group/<id>/members` and
+//! `/account/<id>/groups`, and the API under
+//! `/api/v1/groups/<id>/members` and `/api/v1/accounts/<id>/groups`.
+//!
+//! Reading memberships needs the right to read, changing them the right to
+//! update.
+
+use std::sync::{Mutex, MutexGuard};
+
+use actix_web::{error, http::StatusCode, web, HttpRequest, HttpResponse};
+use common::{Account, Group};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::{
+    api::{self, ApiError},
+    flash::Flash,
+    form::{check_csrf, redirect_to, render, with_store, Page},
+};
+use crate::{
+    auth::policy::Caller,
+    cfg::Cfg,
+    crud::{
+        self,
+        membership::{Membership, MembershipStore, Side},
+        Crud, CrudError, Operation,
+    },
+};
+
+#[derive(Deserialize)]
+pub struct JoinForm {
+    csrf_token: String,
+    account: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct LeaveForm {
+    csrf_token: String,
+}
+
+fn locked(
+    memberships: &Mutex<dyn MembershipStore>,
+) -> crud::Result<MutexGuard<'_, dyn MembershipStore + 'static>> {
+    memberships.lock().map_err(|_| {
+        log::error!("Membership store lock failed");
+        CrudError::UnknownError
+    })
+}
+
+fn locked_store<T>(
+    storage: &Mutex<dyn Crud<T>>,
+) -> Result<MutexGuard<'_, dyn Crud<T> + 'static>, ApiError> {
+    storage.lock().map_err(|_| {
+        log::error!("Storage lock failed");
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Storage lock failed")
+    })
+}
+
+/// The ids at the other side of the memberships of the item `id` at `side`.
+fn linked(
+    memberships: &Mutex<dyn MembershipStore>,
+    side: Side,
+    id: &Uuid,
+) -> crud::Result<Vec<Uuid>> {
+    let other = match side {
+        Side::Account => Side::Group,
+        Side::Group => Side::Account,
+    };
+    Ok(locked(memberships)?
+        .of(side, id)?
+        .into_iter()
+        .map(|membership| membership.id_of(other))
+        .collect())
+}
+
+/// Splits `items` into the ones whose id is in `ids` and the others.
+fn partition<T: crud::Identifiable>(items: Vec<T>, ids: &[Uuid]) -> (Vec<T>, Vec<T>) {
+    items.into_iter().partition(|item| ids.contains(&item.id()))
+}
+
+/// Turns a store error of a membership form into a redirect with a message,
+/// or an error page.
+fn store_error(req: &HttpRequest, group: &Uuid, err: CrudError) -> actix_web::Result<HttpResponse> {
+    let members = format!("/group/{}/members", group);
+    match err {
+        CrudError::NotFound => Ok(redirect_to(
+            req,
+            &members,
+            Flash::error("The group, the account or the membership no longer exists."),
+        )),
+        CrudError::AlreadyExists => Ok(redirect_to(
+            req,
+            &members,
+            Flash::error("The account is already a member."),
+        )),
+        CrudError::Forbidden(operation) => Err(error::ErrorForbidden(format!(
+            "You may not {} groups.",
+            operation
+        ))),
+        err => {
+            log::error!("Membership form failed: {}", err);
+            Err(error::ErrorInternalServerError(
+                "The membership could not be stored",
+            ))
+        }
+    }
+}
+
+/// Shows the members of a group, with forms to add and remove them.
+pub async fn members_page(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    accounts: web::Data<Mutex<dyn Crud<Account>>>,
+    groups: web::Data<Mutex<dyn Crud<Group>>>,
+    memberships: web::Data<Mutex<dyn MembershipStore>>,
+    id: web::Path<Uuid>,
+) -> actix_web::Result<HttpResponse> {
+    let read = with_store(&req, &groups, |store| store.read(&id)).and_then(|group| {
+        let ids = linked(&memberships, Side::Group, &id)?;
+        let accounts = with_store(&req, &accounts, |store| store.read_all())?;
+        Ok((group, partition(accounts, &ids)))
+    });
+    let (group, (members, others)) = match read {
+        Ok(read) => read,
+        Err(CrudError::NotFound) => return Err(error::ErrorNotFound("Group not found")),
+        Err(err) => return store_error(&req, &id, err),
+    };
+    let title = format!("Members of {}", group.name);
+    let page = Page {
+        template: "group_members.html",
+        title: &title,
+        status: StatusCode::OK,
+    };
+    Ok(render(&req, &tmpl, &cfg, page, |ctx| {
+        ctx.insert("group", &group);
+        ctx.insert("members", &members);
+        ctx.insert("others", &others);
+    }))
+}
+
+/// Adds the account picked in the form to a group.
+pub async fn join(
+    req: HttpRequest,
+    accounts: web::Data<Mutex<dyn Crud<Account>>>,
+    groups: web::Data<Mutex<dyn Crud<Group>>>,
+    memberships: web::Data<Mutex<dyn MembershipStore>>,
+    id: web::Path<Uuid>,
+    form: web::Form<JoinForm>,
+) -> actix_web::Result<HttpResponse> {
+    check_csrf(&req, &form.csrf_token)?;
+    // Both stores stay locked until the membership is added, so neither
+    // item can be deleted in between. They are locked before the
+    // memberships, as in their deletes.
+    let joined = with_store(&req, &accounts, |accounts| {
+        with_store(&req, &groups, |groups| {
+            let group = groups.read(&id)?;
+            let account = accounts.read(&form.account)?;
+            Caller::of(&req).check(Operation::Update, Some(group.id))?;
+            locked(&memberships)?.add(&Membership::new(group.id, account.id))?;
+            Ok(account)
+        })
+    });
+    match joined {
+        Ok(account) => Ok(redirect_to(
+            &req,
+            &format!("/group/{}/members", id),
+            Flash::success(format!("{} was added.", account.fullname)),
+        )),
+        Err(err) => store_error(&req, &id, err),
+    }
+}
+
+/// Removes an account from a group.
+pub async fn leave(
+    req: HttpRequest,
+    memberships: web::Data<Mutex<dyn MembershipStore>>,
+    path: web::Path<(Uuid, Uuid)>,
+    form: web::Form<LeaveForm>,
+) -> actix_web::Result<HttpResponse> {
+    check_csrf(&req, &form.csrf_token)?;
+    let (group, account) = path.into_inner();
+    let left = Caller::of(&req)
+        .check(Operation::Update, Some(group))
+        .and_then(|()| locked(&memberships)?.remove(&Membership::new(group, account)));
+    match left {
+        Ok(()) => Ok(redirect_to(
+            &req,
+            &format!("/group/{}/members", group),
+            Flash::success("The member was removed."),
+        )),
+        Err(err) => store_error(&req, &group, err),
+    }
+}
+
+/// Shows the groups of an account.
+pub async fn groups_page(
+    req: HttpRequest,
+    tmpl: web::Data<tera::Tera>,
+    cfg: web::Data<Cfg>,
+    accounts: web::Data<Mutex<dyn Crud<Account>>>,
+    groups: web::Data<Mutex<dyn Crud<Group>>>,
+    memberships: web::Data<Mutex<dyn MembershipStore>>,
+    id: web::Path<Uuid>,
+) -> actix_web::Result<HttpResponse> {
+    let read = with_store(&req, &accounts, |store| store.read(&id)).and_then(|account| {
+        let ids = linked(&memberships, Side::Account, &id)?;
+        let groups = with_store(&req, &groups, |store| store.read_all())?;
+        Ok((account, partition(groups, &ids).0))
+    });
+    let (account, groups) = match read {
+        Ok(read) => read,
+        Err(CrudError::NotFound) => return Err(error::ErrorNotFound("Account not found")),
+        Err(CrudError::Forbidden(operation)) => {
+            return Err(error::ErrorForbidden(format!(
+                "You may not {} accounts.",
+                operation
+            )))
+        }
+        Err(err) => {
+            log::error!("Reading the groups of {} failed: {}", id, err);
+            return Err(error::ErrorInternalServerError("Storage failed"));
+        }
+    };
+    let title = format!("Groups of {}", account.fullname);
+    let page = Page {
+        template: "account_groups.html",
+        title: &title,
+        status: StatusCode::OK,
+    };
+    Ok(render(&req, &tmpl, &cfg, page, |ctx| {
+        ctx.insert("account", &account);
+        ctx.insert("groups", &groups);
+    }))
+}
+
+/// Mounts the membership pages.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/group/{id}/members", web::get().to(members_page))
+        .route("/group/{id}/members", web::post().to(join))
+        .route(
+            "/group/{id}/members/{account}/remove",
+            web::post().to(leave),
+        )
+        .route("/account/{id}/groups", web::get().to(groups_page));
+}
+
+/// Lists the members of a group.
+pub async fn list_members(
+    accounts: web::Data<Mutex<dyn Crud<Account>>>,
+    groups: web::Data<Mutex<dyn Crud<Group>>>,
+    memberships: web::Data<Mutex<dyn MembershipStore>>,
+    caller: Caller,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    api::with_store(&groups, &caller, |store| store.read(&id))?;
+    let ids = linked(&memberships, Side::Group, &id).map_err(ApiError::from_store::<Group>)?;
+    let accounts = api::with_store(&accounts, &caller, |store| store.read_all())?;
+    Ok(HttpResponse::Ok().json(partition(accounts, &ids).0))
+}
+
+/// Adds an account to a group. Adding a member again changes nothing.
+pub async fn add_member(
+    accounts: web::Data<Mutex<dyn Crud<Account>>>,
+    groups: web::Data<Mutex<dyn Crud<Group>>>,
+    memberships: web::Data<Mutex<dyn MembershipStore>>,
+    caller: Caller,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+    let (group, account) = path.into_inner();
+    // Locked until the membership is added, like in `join`.
+    let mut accounts = locked_store(&accounts)?;
+    let mut groups = locked_store(&groups)?;
+    caller
+        .guard(&mut *groups)
+        .read(&group)
+        .map_err(ApiError::from_store::<Group>)?;
+    caller
+        .guard(&mut *accounts)
+        .read(&account)
+        .map_err(ApiError::from_store::<Account>)?;
+    let added = caller
+        .check(Operation::Update, Some(group))
+        .and_then(|()| locked(&memberships)?.add(&Membership::new(group, account)));
+    match added {
+        Ok(()) | Err(CrudError::AlreadyExists) => Ok(HttpResponse::NoContent().finish()),
+        Err(err) => Err(ApiError::from_store::<Group>(err)),
+    }
+}
+
+/// Removes an account from a group.
+pub async fn remove_member(
+    memberships: web::Data<Mutex<dyn MembershipStore>>,
+    caller: Caller,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+    let (group, account) = path.into_inner();
+    let removed = caller
+        .check(Operation::Update, Some(group))
+        .and_then(|()| locked(&memberships)?.remove(&Membership::new(group, account)));
+    match removed {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(CrudError::NotFound) => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "The account is not a member of the group",
+        )),
+        Err(err) => Err(ApiError::from_store::<Group>(err)),
+    }
+}
+
+/// Lists the groups of an account.
+pub async fn list_groups(
+    accounts: web::Data<Mutex<dyn Crud<Account>>>,
+    groups: web::Data<Mutex<dyn Crud<Group>>>,
+    memberships: web::Data<Mutex<dyn MembershipStore>>,
+    caller: Caller,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    api::with_store(&accounts, &caller, |store| store.read(&id))?;
+    let ids = linked(&memberships, Side::Account, &id).map_err(ApiError::from_store::<Account>)?;
+    let groups = api::with_store(&groups, &caller, |store| store.read_all())?;
+    Ok(HttpResponse::Ok().json(partition(groups, &ids).0))
+}
+
+/// Mounts the membership API in the API scope.
+pub fn configure_api(cfg: &mut web::ServiceConfig) {
+    let not_found = |message: &'static str| {
+        web::PathConfig::default()
+            .error_handler(move |_, _| ApiError::new(StatusCode::NOT_FOUND, message).into())
+    };
+    cfg.service(
+        web::resource("/groups/{id}/members")
+            .app_data(not_found("Group not found"))
+            .route(web::get().to(list_members)),
+    )
+    .service(
+        web::resource("/groups/{id}/members/{account}")
+            .app_data(not_found("Group or account not found"))
+            .route(web::put().to(add_member))
+            .route(web::delete().to(remove_member)),
+    )
+    .service(
+        web::resource("/accounts/{id}/groups")
+            .app_data(not_found("Account not found"))
+            .route(web::get().to(list_groups)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{
+        test::{call_and_read_body_json, call_service, init_service, read_body, TestRequest},
+        App,
+    };
+
+    use super::*;
+    use crate::{
+        cfg::default_template_glob,
+        crud::{
+            membership::{with_rules, MembershipRules},
+            memory::MemoryStore,
+        },
+        route::csrf,
+    };
+
+    #[actix_web::test]
+    async fn groups_get_members_over_the_api_and_the_pages() {
+        let tera = tera::Tera::new(&default_template_glob()).expect("Failed to load templates");
+        let memberships: Arc<Mutex<dyn MembershipStore>> =
+            Arc::new(Mutex::new(MemoryStore::<Membership>::new()));
+        let rules = MembershipRules::default();
+        let accounts: Arc<Mutex<dyn Crud<Account>>> = Arc::new(Mutex::new(
+            with_rules(
+                Box::new(MemoryStore::<Account>::new()),
+                Side::Account,
+                rules,
+                memberships.clone(),
+            )
+            .unwrap(),
+        ));
+        let groups: Arc<Mutex<dyn Crud<Group>>> = Arc::new(Mutex::new(
+            with_rules(
+                Box::new(MemoryStore::<Group>::new()),
+                Side::Group,
+                rules,
+                memberships.clone(),
+            )
+            .unwrap(),
+        ));
+        let (erich, anna) = (Account::new("Erich"), Account::new("Anna"));
+        accounts.lock().unwrap().create(&erich).unwrap();
+        accounts.lock().unwrap().create(&anna).unwrap();
+        let sales = Group::new("Sales");
+        groups.lock().unwrap().create(&sales).unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(tera))
+                .app_data(web::Data::new(Cfg::default()))
+                .app_data(web::Data::from(accounts.clone()))
+                .app_data(web::Data::from(groups.clone()))
+                .app_data(web::Data::from(memberships.clone()))
+                .configure(configure)
+                .service(web::scope("/api/v1").configure(configure_api)),
+        )
+        .await;
+
+        let member = format!("/api/v1/groups/{}/members/{}", sales.id, erich.id);
+        for _ in 0..2 {
+            let response = call_service(&app, TestRequest::put().uri(&member).to_request()).await;
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        }
+        let req = TestRequest::put()
+            .uri(&format!(
+                "/api/v1/groups/{}/members/{}",
+                sales.id,
+                Uuid::new_v4()
+            ))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let req = TestRequest::get()
+            .uri(&format!("/api/v1/groups/{}/members", sales.id))
+            .to_request();
+        let members: Vec<Account> = call_and_read_body_json(&app, req).await;
+        assert_eq!(members, vec![erich.clone()]);
+        let req = TestRequest::get()
+            .uri(&format!("/api/v1/accounts/{}/groups", erich.id))
+            .to_request();
+        let joined: Vec<Group> = call_and_read_body_json(&app, req).await;
+        assert_eq!(joined, vec![sales.clone()]);
+
+        assert!(matches!(
+            groups.lock().unwrap().delete(&sales),
+            Err(CrudError::InUse)
+        ));
+
+        let req = TestRequest::get()
+            .uri(&format!("/group/{}/members", sales.id))
+            .to_request();
+        let response = call_service(&app, req).await;
+        let csrf = response
+            .response()
+            .cookies()
+            .find(|cookie| cookie.name() == csrf::TOKEN)
+            .expect("No CSRF cookie")
+            .into_owned();
+        let token = csrf.value().to_string();
+        let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains(&format!("/account/{}\">Erich</a>", erich.id)));
+        assert!(body.contains(&format!("<option value=\"{}\">Anna</option>", anna.id)));
+
+        let anna_id = anna.id.to_string();
+        let req = TestRequest::post()
+            .uri(&format!("/group/{}/members", sales.id))
+            .cookie(csrf.clone())
+            .set_form([
+                ("csrf_token", token.as_str()),
+                ("account", anna_id.as_str()),
+            ])
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::SEE_OTHER
+        );
+        let req = TestRequest::post()
+            .uri(&format!("/group/{}/members/{}/remove", sales.id, erich.id))
+            .cookie(csrf)
+            .set_form([("csrf_token", token.as_str())])
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::SEE_OTHER
+        );
+        assert_eq!(
+            memberships.lock().unwrap().read_all().unwrap(),
+            vec![Membership::new(sales.id, anna.id)]
+        );
+
+        accounts.lock().unwrap().delete(&anna).unwrap();
+        assert!(memberships.lock().unwrap().read_all().unwrap().is_empty());
+    }
+}
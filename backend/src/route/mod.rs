@@ -1,10 +1,17 @@
 use std::env;
 
-pub mod index;
 pub mod account;
+pub mod api;
+pub mod csrf;
+pub mod entity;
+pub mod export;
+pub mod flash;
+pub mod form;
+pub mod import;
+pub mod index;
+pub mod login;
+pub mod membership;
+pub mod openapi;
+pub mod ws;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
-#[cfg(feature = "csv")]
-pub const BACKEND_STRATEGY: &str = "csv";
-#[cfg(feature = "sqlite")]
-pub const BACKEND_STRATEGY: &str = "sqlite";
@@ -0,0 +1,116 @@
+use actix_web::HttpResponse;
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityRequirement, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use super::{account, api, export, import};
+
+/// The OpenAPI document of the account endpoints, generated from the
+/// annotations of their handlers.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Accounts",
+        description = "Manage accounts stored as CSV, SQLite or in memory.",
+        license(name = "MIT", identifier = "MIT")
+    ),
+    paths(
+        api::list_accounts,
+        api::create_account,
+        api::get_account,
+        api::update_account,
+        api::delete_account,
+        api::get_rules,
+        account::list_accounts,
+        account::create_account,
+        account::show_account,
+        account::account_events,
+        import::import_accounts,
+        import::commit_import,
+        export::export_accounts,
+    ),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "api", description = "The versioned JSON API"),
+        (name = "accounts", description = "The account pages, most of which also answer with JSON"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Describes the bearer tokens that the endpoints need when authentication
+/// is enabled.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some(
+                        "A token made with `backend token create`, if `auth.enabled` is set",
+                    ))
+                    .build(),
+            ),
+        );
+        openapi.security = Some(vec![
+            SecurityRequirement::default(),
+            SecurityRequirement::new("bearer", Vec::<String>::new()),
+        ]);
+    }
+}
+
+/// Serves the OpenAPI document.
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn document_describes_account_endpoints_and_schemas() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).expect("Failed to serialize document");
+        assert_eq!(doc["openapi"], "3.1.0");
+        for path in [
+            "/api/v1/accounts",
+            "/api/v1/accounts/{id}",
+            "/account",
+            "/account/create",
+            "/account/{id}",
+            "/account/events",
+            "/account/import",
+            "/account/import/commit",
+            "/account/export",
+        ] {
+            assert!(
+                doc["paths"].get(path).is_some(),
+                "{} is not documented",
+                path
+            );
+        }
+        let account = &doc["components"]["schemas"]["Account"];
+        assert_eq!(account["required"], serde_json::json!(["id", "fullname"]));
+        assert_eq!(account["properties"]["id"]["format"], "uuid");
+        assert!(doc["components"]["schemas"].get("ErrorBody").is_some());
+        assert_eq!(
+            doc["components"]["securitySchemes"]["bearer"]["scheme"],
+            "bearer"
+        );
+        let mut operation_ids: Vec<_> = doc["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .flat_map(|path| path.as_object().unwrap().values())
+            .map(|operation| operation["operationId"].as_str().unwrap())
+            .collect();
+        let count = operation_ids.len();
+        operation_ids.sort();
+        operation_ids.dedup();
+        assert_eq!(operation_ids.len(), count, "operation ids are not unique");
+    }
+}
@@ -0,0 +1,392 @@
+use std::{sync::Mutex, time::Duration};
+
+use actix_web::{rt, web, Error, HttpRequest, HttpResponse};
+use actix_ws::{CloseCode, CloseReason, Message, MessageStream, Session};
+use common::{Account, NewAccount};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use crate::{
+    auth::policy::Caller,
+    cfg::Cfg,
+    crud::{observer::Change, Crud, CrudError},
+    event::EventBus,
+};
+
+/// How long a single outgoing message may wait on a slow client before the
+/// connection is closed.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A JSON command sent by a client, e.g. `{"op":"delete","id":"..."}`.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Command {
+    /// Creates an account under an id assigned by the server.
+    Create {
+        account: NewAccount,
+    },
+    Update {
+        account: Account,
+    },
+    Delete {
+        id: Uuid,
+    },
+    /// Resubscribes to changes, replaying those after the one whose `id`
+    /// is `after` if given.
+    Subscribe {
+        after: Option<String>,
+    },
+    Unsubscribe,
+}
+
+/// A JSON message sent to a client.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Reply {
+    Ok {
+        op: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        account: Option<Account>,
+    },
+    Error {
+        message: String,
+    },
+    Change {
+        /// The event id to resubscribe after, like that of the SSE stream.
+        id: String,
+        #[serde(flatten)]
+        change: Change<Account>,
+    },
+    /// Some changes were dropped because the client fell too far behind,
+    /// or were made before the server restarted.
+    Reset {
+        missed: u64,
+    },
+}
+
+impl Reply {
+    fn error(message: impl ToString) -> Self {
+        Reply::Error {
+            message: message.to_string(),
+        }
+    }
+
+    fn change(events: &EventBus<Account>, change: Change<Account>) -> Self {
+        Reply::Change {
+            id: events.event_id(change.seq),
+            change,
+        }
+    }
+}
+
+/// Runs a store command on behalf of a client.
+fn execute(
+    command: Command,
+    storage: &Mutex<dyn Crud<Account>>,
+    caller: &Caller,
+    cfg: &Cfg,
+) -> Reply {
+    let command = match command {
+        Command::Create { account } => match cfg.validation.new_account(&account) {
+            Ok(account) => Command::Create { account },
+            Err(errors) => return Reply::error(format!("Invalid account: {}", errors)),
+        },
+        Command::Update { account } => match cfg.validation.account(&account) {
+            Ok(account) => Command::Update { account },
+            Err(errors) => return Reply::error(format!("Invalid account: {}", errors)),
+        },
+        command => command,
+    };
+    let mut storage = match storage.lock() {
+        Ok(storage) => storage,
+        Err(_) => {
+            log::error!("Storage lock failed");
+            return Reply::error("Storage unavailable");
+        }
+    };
+    let mut storage = caller.guard(&mut *storage);
+    let (op, result) = match command {
+        Command::Create { account } => {
+            let account = account.into_account(cfg.id_version.generate());
+            ("create", storage.create(&account).map(|_| account))
+        }
+        Command::Update { account } => match storage.update(&account) {
+            Ok(()) => ("update", Ok(account)),
+            Err(CrudError::NotFound) => {
+                return Reply::error(format!("Account '{}' not found", account.id))
+            }
+            Err(e) => ("update", Err(e)),
+        },
+        Command::Delete { id } => match storage.read(&id) {
+            Ok(account) => ("delete", storage.delete(&account).map(|_| account)),
+            Err(CrudError::NotFound) => return Reply::error(format!("Account '{}' not found", id)),
+            Err(e) => ("delete", Err(e)),
+        },
+        Command::Subscribe { .. } | Command::Unsubscribe => {
+            unreachable!("subscriptions are handled by the session")
+        }
+    };
+    match result {
+        Ok(account) => Reply::Ok {
+            op,
+            account: Some(account),
+        },
+        Err(e @ CrudError::Forbidden(_)) => Reply::error(e),
+        Err(e) => {
+            log::error!("WebSocket {} failed: {}", op, e);
+            Reply::error(e)
+        }
+    }
+}
+
+/// The replies that catch a client up on the changes after the event id
+/// `after`, and the receiver of the changes that follow them. An id from
+/// before a restart replays every change after a reset.
+fn resume(
+    events: &EventBus<Account>,
+    after: &str,
+) -> (Vec<Reply>, broadcast::Receiver<Change<Account>>) {
+    let seq = events.seq_of(after);
+    let resume = events.resume(seq.unwrap_or_default());
+    let mut replies = Vec::new();
+    if seq.is_none() || !resume.complete {
+        let after = resume.after;
+        let oldest = resume.missed.first().map_or(after, |c| c.seq);
+        let missed = oldest.saturating_sub(after.saturating_add(1));
+        replies.push(Reply::Reset { missed });
+    }
+    replies.extend(
+        resume
+            .missed
+            .into_iter()
+            .map(|change| Reply::change(events, change)),
+    );
+    (replies, resume.receiver)
+}
+
+async fn next_change(
+    subscription: &mut Option<broadcast::Receiver<Change<Account>>>,
+) -> Result<Change<Account>, RecvError> {
+    match subscription {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Sends `reply`, giving up if the client does not accept it in time.
+async fn send(session: &mut Session, reply: &Reply) -> bool {
+    let text = serde_json::to_string(reply).expect("Failed to serialize Reply");
+    matches!(
+        tokio::time::timeout(SEND_TIMEOUT, session.text(text)).await,
+        Ok(Ok(()))
+    )
+}
+
+async fn run_session(
+    mut session: Session,
+    mut messages: MessageStream,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    events: web::Data<EventBus<Account>>,
+    caller: Caller,
+) {
+    let mut subscription = Some(events.subscribe());
+    loop {
+        let replies = tokio::select! {
+            message = messages.next() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<Command>(&text) {
+                    Ok(Command::Subscribe { after }) => {
+                        let mut replies = vec![Reply::Ok { op: "subscribe", account: None }];
+                        match after {
+                            Some(after) => {
+                                let (missed, receiver) = resume(&events, &after);
+                                replies.extend(missed);
+                                subscription = Some(receiver);
+                            }
+                            None => subscription = Some(events.subscribe()),
+                        }
+                        replies
+                    }
+                    Ok(Command::Unsubscribe) => {
+                        subscription = None;
+                        vec![Reply::Ok { op: "unsubscribe", account: None }]
+                    }
+                    Ok(command) => vec![execute(command, &storage, &caller, &cfg)],
+                    Err(e) => vec![Reply::error(format!("Invalid command: {}", e))],
+                },
+                Some(Ok(Message::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                Some(Ok(Message::Close(reason))) => {
+                    let _ = session.close(reason).await;
+                    return;
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(_)) | None => return,
+            },
+            change = next_change(&mut subscription) => match change {
+                Ok(change) => vec![Reply::change(&events, change)],
+                Err(RecvError::Lagged(missed)) => vec![Reply::Reset { missed }],
+                Err(RecvError::Closed) => {
+                    subscription = None;
+                    continue;
+                }
+            },
+        };
+        for reply in &replies {
+            if !send(&mut session, reply).await {
+                log::warn!("Closing WebSocket session that stopped reading");
+                let _ = session
+                    .close(Some(CloseReason {
+                        code: CloseCode::Policy,
+                        description: Some("Client too slow".to_string()),
+                    }))
+                    .await;
+                return;
+            }
+        }
+    }
+}
+
+/// Upgrades the connection to a WebSocket that accepts JSON [`Command`]s
+/// against the shared store and pushes every account change to the client.
+///
+/// Change notifications are buffered per client up to the event bus
+/// capacity; a client that falls further behind is sent a `reset` message
+/// and should reload. A client that stops reading altogether is
+/// disconnected after [`SEND_TIMEOUT`]. Commands run in the role the client
+/// connected with.
+pub async fn ws(
+    req: HttpRequest,
+    body: web::Payload,
+    cfg: web::Data<Cfg>,
+    storage: web::Data<Mutex<dyn Crud<Account>>>,
+    events: web::Data<EventBus<Account>>,
+    caller: Caller,
+) -> Result<HttpResponse, Error> {
+    let (response, session, messages) = actix_ws::handle(&req, body)?;
+    rt::spawn(run_session(session, messages, cfg, storage, events, caller));
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{policy::Policy, Role},
+        crud::observer::{ChangeKind, Listener},
+    };
+
+    #[test]
+    fn parses_commands() {
+        let id = uuid::uuid!("67e55044-10b1-426f-9247-bb680e5fe0c8");
+        assert_eq!(
+            serde_json::from_str::<Command>(&format!(r#"{{"op":"delete","id":"{}"}}"#, id))
+                .unwrap(),
+            Command::Delete { id }
+        );
+        assert_eq!(
+            serde_json::from_str::<Command>(r#"{"op":"subscribe","after":"1-3"}"#).unwrap(),
+            Command::Subscribe {
+                after: Some("1-3".to_string())
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<Command>(r#"{"op":"subscribe"}"#).unwrap(),
+            Command::Subscribe { after: None }
+        );
+        assert!(serde_json::from_str::<Command>(r#"{"op":"drop"}"#).is_err());
+    }
+
+    #[test]
+    fn create_assigns_id() {
+        let storage: std::sync::Arc<Mutex<dyn Crud<Account>>> = std::sync::Arc::new(Mutex::new(
+            crate::crud::memory::MemoryStore::<Account>::new(),
+        ));
+        let command =
+            serde_json::from_str(r#"{"op":"create","account":{"fullname":" Erich Schroeter "}}"#)
+                .unwrap();
+        let caller = Caller::new("test", Role::Admin, Policy::default(), None);
+        let account = match execute(command, &storage, &caller, &Cfg::default()) {
+            Reply::Ok {
+                op: "create",
+                account: Some(account),
+            } => account,
+            reply => panic!("Unexpected reply {:?}", reply),
+        };
+        assert_eq!(account.fullname, "Erich Schroeter");
+        assert_eq!(storage.lock().unwrap().read(&account.id).unwrap(), account);
+
+        let account = Account::new("Jane Doe");
+        let command = Command::Update {
+            account: account.clone(),
+        };
+        match execute(command, &storage, &caller, &Cfg::default()) {
+            Reply::Error { message } => {
+                assert_eq!(message, format!("Account '{}' not found", account.id))
+            }
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn serializes_replies_with_type_tag() {
+        let reply = Reply::Ok {
+            op: "unsubscribe",
+            account: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            r#"{"type":"ok","op":"unsubscribe"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Reply::Reset { missed: 2 }).unwrap(),
+            r#"{"type":"reset","missed":2}"#
+        );
+    }
+
+    #[test]
+    fn resume_resets_after_a_restart() {
+        let events = EventBus::default();
+        for seq in 1..=2 {
+            events
+                .on_change(&Change {
+                    seq,
+                    kind: ChangeKind::Created,
+                    at: chrono::Utc::now(),
+                    item: Account::new("Test Account"),
+                })
+                .expect("Failed to publish change");
+        }
+        let kinds = |replies: &[Reply]| -> Vec<String> {
+            replies
+                .iter()
+                .map(|reply| {
+                    serde_json::to_value(reply).unwrap()["type"]
+                        .as_str()
+                        .unwrap()
+                        .to_string()
+                })
+                .collect()
+        };
+
+        let (replies, _) = resume(&events, &events.event_id(1));
+        assert_eq!(kinds(&replies), vec!["change"]);
+        match &replies[0] {
+            Reply::Change { id, change } => {
+                assert_eq!(*id, events.event_id(2));
+                assert_eq!(change.seq, 2);
+            }
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+
+        // The same sequence number of an earlier run.
+        let (replies, _) = resume(&events, "1-1");
+        assert_eq!(kinds(&replies), vec!["reset", "change", "change"]);
+    }
+}
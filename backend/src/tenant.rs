@@ -0,0 +1,217 @@
+//! Tenants: namespaces of accounts served by one process, each with a store
+//! of its own.
+//!
+//! The tenants configured under `tenants` are served under `/t/<name>/`,
+//! with the same routes the default tenant has at the root. Clients that
+//! cannot change their paths may name their tenant with the `X-Tenant`
+//! header instead, which is [`route`]d as if the path had the prefix. The
+//! routes of a tenant are only given its own store and settings, so no
+//! request can read the accounts of another tenant.
+
+use std::collections::HashSet;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{StatusCode, Uri},
+    middleware::Next,
+    web, HttpRequest,
+};
+
+use crate::{cfg::Cfg, crud::Strategy, route::api};
+
+/// The header that names the tenant of a request.
+pub const HEADER: &str = "X-Tenant";
+const PREFIX: &str = "/t/";
+
+/// The path the routes of tenant `name` are under.
+pub fn prefix(name: &str) -> String {
+    format!("{}{}", PREFIX, name)
+}
+
+/// The path the routes of the tenant serving `req` are under, which is
+/// empty for the default tenant.
+pub fn base(req: &HttpRequest) -> String {
+    req.app_data::<web::Data<Cfg>>()
+        .and_then(|cfg| cfg.tenant.as_deref().map(prefix))
+        .unwrap_or_default()
+}
+
+/// Splits `/t/<name>/rest` into the tenant name and `/rest`.
+pub fn split(path: &str) -> (Option<&str>, &str) {
+    match path.strip_prefix(PREFIX) {
+        Some(rest) => match rest.find('/') {
+            Some(slash) => (Some(&rest[..slash]), &rest[slash..]),
+            None => (Some(rest), "/"),
+        },
+        None => (None, path),
+    }
+}
+
+/// Tenant names are kept to lowercase letters, digits, `-` and `_`, as they
+/// are used in paths and file names.
+pub fn check_name(name: &str) -> Result<(), String> {
+    let allowed = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_';
+    if name.is_empty() || name.len() > 64 || !name.chars().all(allowed) {
+        Err(format!(
+            "The tenant name {:?} must have 1 to 64 lowercase letters, digits, - or _",
+            name
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that the tenants of `cfg` have valid names and that no two of
+/// them, or a tenant and the default tenant, share a store.
+pub fn check(cfg: &Cfg) -> Result<(), String> {
+    let mut stores = HashSet::new();
+    let mut check_stores = |name: &str, cfg: &Cfg| {
+        let secondaries = cfg.mirror.secondaries.iter().cloned();
+        for store in std::iter::once(cfg.store()).chain(secondaries) {
+            // In-memory stores are never shared.
+            if store.storage == Strategy::Memory {
+                continue;
+            }
+            if !stores.insert(store.to_string()) {
+                return Err(format!("The tenant {} shares the store {}", name, store));
+            }
+        }
+        Ok(())
+    };
+    check_stores("default", cfg)?;
+    for name in cfg.tenants.keys() {
+        check_name(name)?;
+        check_stores(name, &cfg.tenant(name).expect("Tenant is configured"))?;
+    }
+    Ok(())
+}
+
+/// Routes a request that names its tenant with the [`HEADER`] to the routes
+/// of the tenant. Requests for tenants that are not configured are not
+/// found, and requests that name two tenants are refused.
+pub async fn route(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(cfg) = req.app_data::<web::Data<Cfg>>().cloned() else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let named = match req.headers().get(HEADER).map(|value| value.to_str()) {
+        Some(Ok(name)) => Some(name.trim().to_string()),
+        Some(Err(_)) => {
+            let refused = api::error(StatusCode::BAD_REQUEST, "Invalid tenant header");
+            return Ok(req.into_response(refused).map_into_right_body());
+        }
+        None => None,
+    };
+    let (prefixed, _) = split(req.path());
+    let refused = match (prefixed, named.as_deref()) {
+        (Some(prefixed), Some(named)) if prefixed != named => Some(api::error(
+            StatusCode::BAD_REQUEST,
+            "The path and the tenant header name different tenants",
+        )),
+        (Some(name), _) | (None, Some(name)) if !cfg.tenants.contains_key(name) => {
+            Some(api::error(
+                StatusCode::NOT_FOUND,
+                &format!("No tenant is named {:?}", name),
+            ))
+        }
+        _ => None,
+    };
+    if let Some(refused) = refused {
+        return Ok(req.into_response(refused).map_into_right_body());
+    }
+    if let (None, Some(named)) = (prefixed, named) {
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+        let uri: Uri = format!("{}{}", prefix(&named), path_and_query)
+            .parse()
+            .map_err(actix_web::error::ErrorBadRequest)?;
+        req.match_info_mut().get_mut().update(&uri);
+        req.head_mut().uri = uri;
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        middleware::from_fn,
+        test::{call_and_read_body, init_service, TestRequest},
+        App, HttpResponse,
+    };
+
+    use super::*;
+    use crate::cfg::TenantCfg;
+
+    #[test]
+    fn tenants_are_split_from_the_path() {
+        assert_eq!(split("/t/sales/account/1"), (Some("sales"), "/account/1"));
+        assert_eq!(split("/t/sales"), (Some("sales"), "/"));
+        assert_eq!(split("/account"), (None, "/account"));
+        assert!(check_name("sales-2").is_ok());
+        assert!(check_name("Sales").is_err());
+        assert!(check_name("../etc").is_err());
+    }
+
+    #[test]
+    fn tenants_may_not_share_a_store() {
+        let mut cfg = Cfg {
+            storage: Strategy::Csv,
+            ..Cfg::default()
+        };
+        cfg.tenants
+            .insert("sales".to_string(), TenantCfg::default());
+        assert_eq!(check(&cfg), Ok(()));
+        let shared = TenantCfg {
+            storage_path: cfg.tenant("sales").unwrap().storage_path,
+            ..TenantCfg::default()
+        };
+        cfg.tenants.insert("hr".to_string(), shared);
+        assert!(check(&cfg).is_err());
+    }
+
+    #[actix_web::test]
+    async fn header_routes_to_the_tenant() {
+        let mut cfg = Cfg::default();
+        cfg.tenants
+            .insert("sales".to_string(), TenantCfg::default());
+        let tenant = |name: &'static str| move || async move { HttpResponse::Ok().body(name) };
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(cfg))
+                .wrap(from_fn(route))
+                .route("/account", web::get().to(tenant("default")))
+                .route("/t/sales/account", web::get().to(tenant("sales"))),
+        )
+        .await;
+
+        for (uri, header, body) in [
+            ("/account", None, "default"),
+            ("/t/sales/account", None, "sales"),
+            ("/account", Some("sales"), "sales"),
+            ("/t/sales/account", Some("sales"), "sales"),
+            (
+                "/account",
+                Some("hr"),
+                r#"{"error":"No tenant is named \"hr\""}"#,
+            ),
+            (
+                "/t/sales/account",
+                Some("hr"),
+                r#"{"error":"The path and the tenant header name different tenants"}"#,
+            ),
+        ] {
+            let mut req = TestRequest::get().uri(uri);
+            if let Some(header) = header {
+                req = req.insert_header((HEADER, header));
+            }
+            let response = call_and_read_body(&app, req.to_request()).await;
+            assert_eq!(response, body, "{} with {:?}", uri, header);
+        }
+    }
+}
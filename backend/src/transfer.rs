@@ -0,0 +1,825 @@
+//! Reading and writing accounts in bulk, in the formats spreadsheets and
+//! scripts exchange them in.
+
+use std::{
+    collections::HashSet,
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use clap::builder::PossibleValue;
+use common::{validate::Rules, Account, Attributes, IdVersion, NewAccount};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::crud::{self, Crud, CrudError, Entity};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+    Jsonl,
+    Yaml,
+}
+
+impl Format {
+    /// Detects the format from the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "csv" => Some(Format::Csv),
+            "json" => Some(Format::Json),
+            "jsonl" | "ndjson" => Some(Format::Jsonl),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Detects the format from the first bytes of the content.
+    pub fn sniff(content: &[u8]) -> Format {
+        let start = String::from_utf8_lossy(content);
+        let start = start.trim_start();
+        if start.starts_with('[') {
+            Format::Json
+        } else if start.starts_with('{') {
+            Format::Jsonl
+        } else if start.starts_with("---") || start.starts_with("- ") {
+            Format::Yaml
+        } else {
+            Format::Csv
+        }
+    }
+
+    /// The media type of the format, for HTTP responses.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Json => "application/json",
+            Format::Jsonl => "application/x-ndjson",
+            Format::Yaml => "application/yaml",
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Csv => write!(f, "csv"),
+            Format::Json => write!(f, "json"),
+            Format::Jsonl => write!(f, "jsonl"),
+            Format::Yaml => write!(f, "yaml"),
+        }
+    }
+}
+
+impl clap::ValueEnum for Format {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Format::Csv, Format::Json, Format::Jsonl, Format::Yaml]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Format::Csv => PossibleValue::new("csv").help("CSV with a header of the field names"),
+            Format::Json => PossibleValue::new("json").help("A JSON array"),
+            Format::Jsonl => PossibleValue::new("jsonl").help("One JSON object per line"),
+            Format::Yaml => PossibleValue::new("yaml").help("A YAML sequence"),
+        })
+    }
+}
+
+/// An account as read from a file, before validation. The tags and the
+/// attributes are JSON values in JSON and YAML, and JSON text in CSV, where
+/// the tags may also be separated by commas.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Record {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub fullname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<Value>,
+}
+
+/// A record read from the `row`th entry of a file, counting from 1 and
+/// not counting a CSV header. Unparseable entries hold the reason.
+pub type Row = (usize, Result<Record, String>);
+
+/// Reads the records of `input` in `format`. CSV, JSON Lines and JSON
+/// arrays are read one record at a time; YAML documents are read whole.
+pub fn read_records<'a, R: BufRead + 'a>(
+    mut input: R,
+    format: Format,
+) -> std::io::Result<Box<dyn Iterator<Item = Row> + 'a>> {
+    let rows: Box<dyn Iterator<Item = Result<Record, String>> + 'a> = match format {
+        Format::Csv => Box::new(
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::Headers)
+                .flexible(true)
+                .from_reader(input)
+                .into_deserialize::<Record>()
+                .map(|record| record.map_err(|e| e.to_string())),
+        ),
+        Format::Jsonl => Box::new(
+            input
+                .lines()
+                .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .map(|line| match line {
+                    Ok(line) => serde_json::from_str(&line).map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                }),
+        ),
+        Format::Json => Box::new(JsonArray {
+            input,
+            state: JsonState::Start,
+        }),
+        Format::Yaml => {
+            let mut content = String::new();
+            input.read_to_string(&mut content)?;
+            let records: Vec<Record> =
+                serde_yaml::from_str(&content).map_err(std::io::Error::other)?;
+            Box::new(records.into_iter().map(Ok))
+        }
+    };
+    Ok(Box::new(rows.enumerate().map(|(i, row)| (i + 1, row))))
+}
+
+#[derive(PartialEq)]
+enum JsonState {
+    Start,
+    Next,
+    Done,
+}
+
+/// The elements of a JSON array, parsed one at a time as they are read.
+struct JsonArray<R> {
+    input: R,
+    state: JsonState,
+}
+
+impl<R: BufRead> JsonArray<R> {
+    /// Returns the next byte that is not whitespace without consuming it.
+    fn peek(&mut self) -> std::io::Result<Option<u8>> {
+        loop {
+            let buf = self.input.fill_buf()?;
+            match buf.first() {
+                None => return Ok(None),
+                Some(b) if b.is_ascii_whitespace() => self.input.consume(1),
+                Some(b) => return Ok(Some(*b)),
+            }
+        }
+    }
+
+    /// Consumes the `[` or `,` before the next element, and returns whether
+    /// there is one.
+    fn separator(&mut self) -> Result<bool, String> {
+        let expected = if self.state == JsonState::Start {
+            b'['
+        } else {
+            b','
+        };
+        match self.peek().map_err(|e| e.to_string())? {
+            Some(b']') if self.state == JsonState::Next => {
+                self.input.consume(1);
+                Ok(false)
+            }
+            Some(b) if b == expected => {
+                self.input.consume(1);
+                if self.state == JsonState::Start
+                    && self.peek().map_err(|e| e.to_string())? == Some(b']')
+                {
+                    self.input.consume(1);
+                    return Ok(false);
+                }
+                Ok(true)
+            }
+            Some(b) => Err(format!(
+                "expected '{}', found '{}'",
+                expected as char, b as char
+            )),
+            None => Err("unexpected end of JSON array".to_string()),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for JsonArray<R> {
+    type Item = Result<Record, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state == JsonState::Done {
+            return None;
+        }
+        match self.separator() {
+            Ok(true) => self.state = JsonState::Next,
+            Ok(false) => {
+                self.state = JsonState::Done;
+                return None;
+            }
+            Err(e) => {
+                self.state = JsonState::Done;
+                return Some(Err(e));
+            }
+        }
+        // Parse the element as any value first, so that an element that is
+        // not a record is rejected without losing our place in the array.
+        let mut de = serde_json::Deserializer::from_reader(&mut self.input);
+        match serde_json::Value::deserialize(&mut de) {
+            Ok(value) => Some(serde_json::from_value(value).map_err(|e| e.to_string())),
+            Err(e) => {
+                self.state = JsonState::Done;
+                Some(Err(e.to_string()))
+            }
+        }
+    }
+}
+
+/// Writes accounts one at a time, so an export never holds more than one
+/// formatted account in memory.
+pub struct AccountWriter<W: Write> {
+    out: Output<W>,
+    format: Format,
+    count: usize,
+}
+
+enum Output<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Plain(W),
+}
+
+impl<W: Write> AccountWriter<W> {
+    pub fn new(out: W, format: Format) -> std::io::Result<Self> {
+        let out = match format {
+            Format::Csv => Output::Csv(Box::new(csv::Writer::from_writer(out))),
+            Format::Json => {
+                let mut out = out;
+                out.write_all(b"[")?;
+                Output::Plain(out)
+            }
+            Format::Jsonl | Format::Yaml => Output::Plain(out),
+        };
+        Ok(AccountWriter {
+            out,
+            format,
+            count: 0,
+        })
+    }
+
+    pub fn write(&mut self, account: &Account) -> std::io::Result<()> {
+        match &mut self.out {
+            Output::Csv(out) => {
+                if self.count == 0 {
+                    out.write_record(csv_header())?;
+                }
+                out.write_record(std::iter::once(account.id.to_string()).chain(account.values()))?
+            }
+            Output::Plain(out) => match self.format {
+                Format::Json => {
+                    if self.count > 0 {
+                        out.write_all(b",")?;
+                    }
+                    out.write_all(b"\n  ")?;
+                    serde_json::to_writer(&mut *out, account)?;
+                }
+                Format::Jsonl => {
+                    serde_json::to_writer(&mut *out, account)?;
+                    out.write_all(b"\n")?;
+                }
+                Format::Yaml => {
+                    let item = serde_yaml::to_string(&[account]).map_err(std::io::Error::other)?;
+                    out.write_all(item.as_bytes())?;
+                }
+                Format::Csv => unreachable!(),
+            },
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Terminates the document and returns the underlying writer.
+    pub fn finish(self) -> std::io::Result<W> {
+        match self.out {
+            Output::Csv(mut out) => {
+                // An empty export still gets its header row.
+                if self.count == 0 {
+                    out.write_record(csv_header())?;
+                }
+                out.into_inner().map_err(|e| e.into_error())
+            }
+            Output::Plain(mut out) => {
+                match self.format {
+                    Format::Json if self.count > 0 => out.write_all(b"\n]\n")?,
+                    Format::Json => out.write_all(b"]\n")?,
+                    Format::Yaml if self.count == 0 => out.write_all(b"[]\n")?,
+                    _ => {}
+                }
+                Ok(out)
+            }
+        }
+    }
+}
+
+/// The columns of a CSV export: the id and every field.
+fn csv_header() -> impl Iterator<Item = &'static str> {
+    std::iter::once("id").chain(Account::FIELDS.iter().map(|field| field.name))
+}
+
+/// What to do with an imported account whose id is already taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Conflict {
+    /// Keep the stored account and skip the imported one.
+    #[default]
+    Skip,
+    /// Replace the stored account with the imported one.
+    Overwrite,
+    /// Stop the import at the conflicting row.
+    Fail,
+    /// Import the account under a newly generated id.
+    NewId,
+}
+
+impl clap::ValueEnum for Conflict {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Conflict::Skip,
+            Conflict::Overwrite,
+            Conflict::Fail,
+            Conflict::NewId,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Conflict::Skip => PossibleValue::new("skip").help("Keep the stored account"),
+            Conflict::Overwrite => {
+                PossibleValue::new("overwrite").help("Replace the stored account")
+            }
+            Conflict::Fail => PossibleValue::new("fail").help("Stop at the first conflict"),
+            Conflict::NewId => PossibleValue::new("new-id").help("Import under a new id"),
+        })
+    }
+}
+
+/// A row that was not imported, and why.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rejected {
+    pub row: usize,
+    pub record: Option<Record>,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportReport {
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub rejected: Vec<Rejected>,
+    /// Set when [`Conflict::Fail`] stopped the import at this row.
+    pub failed_at: Option<usize>,
+}
+
+impl ImportReport {
+    fn reject(&mut self, row: usize, record: Option<Record>, reason: impl Into<String>) {
+        self.rejected.push(Rejected {
+            row,
+            record,
+            reason: reason.into(),
+        });
+    }
+
+    /// Writes the rejected rows as CSV, with the reason in the last column,
+    /// so they can be fixed and imported again.
+    pub fn write_rejected<W: Write>(&self, out: W) -> std::io::Result<()> {
+        let mut out = csv::Writer::from_writer(out);
+        out.write_record(
+            std::iter::once("row")
+                .chain(csv_header())
+                .chain(std::iter::once("reason")),
+        )?;
+        for rejected in &self.rejected {
+            let record = rejected.record.clone().unwrap_or_default();
+            out.write_record([
+                rejected.row.to_string(),
+                record.id.unwrap_or_default(),
+                record.fullname.unwrap_or_default(),
+                record.email.unwrap_or_default(),
+                record.status.unwrap_or_default(),
+                text_of(record.tags),
+                text_of(record.attributes),
+                rejected.reason.clone(),
+            ])?;
+        }
+        out.flush()
+    }
+}
+
+/// A value of a record as it is written to a CSV cell.
+fn text_of(value: Option<Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(text)) => text,
+        Some(value) => value.to_string(),
+    }
+}
+
+/// The tags of a record: a JSON array, or text holding one or a list
+/// separated by commas.
+fn tags_of(value: Option<&Value>) -> Result<Vec<String>, String> {
+    let tags = match value {
+        None | Some(Value::Null) => return Ok(Vec::new()),
+        Some(Value::String(text)) if !text.trim_start().starts_with('[') => {
+            return Ok(text.split(',').map(str::to_string).collect())
+        }
+        Some(Value::String(text)) => serde_json::from_str(text),
+        Some(value) => serde_json::from_value(value.clone()),
+    };
+    tags.map_err(|e| format!("tags must be a list of texts: {}", e))
+}
+
+/// The attributes of a record: a JSON object, or text holding one.
+fn attributes_of(value: Option<&Value>) -> Result<Attributes, String> {
+    let attributes = match value {
+        None | Some(Value::Null) => return Ok(Attributes::new()),
+        Some(Value::String(text)) if text.trim().is_empty() => return Ok(Attributes::new()),
+        Some(Value::String(text)) => serde_json::from_str(text),
+        Some(value) => serde_json::from_value(value.clone()),
+    };
+    attributes.map_err(|e| format!("attributes must be an object: {}", e))
+}
+
+/// Checks a record against `rules` and returns its id, if it has one, and
+/// the cleaned up account.
+pub fn validate(record: &Record, rules: &Rules) -> Result<(Option<Uuid>, NewAccount), String> {
+    let Some(fullname) = record.fullname.clone() else {
+        return Err("fullname is missing".to_string());
+    };
+    let status = match record.status.as_deref().map(str::trim) {
+        None | Some("") => Default::default(),
+        Some(status) => status
+            .parse()
+            .map_err(|message| format!("status {}", message))?,
+    };
+    let account = NewAccount {
+        fullname,
+        email: record.email.clone(),
+        status,
+        tags: tags_of(record.tags.as_ref())?,
+        attributes: attributes_of(record.attributes.as_ref())?,
+    };
+    let account = rules
+        .new_account(&account)
+        .map_err(|errors| errors.to_string())?;
+    let id = match record.id.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(id) => Some(Uuid::parse_str(id).map_err(|e| format!("invalid id: {}", e))?),
+    };
+    Ok((id, account))
+}
+
+/// Rows are created this many at a time, so that stores which check each
+/// create against every stored account do so once per batch.
+const BATCH_SIZE: usize = 1000;
+
+/// Valid rows waiting to be created, in order.
+#[derive(Default)]
+struct Batch {
+    rows: Vec<(usize, Record)>,
+    accounts: Vec<Account>,
+    ids: HashSet<Uuid>,
+}
+
+impl Batch {
+    fn push(&mut self, row: usize, record: Record, account: Account) {
+        self.ids.insert(account.id);
+        self.rows.push((row, record));
+        self.accounts.push(account);
+    }
+
+    /// Creates the accounts in `store`, forgetting the `ids` of those whose
+    /// unique values are taken and rejecting their rows.
+    fn create(
+        &mut self,
+        store: &mut dyn Crud<Account>,
+        ids: &mut HashSet<Uuid>,
+        report: &mut ImportReport,
+    ) -> crud::Result<()> {
+        let results = store.create_all(&self.accounts)?;
+        for (((row, record), account), result) in self
+            .rows
+            .drain(..)
+            .zip(self.accounts.drain(..))
+            .zip(results)
+        {
+            match result {
+                Ok(()) => report.created += 1,
+                Err(CrudError::AlreadyExists) => {
+                    ids.remove(&account.id);
+                    report.reject(row, Some(record), "a unique value is taken")
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        self.ids.clear();
+        Ok(())
+    }
+}
+
+/// Imports `rows` into `store`, resolving id conflicts with `policy`. Rows
+/// without an id are given one of `id_version`.
+///
+/// Rows are checked against `rules`; invalid rows, and rows whose email
+/// another account has, are reported and skipped. Rows before a row that
+/// fails under [`Conflict::Fail`] stay imported. New accounts are created
+/// in batches of [`BATCH_SIZE`].
+pub fn import(
+    store: &mut dyn Crud<Account>,
+    rows: impl Iterator<Item = Row>,
+    policy: Conflict,
+    rules: &Rules,
+    id_version: IdVersion,
+) -> crud::Result<ImportReport> {
+    let mut ids: HashSet<Uuid> = store.read_all()?.iter().map(|account| account.id).collect();
+    let mut report = ImportReport::default();
+    let mut batch = Batch::default();
+    for (row, record) in rows {
+        let record = match record {
+            Ok(record) => record,
+            Err(reason) => {
+                report.reject(row, None, reason);
+                continue;
+            }
+        };
+        let (id, account) = match validate(&record, rules) {
+            Ok(valid) => valid,
+            Err(reason) => {
+                report.reject(row, Some(record), reason);
+                continue;
+            }
+        };
+        let mut account = account.into_account(id.unwrap_or_else(|| id_version.generate()));
+        // Whether the id is taken is only known once the batch holding it
+        // is created.
+        if batch.ids.contains(&account.id) {
+            batch.create(store, &mut ids, &mut report)?;
+        }
+        if ids.contains(&account.id) {
+            match policy {
+                Conflict::Skip => {
+                    report.skipped += 1;
+                    continue;
+                }
+                Conflict::Overwrite => {
+                    batch.create(store, &mut ids, &mut report)?;
+                    match store.update(&account) {
+                        Ok(()) => report.updated += 1,
+                        Err(CrudError::AlreadyExists) => {
+                            report.reject(row, Some(record), "a unique value is taken")
+                        }
+                        Err(err) => return Err(err),
+                    }
+                    continue;
+                }
+                Conflict::Fail => {
+                    report.reject(row, Some(record), format!("id {} exists", account.id));
+                    report.failed_at = Some(row);
+                    break;
+                }
+                Conflict::NewId => account.id = id_version.generate(),
+            }
+        }
+        ids.insert(account.id);
+        batch.push(row, record, account);
+        if batch.accounts.len() == BATCH_SIZE {
+            batch.create(store, &mut ids, &mut report)?;
+        }
+    }
+    batch.create(store, &mut ids, &mut report)?;
+    report.rejected.sort_by_key(|rejected| rejected.row);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use unindent::unindent;
+
+    use super::*;
+    use crate::crud::memory::MemoryStore;
+
+    fn rows(content: &str, format: Format) -> Vec<Row> {
+        read_records(content.as_bytes(), format)
+            .expect("Failed to read records")
+            .collect()
+    }
+
+    fn export(accounts: &[Account], format: Format) -> String {
+        let mut writer = AccountWriter::new(Vec::new(), format).expect("Failed to start export");
+        for account in accounts {
+            writer.write(account).expect("Failed to write Account");
+        }
+        String::from_utf8(writer.finish().expect("Failed to finish export")).unwrap()
+    }
+
+    #[test]
+    fn detecting_formats() {
+        assert_eq!(Format::from_path(Path::new("a.CSV")), Some(Format::Csv));
+        assert_eq!(Format::from_path(Path::new("a.yml")), Some(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("a.txt")), None);
+        assert_eq!(Format::sniff(b"  [{\"fullname\":\"a\"}]"), Format::Json);
+        assert_eq!(Format::sniff(b"{\"fullname\":\"a\"}\n"), Format::Jsonl);
+        assert_eq!(Format::sniff(b"- fullname: a\n"), Format::Yaml);
+        assert_eq!(Format::sniff(b"id,fullname\n"), Format::Csv);
+    }
+
+    #[test]
+    fn export_round_trips_in_every_format() {
+        let mut erich = Account::new("Schroeter, Erich");
+        erich.email = Some("erich@example.com".to_string());
+        erich.status = common::AccountStatus::Suspended;
+        erich.tags = vec!["staff".to_string(), "emea, north".to_string()];
+        erich
+            .attributes
+            .insert("floor".to_string(), common::Attribute::Integer(3));
+        erich.attributes.insert(
+            "desk".to_string(),
+            common::Attribute::Text("4b".to_string()),
+        );
+        let accounts = vec![erich, Account::new("Jane \"JD\" Doe")];
+        for format in [Format::Csv, Format::Json, Format::Jsonl, Format::Yaml] {
+            let exported = export(&accounts, format);
+            let imported: Vec<Account> = rows(&exported, format)
+                .into_iter()
+                .map(|(_, record)| {
+                    let record = record.expect("Failed to parse record");
+                    let (id, account) =
+                        validate(&record, &Rules::default()).expect("Failed to validate record");
+                    account.into_account(id.expect("No id"))
+                })
+                .collect();
+            assert_eq!(imported, accounts, "{} round trip", format);
+        }
+    }
+
+    #[test]
+    fn tags_may_be_separated_by_commas_in_csv() {
+        let csv = "fullname,tags,attributes\nErich,\"staff, emea\",\"{\"\"vip\"\": true}\"\n";
+        let record = rows(csv, Format::Csv).remove(0).1.unwrap();
+        let (_, account) = validate(&record, &Rules::default()).unwrap();
+        assert_eq!(account.tags, vec!["staff", "emea"]);
+        assert_eq!(account.attributes["vip"], common::Attribute::Bool(true));
+        let record = Record {
+            fullname: Some("Erich".to_string()),
+            attributes: Some(Value::String("[1]".to_string())),
+            ..Record::default()
+        };
+        assert!(validate(&record, &Rules::default())
+            .unwrap_err()
+            .starts_with("attributes must be an object"));
+    }
+
+    #[test]
+    fn json_array_is_read_element_by_element() {
+        let json = r#" [ {"fullname": "Test Account"}, {"fullname": 42},
+            {"id": null, "fullname": "Other Account"} ] "#;
+        let read = rows(json, Format::Json);
+        assert_eq!(read.len(), 3);
+        assert_eq!(
+            read[0].1,
+            Ok(Record {
+                fullname: Some("Test Account".to_string()),
+                ..Record::default()
+            })
+        );
+        assert!(read[1].1.is_err());
+        assert_eq!(read[2].0, 3);
+        assert!(read[2].1.is_ok());
+        assert!(rows("[]", Format::Json).is_empty());
+        assert!(rows("[{\"fullname\": \"a\"}", Format::Json)[1].1.is_err());
+    }
+
+    #[test]
+    fn empty_export_is_a_valid_document() {
+        assert_eq!(
+            export(&[], Format::Csv),
+            "id,fullname,email,status,tags,attributes\n"
+        );
+        assert_eq!(export(&[], Format::Json), "[]\n");
+        assert!(rows(&export(&[], Format::Yaml), Format::Yaml).is_empty());
+    }
+
+    #[test]
+    fn invalid_rows_are_rejected() {
+        let csv = unindent(
+            r#"
+            id,fullname,email
+            ,Test Account,test@example.com
+            not-a-uuid,Other Account,
+            ,"  ",
+            ,Copied Account,TEST@example.com
+            ,Other Account,test
+            "#,
+        );
+        let rules = crud::unique::UniqueRule::defaults_of::<Account>();
+        let mut store = crud::unique::with_unique(Box::new(MemoryStore::new()), &rules).unwrap();
+        let report = import(
+            &mut store,
+            rows(&csv, Format::Csv).into_iter(),
+            Conflict::Skip,
+            &Rules::default(),
+            IdVersion::default(),
+        )
+        .expect("Failed to import");
+        assert_eq!(report.created, 1);
+        assert_eq!(
+            report.rejected.iter().map(|r| r.row).collect::<Vec<_>>(),
+            vec![2, 3, 4, 5]
+        );
+        assert_eq!(report.rejected[2].reason, "a unique value is taken");
+        assert_eq!(
+            report.rejected[3].reason,
+            "email must look like name@example.com"
+        );
+        let mut out = Vec::new();
+        report.write_rejected(&mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with(
+            "row,id,fullname,email,status,tags,attributes,reason\n\
+             2,not-a-uuid,Other Account,,,,,invalid id"
+        ));
+    }
+
+    #[test]
+    fn conflict_policies() {
+        let existing = Account::new("Test Account");
+        let jsonl = format!(
+            "{{\"id\":\"{}\",\"fullname\":\"Imported Account\"}}\n{{\"fullname\":\"New Account\"}}\n",
+            existing.id
+        );
+        for (policy, created, updated, skipped, failed_at) in [
+            (Conflict::Skip, 1, 0, 1, None),
+            (Conflict::Overwrite, 1, 1, 0, None),
+            (Conflict::Fail, 0, 0, 0, Some(1)),
+            (Conflict::NewId, 2, 0, 0, None),
+        ] {
+            let mut store = MemoryStore::new();
+            store.create(&existing).expect("Failed to create Account");
+            let report = import(
+                &mut store,
+                rows(&jsonl, Format::Jsonl).into_iter(),
+                policy,
+                &Rules::default(),
+                IdVersion::V7,
+            )
+            .expect("Failed to import");
+            assert_eq!(
+                (
+                    report.created,
+                    report.updated,
+                    report.skipped,
+                    report.failed_at
+                ),
+                (created, updated, skipped, failed_at),
+                "{:?}",
+                policy
+            );
+            let stored = store.read(&existing.id).unwrap().fullname;
+            let expected = if policy == Conflict::Overwrite {
+                "Imported Account"
+            } else {
+                "Test Account"
+            };
+            assert_eq!(stored, expected, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn ids_repeated_in_a_file_conflict_with_the_earlier_row() {
+        let id = Uuid::new_v4();
+        let jsonl = format!(
+            "{{\"id\":\"{id}\",\"fullname\":\"First Account\"}}\n{{\"id\":\"{id}\",\"fullname\":\"Second Account\"}}\n"
+        );
+        for (policy, created, updated, skipped, expected) in [
+            (Conflict::Skip, 1, 0, 1, "First Account"),
+            (Conflict::Overwrite, 1, 1, 0, "Second Account"),
+        ] {
+            let mut store = MemoryStore::new();
+            let report = import(
+                &mut store,
+                rows(&jsonl, Format::Jsonl).into_iter(),
+                policy,
+                &Rules::default(),
+                IdVersion::default(),
+            )
+            .expect("Failed to import");
+            assert_eq!(
+                (report.created, report.updated, report.skipped),
+                (created, updated, skipped),
+                "{:?}",
+                policy
+            );
+            assert_eq!(store.read(&id).unwrap().fullname, expected, "{:?}", policy);
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use common::Account;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::{
+    cfg::WebhookCfg,
+    crud::observer::{Change, ChangeKind, Listener},
+};
+
+/// Deliveries that still fail after this many attempts are dead-lettered.
+pub const MAX_ATTEMPTS: u32 = 8;
+/// Delay before the first retry; doubled after every further failure.
+const BASE_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+/// A payload waiting to be POSTed to one webhook.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct Delivery {
+    id: Uuid,
+    url: String,
+    event: ChangeKind,
+    payload: String,
+    attempts: u32,
+    next_attempt: DateTime<Utc>,
+    #[serde(default)]
+    last_error: Option<String>,
+}
+
+/// Pending deliveries, persisted as JSON lines so they survive a restart.
+///
+/// Deliveries that exhaust their attempts are appended to a `.dead` file
+/// next to the queue for an operator to inspect.
+struct RetryQueue {
+    path: PathBuf,
+    deliveries: Vec<Delivery>,
+}
+
+impl RetryQueue {
+    fn open(path: &Path) -> std::io::Result<Self> {
+        let mut deliveries = Vec::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                match serde_json::from_str(&line?) {
+                    Ok(delivery) => deliveries.push(delivery),
+                    Err(e) => {
+                        log::warn!("Skipping bad webhook delivery in {}: {}", path.display(), e)
+                    }
+                }
+            }
+        }
+        Ok(RetryQueue {
+            path: path.to_path_buf(),
+            deliveries,
+        })
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let tempfile_path = format!("{}.tmp", self.path.display());
+        let mut tempfile = File::create(&tempfile_path)?;
+        for delivery in &self.deliveries {
+            writeln!(tempfile, "{}", serde_json::to_string(delivery)?)?;
+        }
+        tempfile.sync_all()?;
+        std::fs::rename(&tempfile_path, &self.path)
+    }
+
+    fn dead_letter_path(&self) -> String {
+        format!("{}.dead", self.path.display())
+    }
+
+    fn dead_letter(&self, delivery: &Delivery) -> std::io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dead_letter_path())?;
+        writeln!(file, "{}", serde_json::to_string(delivery)?)
+    }
+}
+
+/// Returns the `sha256=<hex>` HMAC signature of `payload` under `secret`.
+pub fn sign(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    let digest: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha256={}", digest)
+}
+
+fn backoff(attempts: u32) -> Duration {
+    BASE_BACKOFF
+        .checked_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .map_or(MAX_BACKOFF, |d| d.min(MAX_BACKOFF))
+}
+
+/// Sends account changes to the configured webhooks.
+///
+/// As a [`Listener`] it only records a delivery per matching webhook in the
+/// persistent queue; [`Webhooks::run`] POSTs them in the background and
+/// retries failures with exponential backoff.
+pub struct Webhooks {
+    hooks: Vec<WebhookCfg>,
+    queue: Mutex<RetryQueue>,
+    wake: Notify,
+}
+
+impl Webhooks {
+    pub fn new(hooks: Vec<WebhookCfg>, queue_path: &Path) -> std::io::Result<Self> {
+        Ok(Webhooks {
+            hooks,
+            queue: Mutex::new(RetryQueue::open(queue_path)?),
+            wake: Notify::new(),
+        })
+    }
+
+    async fn send(&self, client: &awc::Client, delivery: &Delivery) -> Result<(), String> {
+        let hook = self
+            .hooks
+            .iter()
+            .find(|h| h.url == delivery.url)
+            .ok_or("Webhook is no longer configured")?;
+        let mut request = client
+            .post(&delivery.url)
+            .content_type("application/json")
+            .insert_header((EVENT_HEADER, delivery.event.to_string()))
+            .insert_header((DELIVERY_HEADER, delivery.id.to_string()));
+        if let Some(secret) = &hook.secret {
+            request = request.insert_header((SIGNATURE_HEADER, sign(secret, &delivery.payload)));
+        }
+        let response = request
+            .send_body(delivery.payload.clone())
+            .await
+            .map_err(|e| e.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("HTTP {}", response.status()))
+        }
+    }
+
+    /// Attempts every delivery that is due and returns when the next one
+    /// will be, if any are left.
+    pub async fn deliver_due(&self, client: &awc::Client) -> Option<DateTime<Utc>> {
+        let now = Utc::now();
+        let due: Vec<Delivery> = {
+            let queue = self.queue.lock().unwrap();
+            queue
+                .deliveries
+                .iter()
+                .filter(|d| d.next_attempt <= now)
+                .cloned()
+                .collect()
+        };
+        for delivery in due {
+            let result = self.send(client, &delivery).await;
+            let mut queue = self.queue.lock().unwrap();
+            let Some(index) = queue.deliveries.iter().position(|d| d.id == delivery.id) else {
+                continue;
+            };
+            match result {
+                Ok(()) => {
+                    log::debug!("Delivered webhook {} to {}", delivery.id, delivery.url);
+                    queue.deliveries.remove(index);
+                }
+                Err(e) => {
+                    let entry = &mut queue.deliveries[index];
+                    entry.attempts += 1;
+                    entry.last_error = Some(e.clone());
+                    if entry.attempts >= MAX_ATTEMPTS {
+                        log::error!(
+                            "Giving up on webhook {} to {} after {} attempts: {}",
+                            delivery.id,
+                            delivery.url,
+                            entry.attempts,
+                            e
+                        );
+                        let dead = queue.deliveries.remove(index);
+                        if let Err(e) = queue.dead_letter(&dead) {
+                            log::error!("Failed to dead-letter webhook {}: {}", dead.id, e);
+                        }
+                    } else {
+                        let delay = backoff(entry.attempts);
+                        log::warn!(
+                            "Webhook {} to {} failed ({}), retrying in {:?}",
+                            delivery.id,
+                            delivery.url,
+                            e,
+                            delay
+                        );
+                        entry.next_attempt = Utc::now()
+                            + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::MAX);
+                    }
+                }
+            }
+            if let Err(e) = queue.save() {
+                log::error!("Failed to save webhook queue: {}", e);
+            }
+        }
+        let queue = self.queue.lock().unwrap();
+        queue.deliveries.iter().map(|d| d.next_attempt).min()
+    }
+
+    /// Delivers queued payloads until the process exits.
+    pub async fn run(self: Arc<Self>) {
+        let client = awc::Client::default();
+        loop {
+            let wait = match self.deliver_due(&client).await {
+                Some(next) => (next - Utc::now()).to_std().unwrap_or_default(),
+                None => MAX_BACKOFF,
+            };
+            tokio::select! {
+                _ = self.wake.notified() => {}
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    }
+}
+
+impl Listener<Account> for Webhooks {
+    fn on_change(&self, change: &Change<Account>) -> Result<(), Box<dyn std::error::Error>> {
+        let hooks: Vec<&WebhookCfg> = self
+            .hooks
+            .iter()
+            .filter(|h| h.events.is_empty() || h.events.contains(&change.kind))
+            .collect();
+        if hooks.is_empty() {
+            return Ok(());
+        }
+        let payload = serde_json::to_string(change)?;
+        let mut queue = self.queue.lock().unwrap();
+        for hook in hooks {
+            queue.deliveries.push(Delivery {
+                id: Uuid::new_v4(),
+                url: hook.url.clone(),
+                event: change.kind,
+                payload: payload.clone(),
+                attempts: 0,
+                next_attempt: change.at,
+                last_error: None,
+            });
+        }
+        queue.save()?;
+        self.wake.notify_one();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, rt, web, HttpRequest, HttpResponse, HttpServer};
+    use tempfile::tempdir;
+
+    use super::*;
+
+    type Received = Mutex<Vec<(Option<String>, String)>>;
+
+    async fn record(
+        req: HttpRequest,
+        body: String,
+        status: web::Data<StatusCode>,
+        received: web::Data<Received>,
+    ) -> HttpResponse {
+        let signature = req
+            .headers()
+            .get(SIGNATURE_HEADER)
+            .map(|v| v.to_str().unwrap().to_string());
+        received.lock().unwrap().push((signature, body));
+        HttpResponse::build(**status).finish()
+    }
+
+    /// Starts a local receiver that answers every request with `status`.
+    fn receiver(status: StatusCode, received: web::Data<Received>) -> String {
+        let server = HttpServer::new(move || {
+            actix_web::App::new()
+                .app_data(web::Data::new(status))
+                .app_data(received.clone())
+                .default_service(web::to(record))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .expect("Failed to bind receiver");
+        let addr = server.addrs()[0];
+        rt::spawn(server.run());
+        format!("http://{}/hook", addr)
+    }
+
+    fn change(kind: ChangeKind) -> Change<Account> {
+        Change {
+            seq: 1,
+            kind,
+            at: Utc::now(),
+            item: Account::new("Test Account"),
+        }
+    }
+
+    fn hook(url: &str, events: Vec<ChangeKind>) -> WebhookCfg {
+        WebhookCfg {
+            url: url.to_string(),
+            events,
+            secret: Some("s3cr3t".to_string()),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_limit() {
+        assert_eq!(backoff(1), Duration::from_secs(2));
+        assert_eq!(backoff(2), Duration::from_secs(4));
+        assert_eq!(backoff(4), Duration::from_secs(16));
+        assert_eq!(backoff(40), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn changes_are_filtered_and_persisted() {
+        let dir = tempdir().expect("Failed to create temp directory");
+        let queue_path = dir.path().join("webhooks.jsonl");
+        let webhooks = Webhooks::new(
+            vec![
+                hook("http://127.0.0.1:1/created", vec![ChangeKind::Created]),
+                hook("http://127.0.0.1:1/deleted", vec![ChangeKind::Deleted]),
+            ],
+            &queue_path,
+        )
+        .expect("Failed to open webhook queue");
+        webhooks
+            .on_change(&change(ChangeKind::Created))
+            .expect("Failed to queue webhook");
+        let reopened = RetryQueue::open(&queue_path).expect("Failed to reopen webhook queue");
+        assert_eq!(reopened.deliveries.len(), 1);
+        assert_eq!(reopened.deliveries[0].url, "http://127.0.0.1:1/created");
+    }
+
+    #[actix_web::test]
+    async fn delivers_signed_payload() {
+        let received = web::Data::new(Received::default());
+        let url = receiver(StatusCode::OK, received.clone());
+        let dir = tempdir().expect("Failed to create temp directory");
+        let queue_path = dir.path().join("webhooks.jsonl");
+        let webhooks = Webhooks::new(vec![hook(&url, Vec::new())], &queue_path)
+            .expect("Failed to open webhook queue");
+        webhooks
+            .on_change(&change(ChangeKind::Updated))
+            .expect("Failed to queue webhook");
+        let next = webhooks.deliver_due(&awc::Client::default()).await;
+        assert!(next.is_none());
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let (signature, body) = &received[0];
+        assert_eq!(signature.as_deref(), Some(sign("s3cr3t", body).as_str()));
+        let delivered: Change<Account> = serde_json::from_str(body).unwrap();
+        assert_eq!(delivered.kind, ChangeKind::Updated);
+        assert!(RetryQueue::open(&queue_path).unwrap().deliveries.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn failed_deliveries_are_retried_then_dead_lettered() {
+        let received = web::Data::new(Received::default());
+        let url = receiver(StatusCode::INTERNAL_SERVER_ERROR, received.clone());
+        let dir = tempdir().expect("Failed to create temp directory");
+        let queue_path = dir.path().join("webhooks.jsonl");
+        let webhooks = Webhooks::new(vec![hook(&url, Vec::new())], &queue_path)
+            .expect("Failed to open webhook queue");
+        webhooks
+            .on_change(&change(ChangeKind::Deleted))
+            .expect("Failed to queue webhook");
+        let client = awc::Client::default();
+        assert!(webhooks.deliver_due(&client).await.unwrap() > Utc::now());
+        let queue = RetryQueue::open(&queue_path).unwrap();
+        assert_eq!(queue.deliveries[0].attempts, 1);
+        assert_eq!(
+            queue.deliveries[0].last_error.as_deref(),
+            Some("HTTP 500 Internal Server Error")
+        );
+
+        // Make the delivery due immediately for each remaining attempt.
+        for _ in 1..MAX_ATTEMPTS {
+            for delivery in webhooks.queue.lock().unwrap().deliveries.iter_mut() {
+                delivery.next_attempt = Utc::now();
+            }
+            webhooks.deliver_due(&client).await;
+        }
+        assert_eq!(received.lock().unwrap().len(), MAX_ATTEMPTS as usize);
+        assert!(RetryQueue::open(&queue_path).unwrap().deliveries.is_empty());
+        let dead = std::fs::read_to_string(format!("{}.dead", queue_path.display())).unwrap();
+        assert_eq!(dead.lines().count(), 1);
+    }
+}
@@ -1,12 +1,28 @@
+pub mod validate;
+
 use core::fmt;
+use std::collections::BTreeMap;
 
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use uuid::Uuid;
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Account {
     pub id: Uuid,
+    #[cfg_attr(feature = "openapi", schema(example = "Erich Schroeter"))]
     pub fullname: String,
+    /// Unique among the accounts when given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", schema(example = "erich@example.com"))]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub status: AccountStatus,
+    #[serde(default)]
+    #[cfg_attr(feature = "openapi", schema(example = json!(["staff"])))]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub attributes: Attributes,
 }
 
 impl Account {
@@ -14,11 +30,248 @@ impl Account {
         Account {
             id: Uuid::new_v4(),
             fullname: fullname.to_string(),
+            email: None,
+            status: AccountStatus::default(),
+            tags: Vec::new(),
+            attributes: Attributes::new(),
         }
     }
 
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
     pub fn to_csv(&self) -> String {
-        format!("{},{}", self.id.to_string(), self.fullname)
+        format!("{},{}", self.id, self.fullname)
+    }
+}
+
+/// Whether an account may be used.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    Suspended,
+}
+
+impl AccountStatus {
+    pub const ALL: [AccountStatus; 2] = [AccountStatus::Active, AccountStatus::Suspended];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Suspended => "suspended",
+        }
+    }
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for AccountStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AccountStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| format!("must be one of active, suspended, not {:?}", s))
+    }
+}
+
+/// The value of a custom attribute of an account.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum Attribute {
+    Bool(bool),
+    Integer(i64),
+    Number(f64),
+    Text(String),
+}
+
+impl Attribute {
+    /// Reads `text` as the type it looks like: `true` and `false` as
+    /// booleans, whole numbers as integers, other numbers as numbers and
+    /// anything else as text.
+    pub fn parse(text: &str) -> Attribute {
+        let text = text.trim();
+        if let Ok(value) = text.parse() {
+            Attribute::Bool(value)
+        } else if let Ok(value) = text.parse() {
+            Attribute::Integer(value)
+        } else if let Some(value) = text.parse().ok().filter(|value: &f64| value.is_finite()) {
+            Attribute::Number(value)
+        } else {
+            Attribute::Text(text.to_string())
+        }
+    }
+}
+
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Attribute::Bool(value) => write!(f, "{}", value),
+            Attribute::Integer(value) => write!(f, "{}", value),
+            Attribute::Number(value) => write!(f, "{}", value),
+            Attribute::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// The custom attributes of an account by name.
+pub type Attributes = BTreeMap<String, Attribute>;
+
+/// Which accounts a listing includes. Every criterion given must match.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct AccountFilter {
+    /// Only the accounts with this tag.
+    #[serde(
+        default,
+        deserialize_with = "blank_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tag: Option<String>,
+    /// Only the accounts with this status.
+    #[serde(
+        default,
+        deserialize_with = "blank_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub status: Option<AccountStatus>,
+}
+
+/// Reads a parameter left blank, as forms send it, as one not given.
+fn blank_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: fmt::Display,
+{
+    let text: Option<String> = Option::deserialize(deserializer)?;
+    match text.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(text) => text.parse().map(Some).map_err(de::Error::custom),
+    }
+}
+
+impl AccountFilter {
+    pub fn matches(&self, account: &Account) -> bool {
+        self.tag.as_deref().is_none_or(|tag| account.has_tag(tag))
+            && self.status.is_none_or(|status| account.status == status)
+    }
+}
+
+/// The body of a request to create an account. The server assigns the id.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NewAccount {
+    #[cfg_attr(feature = "openapi", schema(example = "Erich Schroeter"))]
+    pub fullname: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", schema(example = "erich@example.com"))]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub status: AccountStatus,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub attributes: Attributes,
+}
+
+impl NewAccount {
+    pub fn into_account(self, id: Uuid) -> Account {
+        Account {
+            id,
+            fullname: self.fullname,
+            email: self.email,
+            status: self.status,
+            tags: self.tags,
+            attributes: self.attributes,
+        }
+    }
+}
+
+/// The body of a request to change an account. The fields left out keep
+/// their values, and an empty `email` removes the email.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AccountUpdate {
+    #[cfg_attr(feature = "openapi", schema(example = "Erich Schroeter"))]
+    pub fullname: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<AccountStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<Attributes>,
+}
+
+impl AccountUpdate {
+    /// Applies the changes to `account`.
+    pub fn apply(self, account: Account) -> Account {
+        Account {
+            fullname: self.fullname,
+            email: match self.email {
+                Some(email) if email.is_empty() => None,
+                Some(email) => Some(email),
+                None => account.email,
+            },
+            status: self.status.unwrap_or(account.status),
+            tags: self.tags.unwrap_or(account.tags),
+            attributes: self.attributes.unwrap_or(account.attributes),
+            ..account
+        }
+    }
+}
+
+/// A named collection of accounts.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Group {
+    pub id: Uuid,
+    #[cfg_attr(feature = "openapi", schema(example = "Sales"))]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl Group {
+    pub fn new(name: &str) -> Self {
+        Group {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            description: String::new(),
+        }
+    }
+}
+
+/// The UUID version of the ids the server assigns.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdVersion {
+    /// Random ids.
+    #[default]
+    V4,
+    /// Ids that sort by creation time.
+    V7,
+}
+
+impl IdVersion {
+    pub fn generate(&self) -> Uuid {
+        match self {
+            IdVersion::V4 => Uuid::new_v4(),
+            IdVersion::V7 => Uuid::now_v7(),
+        }
     }
 }
 
@@ -27,3 +280,87 @@ impl fmt::Display for Account {
         write!(f, "Full name: {}", self.fullname)
     }
 }
+
+/// One page of a listing, with what is needed to fetch the others.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// The number of this page, starting at 1.
+    pub page: usize,
+    pub per_page: usize,
+    /// The number of items on all pages.
+    pub total: usize,
+    pub pages: usize,
+}
+
+impl<T> Page<T> {
+    /// Collects page `page` of `per_page` items from `items`, counting the
+    /// rest without keeping them.
+    pub fn collect<E>(
+        items: impl IntoIterator<Item = Result<T, E>>,
+        page: usize,
+        per_page: usize,
+    ) -> Result<Self, E> {
+        let page = page.max(1);
+        let per_page = per_page.max(1);
+        let skip = (page - 1).saturating_mul(per_page);
+        let mut taken = Vec::new();
+        let mut total = 0;
+        for item in items {
+            let item = item?;
+            if total >= skip && taken.len() < per_page {
+                taken.push(item);
+            }
+            total += 1;
+        }
+        Ok(Page {
+            items: taken,
+            page,
+            per_page,
+            total,
+            pages: total.div_ceil(per_page),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_are_read_as_the_type_they_look_like() {
+        assert_eq!(Attribute::parse(" true "), Attribute::Bool(true));
+        assert_eq!(Attribute::parse("-3"), Attribute::Integer(-3));
+        assert_eq!(Attribute::parse("2.5"), Attribute::Number(2.5));
+        assert_eq!(Attribute::parse("NaN"), Attribute::Text("NaN".to_string()));
+        assert_eq!(
+            Attribute::parse("3rd floor"),
+            Attribute::Text("3rd floor".to_string())
+        );
+        let attributes: Attributes =
+            serde_json::from_str(r#"{"vip": true, "floor": 3, "score": 0.5, "desk": "4b"}"#)
+                .unwrap();
+        assert_eq!(attributes["floor"], Attribute::Integer(3));
+        assert_eq!(attributes["score"], Attribute::Number(0.5));
+    }
+
+    #[test]
+    fn filters_match_tags_and_status() {
+        let mut account = Account::new("Erich");
+        account.tags = vec!["staff".to_string()];
+        assert!(AccountFilter::default().matches(&account));
+        let filter = AccountFilter {
+            tag: Some("staff".to_string()),
+            status: Some(AccountStatus::Active),
+        };
+        assert!(filter.matches(&account));
+        account.status = AccountStatus::Suspended;
+        assert!(!filter.matches(&account));
+        assert!(!AccountFilter {
+            tag: Some("sales".to_string()),
+            status: None,
+        }
+        .matches(&account));
+    }
+}
@@ -0,0 +1,515 @@
+//! Validation of account payloads, shared by the backend and the frontend.
+//!
+//! A [`TextRule`] cleans a text field up (trimming, Unicode normalization)
+//! and then checks its length and characters. [`Rules`] holds the rule of
+//! every field and can be read from configuration.
+
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::{Account, AccountUpdate, Attributes, Group, NewAccount};
+
+/// The most characters of an email address.
+const MAX_EMAIL_LEN: usize = 254;
+
+/// The characters a text field may contain.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum Charset {
+    /// Anything but control characters.
+    #[default]
+    Printable,
+    /// Letters, combining marks, spaces and `-`, `'`, `.` and `,`.
+    Letters,
+    /// Printable ASCII.
+    Ascii,
+}
+
+impl Charset {
+    pub fn allows(&self, c: char) -> bool {
+        match self {
+            Charset::Printable => !c.is_control(),
+            Charset::Letters => {
+                c.is_alphabetic() || is_combining_mark(c) || c == ' ' || "-'.,".contains(c)
+            }
+            Charset::Ascii => c.is_ascii() && !c.is_ascii_control(),
+        }
+    }
+}
+
+/// The Unicode normalization form a text field is converted to.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum Normalization {
+    None,
+    #[default]
+    Nfc,
+    Nfkc,
+}
+
+/// How a text field is cleaned up and what it must look like afterwards.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TextRule {
+    /// Strip leading and trailing whitespace.
+    pub trim: bool,
+    pub normalization: Normalization,
+    /// The fewest characters, counted after trimming and normalization.
+    pub min_len: usize,
+    /// The most characters, counted after trimming and normalization.
+    pub max_len: usize,
+    pub charset: Charset,
+}
+
+impl Default for TextRule {
+    fn default() -> Self {
+        TextRule {
+            trim: true,
+            normalization: Normalization::default(),
+            min_len: 1,
+            max_len: 200,
+            charset: Charset::default(),
+        }
+    }
+}
+
+impl TextRule {
+    /// Cleans `value` up and checks it, returning the cleaned up value or
+    /// why it is invalid.
+    pub fn apply(&self, value: &str) -> Result<String, String> {
+        let value = if self.trim { value.trim() } else { value };
+        let value: String = match self.normalization {
+            Normalization::None => value.to_string(),
+            Normalization::Nfc => value.nfc().collect(),
+            Normalization::Nfkc => value.nfkc().collect(),
+        };
+        let len = value.chars().count();
+        if len == 0 && self.min_len > 0 {
+            return Err("must not be empty".to_string());
+        }
+        if len < self.min_len {
+            return Err(format!("must be at least {} characters", self.min_len));
+        }
+        if len > self.max_len {
+            return Err(format!("must be at most {} characters", self.max_len));
+        }
+        if let Some(c) = value.chars().find(|c| !self.charset.allows(*c)) {
+            return Err(format!("must not contain {:?}", c));
+        }
+        Ok(value)
+    }
+}
+
+/// The rules of every account field, and of the fields of groups.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Rules {
+    pub fullname: TextRule,
+    /// The rule of each tag of an account.
+    pub tag: TextRule,
+    /// The rule of the name of each custom attribute of an account.
+    pub attribute: TextRule,
+    pub groups: GroupRules,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        let short = TextRule {
+            max_len: 50,
+            ..TextRule::default()
+        };
+        Rules {
+            fullname: TextRule::default(),
+            tag: short.clone(),
+            attribute: short,
+            groups: GroupRules::default(),
+        }
+    }
+}
+
+/// The rules of every group field.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GroupRules {
+    pub name: TextRule,
+    pub description: TextRule,
+}
+
+impl Default for GroupRules {
+    fn default() -> Self {
+        GroupRules {
+            name: TextRule::default(),
+            description: TextRule {
+                min_len: 0,
+                max_len: 1000,
+                ..TextRule::default()
+            },
+        }
+    }
+}
+
+impl Rules {
+    /// Checks `account`, returning it cleaned up or the errors of all its
+    /// invalid fields.
+    pub fn account(&self, account: &Account) -> Result<Account, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        let fullname = errors.check("fullname", self.fullname.apply(&account.fullname));
+        let email = match &account.email {
+            Some(email) => errors.check("email", email_of(email)),
+            None => Some(None),
+        };
+        let tags = errors.check("tags", self.tags_of(&account.tags));
+        let attributes = errors.check("attributes", self.attributes_of(&account.attributes));
+        errors.into_result()?;
+        Ok(Account {
+            id: account.id,
+            fullname: fullname.unwrap_or_default(),
+            email: email.unwrap_or_default(),
+            status: account.status,
+            tags: tags.unwrap_or_default(),
+            attributes: attributes.unwrap_or_default(),
+        })
+    }
+
+    /// Checks a request to create an account.
+    pub fn new_account(&self, account: &NewAccount) -> Result<NewAccount, ValidationErrors> {
+        let checked = self.account(&account.clone().into_account(Default::default()))?;
+        Ok(NewAccount {
+            fullname: checked.fullname,
+            email: checked.email,
+            status: checked.status,
+            tags: checked.tags,
+            attributes: checked.attributes,
+        })
+    }
+
+    /// Checks a request to change an account. An empty `email` stays empty,
+    /// as it removes the email.
+    pub fn account_update(
+        &self,
+        update: &AccountUpdate,
+    ) -> Result<AccountUpdate, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        let fullname = errors.check("fullname", self.fullname.apply(&update.fullname));
+        let email = match &update.email {
+            Some(email) => errors
+                .check("email", email_of(email))
+                .map(|email| Some(email.unwrap_or_default())),
+            None => Some(None),
+        };
+        let tags = match &update.tags {
+            Some(tags) => errors.check("tags", self.tags_of(tags)).map(Some),
+            None => Some(None),
+        };
+        let attributes = match &update.attributes {
+            Some(attributes) => errors
+                .check("attributes", self.attributes_of(attributes))
+                .map(Some),
+            None => Some(None),
+        };
+        errors.into_result()?;
+        Ok(AccountUpdate {
+            fullname: fullname.unwrap_or_default(),
+            email: email.unwrap_or_default(),
+            status: update.status,
+            tags: tags.unwrap_or_default(),
+            attributes: attributes.unwrap_or_default(),
+        })
+    }
+
+    /// Checks `group`, returning it cleaned up or the errors of all its
+    /// invalid fields.
+    pub fn group(&self, group: &Group) -> Result<Group, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        let name = errors.check("name", self.groups.name.apply(&group.name));
+        let description = errors.check(
+            "description",
+            self.groups.description.apply(&group.description),
+        );
+        errors.into_result()?;
+        Ok(Group {
+            id: group.id,
+            name: name.unwrap_or_default(),
+            description: description.unwrap_or_default(),
+        })
+    }
+
+    /// Cleans each tag up, dropping the blank and repeated ones.
+    fn tags_of(&self, tags: &[String]) -> Result<Vec<String>, String> {
+        let mut cleaned: Vec<String> = Vec::new();
+        for tag in tags.iter().filter(|tag| !tag.trim().is_empty()) {
+            let tag = self
+                .tag
+                .apply(tag)
+                .map_err(|message| format!("{:?} {}", tag, message))?;
+            if !cleaned.contains(&tag) {
+                cleaned.push(tag);
+            }
+        }
+        Ok(cleaned)
+    }
+
+    fn attributes_of(&self, attributes: &Attributes) -> Result<Attributes, String> {
+        let mut cleaned = Attributes::new();
+        for (name, value) in attributes {
+            let name = self
+                .attribute
+                .apply(name)
+                .map_err(|message| format!("{:?} {}", name, message))?;
+            if cleaned.insert(name.clone(), value.clone()).is_some() {
+                return Err(format!("{:?} is given twice", name));
+            }
+        }
+        Ok(cleaned)
+    }
+}
+
+/// Cleans an email address up, returning `None` for a blank one.
+///
+/// The address is lowercased, and must have a single `@` between a local
+/// part and a domain with a dot in it.
+pub fn email_of(email: &str) -> Result<Option<String>, String> {
+    let email = email.trim().to_lowercase();
+    if email.is_empty() {
+        return Ok(None);
+    }
+    if email.chars().count() > MAX_EMAIL_LEN {
+        return Err(format!("must be at most {} characters", MAX_EMAIL_LEN));
+    }
+    if email.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err("must not contain spaces".to_string());
+    }
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains("..")
+        }
+        None => false,
+    };
+    if valid {
+        Ok(Some(email))
+    } else {
+        Err("must look like name@example.com".to_string())
+    }
+}
+
+/// Why a field is invalid.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FieldError {
+    #[cfg_attr(feature = "openapi", schema(example = "fullname"))]
+    pub field: String,
+    #[cfg_attr(feature = "openapi", schema(example = "must not be empty"))]
+    pub message: String,
+}
+
+/// The invalid fields of a payload.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ValidationErrors {
+    pub fields: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    /// Records the error of `field`, if any, and passes its value on.
+    pub fn check<T>(&mut self, field: &str, result: Result<T, String>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(message) => {
+                self.fields.push(FieldError {
+                    field: field.to_string(),
+                    message,
+                });
+                None
+            }
+        }
+    }
+
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.fields.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|error| format!("{} {}", error.field, error.message))
+            .collect();
+        write!(f, "{}", fields.join(", "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Attribute;
+
+    #[test]
+    fn fullname_is_trimmed_and_normalized() {
+        let rule = TextRule::default();
+        // "e" followed by a combining acute accent becomes a single "é".
+        assert_eq!(
+            rule.apply("  Rene\u{301} Schroeter \n"),
+            Ok("Ren\u{e9} Schroeter".to_string())
+        );
+        assert_eq!(rule.apply(" \t "), Err("must not be empty".to_string()));
+        assert_eq!(
+            rule.apply("Erich\u{7}"),
+            Err("must not contain '\\u{7}'".to_string())
+        );
+    }
+
+    #[test]
+    fn length_is_counted_in_characters() {
+        let rule = TextRule {
+            min_len: 2,
+            max_len: 4,
+            ..TextRule::default()
+        };
+        assert!(rule.apply("Jörg").is_ok());
+        assert_eq!(
+            rule.apply("J"),
+            Err("must be at least 2 characters".to_string())
+        );
+        assert_eq!(
+            rule.apply("Jürgen"),
+            Err("must be at most 4 characters".to_string())
+        );
+    }
+
+    #[test]
+    fn charsets() {
+        let letters = TextRule {
+            charset: Charset::Letters,
+            ..TextRule::default()
+        };
+        assert!(letters.apply("Anne-Marie O'Neil, Jr.").is_ok());
+        assert!(letters.apply("R2-D2").is_err());
+        let ascii = TextRule {
+            charset: Charset::Ascii,
+            ..TextRule::default()
+        };
+        assert!(ascii.apply("R2-D2").is_ok());
+        assert!(ascii.apply("Jörg").is_err());
+    }
+
+    #[test]
+    fn invalid_account_reports_field() {
+        let account = Account::new("  ");
+        let errors = Rules::default()
+            .account(&account)
+            .expect_err("Blank name is valid");
+        assert_eq!(errors.to_string(), "fullname must not be empty");
+        assert_eq!(errors.fields[0].field, "fullname");
+    }
+
+    #[test]
+    fn emails_are_lowercased_and_checked() {
+        assert_eq!(
+            email_of(" Erich@Example.COM "),
+            Ok(Some("erich@example.com".to_string()))
+        );
+        assert_eq!(email_of("  "), Ok(None));
+        for invalid in [
+            "erich",
+            "@example.com",
+            "erich@example",
+            "a@b@c.d",
+            "e h@x.io",
+            "a@.io",
+        ] {
+            assert!(email_of(invalid).is_err(), "{} is valid", invalid);
+        }
+    }
+
+    #[test]
+    fn tags_and_attributes_are_cleaned_up() {
+        let mut account = Account::new("Erich");
+        account.email = Some("ERICH@example.com".to_string());
+        account.tags = vec![" staff ".into(), "".into(), "staff".into(), "emea".into()];
+        account
+            .attributes
+            .insert(" age ".into(), Attribute::Integer(42));
+        let account = Rules::default().account(&account).unwrap();
+        assert_eq!(account.email.as_deref(), Some("erich@example.com"));
+        assert_eq!(account.tags, vec!["staff", "emea"]);
+        assert_eq!(account.attributes["age"], Attribute::Integer(42));
+
+        let mut invalid = account.clone();
+        invalid.email = Some("erich".into());
+        invalid.tags = vec!["x".repeat(51)];
+        invalid
+            .attributes
+            .insert("age".into(), Attribute::Bool(true));
+        invalid
+            .attributes
+            .insert(" age".into(), Attribute::Bool(false));
+        let fields: Vec<String> = Rules::default()
+            .account(&invalid)
+            .unwrap_err()
+            .fields
+            .into_iter()
+            .map(|error| error.field)
+            .collect();
+        assert_eq!(fields, vec!["email", "tags", "attributes"]);
+    }
+
+    #[test]
+    fn updates_keep_an_empty_email_to_remove_it() {
+        let update = AccountUpdate {
+            fullname: "Erich".into(),
+            email: Some(" ".into()),
+            ..AccountUpdate::default()
+        };
+        let mut account = Account::new("Erich");
+        account.email = Some("erich@example.com".into());
+        let update = Rules::default().account_update(&update).unwrap();
+        assert_eq!(update.email.as_deref(), Some(""));
+        assert_eq!(update.apply(account).email, None);
+    }
+
+    #[test]
+    fn invalid_group_reports_all_fields() {
+        let mut group = Group::new(" ");
+        group.description = "x".repeat(1001);
+        let errors = Rules::default()
+            .group(&group)
+            .expect_err("Blank name is valid");
+        assert_eq!(
+            errors.to_string(),
+            "name must not be empty, description must be at most 1000 characters"
+        );
+        group.name = " Sales ".to_string();
+        group.description = String::new();
+        assert_eq!(Rules::default().group(&group).unwrap().name, "Sales");
+    }
+
+    #[test]
+    fn rules_are_read_with_defaults() {
+        let rules: Rules =
+            serde_json::from_str(r#"{"fullname": {"max_len": 50, "charset": "letters"}}"#)
+                .expect("Failed to parse rules");
+        assert_eq!(rules.fullname.max_len, 50);
+        assert_eq!(rules.fullname.charset, Charset::Letters);
+        assert_eq!(rules.fullname.min_len, 1);
+        assert!(rules.fullname.trim);
+    }
+}
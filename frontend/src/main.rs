@@ -1,3 +1,42 @@
+use common::validate::Rules;
+
+/// The environment variable holding the URL of the backend.
+const BACKEND_URL_VAR: &str = "example-rust-crud-strategy-pattern_backend_url";
+const DEFAULT_BACKEND_URL: &str = "http://127.0.0.1:8080";
+
+/// Fetches the rules the backend at `base` checks account payloads with.
+fn fetch_rules(base: &str) -> Result<Rules, Box<dyn std::error::Error>> {
+    let url = format!("{}/api/v1/rules", base.trim_end_matches('/'));
+    Ok(ureq::get(&url).call()?.into_json()?)
+}
+
+/// Checks the full names given as arguments with the rules the backend
+/// applies to account payloads, printing each name as it would be stored.
 fn main() {
-    println!("Hello, world!");
+    let names: Vec<String> = std::env::args().skip(1).collect();
+    if names.is_empty() {
+        println!("Hello, world!");
+        return;
+    }
+    let base = std::env::var(BACKEND_URL_VAR).unwrap_or_else(|_| DEFAULT_BACKEND_URL.to_string());
+    let rules = match fetch_rules(&base) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("Failed to fetch the validation rules from {}: {}", base, e);
+            std::process::exit(1);
+        }
+    };
+    let mut valid = true;
+    for name in names {
+        match rules.fullname.apply(&name) {
+            Ok(fullname) => println!("{}", fullname),
+            Err(message) => {
+                eprintln!("{:?}: fullname {}", name, message);
+                valid = false;
+            }
+        }
+    }
+    if !valid {
+        std::process::exit(1);
+    }
 }